@@ -37,6 +37,7 @@ use sls_webgpu::{
     gltf_loader::GltfImportOutput,
     handle::{Handle, HandleIndex},
   },
+  wgpu,
   wgpu_renderer::{
     material::Material,
     mesh::{Mesh, MeshGeometry},
@@ -79,7 +80,17 @@ impl App {
     let video_sys = sdl.video().map_err(|s| anyhow!(s))?;
     let mut window = create_window(&video_sys, (1600, 1200))?;
     let event_pump = sdl.event_pump().map_err(|s| anyhow!(s))?;
-    let context = pollster::block_on(Context::new(&mut window).build())?;
+    let mut context = pollster::block_on(Context::new(&mut window).build())?;
+    if let Err(e) = context.load_environment_hdr("./assets/environment.hdr") {
+      log::warn!("could not load environment map: {:?}", e);
+    }
+    if cfg!(debug_assertions) {
+      if let Err(e) =
+        context.watch_shaders(sls_webgpu::wgpu_renderer::shader_reload::SHADER_SOURCE_DIR)
+      {
+        log::warn!("could not watch shader sources: {:?}", e);
+      }
+    }
 
     let models = Arc::downgrade(&context.resources.models);
 
@@ -89,10 +100,7 @@ impl App {
     });
     let imgui_platform = ImguiSdlPlatform::new(&mut imgui_context)?;
 
-    let texture_format = context
-      .surface
-      .get_preferred_format(&context.adapter)
-      .ok_or(anyhow!("no surface texture format available"))?;
+    let texture_format = context.render_target.format();
     let renderer_options = imgui_wgpu::RendererConfig {
       texture_format,
       ..imgui_wgpu::RendererConfig::new_srgb()
@@ -228,17 +236,34 @@ impl App {
     }
     let mut ui = im_ctx.frame();
     self.game_state.draw_ui(&mut ui);
+    {
+      let mut im_platform = platform_arc
+        .write()
+        .map_err(|e| Error::from_other(format!("lock is poisoned! {:?}", e)))?;
+      im_platform.prepare_render(&ui, &self.window);
+    }
+    let draw_data = ui.render();
+    let gui_renderer_arc = self.imgui_renderer.clone();
 
-    // let mut gui_renderer_arc = self
-    //   .imgui_renderer
-    //   .write()
-    //   .map_err(|e| Error::from_other(format!("lock is poisoned! {:?}", e)))?;
-
-    self
-      .context
-      .write()
-      .expect("Deadlock on render context")
-      .render(&mut self.game_state)
+    let mut context = self.context.write().expect("Deadlock on render context");
+    // the hook is run or dropped within the call, while `im_ctx` still holds `draw_data`
+    context
+      .render_with_ui(
+        &mut self.game_state,
+        Box::new(move |queue, device, encoder, target| {
+          let mut gui_renderer = gui_renderer_arc
+            .write()
+            .map_err(|e| Error::from_other(format!("lock is poisoned! {:?}", e)))?;
+          let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("imgui pass"),
+            color_attachments: &[target.color_attachment()],
+            depth_stencil_attachment: None,
+          });
+          gui_renderer
+            .render(draw_data, queue, device, &mut pass)
+            .map_err(|e| Error::from_other(format!("could not render ui: {:?}", e)))
+        }),
+      )
       .map_err(|e| sls_webgpu::Error::FromError(e.into()))
   }
 
@@ -335,7 +360,6 @@ impl App {
         let models_arc = self.models.upgrade().map(|ptr| ptr.clone()).unwrap();
         let mut meshes = models_arc.write().expect("cannot access meshes");
         let avocado_model = meshes.insert(mesh);
-        ctx.models_to_draw.push(avocado_model);
         let assets = MainSceneAssets {
           avocado_model,
           avocado_model_path: mesh_path,
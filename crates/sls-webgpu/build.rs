@@ -1,20 +1,102 @@
-cause std::{env, process::Command};
+//! Compiles the GLSL shaders in src/shaders to SPIR-V and WGSL, written to `$OUT_DIR/shaders`.
+//! WGSL shaders, for features the GLSL frontend lacks, are validated and copied as they are
+use naga::{
+  back::wgsl,
+  front::{spv as spv_in, wgsl as wgsl_in},
+};
+use shader_compiler::{glsl_to_spirv, shader_stage, validate};
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+};
+
+#[path = "src/wgpu_renderer/shader_compiler.rs"]
+mod shader_compiler;
+
+const SHADER_DIR: &str = "src/shaders";
+const SHADER_COMPILER: &str = "src/wgpu_renderer/shader_compiler.rs";
 
 fn main() -> Result<(), String> {
-  let is_windows = env::var("CARGO_CFG_TARGET_FAMILY") == Ok("windows".to_owned());
+  println!("cargo:rerun-if-changed={}", SHADER_DIR);
+  println!("cargo:rerun-if-changed={}", SHADER_COMPILER);
+  let out_dir = PathBuf::from(env::var("OUT_DIR").map_err(|e| e.to_string())?).join("shaders");
+  fs::create_dir_all(&out_dir).map_err(|e| format!("could not create {:?}: {}", out_dir, e))?;
 
-  let path_sep = if is_windows { '\\' } else { '/' };
-  let mut out_dir = env::var("OUT_DIR").unwrap();
+  let mut sources: Vec<PathBuf> = fs::read_dir(SHADER_DIR)
+    .map_err(|e| format!("could not read {}: {}", SHADER_DIR, e))?
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| shader_stage(path).is_some() || is_wgsl(path))
+    .collect();
+  sources.sort();
 
-  if !out_dir.ends_with(path_sep) {
-    out_dir.push(path_sep);
+  let mut failed = 0;
+  for path in &sources {
+    println!("cargo:rerun-if-changed={}", path.display());
+    if let Err(message) = compile_shader(path, &out_dir) {
+      eprintln!("{}", message);
+      failed += 1;
+    }
   }
-  if is_windows {
-    out_dir.push('\\');
-  }
-  let ps = Command::new("make").status().unwrap();
-  if ps.code() != Some(0) {
-    return Err(format!("make failed with status {:?}", ps.code()));
+  if failed > 0 {
+    return Err(format!(
+      "{} of {} shaders failed to compile",
+      failed,
+      sources.len()
+    ));
   }
   Ok(())
 }
+
+fn is_wgsl(path: &Path) -> bool {
+  path
+    .extension()
+    .map_or(false, |extension| extension == "wgsl")
+}
+
+///
+/// Writes `<name>.spv` and `<name>.wgsl` to `out_dir`, where name is the source's file name.
+///
+/// The SPIR-V is compiled by `shader_compiler`, which hot reload shares.
+/// The WGSL is converted from that SPIR-V the same way wgpu converts it at runtime,
+/// so both render the same way
+fn compile_shader(path: &Path, out_dir: &Path) -> Result<(), String> {
+  let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  if is_wgsl(path) {
+    return compile_wgsl(path, &source, out_dir);
+  }
+  let stage = shader_stage(path).ok_or_else(|| format!("{}: unknown stage", path.display()))?;
+  let file_name = path.file_name().unwrap().to_string_lossy();
+
+  let words = glsl_to_spirv(path, &source, stage)?;
+  let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+  write_output(&out_dir.join(format!("{}.spv", file_name)), &bytes)?;
+
+  let spv_module = spv_in::parse_u8_slice(&bytes, &spv_in::Options::default())
+    .map_err(|e| format!("{}: could not read back SPIR-V: {}", path.display(), e))?;
+  let spv_info = validate(&spv_module).map_err(|e| format!("{}: {}", path.display(), e))?;
+  let wgsl_source = wgsl::write_string(&spv_module, &spv_info)
+    .map_err(|e| format!("{}: could not write WGSL: {}", path.display(), e))?;
+  write_output(
+    &out_dir.join(format!("{}.wgsl", file_name)),
+    wgsl_source.as_bytes(),
+  )
+}
+
+///
+/// Validates a WGSL shader, and copies it to `<name>` in `out_dir`.
+/// No SPIR-V is written, as wgpu's SPIR-V frontend can't read back the atomics
+/// these shaders are written for, so they are loaded as WGSL on every platform
+fn compile_wgsl(path: &Path, source: &str, out_dir: &Path) -> Result<(), String> {
+  let module = wgsl_in::parse_str(source)
+    .map_err(|error| format!("{}: {}", path.display(), error.emit_to_string(source)))?;
+  validate(&module).map_err(|e| format!("{}: {}", path.display(), e))?;
+  write_output(&out_dir.join(path.file_name().unwrap()), source.as_bytes())
+}
+
+fn write_output(path: &Path, contents: &[u8]) -> Result<(), String> {
+  // leave unchanged files alone, so their timestamps don't trigger rebuilds
+  if fs::read(path).map_or(false, |existing| existing == contents) {
+    return Ok(());
+  }
+  fs::write(path, contents).map_err(|e| format!("could not write {:?}: {}", path, e))
+}
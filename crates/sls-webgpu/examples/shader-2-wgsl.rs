@@ -1,25 +1,22 @@
 use naga::front;
-// use shaderc;
+use sls_webgpu::wgpu_renderer::shader_compiler::{glsl_to_spirv, shader_stage};
 use std::path::Path;
 
 fn main() {
-  let mut compiler = shaderc::Compiler::new().unwrap();
-  let options = shaderc::CompileOptions::new().unwrap();
   let shaders = ["src/shaders/main.frag", "src/shaders/main.vert"];
 
   for &shader in shaders.iter() {
-    let source = std::fs::read_to_string(shader).unwrap();
-    let spv = compiler
-      .compile_into_spirv(
-        &source,
-        get_shader_kind(shader),
-        shader,
-        "main",
-        Some(&options),
-      )
-      .unwrap();
+    let path = Path::new(shader);
+    let source = std::fs::read_to_string(path).unwrap();
+    let stage = shader_stage(path).unwrap();
+    let spv = match glsl_to_spirv(path, &source, stage) {
+      Ok(words) => words,
+      Err(e) => {
+        panic!("shader compile error!\n{}", e)
+      }
+    };
     let naga_module = front::spv::parse_u8_slice(
-      spv.as_binary_u8(),
+      bytemuck::cast_slice(&spv),
       &front::spv::Options {
         ..Default::default()
       },
@@ -34,18 +31,3 @@ fn main() {
     println!("{:?}", naga_module);
   }
 }
-
-fn get_shader_kind(path: &str) -> shaderc::ShaderKind {
-  use shaderc::ShaderKind;
-  let path: &Path = path.as_ref();
-  let ext = path.extension().unwrap().to_str().unwrap();
-  match ext {
-    "vert" => ShaderKind::Vertex,
-    "frag" => ShaderKind::Fragment,
-    "comp" => ShaderKind::Compute,
-    "tessc" => ShaderKind::TessControl,
-    "tess" => ShaderKind::TessControl,
-    "tesse" => ShaderKind::TessEvaluation,
-    _ => ShaderKind::InferFromSource,
-  }
-}
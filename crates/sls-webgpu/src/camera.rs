@@ -1,4 +1,7 @@
-use crate::game::components::*;
+use crate::{
+  game::components::*,
+  math::{Frustum, Sphere},
+};
 use lazy_static::lazy_static;
 use nalgebra_glm::*;
 use serde::*;
@@ -65,6 +68,22 @@ impl Camera {
     let proj = self.projection();
     proj * view
   }
+
+  /// The world space volume seen by the camera
+  pub fn frustum(&self) -> Frustum {
+    Frustum::from_view_projection(&self.view_projection())
+  }
+
+  ///
+  /// Projected diameter of a world space sphere, as a fraction of the screen's height.
+  /// Spheres around the camera are sized as if they were at their radius' distance
+  pub fn screen_size(&self, sphere: &Sphere) -> f32 {
+    let distance = distance(&self.position, &sphere.center).max(sphere.radius);
+    if distance <= 0.0 {
+      return f32::MAX;
+    }
+    sphere.radius / (distance * (self.fovy * 0.5).tan())
+  }
   #[inline]
   pub fn front(&self) -> &Vec3 {
     &self.front
@@ -115,11 +134,13 @@ impl Default for Camera {
 }
 
 lazy_static! {
+  /// Maps OpenGL's -1..1 clip depth to wgpu's 0..1.
+  /// Mat4::new takes its elements in row-major order
   #[rustfmt::skip]
   pub static ref OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::new(
       1.0, 0.0, 0.0, 0.0,
       0.0, 1.0, 0.0, 0.0,
-      0.0, 0.0, 0.5, 0.0,
-      0.0, 0.0, 0.5, 1.0,
+      0.0, 0.0, 0.5, 0.5,
+      0.0, 0.0, 0.0, 1.0,
   );
 }
@@ -18,6 +18,9 @@ pub enum Error {
   LegionComponent(#[from] ComponentError),
   #[error("legion entity access: {0:?}")]
   LegionEntityAccess(#[from] EntityAccessError),
+  #[cfg(feature = "wgpu_renderer")]
+  #[error("shader reflection: {0}")]
+  Reflection(#[from] crate::wgpu_renderer::reflection::ReflectionError),
 }
 
 impl Error {
@@ -6,8 +6,11 @@ use crate::camera::Camera;
 
 use crate::{
   renderer_common::handle::Handle,
+  scene_graph::components::LocalToWorld,
   wgpu_renderer::{
-    model::StreamingMesh, pipeline_state::ShadingModel, uniforms::PointLightUniform,
+    model::StreamingMesh,
+    pipeline_state::ShadingModel,
+    uniforms::{LightUniform, LIGHT_TYPE_DIRECTIONAL, LIGHT_TYPE_POINT, LIGHT_TYPE_SPOT},
   },
 };
 use serde::{
@@ -114,6 +117,27 @@ pub struct RenderModel {
   /// if false, skip rendering this model
   pub is_shown: bool,
   pub shading_model: ShadingModel,
+  /// if true, the model is drawn into the shadow maps of shadow casting lights
+  #[serde(default = "default_true")]
+  pub casts_shadows: bool,
+  /// if false, the model is lit as if no shadows fall on it
+  #[serde(default = "default_true")]
+  pub receives_shadows: bool,
+  /// screen sizes, as fractions of the screen's height, below which the model is drawn
+  /// at coarser levels of detail: level `i + 1` once it is smaller than `lod_thresholds[i]`.
+  /// Sizes are in decreasing order
+  #[serde(default = "default_lod_thresholds")]
+  pub lod_thresholds: Vec<f32>,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+pub const DEFAULT_LOD_THRESHOLDS: [f32; 3] = [0.25, 0.1, 0.04];
+
+fn default_lod_thresholds() -> Vec<f32> {
+  DEFAULT_LOD_THRESHOLDS.to_vec()
 }
 
 impl Default for RenderModel {
@@ -129,8 +153,24 @@ impl RenderModel {
       model: handle,
       model_id,
       shading_model: ShadingModel::Pbr,
+      casts_shadows: true,
+      receives_shadows: true,
+      lod_thresholds: default_lod_thresholds(),
     }
   }
+
+  /// The level of detail to draw the model at, given its size on screen
+  pub fn select_lod(&self, screen_size: f32) -> usize {
+    select_lod(&self.lod_thresholds, screen_size)
+  }
+}
+
+/// The level of detail picked by `lod_thresholds`, as in [RenderModel::lod_thresholds]
+pub fn select_lod(lod_thresholds: &[f32], screen_size: f32) -> usize {
+  lod_thresholds
+    .iter()
+    .take_while(|&&threshold| screen_size < threshold)
+    .count()
 }
 
 pub type CameraEntityRow = (Transform3D, Camera);
@@ -153,21 +193,47 @@ impl Default for LightType {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LightSource {
   pub light_type: LightType,
   pub color: Vec3,
+  pub intensity: f32,
+  /// distance at which point and spot lights fall off to zero. 0 for unlimited range
+  pub range: f32,
+  /// spotlight inner cone half-angle, in radians
   pub cutoff: f32,
+  /// spotlight outer cone half-angle, in radians
+  pub outer_cutoff: f32,
+  /// if true, directional and spot lights render a shadow map.
+  /// Point lights do not cast shadows
+  pub casts_shadows: bool,
 }
 
 impl LightSource {
-  pub fn as_uniform(&self, transform: &Transform3D) -> LightSourceUniform {
-    match &self.light_type {
-      LightType::Point => LightSourceUniform::Point(PointLightUniform {
-        position: transform.position.into(),
-        _padding: 0,
-        color: self.color.into(),
-      }),
-      _ => LightSourceUniform::Unsupported,
+  ///
+  /// Packs the light into its shader representation.
+  /// Directional and spot lights point down their local -Z axis
+  pub fn as_uniform(&self, local_to_world: &LocalToWorld) -> LightUniform {
+    let matrix = &local_to_world.0;
+    let position: Vec3 = matrix.fixed_slice::<3, 1>(0, 3).into_owned();
+    let direction: Vec3 = (matrix.fixed_slice::<3, 3>(0, 0) * vec3(0.0, 0.0, -1.0))
+      .try_normalize(f32::EPSILON)
+      .unwrap_or_else(|| vec3(0.0, 0.0, -1.0));
+    let light_type = match self.light_type {
+      LightType::Point => LIGHT_TYPE_POINT,
+      LightType::Directional => LIGHT_TYPE_DIRECTIONAL,
+      LightType::Spotlight => LIGHT_TYPE_SPOT,
+    };
+    LightUniform {
+      position: position.into(),
+      light_type,
+      direction: direction.into(),
+      range: self.range,
+      color: self.color.into(),
+      intensity: self.intensity,
+      inner_cutoff: self.cutoff.cos(),
+      outer_cutoff: self.outer_cutoff.max(self.cutoff).cos(),
+      ..Default::default()
     }
   }
 }
@@ -176,13 +242,12 @@ impl Default for LightSource {
   fn default() -> Self {
     Self {
       light_type: Default::default(),
-      cutoff: f32::default(),
       color: vec3(1.0, 1.0, 1.0),
+      intensity: 1.0,
+      range: 0.0,
+      cutoff: std::f32::consts::FRAC_PI_8,
+      outer_cutoff: std::f32::consts::FRAC_PI_4,
+      casts_shadows: true,
     }
   }
 }
-
-pub enum LightSourceUniform {
-  Point(PointLightUniform),
-  Unsupported,
-}
@@ -0,0 +1,415 @@
+//!
+//! Immediate mode debug drawing.
+//!
+//! Systems push lines and wireframe shapes into the [DebugDraw] resource while a frame
+//! is updated, and the renderer draws every line in it as a single batch. Primitives
+//! without a duration are drawn for one frame, and are removed by
+//! `expire_debug_draw_system` at the start of the next one.
+use std::time::Duration;
+
+use nalgebra_glm::{vec3, vec4, Mat4, Vec3, Vec4};
+
+use crate::{
+  math::{Aabb, Sphere},
+  wgpu_renderer::shadows::frustum_corners,
+};
+
+/// Segments of the circles making up spheres and cones
+pub const CIRCLE_SEGMENTS: usize = 32;
+/// Lines from the apex of a cone to the circle at its base
+pub const CONE_LINES: usize = 4;
+
+/// Color, lifetime and depth testing of a debug primitive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugStyle {
+  /// linear RGBA, blended over the scene
+  pub color: Vec4,
+  /// how long the primitive stays after it is pushed. Zero draws it for a single frame
+  pub duration: Duration,
+  /// if false, the primitive is drawn over the scene, even when something is in front of it
+  pub depth_test: bool,
+}
+
+impl DebugStyle {
+  pub fn new(color: Vec4) -> Self {
+    Self {
+      color,
+      duration: Duration::ZERO,
+      depth_test: true,
+    }
+  }
+
+  pub fn with_duration(mut self, duration: Duration) -> Self {
+    self.duration = duration;
+    self
+  }
+
+  /// Draws the primitive over the scene, ignoring the depth buffer
+  pub fn overlay(mut self) -> Self {
+    self.depth_test = false;
+    self
+  }
+
+  fn with_color(mut self, color: Vec4) -> Self {
+    self.color = color;
+    self
+  }
+}
+
+impl From<Vec4> for DebugStyle {
+  fn from(color: Vec4) -> Self {
+    Self::new(color)
+  }
+}
+
+impl From<Vec3> for DebugStyle {
+  fn from(color: Vec3) -> Self {
+    Self::new(vec4(color.x, color.y, color.z, 1.0))
+  }
+}
+
+/// A line segment in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+  pub start: Vec3,
+  pub end: Vec3,
+  pub color: Vec4,
+  pub depth_test: bool,
+  /// time left before the line is removed
+  remaining: Duration,
+}
+
+///
+/// Lines to draw over the scene, pushed by any system during a frame.
+/// Every shape is broken down into lines as it is pushed
+#[derive(Debug, Clone)]
+pub struct DebugDraw {
+  /// when false, pushed primitives are dropped and nothing is drawn
+  pub enabled: bool,
+  lines: Vec<DebugLine>,
+}
+
+impl Default for DebugDraw {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      lines: Vec::new(),
+    }
+  }
+}
+
+impl DebugDraw {
+  pub fn lines(&self) -> &[DebugLine] {
+    &self.lines
+  }
+
+  pub fn clear(&mut self) {
+    self.lines.clear();
+  }
+
+  ///
+  /// Removes primitives whose duration has run out after `dt` more time has passed,
+  /// including every single frame primitive
+  pub fn advance(&mut self, dt: Duration) {
+    self.lines.retain(|line| line.remaining > dt);
+    for line in self.lines.iter_mut() {
+      line.remaining -= dt;
+    }
+  }
+
+  pub fn line<S: Into<DebugStyle>>(&mut self, start: Vec3, end: Vec3, style: S) -> &mut Self {
+    let style = style.into();
+    if self.enabled {
+      self.lines.push(DebugLine {
+        start,
+        end,
+        color: style.color,
+        depth_test: style.depth_test,
+        remaining: style.duration,
+      });
+    }
+    self
+  }
+
+  /// Lines joining consecutive points, and the last point back to the first
+  fn line_loop(&mut self, points: &[Vec3], style: DebugStyle) {
+    for (i, point) in points.iter().enumerate() {
+      self.line(*point, points[(i + 1) % points.len()], style);
+    }
+  }
+
+  ///
+  /// The 12 edges of a box, whose corners are indexed by 3 bits,
+  /// one for each axis, as returned by `frustum_corners`
+  fn box_edges(&mut self, corners: &[Vec3; 8], style: DebugStyle) {
+    for (i, corner) in corners.iter().enumerate() {
+      for bit in [1, 2, 4] {
+        if i & bit == 0 {
+          self.line(*corner, corners[i | bit], style);
+        }
+      }
+    }
+  }
+
+  pub fn aabb<S: Into<DebugStyle>>(&mut self, aabb: &Aabb, style: S) -> &mut Self {
+    let mut corners = [Vec3::zeros(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+      *corner = vec3(
+        if i & 4 == 0 { aabb.min.x } else { aabb.max.x },
+        if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+        if i & 1 == 0 { aabb.min.z } else { aabb.max.z },
+      );
+    }
+    self.box_edges(&corners, style.into());
+    self
+  }
+
+  /// A circle around `normal`
+  pub fn circle<S: Into<DebugStyle>>(
+    &mut self,
+    center: &Vec3,
+    normal: &Vec3,
+    radius: f32,
+    style: S,
+  ) -> &mut Self {
+    let points = circle_points(center, normal, radius);
+    self.line_loop(&points, style.into());
+    self
+  }
+
+  ///
+  /// A cone from `apex` along `direction`, with its base `length` away.
+  /// `half_angle` is in radians, as for spot lights
+  pub fn cone<S: Into<DebugStyle>>(
+    &mut self,
+    apex: &Vec3,
+    direction: &Vec3,
+    length: f32,
+    half_angle: f32,
+    style: S,
+  ) -> &mut Self {
+    let style = style.into();
+    let direction = direction
+      .try_normalize(f32::EPSILON)
+      .unwrap_or_else(|| vec3(0.0, 0.0, -1.0));
+    let base = apex + direction * length;
+    let points = circle_points(&base, &direction, length * half_angle.tan());
+    self.line(*apex, base, style);
+    self.line_loop(&points, style);
+    for point in points.iter().step_by(CIRCLE_SEGMENTS / CONE_LINES) {
+      self.line(*apex, *point, style);
+    }
+    self
+  }
+
+  /// Three circles around the sphere's axes
+  pub fn sphere<S: Into<DebugStyle>>(&mut self, sphere: &Sphere, style: S) -> &mut Self {
+    let style = style.into();
+    for axis in [
+      vec3(1.0, 0.0, 0.0),
+      vec3(0.0, 1.0, 0.0),
+      vec3(0.0, 0.0, 1.0),
+    ] {
+      self.circle(&sphere.center, &axis, sphere.radius, style);
+    }
+    self
+  }
+
+  ///
+  /// The x, y and z axes of `transform`, in red, green and blue.
+  /// Only the style's duration and depth testing are used
+  pub fn axes<S: Into<DebugStyle>>(
+    &mut self,
+    transform: &Mat4,
+    length: f32,
+    style: S,
+  ) -> &mut Self {
+    let style = style.into();
+    let origin: Vec3 = transform.fixed_slice::<3, 1>(0, 3).into_owned();
+    for (axis, color) in [
+      (0, vec4(1.0, 0.0, 0.0, 1.0)),
+      (1, vec4(0.0, 1.0, 0.0, 1.0)),
+      (2, vec4(0.0, 0.0, 1.0, 1.0)),
+    ] {
+      let direction: Vec3 = transform.fixed_slice::<3, 1>(0, axis).into_owned();
+      self.line(origin, origin + direction * length, style.with_color(color));
+    }
+    self
+  }
+
+  /// The frustum seen through `view_projection`, such as a camera's
+  pub fn frustum<S: Into<DebugStyle>>(&mut self, view_projection: &Mat4, style: S) -> &mut Self {
+    let inverse = match view_projection.try_inverse() {
+      Some(inverse) => inverse,
+      None => return self,
+    };
+    self.box_edges(&frustum_corners(&inverse), style.into());
+    self
+  }
+
+  ///
+  /// A grid on the horizontal plane through `center`, covering `half_extent`
+  /// in each direction with lines `spacing` apart
+  pub fn grid<S: Into<DebugStyle>>(
+    &mut self,
+    center: &Vec3,
+    half_extent: f32,
+    spacing: f32,
+    style: S,
+  ) -> &mut Self {
+    if spacing <= 0.0 {
+      return self;
+    }
+    let style = style.into();
+    let steps = (half_extent / spacing).floor() as i32;
+    for i in -steps..=steps {
+      let offset = i as f32 * spacing;
+      self.line(
+        center + vec3(offset, 0.0, -half_extent),
+        center + vec3(offset, 0.0, half_extent),
+        style,
+      );
+      self.line(
+        center + vec3(-half_extent, 0.0, offset),
+        center + vec3(half_extent, 0.0, offset),
+        style,
+      );
+    }
+    self
+  }
+}
+
+/// Points of a circle around `normal`, starting on an arbitrary axis perpendicular to it
+fn circle_points(center: &Vec3, normal: &Vec3, radius: f32) -> Vec<Vec3> {
+  let normal = normal
+    .try_normalize(f32::EPSILON)
+    .unwrap_or_else(|| vec3(0.0, 1.0, 0.0));
+  let helper = if normal.x.abs() < 0.9 {
+    vec3(1.0, 0.0, 0.0)
+  } else {
+    vec3(0.0, 1.0, 0.0)
+  };
+  let u = normal.cross(&helper).normalize();
+  let v = normal.cross(&u);
+  (0..CIRCLE_SEGMENTS)
+    .map(|i| {
+      let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+      center + (u * angle.cos() + v * angle.sin()) * radius
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn white() -> Vec3 {
+    vec3(1.0, 1.0, 1.0)
+  }
+
+  #[test]
+  fn test_durations() {
+    let mut draw = DebugDraw::default();
+    draw.line(Vec3::zeros(), white(), white()).line(
+      Vec3::zeros(),
+      white(),
+      DebugStyle::from(white()).with_duration(Duration::from_millis(50)),
+    );
+    assert_eq!(draw.lines().len(), 2);
+    draw.advance(Duration::from_millis(16));
+    assert_eq!(draw.lines().len(), 1);
+    draw.advance(Duration::from_millis(16));
+    draw.advance(Duration::from_millis(16));
+    assert_eq!(draw.lines().len(), 1);
+    draw.advance(Duration::from_millis(16));
+    assert!(draw.lines().is_empty());
+  }
+
+  #[test]
+  fn test_disabled() {
+    let mut draw = DebugDraw {
+      enabled: false,
+      ..Default::default()
+    };
+    draw.line(Vec3::zeros(), white(), white());
+    assert!(draw.lines().is_empty());
+  }
+
+  #[test]
+  fn test_aabb_edges() {
+    let mut draw = DebugDraw::default();
+    let aabb = Aabb::new(vec3(-1.0, -2.0, -3.0), vec3(1.0, 2.0, 3.0));
+    draw.aabb(&aabb, white());
+    assert_eq!(draw.lines().len(), 12);
+    for line in draw.lines() {
+      // each edge runs along a single axis, with its full length
+      let delta = line.end - line.start;
+      let axes = (0..3).filter(|&i| delta[i] != 0.0).count();
+      assert_eq!(axes, 1, "{:?}", line);
+      assert!(aabb.contains_point(&line.start) && aabb.contains_point(&line.end));
+    }
+  }
+
+  #[test]
+  fn test_sphere_lines_are_on_the_sphere() {
+    let mut draw = DebugDraw::default();
+    let sphere = Sphere::new(vec3(1.0, 2.0, 3.0), 2.0);
+    draw.sphere(&sphere, white());
+    assert_eq!(draw.lines().len(), 3 * CIRCLE_SEGMENTS);
+    for line in draw.lines() {
+      assert!(((line.start - sphere.center).norm() - sphere.radius).abs() < 1e-4);
+    }
+  }
+
+  #[test]
+  fn test_cone() {
+    let mut draw = DebugDraw::default();
+    let apex = vec3(0.0, 1.0, 0.0);
+    draw.cone(
+      &apex,
+      &vec3(0.0, -1.0, 0.0),
+      1.0,
+      std::f32::consts::FRAC_PI_4,
+      white(),
+    );
+    let lines = draw.lines();
+    assert_eq!(lines.len(), 1 + CIRCLE_SEGMENTS + CONE_LINES);
+    assert_eq!(lines[0].end, Vec3::zeros());
+    // the base circle's radius matches the half angle
+    for line in &lines[1..=CIRCLE_SEGMENTS] {
+      assert!((line.start.norm() - 1.0).abs() < 1e-4);
+    }
+    assert!(lines[1 + CIRCLE_SEGMENTS..]
+      .iter()
+      .all(|line| line.start == apex));
+  }
+
+  #[test]
+  fn test_axes_colors() {
+    let mut draw = DebugDraw::default();
+    let transform = Mat4::new_translation(&vec3(1.0, 0.0, 0.0));
+    draw.axes(&transform, 2.0, DebugStyle::from(white()).overlay());
+    let lines = draw.lines();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].start, vec3(1.0, 0.0, 0.0));
+    assert_eq!(lines[0].end, vec3(3.0, 0.0, 0.0));
+    assert_eq!(lines[1].color, vec4(0.0, 1.0, 0.0, 1.0));
+    assert!(lines.iter().all(|line| !line.depth_test));
+  }
+
+  #[test]
+  fn test_grid() {
+    let mut draw = DebugDraw::default();
+    draw.grid(&Vec3::zeros(), 2.0, 1.0, white());
+    // 5 lines along each axis
+    assert_eq!(draw.lines().len(), 10);
+    assert!(draw.lines().iter().all(|line| line.start.y == 0.0));
+  }
+
+  #[test]
+  fn test_frustum_of_identity() {
+    let mut draw = DebugDraw::default();
+    draw.frustum(&Mat4::identity(), white());
+    assert_eq!(draw.lines().len(), 12);
+  }
+}
@@ -15,6 +15,8 @@ pub struct InputState {
   pressed_scancodes: HashSet<Scancode>,
   pressed_keycodes: HashSet<Keycode>,
   mouse_state: MouseButtonState,
+  /// buttons pressed since the start of the frame
+  clicked: MouseButtonState,
   relative_mouse_state: MouseButtonState,
   mouse_delta: TVec2<i32>,
   keymod: KeyMod,
@@ -29,6 +31,7 @@ impl Default for InputState {
       pressed_keycodes: Default::default(),
       pressed_scancodes: Default::default(),
       mouse_state: MouseButtonState::new(0),
+      clicked: MouseButtonState::new(0),
       relative_mouse_state: MouseButtonState::new(0),
       current_mouse_pos: vec2(0, 0),
       mouse_delta: vec2(0, 0),
@@ -42,6 +45,7 @@ impl InputState {
   pub fn on_start_frame(&mut self) {
     self.previous_frame_mouse_pos = Some(self.current_mouse_pos);
     self.mouse_delta = TVec2::zeros();
+    self.clicked.clear();
   }
 
   pub fn mouse_delta(&self) -> TVec2<i32> {
@@ -52,6 +56,18 @@ impl InputState {
   pub fn mouse_state(&self) -> MouseButtonState {
     self.mouse_state
   }
+
+  /// True if `button` went down during this frame
+  #[inline]
+  pub fn clicked(&self, button: MouseButton) -> bool {
+    self.clicked.contains(button)
+  }
+
+  /// Cursor position in window coordinates
+  #[inline]
+  pub fn mouse_position(&self) -> TVec2<i32> {
+    self.current_mouse_pos
+  }
 }
 
 impl InputBackend for InputState {
@@ -138,6 +154,7 @@ mod sdl2_input {
         Event::MouseButtonDown { mouse_btn, .. } => {
           let btn: MouseButton = (*mouse_btn).into();
           self.mouse_state.mask |= btn as u32;
+          self.clicked.insert(btn);
         }
         Event::MouseButtonUp { mouse_btn, .. } => {
           let btn: MouseButton = (*mouse_btn).into();
@@ -7,12 +7,15 @@ pub use wgpu_imgui::*;
 
 use crate::game::{
   components::{DebugShowScene, GameLoopTimer},
+  debug_draw::DebugDraw,
   input::InputResource,
-  resources::{Scene, UIDataIn, UIDataOut},
+  resources::{PickRequest, PickResult, PostProcessSettings, Scene, UIDataIn, UIDataOut},
   systems::*,
 };
+use crate::wgpu_renderer::render_graph::{RenderNode, RenderNodeQueue};
 
 pub mod components;
+pub mod debug_draw;
 pub mod input;
 pub mod resources;
 pub mod systems;
@@ -56,6 +59,8 @@ pub struct GameStateBuilder {
   pub fixed_schedule: Builder,
   pub per_frame_schedule: Builder,
   pub on_resize_schedule: Builder,
+  /// nodes added to the renderer's graph on the first render
+  pub render_nodes: Vec<Box<dyn RenderNode>>,
 }
 
 impl Debug for GameStateBuilder {
@@ -71,22 +76,30 @@ impl GameStateBuilder {
       .add_system(systems::fixed_update_logging_system())
       .add_system(systems::write_camera_ui_data_system())
       .add_system(systems::model_systems::rotate_models_system(0.0));
-    for system in crate::scene_graph::transform_system_bundle::build() {
-      self
-        .fixed_schedule
-        .add_system(DynParallelRunnable::new(system));
-    }
+    systems::transform_systems::add_transform_systems(&mut self.fixed_schedule);
 
     self
       .per_frame_schedule
+      .add_system(systems::debug_draw_systems::expire_debug_draw_system())
       .add_system(systems::per_frame_logging_system())
       .add_thread_local(systems::camera_move_system())
+      .add_system(systems::request_pick_on_click_system())
+      .add_system(systems::debug_draw_systems::draw_light_sources_system())
       .add_system(systems::write_renderable_ui_data_system());
     self
       .on_resize_schedule
       .add_system(camera_systems::camera_on_resize_system());
     self
   }
+
+  ///
+  /// Adds a pass to the render graph, such as a post-process effect.
+  /// The graph orders it by the slots it reads and writes
+  pub fn with_render_node<N: RenderNode>(mut self, node: N) -> Self {
+    self.render_nodes.push(Box::new(node));
+    self
+  }
+
   pub fn build(self) -> GameState {
     GameState::new(self)
   }
@@ -99,6 +112,7 @@ impl Default for GameStateBuilder {
       fixed_schedule: Schedule::builder(),
       per_frame_schedule: Schedule::builder(),
       on_resize_schedule: Schedule::builder(),
+      render_nodes: Vec::new(),
     }
   }
 }
@@ -115,6 +129,9 @@ impl GameState {
     if let Some(asset_loader_queue) = options.asset_loader_queue {
       resources.insert(asset_loader_queue)
     }
+    if !options.render_nodes.is_empty() {
+      resources.insert(RenderNodeQueue(options.render_nodes));
+    }
     let state = Self {
       world,
       fixed_schedule: options.fixed_schedule.build(),
@@ -168,6 +185,10 @@ impl GameState {
     resources.insert(Scene { main_camera: None });
     resources.insert(UIDataIn::default());
     resources.insert(UIDataOut::default());
+    resources.insert(PostProcessSettings::default());
+    resources.insert(PickRequest::default());
+    resources.insert(PickResult::default());
+    resources.insert(DebugDraw::default());
 
     resources.insert(MeshLookup::default());
     #[cfg(not(target_arch = "wasm32"))]
@@ -328,7 +349,10 @@ impl GameState {
 mod wgpu_imgui {
   use imgui::*;
 
-  use crate::platform::gui::wgpu_imgui::DrawUi;
+  use crate::{
+    game::resources::{RenderSettings, Tonemapper},
+    platform::gui::wgpu_imgui::DrawUi,
+  };
 
   use super::*;
 
@@ -337,6 +361,12 @@ mod wgpu_imgui {
       use imgui::*;
 
       let main_camera_data = self.resources.get::<UIDataIn>();
+      // clicks on the ui shouldn't pick what's behind it
+      if ui.io().want_capture_mouse {
+        if let Some(mut request) = self.resources.get_mut::<PickRequest>() {
+          request.pixel = None;
+        }
+      }
 
       Window::new(im_str!("Window!!"))
         .size([600.0, 300.0], Condition::Appearing)
@@ -365,9 +395,70 @@ mod wgpu_imgui {
               }
             });
           }
+          if let Some(mut settings) = self.resources.get_mut::<RenderSettings>() {
+            ui.text(im_str!("MSAA samples per pixel"));
+            for count in settings.supported_sample_counts.clone() {
+              ui.radio_button(&im_str!("{}x", count), &mut settings.sample_count, count);
+            }
+            if settings.gpu_culling_supported {
+              ui.checkbox(im_str!("GPU culling"), &mut settings.gpu_culling);
+            }
+            ui.checkbox(im_str!("Picking"), &mut settings.picking);
+          }
+          if let Some(mut debug_draw) = self.resources.get_mut::<DebugDraw>() {
+            ui.checkbox(im_str!("Debug drawing"), &mut debug_draw.enabled);
+          }
+          if let Some(pick) = self.resources.get::<PickResult>() {
+            match pick.entity {
+              Some(entity) => ui.text(format!(
+                "selected {:?} at {:?}",
+                entity, pick.world_position
+              )),
+              None => ui.text(im_str!("nothing selected")),
+            }
+          }
+          if let Some(mut settings) = self.resources.get_mut::<PostProcessSettings>() {
+            draw_post_process_settings(ui, &mut settings);
+          }
         });
     }
   }
+
+  fn draw_post_process_settings(ui: &Ui, settings: &mut PostProcessSettings) {
+    ui.text(im_str!("Post processing"));
+    ui.input_float(im_str!("exposure"), &mut settings.exposure)
+      .step(0.1)
+      .build();
+    settings.exposure = settings.exposure.max(0.0);
+    for (label, tonemapper) in [
+      (im_str!("no tonemapping"), Tonemapper::None),
+      (im_str!("Reinhard"), Tonemapper::Reinhard),
+      (im_str!("ACES"), Tonemapper::Aces),
+    ] {
+      ui.radio_button(label, &mut settings.tonemapper, tonemapper);
+    }
+
+    ui.checkbox(im_str!("bloom"), &mut settings.bloom.enabled);
+    ui.input_float(im_str!("bloom intensity"), &mut settings.bloom.intensity)
+      .step(0.01)
+      .build();
+    settings.bloom.intensity = settings.bloom.intensity.clamp(0.0, 1.0);
+    ui.input_float(im_str!("bloom radius"), &mut settings.bloom.filter_radius)
+      .step(0.001)
+      .build();
+    settings.bloom.filter_radius = settings.bloom.filter_radius.max(0.0);
+
+    ui.checkbox(im_str!("FXAA"), &mut settings.fxaa.enabled);
+    ui.input_float(
+      im_str!("FXAA edge threshold"),
+      &mut settings.fxaa.edge_threshold,
+    )
+    .step(0.01)
+    .build();
+    ui.input_float(im_str!("FXAA span"), &mut settings.fxaa.span_max)
+      .step(1.0)
+      .build();
+  }
 }
 
 use crate::{
@@ -89,6 +89,162 @@ impl Default for UIDataIn {
 #[derive(Debug, Clone, Default)]
 pub struct UIDataOut {}
 
+///
+/// Renderer settings which can be changed while the game runs.
+/// The Context applies changes on the next render
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+  /// MSAA samples per pixel
+  pub sample_count: u32,
+  /// filled in by the Context from the adapter
+  pub supported_sample_counts: Vec<u32>,
+  /// cull opaque instances in a compute pass, and draw them indirectly
+  pub gpu_culling: bool,
+  /// filled in by the Context from the adapter
+  pub gpu_culling_supported: bool,
+  /// render entity ids under the cursor when a pick is requested
+  pub picking: bool,
+}
+
+impl Default for RenderSettings {
+  fn default() -> Self {
+    Self {
+      sample_count: 1,
+      supported_sample_counts: vec![1],
+      gpu_culling: false,
+      gpu_culling_supported: false,
+      picking: false,
+    }
+  }
+}
+
+/// Maps HDR color to the display's range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+  /// clamps to 0..1, leaving colors in range untouched
+  None,
+  Reinhard,
+  /// Stephen Hill's fit of the ACES filmic curve
+  Aces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+  pub enabled: bool,
+  /// fraction of the blurred image mixed into the scene
+  pub intensity: f32,
+  /// blur radius of each upsample, in uv units
+  pub filter_radius: f32,
+}
+
+impl Default for BloomSettings {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      intensity: 0.04,
+      filter_radius: 0.005,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FxaaSettings {
+  pub enabled: bool,
+  /// smallest contrast, relative to the brightest neighbour, that counts as an edge
+  pub edge_threshold: f32,
+  /// contrast below which dark pixels are skipped
+  pub edge_threshold_min: f32,
+  /// longest blur along an edge, in pixels
+  pub span_max: f32,
+}
+
+impl Default for FxaaSettings {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      edge_threshold: 0.125,
+      edge_threshold_min: 0.0312,
+      span_max: 8.0,
+    }
+  }
+}
+
+///
+/// Settings of the post-processing chain run after the scene is rendered
+/// into an HDR target. Read by the render graph every frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostProcessSettings {
+  pub exposure: f32,
+  pub tonemapper: Tonemapper,
+  pub bloom: BloomSettings,
+  pub fxaa: FxaaSettings,
+}
+
+impl Default for PostProcessSettings {
+  fn default() -> Self {
+    Self {
+      exposure: 1.0,
+      tonemapper: Tonemapper::Aces,
+      bloom: BloomSettings::default(),
+      fxaa: FxaaSettings::default(),
+    }
+  }
+}
+
+impl PostProcessSettings {
+  ///
+  /// Passes the HDR scene through unchanged, apart from clamping.
+  /// Useful for comparing renders of the scene itself
+  pub fn disabled() -> Self {
+    Self {
+      exposure: 1.0,
+      tonemapper: Tonemapper::None,
+      bloom: BloomSettings {
+        enabled: false,
+        ..BloomSettings::default()
+      },
+      fxaa: FxaaSettings {
+        enabled: false,
+        ..FxaaSettings::default()
+      },
+    }
+  }
+}
+
+///
+/// A pixel of the render target whose entity should be picked.
+/// Taken by the Context on the next render, if picking is enabled
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PickRequest {
+  pub pixel: Option<(u32, u32)>,
+}
+
+///
+/// The entity found under the last picked pixel, published by the Context
+/// once the pixel has been read back from the GPU, a frame or more after the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickResult {
+  /// the picked pixel
+  pub pixel: (u32, u32),
+  /// None if no RenderModel covers the pixel
+  pub entity: Option<Entity>,
+  /// world space position of the picked surface
+  pub world_position: Vec3,
+  /// depth buffer value of the picked surface, 1.0 if nothing was hit
+  pub depth: f32,
+}
+
+impl Default for PickResult {
+  fn default() -> Self {
+    Self {
+      pixel: (0, 0),
+      entity: None,
+      world_position: Vec3::zeros(),
+      depth: 1.0,
+    }
+  }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ScreenResolution {
   pub drawable_size: (usize, usize),
@@ -99,6 +255,24 @@ impl ScreenResolution {
   pub fn aspect_ratio(&self) -> f32 {
     (self.drawable_size.0 as f32) / (self.drawable_size.1 as f32)
   }
+
+  ///
+  /// Converts a position in window coordinates to a pixel of the drawable,
+  /// which is larger on high DPI displays. None if the position is outside the window
+  pub fn window_to_drawable(&self, position: &TVec2<i32>) -> Option<(u32, u32)> {
+    let (window_width, window_height) = self.window_size;
+    let (drawable_width, drawable_height) = self.drawable_size;
+    if position.x < 0
+      || position.y < 0
+      || position.x as usize >= window_width
+      || position.y as usize >= window_height
+    {
+      return None;
+    }
+    let x = position.x as usize * drawable_width / window_width;
+    let y = position.y as usize * drawable_height / window_height;
+    Some((x as u32, y as u32))
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -115,3 +289,24 @@ impl Default for MeshLookup {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::nalgebra_glm::vec2;
+
+  #[test]
+  fn test_window_to_drawable() {
+    let resolution = ScreenResolution {
+      drawable_size: (1600, 1200),
+      window_size: (800, 600),
+    };
+    assert_eq!(resolution.window_to_drawable(&vec2(0, 0)), Some((0, 0)));
+    assert_eq!(
+      resolution.window_to_drawable(&vec2(799, 599)),
+      Some((1598, 1198))
+    );
+    assert_eq!(resolution.window_to_drawable(&vec2(800, 10)), None);
+    assert_eq!(resolution.window_to_drawable(&vec2(-1, 10)), None);
+  }
+}
@@ -0,0 +1,123 @@
+//! Systems maintaining the [DebugDraw] resource, and drawing built-in debug shapes into it.
+use legion::*;
+use nalgebra_glm::Vec3;
+
+use crate::{
+  game::{
+    components::{GameLoopTimer, LightSource, LightType},
+    debug_draw::{DebugDraw, DebugStyle},
+  },
+  math::Sphere,
+  scene_graph::components::LocalToWorld,
+};
+
+/// radius of the marker drawn at each light's position
+const LIGHT_MARKER_RADIUS: f32 = 0.1;
+/// length of the lines showing light directions, and of unlimited spot light cones
+const LIGHT_DIRECTION_LENGTH: f32 = 1.0;
+
+///
+/// Removes the previous frame's debug primitives.
+/// Runs first in the per frame schedule, so lines pushed by later systems are drawn
+#[system]
+pub fn expire_debug_draw(
+  #[resource] timer: &GameLoopTimer,
+  #[resource] debug_draw: &mut DebugDraw,
+) {
+  debug_draw.advance(timer.per_frame_dt);
+}
+
+///
+/// Draws every light's position in its color. Point lights show their range as a sphere,
+/// directional lights their direction, and spot lights their outer cone
+#[system(for_each)]
+pub fn draw_light_sources(
+  #[resource] debug_draw: &mut DebugDraw,
+  light: &LightSource,
+  local_to_world: &LocalToWorld,
+) {
+  if !debug_draw.enabled {
+    return;
+  }
+  let uniform = light.as_uniform(local_to_world);
+  let position: Vec3 = uniform.position.into();
+  let direction: Vec3 = uniform.direction.into();
+  let style = DebugStyle::from(light.color);
+  debug_draw.sphere(&Sphere::new(position, LIGHT_MARKER_RADIUS), style);
+  match light.light_type {
+    LightType::Point => {
+      if light.range > 0.0 {
+        debug_draw.sphere(&Sphere::new(position, light.range), style);
+      }
+    }
+    LightType::Directional => {
+      debug_draw.line(
+        position,
+        position + direction * LIGHT_DIRECTION_LENGTH,
+        style,
+      );
+    }
+    LightType::Spotlight => {
+      let length = if light.range > 0.0 {
+        light.range
+      } else {
+        LIGHT_DIRECTION_LENGTH
+      };
+      let half_angle = light.outer_cutoff.max(light.cutoff);
+      debug_draw.cone(&position, &direction, length, half_angle, style);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::game::debug_draw::{DebugLine, CIRCLE_SEGMENTS, CONE_LINES};
+  use nalgebra_glm::{vec3, Mat4};
+
+  fn run_draw_light_sources(light: LightSource) -> Vec<DebugLine> {
+    let mut world = World::default();
+    world.push((
+      light,
+      LocalToWorld(Mat4::new_translation(&vec3(0.0, 2.0, 0.0))),
+    ));
+    let mut resources = Resources::default();
+    resources.insert(DebugDraw::default());
+    let mut schedule = Schedule::builder()
+      .add_system(draw_light_sources_system())
+      .build();
+    schedule.execute(&mut world, &mut resources);
+    let debug_draw = resources.get::<DebugDraw>().unwrap();
+    debug_draw.lines().to_vec()
+  }
+
+  #[test]
+  fn test_point_light_range() {
+    let lines = run_draw_light_sources(LightSource {
+      range: 5.0,
+      ..Default::default()
+    });
+    // the marker and the range sphere
+    assert_eq!(lines.len(), 2 * 3 * CIRCLE_SEGMENTS);
+    let center = vec3(0.0, 2.0, 0.0);
+    assert!(lines
+      .iter()
+      .any(|line| ((line.start - center).norm() - 5.0).abs() < 1e-4));
+  }
+
+  #[test]
+  fn test_spot_light_cone() {
+    let lines = run_draw_light_sources(LightSource {
+      light_type: LightType::Spotlight,
+      range: 2.0,
+      ..Default::default()
+    });
+    assert_eq!(
+      lines.len(),
+      3 * CIRCLE_SEGMENTS + 1 + CIRCLE_SEGMENTS + CONE_LINES
+    );
+    // lights point down -Z
+    let axis = &lines[3 * CIRCLE_SEGMENTS];
+    assert!((axis.end - vec3(0.0, 2.0, -2.0)).norm() < 1e-4);
+  }
+}
@@ -12,10 +12,12 @@ use crate::{
 };
 
 pub mod camera_systems;
+pub mod debug_draw_systems;
 pub mod main_systems_bundle;
 pub mod model_systems;
 pub mod renderer;
 mod runnable_ext;
+pub mod transform_systems;
 pub use runnable_ext::DynParallelRunnable;
 
 use super::components::RenderModel;
@@ -23,14 +25,16 @@ use crate::{
   error::Error::Render,
   game::{
     asset_loading::resources::AssetLoaderQueue,
-    components::{LightSource, LightType},
+    components::{LightSource, LightType, DEFAULT_LOD_THRESHOLDS},
     input::InputResource,
-    resources::{MeshLookup, ScreenResolution},
+    resources::{MeshLookup, PickRequest, ScreenResolution},
   },
   nalgebra_glm::vec3,
+  platform::mouse::MouseButton,
   renderer_common::{allocator::ResourceManager, handle::Handle},
   wgpu_renderer::{
     model::{ModelLoadState, StreamingMesh},
+    pipeline_state::ShadingModel,
     resource_view::ReadWriteResources,
   },
 };
@@ -83,6 +87,7 @@ pub fn setup_scene(
     LightSource {
       light_type: LightType::Point,
       color: vec3(1.0, 1.0, 0.0),
+      intensity: 20.0,
       ..Default::default()
     },
     RenderModel {
@@ -92,10 +97,15 @@ pub fn setup_scene(
         state: ModelLoadState::NotLoaded,
         primitives: vec![],
         materials: None,
+        bounds: None,
       })),
       model_id: ":CUBE:".to_string(),
       is_shown: true,
-      shading_model: Default::default(),
+      shading_model: ShadingModel::DebugLight,
+      // marks the light's position, and shouldn't block it
+      casts_shadows: false,
+      receives_shadows: true,
+      lod_thresholds: DEFAULT_LOD_THRESHOLDS.to_vec(),
     },
   );
   command_buffer.push(light_entity);
@@ -151,3 +161,20 @@ pub fn write_renderable_ui_data(
       .push((render_model.clone(), xform.clone()))
   }
 }
+
+///
+/// Requests a pick of the pixel under the cursor when the left mouse button is clicked.
+/// Clicks while looking around with the mouse are ignored
+#[system]
+pub fn request_pick_on_click(
+  #[resource] input: &InputResource,
+  #[resource] resolution: &ScreenResolution,
+  #[resource] request: &mut PickRequest,
+) {
+  if !input.backend.clicked(MouseButton::Left) || input.is_mouselook_enabled() {
+    return;
+  }
+  if let Some(pixel) = resolution.window_to_drawable(&input.backend.mouse_position()) {
+    request.pixel = Some(pixel);
+  }
+}
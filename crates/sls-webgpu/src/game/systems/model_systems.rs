@@ -8,7 +8,7 @@ pub use wgpu_renderer::*;
 use crate::{
   game::{
     asset_loading::resources::MainSceneAssets,
-    components::{GameLoopTimer, RenderModel, Transform3D},
+    components::{GameLoopTimer, RenderModel, Transform3D, DEFAULT_LOD_THRESHOLDS},
   },
   nalgebra_glm::*,
 };
@@ -78,6 +78,9 @@ fn create_random_model(assets: &MainSceneAssets) -> (RenderModel, Transform3D) {
     model_id: assets.avocado_model_path.clone(),
     is_shown: true,
     shading_model: Default::default(),
+    casts_shadows: true,
+    receives_shadows: true,
+    lod_thresholds: DEFAULT_LOD_THRESHOLDS.to_vec(),
   };
   (model, transform)
 }
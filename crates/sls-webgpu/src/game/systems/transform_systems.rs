@@ -0,0 +1,142 @@
+//! Systems which drive the scene_graph transform systems from `Transform3D`.
+//!
+//! `Transform3D` is the transform relative to the entity's `Parent`, or to the world
+//! for root entities. The renderer only reads the resulting `LocalToWorld`.
+use legion::{
+  systems::{Builder, CommandBuffer},
+  *,
+};
+
+use crate::{
+  game::{components::Transform3D, systems::DynParallelRunnable},
+  na::UnitQuaternion,
+  scene_graph::{components::*, transform_system_bundle},
+};
+
+/// Adds scene_graph transform components to entities which only have a Transform3D
+#[system(for_each)]
+#[filter(!component::<LocalToWorld>())]
+pub fn add_transform_components(
+  entity: &Entity,
+  xform: &Transform3D,
+  parent: Option<&Parent>,
+  commands: &mut CommandBuffer,
+) {
+  let matrix = xform.matrix();
+  commands.add_component(*entity, Translation::from(*xform.position()));
+  commands.add_component(*entity, rotation_from_transform(xform));
+  commands.add_component(*entity, NonUniformScale::from(xform.scale()));
+  commands.add_component(*entity, LocalToWorld(matrix));
+  if parent.is_some() {
+    commands.add_component(*entity, LocalToParent(matrix));
+  }
+}
+
+/// Copies changed Transform3D components into Translation, Rotation and NonUniformScale
+#[system(for_each)]
+#[filter(maybe_changed::<Transform3D>())]
+pub fn sync_transform_components(
+  xform: &Transform3D,
+  translation: &mut Translation,
+  rotation: &mut Rotation,
+  scale: &mut NonUniformScale,
+) {
+  *translation = Translation::from(*xform.position());
+  *rotation = rotation_from_transform(xform);
+  *scale = NonUniformScale::from(xform.scale());
+}
+
+#[inline]
+fn rotation_from_transform(xform: &Transform3D) -> Rotation {
+  Rotation(UnitQuaternion::new_normalize(*xform.rotation()))
+}
+
+///
+/// Adds the Transform3D sync systems, followed by the scene_graph transform bundle.
+/// Each system is flushed, so new hierarchies have a correct LocalToWorld
+/// after a single execution of the schedule
+pub fn add_transform_systems(builder: &mut Builder) -> &mut Builder {
+  builder
+    .add_system(add_transform_components_system())
+    .add_system(sync_transform_components_system())
+    .flush();
+  for system in transform_system_bundle::build() {
+    builder.add_system(DynParallelRunnable::new(system)).flush();
+  }
+  builder
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{game::GameStateBuilder, na::Vector3};
+  use nalgebra_glm::vec3;
+  use std::time::Duration;
+
+  fn world_position(world: &World, entity: Entity) -> Vector3<f32> {
+    let entry = world.entry_ref(entity).unwrap();
+    let local_to_world = entry.get_component::<LocalToWorld>().unwrap();
+    local_to_world.fixed_slice::<3, 1>(0, 3).into_owned()
+  }
+
+  fn game_with_transform_systems() -> crate::game::GameState {
+    let mut builder = GameStateBuilder::default();
+    add_transform_systems(&mut builder.fixed_schedule);
+    builder.build()
+  }
+
+  #[test]
+  fn test_transform3d_hierarchy_propagates_in_one_update() {
+    let mut game = game_with_transform_systems();
+    let parent = game
+      .world_mut()
+      .push((Transform3D::default().with_position(vec3(1.0, 0.0, 0.0)),));
+    let child = game.world_mut().push((
+      Transform3D::default().with_position(vec3(0.0, 2.0, 0.0)),
+      Parent(parent),
+    ));
+
+    game.fixed_update(&Duration::from_millis(16));
+
+    assert_eq!(world_position(game.world(), parent), vec3(1.0, 0.0, 0.0));
+    assert_eq!(world_position(game.world(), child), vec3(1.0, 2.0, 0.0));
+  }
+
+  #[test]
+  fn test_moving_parent_moves_child() {
+    let mut game = game_with_transform_systems();
+    let parent = game.world_mut().push((Transform3D::default(),));
+    let child = game.world_mut().push((
+      Transform3D::default().with_position(vec3(0.0, 0.0, 3.0)),
+      Parent(parent),
+    ));
+    game.fixed_update(&Duration::from_millis(16));
+
+    game
+      .world_mut()
+      .entry(parent)
+      .unwrap()
+      .get_component_mut::<Transform3D>()
+      .unwrap()
+      .set_position(vec3(5.0, 0.0, 0.0));
+    game.fixed_update(&Duration::from_millis(16));
+
+    assert_eq!(world_position(game.world(), parent), vec3(5.0, 0.0, 0.0));
+    assert_eq!(world_position(game.world(), child), vec3(5.0, 0.0, 3.0));
+  }
+
+  #[test]
+  fn test_scaled_parent_scales_child_offset() {
+    let mut game = game_with_transform_systems();
+    let parent = game
+      .world_mut()
+      .push((Transform3D::default().with_scale(vec3(2.0, 2.0, 2.0)),));
+    let child = game.world_mut().push((
+      Transform3D::default().with_position(vec3(1.0, 0.0, 0.0)),
+      Parent(parent),
+    ));
+    game.fixed_update(&Duration::from_millis(16));
+
+    assert_eq!(world_position(game.world(), child), vec3(2.0, 0.0, 0.0));
+  }
+}
@@ -1 +1,405 @@
+//!
+//! Bounding volumes, and the intersection tests used for culling
 
+use nalgebra_glm::{max2, min2, vec3, vec4, Mat4, Vec3, Vec4};
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl Aabb {
+  pub fn new(min: Vec3, max: Vec3) -> Self {
+    Self { min, max }
+  }
+
+  /// The smallest box containing all `points`, or None if there are none
+  pub fn from_points<I: IntoIterator<Item = Vec3>>(points: I) -> Option<Self> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+    Some(points.fold(Self::new(first, first), |aabb, point| Self {
+      min: min2(&aabb.min, &point),
+      max: max2(&aabb.max, &point),
+    }))
+  }
+
+  #[inline]
+  pub fn center(&self) -> Vec3 {
+    (self.min + self.max) * 0.5
+  }
+
+  #[inline]
+  pub fn half_extents(&self) -> Vec3 {
+    (self.max - self.min) * 0.5
+  }
+
+  /// The smallest box containing both boxes
+  pub fn union(&self, other: &Self) -> Self {
+    Self {
+      min: min2(&self.min, &other.min),
+      max: max2(&self.max, &other.max),
+    }
+  }
+
+  pub fn contains_point(&self, point: &Vec3) -> bool {
+    (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+  }
+
+  pub fn intersects(&self, other: &Self) -> bool {
+    (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+  }
+
+  ///
+  /// Box containing this box after it is transformed by the affine `matrix`.
+  /// The result is axis aligned again, so it may be larger than the transformed box
+  pub fn transformed(&self, matrix: &Mat4) -> Self {
+    let center = transform_point(matrix, &self.center());
+    let half_extents = self.half_extents();
+    let extents = Vec3::from_fn(|row, _| {
+      (0..3)
+        .map(|col| matrix[(row, col)].abs() * half_extents[col])
+        .sum()
+    });
+    Self {
+      min: center - extents,
+      max: center + extents,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+  pub center: Vec3,
+  pub radius: f32,
+}
+
+impl Sphere {
+  pub fn new(center: Vec3, radius: f32) -> Self {
+    Self { center, radius }
+  }
+
+  ///
+  /// A sphere around the center of `points`' bounding box.
+  /// Not the smallest enclosing sphere, but close enough for culling
+  pub fn from_points<I: IntoIterator<Item = Vec3> + Clone>(points: I) -> Option<Self> {
+    let center = Aabb::from_points(points.clone())?.center();
+    let radius = points
+      .into_iter()
+      .map(|point| (point - center).norm())
+      .fold(0.0, f32::max);
+    Some(Self { center, radius })
+  }
+
+  pub fn contains_point(&self, point: &Vec3) -> bool {
+    (point - self.center).norm_squared() <= self.radius * self.radius
+  }
+
+  pub fn intersects(&self, other: &Self) -> bool {
+    let radii = self.radius + other.radius;
+    (other.center - self.center).norm_squared() <= radii * radii
+  }
+
+  /// The smallest sphere containing both spheres
+  pub fn union(&self, other: &Self) -> Self {
+    let offset = other.center - self.center;
+    let distance = offset.norm();
+    if distance + other.radius <= self.radius {
+      return *self;
+    }
+    if distance + self.radius <= other.radius {
+      return *other;
+    }
+    let radius = (distance + self.radius + other.radius) * 0.5;
+    let center = self.center + offset * ((radius - self.radius) / distance);
+    Self { center, radius }
+  }
+
+  /// Sphere containing this sphere after it is transformed by the affine `matrix`
+  pub fn transformed(&self, matrix: &Mat4) -> Self {
+    let max_scale = (0..3)
+      .map(|col| vec3(matrix[(0, col)], matrix[(1, col)], matrix[(2, col)]).norm())
+      .fold(0.0, f32::max);
+    Self {
+      center: transform_point(matrix, &self.center),
+      radius: self.radius * max_scale,
+    }
+  }
+}
+
+///
+/// Bounding volumes of a mesh.
+/// The sphere is the cheaper test, and the box the tighter one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+  pub aabb: Aabb,
+  pub sphere: Sphere,
+}
+
+impl Bounds {
+  pub fn from_points<I: IntoIterator<Item = Vec3> + Clone>(points: I) -> Option<Self> {
+    Some(Self {
+      aabb: Aabb::from_points(points.clone())?,
+      sphere: Sphere::from_points(points)?,
+    })
+  }
+
+  pub fn union(&self, other: &Self) -> Self {
+    Self {
+      aabb: self.aabb.union(&other.aabb),
+      sphere: self.sphere.union(&other.sphere),
+    }
+  }
+
+  pub fn transformed(&self, matrix: &Mat4) -> Self {
+    Self {
+      aabb: self.aabb.transformed(matrix),
+      sphere: self.sphere.transformed(matrix),
+    }
+  }
+}
+
+///
+/// A plane of points `p` where `normal.dot(p) + distance == 0`.
+/// Points on the side the normal points to have a positive signed distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+  pub normal: Vec3,
+  pub distance: f32,
+}
+
+impl Plane {
+  pub fn new(normal: Vec3, distance: f32) -> Self {
+    Self { normal, distance }
+  }
+
+  /// Plane from the coefficients of `ax + by + cz + d = 0`, with a normalized normal
+  pub fn from_coefficients(coefficients: &Vec4) -> Self {
+    let normal = coefficients.xyz();
+    let length = normal.norm();
+    Self {
+      normal: normal / length,
+      distance: coefficients.w / length,
+    }
+  }
+
+  #[inline]
+  pub fn signed_distance(&self, point: &Vec3) -> f32 {
+    self.normal.dot(point) + self.distance
+  }
+}
+
+///
+/// The volume seen by a camera, bounded by six planes whose normals point inwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+  /// left, right, bottom, top, near and far planes
+  pub planes: [Plane; 6],
+}
+
+impl Frustum {
+  ///
+  /// Extracts the planes of a view projection matrix,
+  /// which maps depth to wgpu's 0..1 clip range
+  pub fn from_view_projection(view_projection: &Mat4) -> Self {
+    let m = view_projection;
+    let row = |i: usize| vec4(m[(i, 0)], m[(i, 1)], m[(i, 2)], m[(i, 3)]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    Self {
+      planes: [
+        Plane::from_coefficients(&(w + x)),
+        Plane::from_coefficients(&(w - x)),
+        Plane::from_coefficients(&(w + y)),
+        Plane::from_coefficients(&(w - y)),
+        Plane::from_coefficients(&z),
+        Plane::from_coefficients(&(w - z)),
+      ],
+    }
+  }
+
+  pub fn contains_point(&self, point: &Vec3) -> bool {
+    self
+      .planes
+      .iter()
+      .all(|plane| plane.signed_distance(point) >= 0.0)
+  }
+
+  ///
+  /// False if the sphere is entirely outside of a plane.
+  /// Spheres near the frustum's corners may pass without intersecting it
+  pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+    self
+      .planes
+      .iter()
+      .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+  }
+
+  ///
+  /// False if the box is entirely outside of a plane.
+  /// Boxes near the frustum's corners may pass without intersecting it
+  pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+    self.planes.iter().all(|plane| {
+      // the corner furthest along the plane's normal
+      let corner = Vec3::from_fn(|i, _| {
+        if plane.normal[i] >= 0.0 {
+          aabb.max[i]
+        } else {
+          aabb.min[i]
+        }
+      });
+      plane.signed_distance(&corner) >= 0.0
+    })
+  }
+
+  pub fn intersects_bounds(&self, bounds: &Bounds) -> bool {
+    self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+  }
+}
+
+#[inline]
+fn transform_point(matrix: &Mat4, point: &Vec3) -> Vec3 {
+  (matrix * vec4(point.x, point.y, point.z, 1.0)).xyz()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use nalgebra_glm::{look_at, perspective, rotation, scaling, translation};
+
+  fn unit_box() -> Aabb {
+    Aabb::new(vec3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0))
+  }
+
+  /// looks down -z from the origin, seeing depths 1..10
+  fn test_frustum() -> Frustum {
+    let view = look_at(&Vec3::zeros(), &vec3(0.0, 0.0, -1.0), &vec3(0.0, 1.0, 0.0));
+    let projection = *crate::camera::OPENGL_TO_WGPU_MATRIX
+      * perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 10.0);
+    Frustum::from_view_projection(&(projection * view))
+  }
+
+  #[test]
+  fn test_aabb_from_points() {
+    assert_eq!(Aabb::from_points(vec![]), None);
+    let aabb = Aabb::from_points(vec![
+      vec3(1.0, -2.0, 0.0),
+      vec3(-1.0, 3.0, 0.5),
+      vec3(0.0, 0.0, -4.0),
+    ])
+    .unwrap();
+    assert_eq!(aabb, Aabb::new(vec3(-1.0, -2.0, -4.0), vec3(1.0, 3.0, 0.5)));
+    assert!(aabb.contains_point(&vec3(0.0, 0.0, 0.0)));
+    assert!(!aabb.contains_point(&vec3(0.0, 0.0, 1.0)));
+  }
+
+  #[test]
+  fn test_aabb_intersection_and_union() {
+    let a = unit_box();
+    let b = Aabb::new(vec3(0.5, 0.5, 0.5), vec3(2.0, 2.0, 2.0));
+    let c = Aabb::new(vec3(1.5, -1.0, -1.0), vec3(2.0, 1.0, 1.0));
+    assert!(a.intersects(&b));
+    assert!(!a.intersects(&c));
+    assert!(b.intersects(&c));
+    assert_eq!(
+      a.union(&c),
+      Aabb::new(vec3(-1.0, -1.0, -1.0), vec3(2.0, 1.0, 1.0))
+    );
+  }
+
+  #[test]
+  fn test_aabb_transformed() {
+    let moved =
+      unit_box().transformed(&(translation(&vec3(1.0, 2.0, 3.0)) * scaling(&vec3(2.0, 1.0, 1.0))));
+    assert_eq!(moved, Aabb::new(vec3(-1.0, 1.0, 2.0), vec3(3.0, 3.0, 4.0)));
+
+    // a box rotated by 45 degrees around y grows to enclose its corners
+    let rotated =
+      unit_box().transformed(&rotation(std::f32::consts::FRAC_PI_4, &vec3(0.0, 1.0, 0.0)));
+    let half = std::f32::consts::SQRT_2;
+    assert!((rotated.max.x - half).abs() < 1e-5);
+    assert!((rotated.max.z - half).abs() < 1e-5);
+    assert!((rotated.max.y - 1.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_sphere_from_points_and_transform() {
+    let sphere = Sphere::from_points(vec![vec3(-1.0, 0.0, 0.0), vec3(3.0, 0.0, 0.0)]).unwrap();
+    assert_eq!(sphere, Sphere::new(vec3(1.0, 0.0, 0.0), 2.0));
+    assert!(sphere.contains_point(&vec3(2.5, 0.0, 0.0)));
+    assert!(!sphere.contains_point(&vec3(1.0, 2.5, 0.0)));
+
+    // non uniform scale grows the radius by the largest axis scale
+    let transformed =
+      sphere.transformed(&(translation(&vec3(0.0, 1.0, 0.0)) * scaling(&vec3(1.0, 3.0, 1.0))));
+    assert_eq!(transformed, Sphere::new(vec3(1.0, 1.0, 0.0), 6.0));
+  }
+
+  #[test]
+  fn test_sphere_union() {
+    let a = Sphere::new(vec3(0.0, 0.0, 0.0), 1.0);
+    let b = Sphere::new(vec3(4.0, 0.0, 0.0), 1.0);
+    assert!(!a.intersects(&b));
+    let union = a.union(&b);
+    assert_eq!(union, Sphere::new(vec3(2.0, 0.0, 0.0), 3.0));
+    assert!(union.intersects(&a) && union.intersects(&b));
+    // contained spheres don't grow the union
+    let inner = Sphere::new(vec3(2.5, 0.0, 0.0), 0.5);
+    assert_eq!(union.union(&inner), union);
+    assert_eq!(inner.union(&union), union);
+  }
+
+  #[test]
+  fn test_plane_signed_distance() {
+    let plane = Plane::from_coefficients(&vec4(0.0, 2.0, 0.0, -2.0));
+    assert_eq!(plane, Plane::new(vec3(0.0, 1.0, 0.0), -1.0));
+    assert_eq!(plane.signed_distance(&vec3(5.0, 3.0, 0.0)), 2.0);
+    assert_eq!(plane.signed_distance(&vec3(0.0, 0.0, 0.0)), -1.0);
+  }
+
+  #[test]
+  fn test_frustum_planes() {
+    let frustum = test_frustum();
+    let near = frustum.planes[4];
+    let far = frustum.planes[5];
+    assert!((near.signed_distance(&vec3(0.0, 0.0, -1.0))).abs() < 1e-5);
+    assert!((far.signed_distance(&vec3(0.0, 0.0, -10.0))).abs() < 1e-4);
+    assert!(frustum.contains_point(&vec3(0.0, 0.0, -5.0)));
+    // a 90 degree field of view spans x in -5..5 at a depth of 5
+    assert!(frustum.contains_point(&vec3(4.9, -4.9, -5.0)));
+    assert!(!frustum.contains_point(&vec3(5.1, 0.0, -5.0)));
+    assert!(!frustum.contains_point(&vec3(0.0, 0.0, -0.5)));
+    assert!(!frustum.contains_point(&vec3(0.0, 0.0, -11.0)));
+    assert!(!frustum.contains_point(&vec3(0.0, 0.0, 5.0)));
+  }
+
+  #[test]
+  fn test_frustum_intersects_volumes() {
+    let frustum = test_frustum();
+    let at = |x: f32, y: f32, z: f32| {
+      let center = vec3(x, y, z);
+      Bounds {
+        aabb: Aabb::new(center - vec3(1.0, 1.0, 1.0), center + vec3(1.0, 1.0, 1.0)),
+        sphere: Sphere::new(center, 3.0f32.sqrt()),
+      }
+    };
+    // inside
+    assert!(frustum.intersects_bounds(&at(0.0, 0.0, -5.0)));
+    // straddling the right and far planes
+    assert!(frustum.intersects_bounds(&at(5.5, 0.0, -5.0)));
+    assert!(frustum.intersects_bounds(&at(0.0, 0.0, -10.5)));
+    // behind the camera, past the far plane, and off to the side
+    assert!(!frustum.intersects_bounds(&at(0.0, 0.0, 3.0)));
+    assert!(!frustum.intersects_bounds(&at(0.0, 0.0, -12.0)));
+    assert!(!frustum.intersects_bounds(&at(0.0, 8.0, -5.0)));
+
+    // the sphere reaches into the frustum, but the box doesn't
+    let sphere_only = Bounds {
+      aabb: Aabb::new(vec3(6.5, -0.5, -5.5), vec3(7.5, 0.5, -4.5)),
+      sphere: Sphere::new(vec3(7.0, 0.0, -5.0), 3.0),
+    };
+    assert!(frustum.intersects_sphere(&sphere_only.sphere));
+    assert!(!frustum.intersects_aabb(&sphere_only.aabb));
+    assert!(!frustum.intersects_bounds(&sphere_only));
+  }
+}
@@ -28,7 +28,93 @@ impl Default for Vertex {
 
 impl Vertex {}
 
-use crate::renderer_common::gltf_loader::LoadPrimitive;
+///
+/// Index data of a mesh. 16 bit indices are used when every index fits,
+/// and 32 bit indices for larger meshes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indices {
+  U16(Vec<u16>),
+  U32(Vec<u32>),
+}
+
+impl Default for Indices {
+  fn default() -> Self {
+    Self::U16(Vec::new())
+  }
+}
+
+impl Indices {
+  /// Stores `indices` in the narrowest type that fits all of them
+  pub fn from_u32(indices: Vec<u32>) -> Self {
+    if indices.iter().all(|&i| i <= u16::MAX as u32) {
+      Self::U16(indices.into_iter().map(|i| i as u16).collect())
+    } else {
+      Self::U32(indices)
+    }
+  }
+
+  /// Stores `indices` in the same type as `self`. Every index must fit the type
+  pub fn with_type_of(&self, indices: Vec<u32>) -> Self {
+    match self {
+      Self::U16(_) => Self::U16(indices.into_iter().map(|i| i as u16).collect()),
+      Self::U32(_) => Self::U32(indices),
+    }
+  }
+
+  /// Indices `0..count`, for drawing vertices in order
+  pub fn sequential(count: usize) -> Self {
+    if count <= u16::MAX as usize + 1 {
+      Self::U16((0..count).map(|i| i as u16).collect())
+    } else {
+      Self::U32((0..count as u32).collect())
+    }
+  }
+
+  #[inline]
+  pub fn len(&self) -> usize {
+    match self {
+      Self::U16(indices) => indices.len(),
+      Self::U32(indices) => indices.len(),
+    }
+  }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+    match self {
+      Self::U16(indices) => Box::new(indices.iter().map(|&i| i as u32)),
+      Self::U32(indices) => Box::new(indices.iter().copied()),
+    }
+  }
+
+  /// The index data, for uploading to an index buffer
+  pub fn as_bytes(&self) -> &[u8] {
+    match self {
+      Self::U16(indices) => bytemuck::cast_slice(indices),
+      Self::U32(indices) => bytemuck::cast_slice(indices),
+    }
+  }
+}
+
+impl From<Vec<u16>> for Indices {
+  fn from(indices: Vec<u16>) -> Self {
+    Self::U16(indices)
+  }
+}
+
+impl From<Vec<u32>> for Indices {
+  fn from(indices: Vec<u32>) -> Self {
+    Self::from_u32(indices)
+  }
+}
+
+use crate::{
+  math::Bounds,
+  renderer_common::{gltf_loader::LoadPrimitive, simplify::simplify},
+};
 #[cfg(feature = "wgpu_renderer")]
 pub use wgpu_renderer::*;
 
@@ -47,8 +133,8 @@ mod wgpu_renderer {
     2=>Float32x2,
     3=>Float32x2,
     4=>Float32x3,
-    5=>Float32x3,
-    6=>Float32x3
+    5=>Float32x4,
+    6=>Float32x4
   ];
 
   impl Vertex {
@@ -61,12 +147,30 @@ mod wgpu_renderer {
     }
   }
 
+  impl Indices {
+    pub fn format(&self) -> wgpu::IndexFormat {
+      match self {
+        Self::U16(_) => wgpu::IndexFormat::Uint16,
+        Self::U32(_) => wgpu::IndexFormat::Uint32,
+      }
+    }
+  }
+
   impl MeshGeometry {
     pub fn create_buffers(&self, device: &wgpu::Device) -> Result<MeshBuffers, Error> {
       let label = self.label.as_deref();
+      // levels of detail follow the full mesh in the index buffer
+      let mut indices = self.indices.as_bytes().to_vec();
+      let mut lod_ranges = vec![0..self.indices.len() as u32];
+      for lod in &self.lods {
+        debug_assert_eq!(lod.format(), self.indices.format());
+        let start = lod_ranges[lod_ranges.len() - 1].end;
+        lod_ranges.push(start..(start + lod.len() as u32));
+        indices.extend_from_slice(lod.as_bytes());
+      }
       let ibo = device.create_buffer_init(&BufferInitDescriptor {
         label,
-        contents: bytemuck::cast_slice(&self.indices),
+        contents: &indices,
         usage: wgpu::BufferUsages::INDEX,
       });
 
@@ -78,6 +182,8 @@ mod wgpu_renderer {
       Ok(MeshBuffers {
         vertex_buffer: vbo,
         index_buffer: ibo,
+        index_format: self.indices.format(),
+        lod_ranges,
       })
     }
   }
@@ -86,16 +192,28 @@ mod wgpu_renderer {
 #[derive(Debug, Clone)]
 pub struct MeshGeometry {
   pub vertices: Vec<Vertex>,
-  pub indices: Vec<u16>,
+  pub indices: Indices,
+  /// coarser levels of detail, from finest to coarsest.
+  /// They index the same vertices as `indices`, in the same type
+  pub lods: Vec<Indices>,
   pub label: Option<String>,
   pub gltf_mat_index: Option<usize>,
 }
 
+/// each level of detail aims for this fraction of the previous level's triangles
+const LOD_REDUCTION: f32 = 0.5;
+/// a level of detail must drop at least this fraction of the previous level's triangles
+const MIN_LOD_REDUCTION: f32 = 0.25;
+/// how far simplifying the first level of detail may move the surface,
+/// relative to the mesh's bounding radius. It doubles with each level
+const LOD_ERROR: f32 = 0.01;
+
 impl Default for MeshGeometry {
   fn default() -> Self {
     Self {
       vertices: vec![],
-      indices: vec![],
+      indices: Indices::default(),
+      lods: vec![],
       label: None,
       gltf_mat_index: None,
     }
@@ -157,7 +275,7 @@ impl MeshGeometry {
     Self {
       label: Some("unit plane".to_owned()),
       vertices: verts.to_vec(),
-      indices: vec![0, 2, 1, 2, 0, 3],
+      indices: Indices::U16(vec![0, 2, 1, 2, 0, 3]),
       ..Default::default()
     }
   }
@@ -185,15 +303,54 @@ impl MeshGeometry {
   }
 
   fn from_vertices(verts: Vec<Vertex>) -> Self {
-    let len = verts.len() as u16;
     Self {
       label: Some("unit sphere".to_owned()),
+      indices: Indices::sequential(verts.len()),
       vertices: verts,
-      indices: (0u16..len).collect(),
       ..Default::default()
     }
   }
 
+  /// Bounding volumes of the vertex positions, or None if there are no vertices
+  pub fn bounds(&self) -> Option<Bounds> {
+    Bounds::from_points(
+      self
+        .vertices
+        .iter()
+        .map(|vertex| nalgebra_glm::Vec3::from(vertex.position)),
+    )
+  }
+
+  /// Number of levels of detail, including the full mesh
+  #[inline]
+  pub fn lod_count(&self) -> usize {
+    self.lods.len() + 1
+  }
+
+  ///
+  /// Replaces `lods` with up to `levels` levels of detail, each simplified from the previous one.
+  /// Stops early when a level can't be simplified much further without visibly changing its shape
+  pub fn build_lods(&mut self, levels: usize) {
+    self.lods.clear();
+    let radius = match self.bounds() {
+      Some(bounds) => bounds.sphere.radius,
+      None => return,
+    };
+    let mut max_error = radius * LOD_ERROR;
+    for _ in 0..levels {
+      let previous = self.lods.last().unwrap_or(&self.indices);
+      let triangles = previous.len() / 3;
+      let target = (triangles as f32 * LOD_REDUCTION) as usize;
+      let simplified = simplify(&self.vertices, previous, target, max_error);
+      if (simplified.len() / 3) as f32 > triangles as f32 * (1.0 - MIN_LOD_REDUCTION) {
+        break;
+      }
+      let lod = self.indices.with_type_of(simplified);
+      self.lods.push(lod);
+      max_error *= 2.0;
+    }
+  }
+
   pub fn from_gltf_mesh(
     mesh: &gltf::Mesh,
     buffers: &[gltf::buffer::Data],
@@ -208,3 +365,60 @@ impl MeshGeometry {
     Ok(meshes)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_indices_use_narrowest_type() {
+    assert_eq!(
+      Indices::from_u32(vec![0, 1, 65535]),
+      Indices::U16(vec![0, 1, 65535])
+    );
+    assert_eq!(
+      Indices::from_u32(vec![0, 65536]),
+      Indices::U32(vec![0, 65536])
+    );
+
+    assert!(matches!(Indices::sequential(65536), Indices::U16(_)));
+    let large = Indices::sequential(65537);
+    assert!(matches!(large, Indices::U32(_)));
+    assert_eq!(large.len(), 65537);
+    assert_eq!(large.iter().last(), Some(65536));
+    assert_eq!(large.as_bytes().len(), 65537 * 4);
+  }
+
+  #[test]
+  fn test_bounds() {
+    assert_eq!(MeshGeometry::default().bounds(), None);
+
+    let bounds = MeshGeometry::unit_plane().bounds().unwrap();
+    assert_eq!(bounds.aabb.min, nalgebra_glm::vec3(-0.5, -0.5, 0.0));
+    assert_eq!(bounds.aabb.max, nalgebra_glm::vec3(0.5, 0.5, 0.0));
+    assert_eq!(bounds.sphere.center, nalgebra_glm::Vec3::zeros());
+    assert!((bounds.sphere.radius - 0.5f32.hypot(0.5)).abs() < 1e-6);
+  }
+
+  #[test]
+  fn test_build_lods() {
+    let mut sphere = MeshGeometry::unit_sphere(64, 32);
+    sphere.build_lods(3);
+    assert_eq!(sphere.lod_count(), 4);
+    let mut triangles = sphere.indices.len() / 3;
+    for lod in &sphere.lods {
+      assert!(matches!(lod, Indices::U16(_)));
+      assert!(lod.len() / 3 <= triangles * 3 / 4);
+      triangles = lod.len() / 3;
+    }
+
+    // a cube can't lose any triangles without changing its shape
+    let mut cube = MeshGeometry::cube();
+    cube.build_lods(3);
+    assert_eq!(cube.lod_count(), 1);
+
+    let mut empty = MeshGeometry::default();
+    empty.build_lods(3);
+    assert!(empty.lods.is_empty());
+  }
+}
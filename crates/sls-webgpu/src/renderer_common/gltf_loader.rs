@@ -1,6 +1,5 @@
-use super::geometry::{MeshGeometry, Vertex};
+use super::geometry::{Indices, MeshGeometry, Vertex};
 use gltf::Primitive;
-use std::convert::TryInto;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -47,10 +46,11 @@ impl LoadPrimitive for MeshGeometry {
         .as_mut()
         .and_then(|iter| iter.next())
         .unwrap_or([0.0, 1.0, 0.0]);
+      // a zero tangent tells the shader to derive one from screen space derivatives
       let tangent = tangents
         .as_mut()
         .and_then(|iter| iter.next())
-        .unwrap_or([0.0, 1.0, 0.0, 1.0]);
+        .unwrap_or([0.0; 4]);
       verts.push(Vertex {
         position: *position,
         normal,
@@ -92,17 +92,15 @@ impl LoadPrimitive for MeshGeometry {
       }
     }
     // load index data
-    let indices: Vec<u16> = match reader.read_indices().map(|i| i.into_u32()) {
-      Some(iter) => iter.map(|i| i.try_into().unwrap()).collect(),
-      None => {
-        let limit: u16 = verts.len().try_into().unwrap();
-        (0..limit).collect()
-      }
+    let indices = match reader.read_indices() {
+      Some(indices) => Indices::from_u32(indices.into_u32().collect()),
+      None => Indices::sequential(verts.len()),
     };
 
     Ok(Self {
       indices,
       vertices: verts,
+      lods: vec![],
       label: None,
       gltf_mat_index: primitive.material().index(),
     })
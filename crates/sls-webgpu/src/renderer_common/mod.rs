@@ -6,6 +6,7 @@ pub mod handle;
 mod has_uuid;
 pub mod images;
 pub mod render_context;
+pub mod simplify;
 pub mod sparse_array_allocator;
 
 pub use render_context::RenderContext;
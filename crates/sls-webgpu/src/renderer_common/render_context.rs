@@ -45,4 +45,13 @@ where
     uniforms: &'a Self::Uniforms,
     instances: Range<u32>,
   );
+  /// Draws level of detail `lod` of the mesh, or its coarsest level if it has fewer levels
+  fn draw_mesh_lod_instanced(
+    &mut self,
+    model: &'b Self::Mesh,
+    lod: usize,
+    material: &'a Self::Material,
+    uniforms: &'a Self::Uniforms,
+    instances: Range<u32>,
+  );
 }
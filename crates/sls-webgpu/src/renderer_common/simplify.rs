@@ -0,0 +1,520 @@
+//!
+//! Mesh simplification with quadric error metrics, for building levels of detail.
+//!
+//! Edges are collapsed onto one of their endpoints, cheapest first. The cost of a collapse
+//! is the mean squared distance of the remaining vertex from the planes of the triangles
+//! merged into it (Garland and Heckbert's quadric error metric). Collapses only keep existing
+//! vertices, so simplified indices draw the original vertex buffer, and a simplified mesh
+//! never grows outside the original's bounds.
+
+use crate::renderer_common::geometry::{Indices, Vertex};
+use nalgebra_glm::Vec3;
+use std::{
+  cmp::Ordering,
+  collections::{BinaryHeap, HashMap},
+};
+
+/// weight of the planes that hold open boundaries in place, relative to the triangles' planes
+const BOUNDARY_WEIGHT: f64 = 10.0;
+/// collapses can't turn a triangle's normal further than this, as the cosine of the angle
+const MIN_NORMAL_COSINE: f32 = 0.2;
+/// positions closer than this fraction of the mesh's extent are welded together
+const WELD_TOLERANCE: f32 = 1e-6;
+
+///
+/// Simplifies the triangle list `indices` over `vertices` down to `target_triangles`,
+/// or until the cheapest collapse would move the surface further than `max_error`.
+/// Returns the simplified triangle list, indexing the same vertices
+pub fn simplify(
+  vertices: &[Vertex],
+  indices: &Indices,
+  target_triangles: usize,
+  max_error: f32,
+) -> Vec<u32> {
+  let mut mesh = CollapseMesh::new(vertices, indices);
+  let max_error = (max_error as f64).powi(2);
+  let mut collapses = BinaryHeap::new();
+  for triangle in 0..mesh.triangles.len() {
+    mesh.push_collapses(triangle, &mut collapses);
+  }
+  while mesh.live_triangles > target_triangles {
+    let collapse = match collapses.pop() {
+      Some(collapse) => collapse,
+      None => break,
+    };
+    if !mesh.live_positions[collapse.from] || !mesh.live_positions[collapse.to] {
+      continue;
+    }
+    // costs change as quadrics are merged, so outdated collapses are queued again
+    let cost = mesh.cost(collapse.from, collapse.to);
+    if (cost - collapse.cost).abs() > 1e-9 * cost.max(1e-9) {
+      collapses.push(Collapse { cost, ..collapse });
+      continue;
+    }
+    if cost > max_error {
+      break;
+    }
+    if mesh.can_collapse(collapse.from, collapse.to) {
+      mesh.collapse(collapse.from, collapse.to, &mut collapses);
+    }
+  }
+  mesh.indices()
+}
+
+/// A symmetric 4x4 matrix accumulating weighted squared distances to planes
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric {
+  /// upper triangle of the matrix: xx, xy, xz, xw, yy, yz, yw, zz, zw, ww
+  m: [f64; 10],
+  weight: f64,
+}
+
+impl Quadric {
+  /// the plane `normal . p + distance = 0`, with a unit length normal
+  fn from_plane(normal: &Vec3, distance: f32, weight: f64) -> Self {
+    let [a, b, c, d] = [
+      normal.x as f64,
+      normal.y as f64,
+      normal.z as f64,
+      distance as f64,
+    ];
+    let m = [
+      a * a,
+      a * b,
+      a * c,
+      a * d,
+      b * b,
+      b * c,
+      b * d,
+      c * c,
+      c * d,
+      d * d,
+    ];
+    Self {
+      m: m.map(|x| x * weight),
+      weight,
+    }
+  }
+
+  fn add(&mut self, other: &Quadric) {
+    for (m, o) in self.m.iter_mut().zip(other.m.iter()) {
+      *m += o;
+    }
+    self.weight += other.weight;
+  }
+
+  /// mean squared distance of `p` from the planes
+  fn error(&self, p: &Vec3) -> f64 {
+    if self.weight <= 0.0 {
+      return 0.0;
+    }
+    let [x, y, z] = [p.x as f64, p.y as f64, p.z as f64];
+    let m = &self.m;
+    let error = m[0] * x * x
+      + m[4] * y * y
+      + m[7] * z * z
+      + m[9]
+      + 2.0 * (m[1] * x * y + m[2] * x * z + m[5] * y * z)
+      + 2.0 * (m[3] * x + m[6] * y + m[8] * z);
+    (error / self.weight).max(0.0)
+  }
+}
+
+/// A queued collapse of the position `from` onto the position `to`
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+  cost: f64,
+  from: usize,
+  to: usize,
+}
+
+impl PartialEq for Collapse {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Collapse {
+  /// reversed, so the cheapest collapse is on top of the max-heap
+  fn cmp(&self, other: &Self) -> Ordering {
+    other
+      .cost
+      .partial_cmp(&self.cost)
+      .unwrap_or(Ordering::Equal)
+      .then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+  }
+}
+
+///
+/// Triangles over welded positions, so that vertices which only differ by their attributes
+/// (along UV seams and hard edges) collapse together
+struct CollapseMesh<'a> {
+  vertices: &'a [Vertex],
+  /// the welded position of each vertex
+  position_of: Vec<usize>,
+  positions: Vec<Vec3>,
+  live_positions: Vec<bool>,
+  /// the vertices at each position
+  vertices_at: Vec<Vec<u32>>,
+  quadrics: Vec<Quadric>,
+  /// triangles as vertex indices, which are remapped as their positions collapse
+  triangles: Vec<[u32; 3]>,
+  live: Vec<bool>,
+  live_triangles: usize,
+  /// the triangles around each position, including ones which have been collapsed
+  triangles_at: Vec<Vec<usize>>,
+}
+
+impl<'a> CollapseMesh<'a> {
+  fn new(vertices: &'a [Vertex], indices: &Indices) -> Self {
+    let (position_of, positions) = weld(vertices);
+    let mut vertices_at = vec![Vec::new(); positions.len()];
+    for (vertex, &position) in position_of.iter().enumerate() {
+      vertices_at[position].push(vertex as u32);
+    }
+    let indices: Vec<u32> = indices.iter().collect();
+    let triangles: Vec<[u32; 3]> = indices
+      .chunks_exact(3)
+      .map(|t| [t[0], t[1], t[2]])
+      .filter(|t| {
+        let [a, b, c] = t.map(|v| position_of[v as usize]);
+        a != b && b != c && c != a
+      })
+      .collect();
+
+    let mut mesh = Self {
+      vertices,
+      live_positions: vec![true; positions.len()],
+      vertices_at,
+      quadrics: vec![Quadric::default(); positions.len()],
+      live: vec![true; triangles.len()],
+      live_triangles: triangles.len(),
+      triangles_at: vec![Vec::new(); positions.len()],
+      triangles,
+      position_of,
+      positions,
+    };
+    mesh.init_quadrics();
+    mesh
+  }
+
+  fn init_quadrics(&mut self) {
+    // edges used by a single triangle are on an open boundary
+    let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+    for triangle in 0..self.triangles.len() {
+      let corners = self.corners(triangle);
+      for (i, &position) in corners.iter().enumerate() {
+        self.triangles_at[position].push(triangle);
+        let next = corners[(i + 1) % 3];
+        *edges
+          .entry((position.min(next), position.max(next)))
+          .or_default() += 1;
+      }
+
+      let [a, b, c] = corners.map(|p| self.positions[p]);
+      let normal = (b - a).cross(&(c - a));
+      let area = normal.norm();
+      if area <= f32::EPSILON {
+        continue;
+      }
+      let normal = normal / area;
+      let quadric = Quadric::from_plane(&normal, -normal.dot(&a), area as f64 * 0.5);
+      for &position in &corners {
+        self.quadrics[position].add(&quadric);
+      }
+    }
+
+    for triangle in 0..self.triangles.len() {
+      let corners = self.corners(triangle);
+      let [a, b, c] = corners.map(|p| self.positions[p]);
+      let normal = (b - a).cross(&(c - a));
+      for i in 0..3 {
+        let (from, to) = (corners[i], corners[(i + 1) % 3]);
+        if edges[&(from.min(to), from.max(to))] != 1 {
+          continue;
+        }
+        // a plane through the edge, perpendicular to the triangle
+        let edge = self.positions[to] - self.positions[from];
+        let plane_normal = edge.cross(&normal);
+        let len = plane_normal.norm();
+        if len <= f32::EPSILON {
+          continue;
+        }
+        let plane_normal = plane_normal / len;
+        let quadric = Quadric::from_plane(
+          &plane_normal,
+          -plane_normal.dot(&self.positions[from]),
+          BOUNDARY_WEIGHT * edge.norm_squared() as f64,
+        );
+        self.quadrics[from].add(&quadric);
+        self.quadrics[to].add(&quadric);
+      }
+    }
+  }
+
+  #[inline]
+  fn corners(&self, triangle: usize) -> [usize; 3] {
+    self.triangles[triangle].map(|v| self.position_of[v as usize])
+  }
+
+  fn cost(&self, from: usize, to: usize) -> f64 {
+    let mut quadric = self.quadrics[from];
+    quadric.add(&self.quadrics[to]);
+    quadric.error(&self.positions[to])
+  }
+
+  fn push_collapses(&self, triangle: usize, collapses: &mut BinaryHeap<Collapse>) {
+    let corners = self.corners(triangle);
+    for i in 0..3 {
+      let (a, b) = (corners[i], corners[(i + 1) % 3]);
+      for &(from, to) in &[(a, b), (b, a)] {
+        collapses.push(Collapse {
+          cost: self.cost(from, to),
+          from,
+          to,
+        });
+      }
+    }
+  }
+
+  fn live_triangles_at(&self, position: usize) -> impl Iterator<Item = usize> + '_ {
+    self.triangles_at[position]
+      .iter()
+      .copied()
+      .filter(move |&t| self.live[t])
+  }
+
+  fn neighbours(&self, position: usize) -> Vec<usize> {
+    let mut neighbours: Vec<usize> = self
+      .live_triangles_at(position)
+      .flat_map(|t| self.corners(t))
+      .filter(|&p| p != position)
+      .collect();
+    neighbours.sort_unstable();
+    neighbours.dedup();
+    neighbours
+  }
+
+  ///
+  /// Whether `from` and `to` still share an edge, and collapsing it keeps the mesh
+  /// manifold and doesn't fold any triangle over
+  fn can_collapse(&self, from: usize, to: usize) -> bool {
+    let shared = self
+      .live_triangles_at(from)
+      .filter(|&t| self.corners(t).contains(&to))
+      .count();
+    if shared == 0 {
+      return false;
+    }
+    // the link condition: the edge's triangles must be the only ones joining its ends
+    let to_neighbours = self.neighbours(to);
+    let common = self
+      .neighbours(from)
+      .into_iter()
+      .filter(|p| to_neighbours.binary_search(p).is_ok())
+      .count();
+    if common > shared {
+      return false;
+    }
+
+    self.live_triangles_at(from).all(|t| {
+      let corners = self.corners(t);
+      if corners.contains(&to) {
+        return true;
+      }
+      let [a, b, c] = corners.map(|p| self.positions[p]);
+      let [a2, b2, c2] = corners.map(|p| self.positions[if p == from { to } else { p }]);
+      let before = (b - a).cross(&(c - a));
+      let after = (b2 - a2).cross(&(c2 - a2));
+      let (before_len, after_len) = (before.norm(), after.norm());
+      after_len > f32::EPSILON && before.dot(&after) >= MIN_NORMAL_COSINE * before_len * after_len
+    })
+  }
+
+  fn collapse(&mut self, from: usize, to: usize, collapses: &mut BinaryHeap<Collapse>) {
+    // each vertex at `from` becomes the vertex at `to` with the closest attributes
+    let remap: Vec<(u32, u32)> = self.vertices_at[from]
+      .iter()
+      .map(|&vertex| (vertex, self.closest_vertex(vertex, to)))
+      .collect();
+
+    let triangles = std::mem::take(&mut self.triangles_at[from]);
+    for triangle in triangles {
+      if !self.live[triangle] {
+        continue;
+      }
+      if self.corners(triangle).contains(&to) {
+        self.live[triangle] = false;
+        self.live_triangles -= 1;
+        continue;
+      }
+      for vertex in self.triangles[triangle].iter_mut() {
+        if let Some(&(_, target)) = remap.iter().find(|(source, _)| source == vertex) {
+          *vertex = target;
+        }
+      }
+      self.triangles_at[to].push(triangle);
+    }
+
+    let quadric = self.quadrics[from];
+    self.quadrics[to].add(&quadric);
+    self.live_positions[from] = false;
+
+    let live = &self.live;
+    self.triangles_at[to].retain(|&t| live[t]);
+    for triangle in self.triangles_at[to].clone() {
+      self.push_collapses(triangle, collapses);
+    }
+  }
+
+  fn closest_vertex(&self, vertex: u32, position: usize) -> u32 {
+    let source = &self.vertices[vertex as usize];
+    let distance = |candidate: &u32| {
+      let candidate = &self.vertices[*candidate as usize];
+      let normal: Vec3 = Vec3::from(source.normal) - Vec3::from(candidate.normal);
+      let uv = [
+        source.uv[0] - candidate.uv[0],
+        source.uv[1] - candidate.uv[1],
+      ];
+      normal.norm_squared() + uv[0] * uv[0] + uv[1] * uv[1]
+    };
+    self.vertices_at[position]
+      .iter()
+      .copied()
+      .min_by(|a, b| {
+        distance(a)
+          .partial_cmp(&distance(b))
+          .unwrap_or(Ordering::Equal)
+      })
+      .unwrap_or(vertex)
+  }
+
+  fn indices(&self) -> Vec<u32> {
+    self
+      .triangles
+      .iter()
+      .zip(self.live.iter())
+      .filter(|(_, &live)| live)
+      .flat_map(|(triangle, _)| triangle.iter().copied())
+      .collect()
+  }
+}
+
+///
+/// Merges vertices at (nearly) the same position.
+/// Returns the position index of each vertex, and the positions
+fn weld(vertices: &[Vertex]) -> (Vec<usize>, Vec<Vec3>) {
+  let extent = vertices
+    .iter()
+    .flat_map(|v| v.position.iter())
+    .fold(0.0f32, |extent, x| extent.max(x.abs()));
+  let cell = if extent > 0.0 {
+    extent * WELD_TOLERANCE
+  } else {
+    1.0
+  };
+  let mut cells: HashMap<[i64; 3], usize> = HashMap::new();
+  let mut positions = Vec::new();
+  let position_of = vertices
+    .iter()
+    .map(|vertex| {
+      let key = vertex.position.map(|x| (x / cell).round() as i64);
+      *cells.entry(key).or_insert_with(|| {
+        positions.push(Vec3::from(vertex.position));
+        positions.len() - 1
+      })
+    })
+    .collect();
+  (position_of, positions)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{math::Aabb, renderer_common::geometry::MeshGeometry};
+
+  fn triangle_count(indices: &[u32]) -> usize {
+    indices.len() / 3
+  }
+
+  #[test]
+  fn test_simplify_sphere() {
+    let sphere = MeshGeometry::unit_sphere(32, 16);
+    let triangles = sphere.indices.len() / 3;
+
+    let half = simplify(&sphere.vertices, &sphere.indices, triangles / 2, f32::MAX);
+    assert!(triangle_count(&half) <= triangles / 2);
+    assert!(triangle_count(&half) >= triangles / 2 - 2);
+    let coarse = simplify(&sphere.vertices, &sphere.indices, 64, f32::MAX);
+    assert!(triangle_count(&coarse) <= 64);
+    assert!(triangle_count(&coarse) > 32);
+
+    // every vertex is still on the sphere, so the simplified mesh stays inside its bounds,
+    // and spreading the remaining vertices over it keeps the bounds close
+    let bounds = sphere.bounds().unwrap();
+    for indices in &[half, coarse] {
+      assert!(indices
+        .iter()
+        .all(|&i| (i as usize) < sphere.vertices.len()));
+      let simplified = Aabb::from_points(
+        indices
+          .iter()
+          .map(|&i| Vec3::from(sphere.vertices[i as usize].position)),
+      )
+      .unwrap();
+      assert!(bounds.aabb.contains_point(&simplified.min));
+      assert!(bounds.aabb.contains_point(&simplified.max));
+      for axis in 0..3 {
+        let extent = simplified.max[axis] - simplified.min[axis];
+        assert!(extent > 1.8, "{:?} on axis {}", simplified, axis);
+      }
+    }
+  }
+
+  #[test]
+  fn test_simplify_keeps_the_cube() {
+    let cube = MeshGeometry::cube();
+    let triangles = cube.indices.len() / 3;
+    assert_eq!(triangles, 12);
+
+    // every collapse of a cube moves a corner off its faces
+    let simplified = simplify(&cube.vertices, &cube.indices, 6, 0.1);
+    assert_eq!(triangle_count(&simplified), 12);
+    let original: Vec<u32> = cube.indices.iter().collect();
+    assert_eq!(simplified, original);
+  }
+
+  #[test]
+  fn test_simplify_cube_without_error_limit() {
+    let cube = MeshGeometry::cube();
+    let simplified = simplify(&cube.vertices, &cube.indices, 6, f32::MAX);
+    let triangles = triangle_count(&simplified);
+    assert!(triangles <= 6 && triangles >= 4, "{} triangles", triangles);
+  }
+
+  #[test]
+  fn test_simplify_keeps_open_boundaries() {
+    let plane = MeshGeometry::unit_plane();
+    let simplified = simplify(&plane.vertices, &plane.indices, 0, 0.01);
+    assert_eq!(triangle_count(&simplified), 2);
+  }
+
+  #[test]
+  fn test_weld() {
+    let mut vertices = vec![Vertex::default(); 3];
+    vertices[1].position = [1.0, 0.0, 0.0];
+    vertices[2].position = [1.0, -1e-9, 0.0];
+    vertices[2].normal = [0.0, 1.0, 0.0];
+    let (position_of, positions) = weld(&vertices);
+    assert_eq!(position_of, vec![0, 1, 1]);
+    assert_eq!(positions.len(), 2);
+  }
+}
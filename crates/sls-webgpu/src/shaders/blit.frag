@@ -0,0 +1,16 @@
+//// blit.frag
+// copies a texture into a render target with linear filtering.
+// Used to downsample each mip level from the level above it
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=0, binding=0) uniform texture2D source_tex;
+layout(set=0, binding=1) uniform sampler source_sampler;
+
+void main() {
+    output_color = textureLod(sampler2D(source_tex, source_sampler), varying_uv, 0.0);
+}
@@ -0,0 +1,74 @@
+//// bloom_downsample.frag
+// downsamples one level of the bloom chain with a 13 tap filter.
+// The first level is taken from the HDR scene, with a Karis average to keep
+// small, very bright pixels from flickering
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=0, binding=0) uniform texture2D source_tex;
+layout(set=0, binding=1) uniform sampler source_sampler;
+layout(set=0, binding=2) uniform BloomPass {
+    float filter_radius;
+    uint karis_average;
+} bloom_pass;
+
+vec3 sample_source(vec2 offset) {
+    return textureLod(sampler2D(source_tex, source_sampler), varying_uv + offset, 0.0).rgb;
+}
+
+float karis_weight(vec3 color) {
+    float luma = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    return 1.0 / (1.0 + luma);
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(source_tex, source_sampler), 0));
+    float x = texel.x;
+    float y = texel.y;
+
+    // a - b - c
+    // - j - k -
+    // d - e - f
+    // - l - m -
+    // g - h - i
+    vec3 a = sample_source(vec2(-2.0 * x, 2.0 * y));
+    vec3 b = sample_source(vec2(0.0, 2.0 * y));
+    vec3 c = sample_source(vec2(2.0 * x, 2.0 * y));
+    vec3 d = sample_source(vec2(-2.0 * x, 0.0));
+    vec3 e = sample_source(vec2(0.0, 0.0));
+    vec3 f = sample_source(vec2(2.0 * x, 0.0));
+    vec3 g = sample_source(vec2(-2.0 * x, -2.0 * y));
+    vec3 h = sample_source(vec2(0.0, -2.0 * y));
+    vec3 i = sample_source(vec2(2.0 * x, -2.0 * y));
+    vec3 j = sample_source(vec2(-x, y));
+    vec3 k = sample_source(vec2(x, y));
+    vec3 l = sample_source(vec2(-x, -y));
+    vec3 m = sample_source(vec2(x, -y));
+
+    vec3 color;
+    if (bloom_pass.karis_average != 0u) {
+        // each of the five overlapping boxes is weighted by its inverse luma
+        vec3 box_0 = (a + b + d + e) * 0.25;
+        vec3 box_1 = (b + c + e + f) * 0.25;
+        vec3 box_2 = (d + e + g + h) * 0.25;
+        vec3 box_3 = (e + f + h + i) * 0.25;
+        vec3 box_4 = (j + k + l + m) * 0.25;
+        float w_0 = 0.125 * karis_weight(box_0);
+        float w_1 = 0.125 * karis_weight(box_1);
+        float w_2 = 0.125 * karis_weight(box_2);
+        float w_3 = 0.125 * karis_weight(box_3);
+        float w_4 = 0.5 * karis_weight(box_4);
+        color = (box_0 * w_0 + box_1 * w_1 + box_2 * w_2 + box_3 * w_3 + box_4 * w_4)
+            / (w_0 + w_1 + w_2 + w_3 + w_4);
+    } else {
+        color = e * 0.125
+            + (a + c + g + i) * 0.03125
+            + (b + d + f + h) * 0.0625
+            + (j + k + l + m) * 0.125;
+    }
+    output_color = vec4(max(color, vec3(0.0001)), 1.0);
+}
@@ -0,0 +1,39 @@
+//// bloom_upsample.frag
+// blurs one level of the bloom chain with a 3x3 tent filter,
+// and is added to the larger level above it
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=0, binding=0) uniform texture2D source_tex;
+layout(set=0, binding=1) uniform sampler source_sampler;
+layout(set=0, binding=2) uniform BloomPass {
+    float filter_radius;
+    uint karis_average;
+} bloom_pass;
+
+vec3 sample_source(vec2 offset) {
+    return textureLod(sampler2D(source_tex, source_sampler), varying_uv + offset, 0.0).rgb;
+}
+
+void main() {
+    // the radius is in uv units, so the blur covers the same area at every level
+    float x = bloom_pass.filter_radius;
+    float y = bloom_pass.filter_radius;
+
+    vec3 a = sample_source(vec2(-x, y));
+    vec3 b = sample_source(vec2(0.0, y));
+    vec3 c = sample_source(vec2(x, y));
+    vec3 d = sample_source(vec2(-x, 0.0));
+    vec3 e = sample_source(vec2(0.0, 0.0));
+    vec3 f = sample_source(vec2(x, 0.0));
+    vec3 g = sample_source(vec2(-x, -y));
+    vec3 h = sample_source(vec2(0.0, -y));
+    vec3 i = sample_source(vec2(x, -y));
+
+    vec3 color = e * 4.0 + (b + d + f + h) * 2.0 + (a + c + g + i);
+    output_color = vec4(color / 16.0, 1.0);
+}
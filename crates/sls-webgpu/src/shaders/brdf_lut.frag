@@ -0,0 +1,72 @@
+//// brdf_lut.frag
+// integrates the split sum BRDF term. x is n dot v, y is roughness
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#define PI 3.14159265359
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec2 output_scale_bias;
+
+// matches BakeParams in environment.rs
+layout(set=0, binding=0) uniform BakeParams {
+    uint face;
+    float roughness;
+    float face_size;
+    uint sample_count;
+} params;
+
+float radical_inverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), radical_inverse(i));
+}
+
+vec3 importance_sample_ggx(vec2 xi, vec3 n, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// uses k = a^2 / 2 for image based lighting
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+    float k = (roughness * roughness) / 2.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+void main() {
+    vec2 coord = gl_FragCoord.xy / params.face_size;
+    float n_dot_v = max(coord.x, 0.001);
+    float roughness = coord.y;
+
+    vec3 v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    vec3 n = vec3(0.0, 0.0, 1.0);
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint i = 0u; i < params.sample_count; i++) {
+        vec2 xi = hammersley(i, params.sample_count);
+        vec3 h = importance_sample_ggx(xi, n, roughness);
+        vec3 l = normalize(2.0 * dot(v, h) * h - v);
+        float n_dot_l = max(l.z, 0.0);
+        float n_dot_h = max(h.z, 0.0);
+        float v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            float g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            float g_vis = (g * v_dot_h) / max(n_dot_h * n_dot_v, 0.0001);
+            float fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    output_scale_bias = vec2(scale, bias) / float(params.sample_count);
+}
@@ -0,0 +1,188 @@
+// Frustum culls model instances, and compacts the visible ones for indirect draws.
+//
+// Each invocation tests one instance's bounding sphere against the frustum, and picks
+// its level of detail from the sphere's size on screen, like RenderModel::select_lod.
+// A visible instance takes the next slot of its batch's level, by counting itself into
+// the instance_count of every indirect draw of the level, and is copied to
+// that slot of the level's range in the culled instance buffer.
+//
+// Written in WGSL, as the GLSL frontend doesn't support atomics
+
+[[block]]
+struct Cull {
+  // left, right, bottom, top, near and far planes, with inward normals
+  planes: array<vec4<f32>, 6>;
+  // the camera's position, and the tangent of half its vertical field of view
+  eye: vec4<f32>;
+  instance_count: u32;
+  batch_count: u32;
+};
+
+// a packed ModelInstance. The matrix is stored as floats, as ModelInstance isn't
+// aligned like a mat4x4
+struct Instance {
+  model: array<f32, 16>;
+  receives_shadows: u32;
+};
+
+[[block]]
+struct Instances {
+  instances: array<Instance>;
+};
+
+// instances of a model, drawn by draw_count indirect draws at each level of detail
+struct Batch {
+  // model space bounding sphere, with a negative radius if the model has no bounds
+  sphere: vec4<f32>;
+  // screen sizes below which instances are drawn at the next level of detail
+  lod_thresholds: vec4<f32>;
+  first_instance: u32;
+  instance_end: u32;
+  first_draw: u32;
+  draw_count: u32;
+  lod_count: u32;
+  // start of the batch's levels in the culled instance buffer
+  first_culled: u32;
+};
+
+[[block]]
+struct Batches {
+  batches: array<Batch>;
+};
+
+// wgpu's DrawIndexedIndirect arguments
+struct DrawArgs {
+  index_count: u32;
+  instance_count: atomic<u32>;
+  first_index: u32;
+  base_vertex: i32;
+  first_instance: u32;
+};
+
+[[block]]
+struct Draws {
+  draws: array<DrawArgs>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> cull: Cull;
+[[group(0), binding(1)]]
+var<storage, read> instances: Instances;
+[[group(0), binding(2)]]
+var<storage, read> batches: Batches;
+[[group(0), binding(3)]]
+var<storage, read_write> draws: Draws;
+[[group(0), binding(4)]]
+var<storage, read_write> culled: Instances;
+
+// the model matrix's column, as stored in the instance
+fn model_column(instance: u32, column: u32) -> vec4<f32> {
+  let first = column * 4u;
+  return vec4<f32>(
+    instances.instances[instance].model[first],
+    instances.instances[instance].model[first + 1u],
+    instances.instances[instance].model[first + 2u],
+    instances.instances[instance].model[first + 3u]
+  );
+}
+
+// the instance's world space bounding sphere
+fn world_sphere(instance: u32, sphere: vec4<f32>) -> vec4<f32> {
+  let x = model_column(instance, 0u);
+  let y = model_column(instance, 1u);
+  let z = model_column(instance, 2u);
+  let w = model_column(instance, 3u);
+  let center = (x * sphere.x + y * sphere.y + z * sphere.z + w).xyz;
+  let scale = max(length(x.xyz), max(length(y.xyz), length(z.xyz)));
+  return vec4<f32>(center, sphere.w * scale);
+}
+
+fn is_visible(sphere: vec4<f32>) -> bool {
+  let center = sphere.xyz;
+  let radius = sphere.w;
+  var plane: u32 = 0u;
+  loop {
+    if (plane >= 6u) {
+      break;
+    }
+    let coefficients = cull.planes[plane];
+    if (dot(coefficients.xyz, center) + coefficients.w < -radius) {
+      return false;
+    }
+    plane = plane + 1u;
+  }
+  return true;
+}
+
+// projected diameter of the sphere, as a fraction of the screen's height, like Camera::screen_size
+fn screen_size(sphere: vec4<f32>) -> f32 {
+  let distance = max(distance(cull.eye.xyz, sphere.xyz), sphere.w);
+  if (distance <= 0.0) {
+    return 3.4e38;
+  }
+  return sphere.w / (distance * cull.eye.w);
+}
+
+fn select_lod(batch: Batch, size: f32) -> u32 {
+  var thresholds: vec4<f32> = batch.lod_thresholds;
+  var lod: u32 = 0u;
+  loop {
+    if (lod + 1u >= batch.lod_count || size >= thresholds[lod]) {
+      break;
+    }
+    lod = lod + 1u;
+  }
+  return lod;
+}
+
+// the batch whose range contains the instance. Batches are sorted by their first instance
+fn find_batch(instance: u32) -> u32 {
+  var low: u32 = 0u;
+  var high: u32 = cull.batch_count;
+  loop {
+    if (low + 1u >= high) {
+      break;
+    }
+    let middle = (low + high) / 2u;
+    if (batches.batches[middle].first_instance <= instance) {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  return low;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+  let instance = id.x;
+  if (instance >= cull.instance_count) {
+    return;
+  }
+  let batch = batches.batches[find_batch(instance)];
+  if (batch.draw_count == 0u || instance >= batch.instance_end) {
+    return;
+  }
+  var lod: u32 = 0u;
+  if (batch.sphere.w >= 0.0) {
+    let sphere = world_sphere(instance, batch.sphere);
+    if (!is_visible(sphere)) {
+      return;
+    }
+    lod = select_lod(batch, screen_size(sphere));
+  }
+  let first_draw = batch.first_draw + lod * batch.draw_count;
+  let slot = atomicAdd(&draws.draws[first_draw].instance_count, 1u);
+  var draw: u32 = first_draw + 1u;
+  loop {
+    if (draw >= first_draw + batch.draw_count) {
+      break;
+    }
+    // statements can't call atomics without using their result
+    let counted = atomicAdd(&draws.draws[draw].instance_count, 1u);
+    draw = draw + 1u;
+  }
+
+  let level_size = batch.instance_end - batch.first_instance;
+  culled.instances[batch.first_culled + lod * level_size + slot] = instances.instances[instance];
+}
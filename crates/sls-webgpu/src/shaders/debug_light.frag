@@ -12,12 +12,6 @@ layout(location = 0) out vec4 output_color;
 layout(set=1, binding=0) uniform texture2D diffuse_tex;
 layout(set=1, binding=1) uniform sampler diffuse;
 
-layout(set=1, binding=0)
-uniform Light {
-    vec3 position;
-    vec3 color;
-};
-
 void main() {
 
     output_color= vec4(1.0, 1.0, 1.0, 1.0);
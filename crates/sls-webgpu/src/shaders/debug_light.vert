@@ -5,9 +5,9 @@ layout(location = 0) in vec3 vertex_position;
 layout(location = 1) in vec4 color;
 layout(location = 2) in vec2 uv;
 layout(location = 3) in vec2 uv_1;
-layout(location = 4) in vec4 normal;
-layout(location = 5) in vec3 tangent;
-layout(location = 6) in vec3 bitangent;
+layout(location = 4) in vec3 normal;
+layout(location = 5) in vec4 tangent;
+layout(location = 6) in vec4 bitangent;
 
 
 // model matrix for instance
@@ -26,12 +26,6 @@ layout(set=0, binding=0) uniform UniformBufferObject {
     mat4 view_projection;
 } ubo;
 
-layout(set=1, binding=0)
-uniform Light {
-    vec3 position;
-    vec3 color;
-} light_ubo;
-
 
 void main() {
     mat4 model_mat = mat4(
@@ -42,7 +36,7 @@ void main() {
     );
     varying_uv_0 = uv;
     varying_uv_1 = uv_1;
-    varying_color = normal;
+    varying_color = vec4(normal, 1.0);
     varying_pos = model_mat * vec4(vertex_position, 1.0);
     gl_Position = ubo.view_projection * varying_pos;
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+//// equirect_to_cube.frag
+// projects an equirectangular environment onto one face of a cubemap
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#define PI 3.14159265359
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+// matches BakeParams in environment.rs
+layout(set=0, binding=0) uniform BakeParams {
+    uint face;
+    float roughness;
+    float face_size;
+    uint sample_count;
+} params;
+
+layout(set=0, binding=1) uniform texture2D source_tex;
+layout(set=0, binding=2) uniform sampler source_sampler;
+
+// direction through a texel of a cubemap face, following the
+// face orientations used by the hardware for cube lookups
+vec3 cube_direction(uint face, vec2 frag_coord, float face_size) {
+    vec2 uv = frag_coord / face_size * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -uv.y, -uv.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -uv.y, uv.x));
+    } else if (face == 2u) {
+        return normalize(vec3(uv.x, 1.0, uv.y));
+    } else if (face == 3u) {
+        return normalize(vec3(uv.x, -1.0, -uv.y));
+    } else if (face == 4u) {
+        return normalize(vec3(uv.x, -uv.y, 1.0));
+    }
+    return normalize(vec3(-uv.x, -uv.y, -1.0));
+}
+
+void main() {
+    vec3 direction = cube_direction(params.face, gl_FragCoord.xy, params.face_size);
+    vec2 equirect_uv = vec2(
+        atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI
+    );
+    output_color = vec4(textureLod(sampler2D(source_tex, source_sampler), equirect_uv, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,74 @@
+//// fxaa.frag
+// fast approximate antialiasing of the tonemapped frame.
+// Blurs along the direction of edges found from the luma of neighbouring pixels
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=0, binding=0) uniform texture2D ldr_tex;
+layout(set=0, binding=1) uniform sampler post_sampler;
+layout(set=0, binding=2) uniform Fxaa {
+    uint enabled;
+    float edge_threshold;
+    float edge_threshold_min;
+    float span_max;
+} fxaa;
+
+#define FXAA_REDUCE_MUL (1.0 / 8.0)
+#define FXAA_REDUCE_MIN (1.0 / 128.0)
+
+vec3 sample_ldr(vec2 uv) {
+    return textureLod(sampler2D(ldr_tex, post_sampler), uv, 0.0).rgb;
+}
+
+// the texture is sampled as linear color, so luma is taken back to a perceptual scale
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+void main() {
+    vec3 rgb_m = sample_ldr(varying_uv);
+    if (fxaa.enabled == 0u) {
+        output_color = vec4(rgb_m, 1.0);
+        return;
+    }
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(ldr_tex, post_sampler), 0));
+    float luma_nw = luma(sample_ldr(varying_uv + vec2(-1.0, -1.0) * texel));
+    float luma_ne = luma(sample_ldr(varying_uv + vec2(1.0, -1.0) * texel));
+    float luma_sw = luma(sample_ldr(varying_uv + vec2(-1.0, 1.0) * texel));
+    float luma_se = luma(sample_ldr(varying_uv + vec2(1.0, 1.0) * texel));
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+    // low contrast pixels aren't on an edge
+    if (luma_max - luma_min < max(fxaa.edge_threshold_min, luma_max * fxaa.edge_threshold)) {
+        output_color = vec4(rgb_m, 1.0);
+        return;
+    }
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * FXAA_REDUCE_MUL), FXAA_REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-fxaa.span_max), vec2(fxaa.span_max)) * texel;
+
+    vec3 rgb_a = 0.5 * (
+        sample_ldr(varying_uv + dir * (1.0 / 3.0 - 0.5)) +
+        sample_ldr(varying_uv + dir * (2.0 / 3.0 - 0.5)));
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        sample_ldr(varying_uv + dir * -0.5) +
+        sample_ldr(varying_uv + dir * 0.5));
+    // the wider blur is only kept if it stays within the range of the neighbourhood
+    float luma_b = luma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        output_color = vec4(rgb_a, 1.0);
+    } else {
+        output_color = vec4(rgb_b, 1.0);
+    }
+}
@@ -0,0 +1,61 @@
+//// irradiance.frag
+// convolves an environment cubemap into a diffuse irradiance cubemap
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#define PI 3.14159265359
+#define PHI_STEPS 64u
+#define THETA_STEPS 16u
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+// matches BakeParams in environment.rs
+layout(set=0, binding=0) uniform BakeParams {
+    uint face;
+    float roughness;
+    float face_size;
+    uint sample_count;
+} params;
+
+layout(set=0, binding=1) uniform textureCube source_tex;
+layout(set=0, binding=2) uniform sampler source_sampler;
+
+vec3 cube_direction(uint face, vec2 frag_coord, float face_size) {
+    vec2 uv = frag_coord / face_size * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -uv.y, -uv.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -uv.y, uv.x));
+    } else if (face == 2u) {
+        return normalize(vec3(uv.x, 1.0, uv.y));
+    } else if (face == 3u) {
+        return normalize(vec3(uv.x, -1.0, -uv.y));
+    } else if (face == 4u) {
+        return normalize(vec3(uv.x, -uv.y, 1.0));
+    }
+    return normalize(vec3(-uv.x, -uv.y, -1.0));
+}
+
+void main() {
+    vec3 normal = cube_direction(params.face, gl_FragCoord.xy, params.face_size);
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    // riemann sum over the hemisphere, weighted by the cosine term
+    vec3 irradiance = vec3(0.0);
+    for (uint i = 0u; i < PHI_STEPS; i++) {
+        float phi = 2.0 * PI * (float(i) + 0.5) / float(PHI_STEPS);
+        for (uint j = 0u; j < THETA_STEPS; j++) {
+            float theta = 0.5 * PI * (float(j) + 0.5) / float(THETA_STEPS);
+            vec3 tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 sample_dir = tangent_sample.x * right + tangent_sample.y * up + tangent_sample.z * normal;
+            vec3 radiance = textureLod(samplerCube(source_tex, source_sampler), sample_dir, 0.0).rgb;
+            irradiance += radiance * cos(theta) * sin(theta);
+        }
+    }
+    irradiance = PI * irradiance / float(PHI_STEPS * THETA_STEPS);
+    output_color = vec4(irradiance, 1.0);
+}
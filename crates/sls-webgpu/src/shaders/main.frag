@@ -1,38 +1,303 @@
-//// simple.frag
+//// main.frag
+// Cook-Torrance metallic-roughness shading, following the glTF 2.0 BRDF
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+#define PI 3.14159265359
+
+#define MAX_LIGHTS 16
+// matches PREFILTERED_MIP_LEVELS in environment.rs
+#define PREFILTERED_MIP_LEVELS 5
+#define LIGHT_TYPE_POINT 0u
+#define LIGHT_TYPE_DIRECTIONAL 1u
+#define LIGHT_TYPE_SPOT 2u
+
+// matches MAX_SHADOW_LAYERS in uniforms.rs
+#define MAX_SHADOW_LAYERS 8
+#define SHADOW_BIAS 0.0005
+
+// matches MaterialTextures in material.rs
+#define MATERIAL_HAS_ALBEDO_TEX 1u
+#define MATERIAL_HAS_METALLIC_ROUGHNESS_TEX 2u
+#define MATERIAL_HAS_NORMAL_TEX 4u
+#define MATERIAL_HAS_OCCLUSION_TEX 8u
+#define MATERIAL_HAS_EMISSIVE_TEX 16u
+
 layout(location = 0) in vec4 varying_color;
 layout(location = 1) in vec2 varying_uv_0;
 layout(location = 2) in vec2 varying_uv_1;
 layout(location = 3) in vec4 varying_pos;
 layout(location = 4) in vec3 varying_normal;
+layout(location = 5) in vec4 varying_tangent;
+layout(location = 6) flat in uint varying_receives_shadows;
 
 
 layout(location = 0) out vec4 output_color;
 
+layout(set=0, binding=0) uniform UniformBufferObject {
+    mat4 view_projection;
+    vec4 camera_position;
+    mat4 inverse_view_projection;
+} ubo;
+
+// matches MaterialUniform in material.rs
+layout(set=1, binding=0) uniform MaterialUniform {
+    vec4 albedo_factor;
+    vec3 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha_cutoff;
+    uint texture_flags;
+} material;
 
-layout(set=1, binding=0) uniform texture2D diffuse_tex;
-layout(set=1, binding=1) uniform sampler diffuse;
+layout(set=1, binding=1) uniform texture2D albedo_tex;
+layout(set=1, binding=2) uniform sampler albedo_sampler;
+layout(set=1, binding=3) uniform texture2D metallic_roughness_tex;
+layout(set=1, binding=4) uniform sampler metallic_roughness_sampler;
+layout(set=1, binding=5) uniform texture2D normal_tex;
+layout(set=1, binding=6) uniform sampler normal_sampler;
+layout(set=1, binding=7) uniform texture2D occlusion_tex;
+layout(set=1, binding=8) uniform sampler occlusion_sampler;
+layout(set=1, binding=9) uniform texture2D emissive_tex;
+layout(set=1, binding=10) uniform sampler emissive_sampler;
 
-layout(set=1, binding=0)
-uniform Light {
+// matches LightUniform in uniforms.rs.
+// cutoffs are cosines of the cone half-angles.
+// shadow_layer is the light's first layer in shadow_maps, or -1 if it has no shadow
+struct Light {
     vec3 position;
+    uint light_type;
+    vec3 direction;
+    float range;
     vec3 color;
-} light;
+    float intensity;
+    float inner_cutoff;
+    float outer_cutoff;
+    int shadow_layer;
+    uint shadow_layer_count;
+};
+
+layout(set=2, binding=0)
+uniform Lights {
+    uint count;
+    Light lights[MAX_LIGHTS];
+} light_data;
+
+// matches ShadowUniform in uniforms.rs.
+// directional lights have a layer per cascade, and spot lights a single layer
+layout(set=2, binding=1)
+uniform Shadows {
+    mat4 view_projections[MAX_SHADOW_LAYERS];
+    // view depth at the far end of each cascade
+    vec4 cascade_splits;
+    vec4 camera_forward;
+    uint layer_count;
+    float texel_size;
+} shadow_data;
+
+layout(set=2, binding=2) uniform texture2DArray shadow_maps;
+layout(set=2, binding=3) uniform samplerShadow shadow_sampler;
+
+// image based lighting, see environment.rs
+layout(set=3, binding=1) uniform textureCube irradiance_tex;
+layout(set=3, binding=2) uniform textureCube prefiltered_tex;
+layout(set=3, binding=3) uniform texture2D brdf_lut;
+layout(set=3, binding=4) uniform sampler environment_sampler;
+
+// 1.0 if the material has the texture, otherwise 0.0.
+// textures are always sampled, since derivatives are only defined in uniform control flow
+float texture_weight(uint flag) {
+    return (material.texture_flags & flag) != 0u ? 1.0 : 0.0;
+}
+
+// incoming radiance from a light, and the direction towards it
+vec3 light_radiance(Light light, vec3 position, out vec3 light_dir) {
+    float attenuation = 1.0;
+    if (light.light_type == LIGHT_TYPE_DIRECTIONAL) {
+        light_dir = -light.direction;
+    } else {
+        vec3 to_light = light.position - position;
+        float dist = length(to_light);
+        light_dir = to_light / max(dist, 0.0001);
+        attenuation = 1.0 / max(dist * dist, 0.0001);
+        if (light.range > 0.0) {
+            attenuation *= clamp(1.0 - pow(dist / light.range, 4.0), 0.0, 1.0);
+        }
+        if (light.light_type == LIGHT_TYPE_SPOT) {
+            float cos_angle = dot(-light_dir, light.direction);
+            float cone = max(light.inner_cutoff - light.outer_cutoff, 0.0001);
+            attenuation *= clamp((cos_angle - light.outer_cutoff) / cone, 0.0, 1.0);
+        }
+    }
+    return attenuation * light.intensity * light.color;
+}
+
+// fraction of the light reaching position, filtered with 3x3 PCF
+float shadow_factor(Light light, vec3 position) {
+    if (light.shadow_layer < 0 || varying_receives_shadows == 0u) {
+        return 1.0;
+    }
+    int layer = light.shadow_layer;
+    if (light.light_type == LIGHT_TYPE_DIRECTIONAL) {
+        float view_depth = dot(position - ubo.camera_position.xyz, shadow_data.camera_forward.xyz);
+        uint last = light.shadow_layer_count - 1u;
+        if (view_depth > shadow_data.cascade_splits[last]) {
+            return 1.0;
+        }
+        for (uint i = 0u; i < last; i++) {
+            if (view_depth > shadow_data.cascade_splits[i]) {
+                layer = light.shadow_layer + int(i) + 1;
+            }
+        }
+    }
+
+    vec4 light_space = shadow_data.view_projections[layer] * vec4(position, 1.0);
+    vec3 ndc = light_space.xyz / light_space.w;
+    if (abs(ndc.x) > 1.0 || abs(ndc.y) > 1.0 || ndc.z < 0.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    // zero gradients sample the only mip level, which keeps the lookup
+    // valid outside of uniform control flow
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(float(x), float(y)) * shadow_data.texel_size;
+            lit += textureGrad(
+                sampler2DArrayShadow(shadow_maps, shadow_sampler),
+                vec4(uv + offset, float(layer), ndc.z - SHADOW_BIAS),
+                vec2(0.0),
+                vec2(0.0)
+            );
+        }
+    }
+    return lit / 9.0;
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.000001);
+}
+
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+    float r = roughness + 1.0;
+    float k = (r * r) / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
 
-vec3 ambient = vec3(0.1, 0.1, 0.0);
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    return geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// fresnel for ambient light, which has no single half vector
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// split sum approximation of the environment's diffuse and specular contribution
+vec3 ambient_light(vec3 n, vec3 v, float n_dot_v, vec3 albedo, vec3 f0, float metallic, float roughness) {
+    vec3 k_specular = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    vec3 k_diffuse = (vec3(1.0) - k_specular) * (1.0 - metallic);
+    vec3 irradiance = textureLod(samplerCube(irradiance_tex, environment_sampler), n, 0.0).rgb;
+
+    vec3 r = reflect(-v, n);
+    float lod = roughness * float(PREFILTERED_MIP_LEVELS - 1);
+    vec3 prefiltered = textureLod(samplerCube(prefiltered_tex, environment_sampler), r, lod).rgb;
+    vec2 scale_bias = textureLod(sampler2D(brdf_lut, environment_sampler), vec2(n_dot_v, roughness), 0.0).rg;
+    vec3 specular = prefiltered * (k_specular * scale_bias.x + scale_bias.y);
+
+    return k_diffuse * irradiance * albedo + specular;
+}
+
+// tangent frame from screen space derivatives, for meshes without tangents
+mat3 cotangent_frame(vec3 n, vec3 p, vec2 uv) {
+    vec3 dp1 = dFdx(p);
+    vec3 dp2 = dFdy(p);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float inv_max = inversesqrt(max(max(dot(t, t), dot(b, b)), 0.000001));
+    return mat3(t * inv_max, b * inv_max, n);
+}
+
+vec3 surface_normal(vec3 n, mat3 derivative_frame) {
+    vec3 tangent_normal = texture(sampler2D(normal_tex, normal_sampler), varying_uv_0).xyz * 2.0 - 1.0;
+    tangent_normal.xy *= material.normal_scale;
 
+    mat3 tbn = derivative_frame;
+    if (dot(varying_tangent.xyz, varying_tangent.xyz) > 0.000001) {
+        vec3 t = normalize(varying_tangent.xyz - n * dot(n, varying_tangent.xyz));
+        vec3 b = cross(n, t) * (varying_tangent.w < 0.0 ? -1.0 : 1.0);
+        tbn = mat3(t, b, n);
+    }
+    vec3 mapped = normalize(tbn * tangent_normal);
+    return normalize(mix(n, mapped, texture_weight(MATERIAL_HAS_NORMAL_TEX)));
+}
 
 void main() {
-    vec3 norm = normalize(varying_normal);
-    vec3 light_dir = normalize(light.position - varying_pos.xyz);
-    float diffuse_factor = max(dot(norm, light_dir), 0.0);
-    vec4 object_albedo = texture(sampler2D(diffuse_tex, diffuse), varying_uv_0);
-    vec3 diffuse= diffuse_factor * light.color;
-    vec3 ambient_diffuse = (diffuse + ambient) * object_albedo.xyz;
+    // back faces are only drawn for double sided materials, and face the other way
+    vec3 geometry_normal = normalize(gl_FrontFacing ? varying_normal : -varying_normal);
+    mat3 derivative_frame = cotangent_frame(geometry_normal, varying_pos.xyz, varying_uv_0);
+
+    vec4 albedo_sample = texture(sampler2D(albedo_tex, albedo_sampler), varying_uv_0);
+    vec4 metallic_roughness =
+        texture(sampler2D(metallic_roughness_tex, metallic_roughness_sampler), varying_uv_0);
+    float occlusion_sample = texture(sampler2D(occlusion_tex, occlusion_sampler), varying_uv_0).r;
+    vec3 emissive_sample = texture(sampler2D(emissive_tex, emissive_sampler), varying_uv_0).rgb;
+    vec3 n = surface_normal(geometry_normal, derivative_frame);
+
+    vec4 albedo = material.albedo_factor * varying_color
+        * mix(vec4(1.0), albedo_sample, texture_weight(MATERIAL_HAS_ALBEDO_TEX));
+    if (albedo.a < material.alpha_cutoff) {
+        discard;
+    }
+
+    // roughness is sampled from green, metalness from blue
+    vec2 metallic_roughness_scale = mix(
+        vec2(1.0), metallic_roughness.bg, texture_weight(MATERIAL_HAS_METALLIC_ROUGHNESS_TEX));
+    float metallic = clamp(material.metallic_factor * metallic_roughness_scale.x, 0.0, 1.0);
+    float roughness = clamp(material.roughness_factor * metallic_roughness_scale.y, 0.04, 1.0);
+
+    float occlusion = 1.0 + material.occlusion_strength * (occlusion_sample - 1.0);
+    occlusion = mix(1.0, occlusion, texture_weight(MATERIAL_HAS_OCCLUSION_TEX));
+
+    vec3 emissive = material.emissive_factor
+        * mix(vec3(1.0), emissive_sample, texture_weight(MATERIAL_HAS_EMISSIVE_TEX));
+
+    vec3 v = normalize(ubo.camera_position.xyz - varying_pos.xyz);
+    float n_dot_v = max(dot(n, v), 0.0001);
+    vec3 f0 = mix(vec3(0.04), albedo.rgb, metallic);
+
+    vec3 radiance_out = vec3(0.0);
+    for (uint i = 0u; i < min(light_data.count, uint(MAX_LIGHTS)); i++) {
+        vec3 l;
+        Light light = light_data.lights[i];
+        vec3 radiance = light_radiance(light, varying_pos.xyz, l) * shadow_factor(light, varying_pos.xyz);
+        vec3 h = normalize(v + l);
+        float n_dot_l = max(dot(n, l), 0.0);
+        float n_dot_h = max(dot(n, h), 0.0);
 
-    output_color = vec4(ambient_diffuse, object_albedo.w);
+        vec3 f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+        float d = distribution_ggx(n_dot_h, roughness);
+        float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        vec3 specular = d * g * f / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+        vec3 k_diffuse = (vec3(1.0) - f) * (1.0 - metallic);
 
+        radiance_out += (k_diffuse * albedo.rgb / PI + specular) * radiance * n_dot_l;
+    }
 
+    vec3 ambient = ambient_light(n, v, n_dot_v, albedo.rgb, f0, metallic, roughness);
+    vec3 color = ambient * occlusion + radiance_out + emissive;
+    output_color = vec4(color, albedo.a);
 }
@@ -5,9 +5,9 @@ layout(location = 0) in vec3 vertex_position;
 layout(location = 1) in vec4 color;
 layout(location = 2) in vec2 uv;
 layout(location = 3) in vec2 uv_1;
-layout(location = 4) in vec4 normal;
-layout(location = 5) in vec3 tangent;
-layout(location = 6) in vec3 bitangent;
+layout(location = 4) in vec3 normal;
+layout(location = 5) in vec4 tangent;
+layout(location = 6) in vec4 bitangent;
 
 
 // model matrix for instance
@@ -15,6 +15,7 @@ layout(location = 7) in vec4 instance_model_x;
 layout(location = 8) in vec4 instance_model_y;
 layout(location = 9) in vec4 instance_model_z;
 layout(location = 10) in vec4 instance_model_w;
+layout(location = 11) in uint instance_receives_shadows;
 
 
 layout(location = 0) out vec4 varying_color;
@@ -22,17 +23,15 @@ layout(location = 1) out vec2 varying_uv_0;
 layout(location = 2) out vec2 varying_uv_1;
 layout(location = 3) out vec4 varying_pos;
 layout(location = 4) out vec3 varying_normal;
+layout(location = 5) out vec4 varying_tangent;
+layout(location = 6) flat out uint varying_receives_shadows;
 
-layout(binding=0) uniform UniformBufferObject {
+layout(set=0, binding=0) uniform UniformBufferObject {
     mat4 view_projection;
+    vec4 camera_position;
+    mat4 inverse_view_projection;
 } ubo;
 
-layout(set=1, binding=0)
-uniform Light {
-    vec3 position;
-    vec3 color;
-} light_ubo;
-
 
 void main() {
     mat4 model_mat = mat4(
@@ -43,8 +42,10 @@ void main() {
     );
     varying_uv_0 = uv;
     varying_uv_1 = uv_1;
-    varying_color = normal;
-    varying_normal = normal.xyz;
+    varying_color = color;
     varying_pos = model_mat * vec4(vertex_position, 1.0);
+    varying_normal = mat3(model_mat) * normal;
+    varying_tangent = vec4(mat3(model_mat) * tangent.xyz, tangent.w);
+    varying_receives_shadows = instance_receives_shadows;
     gl_Position = ubo.view_projection * varying_pos;
 }
\ No newline at end of file
@@ -0,0 +1,13 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) flat in uint pick_id;
+
+layout(location = 0) out uint output_id;
+// copy of the depth buffer, which can't be read back directly
+layout(location = 1) out float output_depth;
+
+void main() {
+    output_id = pick_id;
+    output_depth = gl_FragCoord.z;
+}
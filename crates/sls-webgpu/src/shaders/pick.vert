@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 vertex_position;
+
+// model matrix for instance
+layout(location = 7) in vec4 instance_model_x;
+layout(location = 8) in vec4 instance_model_y;
+layout(location = 9) in vec4 instance_model_z;
+layout(location = 10) in vec4 instance_model_w;
+// index of the instance's entity in the pick table, starting at 1
+layout(location = 11) in uint instance_pick_id;
+
+layout(location = 0) flat out uint pick_id;
+
+layout(set=0, binding=0) uniform UniformBufferObject {
+    mat4 view_projection;
+} ubo;
+
+void main() {
+    mat4 model_mat = mat4(
+        instance_model_x,
+        instance_model_y,
+        instance_model_z,
+        instance_model_w
+    );
+    pick_id = instance_pick_id;
+    gl_Position = ubo.view_projection * model_mat * vec4(vertex_position, 1.0);
+}
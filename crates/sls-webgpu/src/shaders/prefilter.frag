@@ -0,0 +1,84 @@
+//// prefilter.frag
+// prefilters an environment cubemap with the GGX distribution,
+// for one roughness level of the specular mip chain
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#define PI 3.14159265359
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+// matches BakeParams in environment.rs
+layout(set=0, binding=0) uniform BakeParams {
+    uint face;
+    float roughness;
+    float face_size;
+    uint sample_count;
+} params;
+
+layout(set=0, binding=1) uniform textureCube source_tex;
+layout(set=0, binding=2) uniform sampler source_sampler;
+
+vec3 cube_direction(uint face, vec2 frag_coord, float face_size) {
+    vec2 uv = frag_coord / face_size * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -uv.y, -uv.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -uv.y, uv.x));
+    } else if (face == 2u) {
+        return normalize(vec3(uv.x, 1.0, uv.y));
+    } else if (face == 3u) {
+        return normalize(vec3(uv.x, -1.0, -uv.y));
+    } else if (face == 4u) {
+        return normalize(vec3(uv.x, -uv.y, 1.0));
+    }
+    return normalize(vec3(-uv.x, -uv.y, -1.0));
+}
+
+float radical_inverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), radical_inverse(i));
+}
+
+vec3 importance_sample_ggx(vec2 xi, vec3 n, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    vec3 h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
+
+void main() {
+    // assumes the view direction is the normal, as in the split sum approximation
+    vec3 n = cube_direction(params.face, gl_FragCoord.xy, params.face_size);
+    vec3 v = n;
+
+    vec3 color = vec3(0.0);
+    float total_weight = 0.0;
+    for (uint i = 0u; i < params.sample_count; i++) {
+        vec2 xi = hammersley(i, params.sample_count);
+        vec3 h = importance_sample_ggx(xi, n, params.roughness);
+        vec3 l = normalize(2.0 * dot(v, h) * h - v);
+        float n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            color += textureLod(samplerCube(source_tex, source_sampler), l, 0.0).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    output_color = vec4(color / max(total_weight, 0.0001), 1.0);
+}
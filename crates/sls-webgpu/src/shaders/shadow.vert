@@ -0,0 +1,27 @@
+//// shadow.vert
+// depth only pass, rendering shadow casters from a light's point of view
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 vertex_position;
+
+// model matrix for instance
+layout(location = 7) in vec4 instance_model_x;
+layout(location = 8) in vec4 instance_model_y;
+layout(location = 9) in vec4 instance_model_z;
+layout(location = 10) in vec4 instance_model_w;
+
+// matches ShadowPassUniform in shadows.rs
+layout(set=0, binding=0) uniform ShadowPass {
+    mat4 light_view_projection;
+} pass;
+
+void main() {
+    mat4 model_mat = mat4(
+        instance_model_x,
+        instance_model_y,
+        instance_model_z,
+        instance_model_w
+    );
+    gl_Position = pass.light_view_projection * model_mat * vec4(vertex_position, 1.0);
+}
@@ -0,0 +1,15 @@
+//// skybox.frag
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 varying_direction;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=1, binding=0) uniform textureCube environment_tex;
+layout(set=1, binding=4) uniform sampler environment_sampler;
+
+void main() {
+    vec3 direction = normalize(varying_direction);
+    output_color = vec4(textureLod(samplerCube(environment_tex, environment_sampler), direction, 0.0).rgb, 1.0);
+}
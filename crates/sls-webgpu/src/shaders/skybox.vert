@@ -0,0 +1,19 @@
+//// skybox.vert
+// fullscreen triangle on the far plane, with the world space view direction for each corner
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) out vec3 varying_direction;
+
+layout(set=0, binding=0) uniform UniformBufferObject {
+    mat4 view_projection;
+    vec4 camera_position;
+    mat4 inverse_view_projection;
+} ubo;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    vec4 far_point = ubo.inverse_view_projection * vec4(position, 1.0, 1.0);
+    varying_direction = far_point.xyz / far_point.w - ubo.camera_position.xyz;
+    gl_Position = vec4(position, 1.0, 1.0);
+}
@@ -0,0 +1,57 @@
+//// tonemap.frag
+// mixes bloom into the HDR scene, applies exposure, and maps it to display range
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#define TONEMAPPER_NONE 0u
+#define TONEMAPPER_REINHARD 1u
+#define TONEMAPPER_ACES 2u
+
+layout(location = 0) in vec2 varying_uv;
+
+layout(location = 0) out vec4 output_color;
+
+layout(set=0, binding=0) uniform texture2D hdr_tex;
+layout(set=0, binding=1) uniform texture2D bloom_tex;
+layout(set=0, binding=2) uniform sampler post_sampler;
+layout(set=0, binding=3) uniform Tonemap {
+    float exposure;
+    float bloom_intensity;
+    uint tonemapper;
+} tonemap;
+
+vec3 rrt_and_odt_fit(vec3 v) {
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return a / b;
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+vec3 aces_fitted(vec3 color) {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    mat3 aces_input = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777);
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    mat3 aces_output = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602);
+    color = aces_input * color;
+    color = rrt_and_odt_fit(color);
+    return aces_output * color;
+}
+
+void main() {
+    vec3 hdr = textureLod(sampler2D(hdr_tex, post_sampler), varying_uv, 0.0).rgb;
+    vec3 bloom = textureLod(sampler2D(bloom_tex, post_sampler), varying_uv, 0.0).rgb;
+    vec3 color = mix(hdr, bloom, tonemap.bloom_intensity) * tonemap.exposure;
+
+    if (tonemap.tonemapper == TONEMAPPER_REINHARD) {
+        color = color / (1.0 + color);
+    } else if (tonemap.tonemapper == TONEMAPPER_ACES) {
+        color = aces_fitted(color);
+    }
+    output_color = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,1125 @@
+//! GPU compressed textures.
+//!
+//! KTX2 and DDS files are uploaded in their block compressed format when the device
+//! supports it, so they stay compressed in video memory. Basis Universal KTX2 files, UASTC
+//! or BasisLZ supercompressed ETC1S, are transcoded on the CPU to BC7, ASTC or ETC2,
+//! whichever is enabled. The transcoder is only built for native targets.
+//! Textures without a usable compressed format are decompressed to RGBA8.
+use std::{convert::TryInto, io::Read, ops::Range};
+
+use bitflags::bitflags;
+use image::{codecs::dxt::DxtVariant, DynamicImage};
+use wgpu::{Features, TextureFormat};
+
+use crate::wgpu_renderer::textures::TextureError;
+
+pub const KTX2_IDENTIFIER: [u8; 12] = [
+  0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+pub const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+bitflags! {
+  /// Families of block compressed formats enabled on a device
+  #[derive(Default)]
+  pub struct CompressedFormats: u32 {
+    const BC = 1 << 0;
+    const ETC2 = 1 << 1;
+    const ASTC = 1 << 2;
+  }
+}
+
+impl CompressedFormats {
+  pub fn from_features(features: Features) -> Self {
+    let mut formats = Self::empty();
+    formats.set(
+      Self::BC,
+      features.contains(Features::TEXTURE_COMPRESSION_BC),
+    );
+    formats.set(
+      Self::ETC2,
+      features.contains(Features::TEXTURE_COMPRESSION_ETC2),
+    );
+    formats.set(
+      Self::ASTC,
+      features.contains(Features::TEXTURE_COMPRESSION_ASTC_LDR),
+    );
+    formats
+  }
+
+  /// The device features enabling these formats
+  pub fn features(self) -> Features {
+    let mut features = Features::empty();
+    features.set(Features::TEXTURE_COMPRESSION_BC, self.contains(Self::BC));
+    features.set(
+      Features::TEXTURE_COMPRESSION_ETC2,
+      self.contains(Self::ETC2),
+    );
+    features.set(
+      Features::TEXTURE_COMPRESSION_ASTC_LDR,
+      self.contains(Self::ASTC),
+    );
+    features
+  }
+
+  /// Whether `format` can be uploaded. Always true for uncompressed formats
+  pub fn supports(self, format: TextureFormat) -> bool {
+    self
+      .features()
+      .contains(format.describe().required_features)
+  }
+}
+
+///
+/// Texture data for every mip level, in a format which is uploaded as is
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipChain {
+  pub format: TextureFormat,
+  pub width: u32,
+  pub height: u32,
+  /// tightly packed rows of blocks of each level, from the base level down
+  pub levels: Vec<Vec<u8>>,
+}
+
+impl MipChain {
+  pub fn level_size(&self, level: u32) -> (u32, u32) {
+    ((self.width >> level).max(1), (self.height >> level).max(1))
+  }
+}
+
+#[derive(Debug)]
+pub enum DecodedTexture {
+  /// uploaded with its mip levels as is
+  MipChain(MipChain),
+  /// decompressed to RGBA8, and uploaded with generated mip levels
+  Image(DynamicImage),
+}
+
+/// Whether `bytes` are a KTX2 or DDS file
+pub fn is_compressed_container(bytes: &[u8]) -> bool {
+  bytes.starts_with(&KTX2_IDENTIFIER) || bytes.starts_with(&DDS_MAGIC)
+}
+
+///
+/// Decodes a KTX2 or DDS file into the best format in `supported`
+pub fn decode_compressed(
+  bytes: &[u8],
+  supported: CompressedFormats,
+) -> Result<DecodedTexture, TextureError> {
+  if bytes.starts_with(&KTX2_IDENTIFIER) {
+    decode_ktx2(bytes, supported)
+  } else if bytes.starts_with(&DDS_MAGIC) {
+    decode_dds(bytes, supported)
+  } else {
+    Err(TextureError::Other("not a KTX2 or DDS file".to_string()))
+  }
+}
+
+/// Size in bytes of one mip level, including the padding of partial blocks
+pub fn level_byte_size(format: TextureFormat, width: u32, height: u32) -> usize {
+  let info = format.describe();
+  let (block_width, block_height) = info.block_dimensions;
+  let blocks_x = (width + block_width as u32 - 1) / block_width as u32;
+  let blocks_y = (height + block_height as u32 - 1) / block_height as u32;
+  blocks_x as usize * blocks_y as usize * info.block_size as usize
+}
+
+/// Keeps `chain` if the device supports its format, otherwise decompresses its base level
+fn supported_or_decompressed(
+  chain: MipChain,
+  supported: CompressedFormats,
+) -> Result<DecodedTexture, TextureError> {
+  if supported.supports(chain.format) {
+    return Ok(DecodedTexture::MipChain(chain));
+  }
+  let variant = match chain.format {
+    TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => DxtVariant::DXT1,
+    TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => DxtVariant::DXT3,
+    TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => DxtVariant::DXT5,
+    format => {
+      return Err(TextureError::Other(format!(
+        "{:?} is not supported by the device, and can not be decompressed",
+        format
+      )))
+    }
+  };
+  // the decoder works on whole blocks, so partial blocks are cropped afterwards
+  let padded_width = (chain.width + 3) / 4 * 4;
+  let padded_height = (chain.height + 3) / 4 * 4;
+  let decoder = image::codecs::dxt::DxtDecoder::new(
+    chain.levels[0].as_slice(),
+    padded_width,
+    padded_height,
+    variant,
+  )?;
+  let image = DynamicImage::from_decoder(decoder)?;
+  Ok(DecodedTexture::Image(image.crop_imm(
+    0,
+    0,
+    chain.width,
+    chain.height,
+  )))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, TextureError> {
+  bytes
+    .get(offset..offset + 4)
+    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    .ok_or_else(|| TextureError::Other(format!("file is truncated at byte {}", offset)))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, TextureError> {
+  bytes
+    .get(offset..offset + 8)
+    .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    .ok_or_else(|| TextureError::Other(format!("file is truncated at byte {}", offset)))
+}
+
+fn byte_range(bytes: &[u8], offset: u64, length: u64) -> Result<Range<usize>, TextureError> {
+  let start = offset as usize;
+  let end = start.checked_add(length as usize);
+  match end {
+    Some(end) if end <= bytes.len() => Ok(start..end),
+    _ => Err(TextureError::Other(format!(
+      "data at {}..{} is past the end of the file",
+      offset,
+      offset + length
+    ))),
+  }
+}
+
+// KTX2 supercompression schemes
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZSTANDARD: u32 = 2;
+
+// Khronos data format descriptor values
+const DF_MODEL_ETC1S: u8 = 163;
+const DF_MODEL_UASTC: u8 = 166;
+const DF_TRANSFER_SRGB: u8 = 2;
+const DF_CHANNEL_UASTC_RGBA: u8 = 3;
+const DF_CHANNEL_UASTC_RRRG: u8 = 5;
+
+/// The parts of a KTX2 file used to load 2D textures
+#[derive(Debug)]
+struct Ktx2<'a> {
+  vk_format: u32,
+  width: u32,
+  height: u32,
+  supercompression: u32,
+  /// color model, transfer function and first sample channel of the data format descriptor
+  color_model: u8,
+  transfer_function: u8,
+  channel: u8,
+  /// data of each level, from the base level down
+  levels: Vec<&'a [u8]>,
+  /// supercompression global data, which holds the codebooks of BasisLZ files
+  global_data: &'a [u8],
+}
+
+impl<'a> Ktx2<'a> {
+  fn parse(bytes: &'a [u8]) -> Result<Self, TextureError> {
+    if !bytes.starts_with(&KTX2_IDENTIFIER) {
+      return Err(TextureError::Other("not a KTX2 file".into()));
+    }
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let layer_count = read_u32(bytes, 32)?;
+    let face_count = read_u32(bytes, 36)?;
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+    if depth > 1 || layer_count > 1 || face_count != 1 || height == 0 {
+      return Err(TextureError::Other(
+        "only 2D KTX2 textures are supported, not arrays, cubemaps or 3D textures".into(),
+      ));
+    }
+
+    let dfd_offset = read_u32(bytes, 48)? as usize;
+    // skips the total size of the descriptor, and the header of its first block
+    let color_model = read_u32(bytes, dfd_offset + 12)?.to_le_bytes();
+    let channel = read_u32(bytes, dfd_offset + 28)?.to_le_bytes()[3] & 0x0f;
+    let global_data = byte_range(bytes, read_u64(bytes, 64)?, read_u64(bytes, 72)?)?;
+
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count as usize {
+      let index = 80 + level * 24;
+      let range = byte_range(bytes, read_u64(bytes, index)?, read_u64(bytes, index + 8)?)?;
+      levels.push(&bytes[range]);
+    }
+    Ok(Self {
+      vk_format,
+      width,
+      height,
+      supercompression,
+      color_model: color_model[0],
+      transfer_function: color_model[2],
+      channel,
+      levels,
+      global_data: &bytes[global_data],
+    })
+  }
+
+  fn is_srgb(&self) -> bool {
+    self.transfer_function == DF_TRANSFER_SRGB
+  }
+
+  fn uastc_has_alpha(&self) -> bool {
+    self.channel == DF_CHANNEL_UASTC_RGBA || self.channel == DF_CHANNEL_UASTC_RRRG
+  }
+
+  fn level_size(&self, level: usize) -> (u32, u32) {
+    ((self.width >> level).max(1), (self.height >> level).max(1))
+  }
+
+  /// Whether [decode_ktx2] handles the file's supercompression scheme and format
+  fn check_supported(&self) -> Result<(), TextureError> {
+    // a format of 0 means the data is described by the data format descriptor only
+    let is_basis = self.vk_format == 0;
+    match (self.supercompression, is_basis) {
+      (SUPERCOMPRESSION_BASIS_LZ, true) if self.color_model == DF_MODEL_ETC1S => {
+        if self.global_data.is_empty() {
+          return Err(TextureError::Other(
+            "BasisLZ KTX2 file has no supercompression global data".into(),
+          ));
+        }
+      }
+      (SUPERCOMPRESSION_BASIS_LZ, _) => {
+        return Err(TextureError::Other(
+          "BasisLZ supercompression is only supported for ETC1S data".into(),
+        ))
+      }
+      (SUPERCOMPRESSION_NONE, true) | (SUPERCOMPRESSION_ZSTANDARD, true)
+        if self.color_model == DF_MODEL_UASTC => {}
+      (SUPERCOMPRESSION_NONE, true) | (SUPERCOMPRESSION_ZSTANDARD, true) => {
+        return Err(TextureError::Other(format!(
+          "unsupported KTX2 color model {}",
+          self.color_model
+        )))
+      }
+      (SUPERCOMPRESSION_NONE, false) | (SUPERCOMPRESSION_ZSTANDARD, false) => {
+        if format_from_vk(self.vk_format).is_none() {
+          return Err(TextureError::Other(format!(
+            "unsupported KTX2 vkFormat {}",
+            self.vk_format
+          )));
+        }
+      }
+      (scheme, _) => {
+        return Err(TextureError::Other(format!(
+          "unsupported KTX2 supercompression scheme {}",
+          scheme
+        )))
+      }
+    }
+    if is_basis && cfg!(target_arch = "wasm32") {
+      return Err(TextureError::Other(
+        "Basis Universal transcoding is not available on wasm".into(),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Level data with its supercompression removed
+  fn decompressed_levels(&self) -> Result<Vec<Vec<u8>>, TextureError> {
+    self
+      .levels
+      .iter()
+      .map(|level| match self.supercompression {
+        SUPERCOMPRESSION_NONE => Ok(level.to_vec()),
+        SUPERCOMPRESSION_ZSTANDARD => {
+          let mut data = *level;
+          let mut decoder = ruzstd::StreamingDecoder::new(&mut data)
+            .map_err(|e| TextureError::Other(format!("invalid zstd level data: {}", e)))?;
+          let mut decompressed = Vec::new();
+          decoder
+            .read_to_end(&mut decompressed)
+            .map_err(|e| TextureError::Other(format!("invalid zstd level data: {}", e)))?;
+          Ok(decompressed)
+        }
+        scheme => Err(TextureError::Other(format!(
+          "unsupported KTX2 supercompression scheme {}",
+          scheme
+        ))),
+      })
+      .collect()
+  }
+}
+
+///
+/// The BasisLZ supercompression global data: the codebooks and Huffman tables
+/// shared by the ETC1S slices, and where each level's slices are
+#[derive(Debug)]
+struct BasisLzGlobalData<'a> {
+  endpoint_count: u16,
+  selector_count: u16,
+  endpoints: &'a [u8],
+  selectors: &'a [u8],
+  tables: &'a [u8],
+  /// color and alpha slices of each level, as ranges of the level's data.
+  /// Alpha slices are empty if the texture is opaque
+  slices: Vec<[Range<usize>; 2]>,
+}
+
+impl<'a> BasisLzGlobalData<'a> {
+  fn parse(ktx2: &Ktx2<'a>) -> Result<Self, TextureError> {
+    let data = ktx2.global_data;
+    let endpoint_count = read_u32(data, 0)?;
+    let endpoints_length = read_u32(data, 4)? as u64;
+    let selectors_length = read_u32(data, 8)? as u64;
+    let tables_length = read_u32(data, 12)? as u64;
+    let extended_length = read_u32(data, 16)? as u64;
+
+    // one image description per level, after the 20 byte header
+    let mut slices = Vec::with_capacity(ktx2.levels.len());
+    for (level, level_data) in ktx2.levels.iter().enumerate() {
+      let index = 20 + level * 20;
+      let rgb = byte_range(
+        level_data,
+        read_u32(data, index + 4)? as u64,
+        read_u32(data, index + 8)? as u64,
+      )?;
+      let alpha = byte_range(
+        level_data,
+        read_u32(data, index + 12)? as u64,
+        read_u32(data, index + 16)? as u64,
+      )?;
+      slices.push([rgb, alpha]);
+    }
+    let endpoints_offset = 20 + 20 * ktx2.levels.len() as u64;
+    let endpoints = byte_range(data, endpoints_offset, endpoints_length)?;
+    let selectors = byte_range(data, endpoints.end as u64, selectors_length)?;
+    let tables = byte_range(data, selectors.end as u64, tables_length)?;
+    byte_range(data, tables.end as u64, extended_length)?;
+    Ok(Self {
+      endpoint_count: endpoint_count as u16,
+      selector_count: (endpoint_count >> 16) as u16,
+      endpoints: &data[endpoints],
+      selectors: &data[selectors],
+      tables: &data[tables],
+      slices,
+    })
+  }
+
+  fn has_alpha(&self) -> bool {
+    self.slices.iter().any(|[_, alpha]| !alpha.is_empty())
+  }
+}
+
+// .basis file layout
+const BASIS_SIGNATURE: u16 = 0x4273;
+const BASIS_VERSION: u16 = 0x13;
+const BASIS_HEADER_SIZE: usize = 77;
+const BASIS_SLICE_DESC_SIZE: usize = 23;
+const BASIS_HEADER_FLAG_ETC1S: u16 = 1;
+const BASIS_HEADER_FLAG_HAS_ALPHA_SLICES: u16 = 4;
+const BASIS_HEADER_FLAG_SRGB: u16 = 16;
+const BASIS_SLICE_FLAG_HAS_ALPHA: u8 = 1;
+
+/// Appends the `size` low bytes of `value`, the packed little endian fields of .basis files
+fn push_uint(bytes: &mut Vec<u8>, value: u64, size: usize) {
+  bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+}
+
+///
+/// Repackages the ETC1S levels of a BasisLZ KTX2 file as a .basis file, since the transcoder
+/// only reads ETC1S data from those. The KTX2 global data has the same codebooks and tables,
+/// so the slices are copied as is, and the header describes a single image with a slice
+/// per level, followed by an alpha slice if the texture has alpha
+fn basis_file(ktx2: &Ktx2, global_data: &BasisLzGlobalData) -> Vec<u8> {
+  let has_alpha = global_data.has_alpha();
+  let slice_count = ktx2.levels.len() * if has_alpha { 2 } else { 1 };
+  let endpoints_offset = BASIS_HEADER_SIZE + slice_count * BASIS_SLICE_DESC_SIZE;
+  let selectors_offset = endpoints_offset + global_data.endpoints.len();
+  let tables_offset = selectors_offset + global_data.selectors.len();
+  let mut slices_offset = tables_offset + global_data.tables.len();
+  let slices_size: usize = ktx2.levels.iter().map(|level| level.len()).sum();
+
+  let mut flags = BASIS_HEADER_FLAG_ETC1S;
+  if has_alpha {
+    flags |= BASIS_HEADER_FLAG_HAS_ALPHA_SLICES;
+  }
+  if ktx2.is_srgb() {
+    flags |= BASIS_HEADER_FLAG_SRGB;
+  }
+  let mut bytes = Vec::with_capacity(slices_offset + slices_size);
+  push_uint(&mut bytes, BASIS_SIGNATURE as u64, 2);
+  push_uint(&mut bytes, BASIS_VERSION as u64, 2);
+  push_uint(&mut bytes, BASIS_HEADER_SIZE as u64, 2);
+  // the checksums are only read when validating a file's checksums
+  push_uint(&mut bytes, 0, 2);
+  push_uint(
+    &mut bytes,
+    (slices_offset + slices_size - BASIS_HEADER_SIZE) as u64,
+    4,
+  );
+  push_uint(&mut bytes, 0, 2);
+  push_uint(&mut bytes, slice_count as u64, 3);
+  // a single 2D image in ETC1S
+  push_uint(&mut bytes, 1, 3);
+  push_uint(&mut bytes, 0, 1);
+  push_uint(&mut bytes, flags as u64, 2);
+  push_uint(&mut bytes, 0, 1);
+  // video frame time, reserved and user data
+  for size in &[3, 4, 4, 4] {
+    push_uint(&mut bytes, 0, *size);
+  }
+  push_uint(&mut bytes, global_data.endpoint_count as u64, 2);
+  push_uint(&mut bytes, endpoints_offset as u64, 4);
+  push_uint(&mut bytes, global_data.endpoints.len() as u64, 3);
+  push_uint(&mut bytes, global_data.selector_count as u64, 2);
+  push_uint(&mut bytes, selectors_offset as u64, 4);
+  push_uint(&mut bytes, global_data.selectors.len() as u64, 3);
+  push_uint(&mut bytes, tables_offset as u64, 4);
+  push_uint(&mut bytes, global_data.tables.len() as u64, 4);
+  push_uint(&mut bytes, BASIS_HEADER_SIZE as u64, 4);
+  // no extended data
+  push_uint(&mut bytes, 0, 4);
+  push_uint(&mut bytes, 0, 4);
+  debug_assert_eq!(bytes.len(), BASIS_HEADER_SIZE);
+
+  let mut slices = Vec::with_capacity(slice_count);
+  for (level, (data, [rgb, alpha])) in ktx2.levels.iter().zip(&global_data.slices).enumerate() {
+    slices.push((level, 0, &data[rgb.clone()]));
+    if has_alpha {
+      slices.push((level, BASIS_SLICE_FLAG_HAS_ALPHA, &data[alpha.clone()]));
+    }
+  }
+  for (level, slice_flags, data) in &slices {
+    let (width, height) = ktx2.level_size(*level);
+    push_uint(&mut bytes, 0, 3);
+    push_uint(&mut bytes, *level as u64, 1);
+    push_uint(&mut bytes, *slice_flags as u64, 1);
+    push_uint(&mut bytes, width as u64, 2);
+    push_uint(&mut bytes, height as u64, 2);
+    push_uint(&mut bytes, ((width + 3) / 4) as u64, 2);
+    push_uint(&mut bytes, ((height + 3) / 4) as u64, 2);
+    push_uint(&mut bytes, slices_offset as u64, 4);
+    push_uint(&mut bytes, data.len() as u64, 4);
+    push_uint(&mut bytes, 0, 2);
+    slices_offset += data.len();
+  }
+  bytes.extend_from_slice(global_data.endpoints);
+  bytes.extend_from_slice(global_data.selectors);
+  bytes.extend_from_slice(global_data.tables);
+  for (_, _, data) in &slices {
+    bytes.extend_from_slice(data);
+  }
+  bytes
+}
+
+/// wgpu format of a KTX2 `vkFormat`
+fn format_from_vk(vk_format: u32) -> Option<TextureFormat> {
+  use TextureFormat::*;
+  Some(match vk_format {
+    37 => Rgba8Unorm,
+    43 => Rgba8UnormSrgb,
+    44 => Bgra8Unorm,
+    50 => Bgra8UnormSrgb,
+    97 => Rgba16Float,
+    109 => Rgba32Float,
+    131 | 133 => Bc1RgbaUnorm,
+    132 | 134 => Bc1RgbaUnormSrgb,
+    135 => Bc2RgbaUnorm,
+    136 => Bc2RgbaUnormSrgb,
+    137 => Bc3RgbaUnorm,
+    138 => Bc3RgbaUnormSrgb,
+    139 => Bc4RUnorm,
+    140 => Bc4RSnorm,
+    141 => Bc5RgUnorm,
+    142 => Bc5RgSnorm,
+    143 => Bc6hRgbUfloat,
+    144 => Bc6hRgbSfloat,
+    145 => Bc7RgbaUnorm,
+    146 => Bc7RgbaUnormSrgb,
+    147 => Etc2RgbUnorm,
+    148 => Etc2RgbUnormSrgb,
+    149 => Etc2RgbA1Unorm,
+    150 => Etc2RgbA1UnormSrgb,
+    153 => EacRUnorm,
+    154 => EacRSnorm,
+    155 => EacRgUnorm,
+    156 => EacRgSnorm,
+    157 => Astc4x4RgbaUnorm,
+    158 => Astc4x4RgbaUnormSrgb,
+    _ => return None,
+  })
+}
+
+///
+/// Checks that `bytes` are a KTX2 file [decode_ktx2] can decode, without decoding its levels.
+/// Files it can't decode should be replaced by another image before they are uploaded
+pub fn check_ktx2(bytes: &[u8]) -> Result<(), TextureError> {
+  Ktx2::parse(bytes)?.check_supported()
+}
+
+pub fn decode_ktx2(
+  bytes: &[u8],
+  supported: CompressedFormats,
+) -> Result<DecodedTexture, TextureError> {
+  let ktx2 = Ktx2::parse(bytes)?;
+  ktx2.check_supported()?;
+  if ktx2.supercompression == SUPERCOMPRESSION_BASIS_LZ {
+    let global_data = BasisLzGlobalData::parse(&ktx2)?;
+    let target = BasisTarget::select(supported, global_data.has_alpha());
+    let file = basis_file(&ktx2, &global_data);
+    return Ok(DecodedTexture::MipChain(MipChain {
+      format: target.format(ktx2.is_srgb()),
+      width: ktx2.width,
+      height: ktx2.height,
+      levels: transcode_etc1s(&file, ktx2.levels.len(), target)?,
+    }));
+  }
+  let levels = ktx2.decompressed_levels()?;
+  if ktx2.vk_format == 0 {
+    let target = BasisTarget::select(supported, ktx2.uastc_has_alpha());
+    return Ok(DecodedTexture::MipChain(MipChain {
+      format: target.format(ktx2.is_srgb()),
+      width: ktx2.width,
+      height: ktx2.height,
+      levels: transcode_uastc(&ktx2, &levels, target)?,
+    }));
+  }
+  let format = format_from_vk(ktx2.vk_format)
+    .ok_or_else(|| TextureError::Other(format!("unsupported KTX2 vkFormat {}", ktx2.vk_format)))?;
+  supported_or_decompressed(
+    MipChain {
+      format,
+      width: ktx2.width,
+      height: ktx2.height,
+      levels,
+    },
+    supported,
+  )
+}
+
+///
+/// Format Basis Universal data, UASTC or ETC1S, is transcoded to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BasisTarget {
+  Bc7,
+  Astc4x4,
+  /// ETC2 without alpha, since wgpu has no 8 bit alpha ETC2 format
+  Etc2Rgb,
+  /// uncompressed, when no compressed format is enabled
+  Rgba8,
+}
+
+impl BasisTarget {
+  pub fn select(supported: CompressedFormats, has_alpha: bool) -> Self {
+    if supported.contains(CompressedFormats::BC) {
+      Self::Bc7
+    } else if supported.contains(CompressedFormats::ASTC) {
+      Self::Astc4x4
+    } else if supported.contains(CompressedFormats::ETC2) && !has_alpha {
+      Self::Etc2Rgb
+    } else {
+      Self::Rgba8
+    }
+  }
+
+  pub fn format(self, srgb: bool) -> TextureFormat {
+    match (self, srgb) {
+      (Self::Bc7, false) => TextureFormat::Bc7RgbaUnorm,
+      (Self::Bc7, true) => TextureFormat::Bc7RgbaUnormSrgb,
+      (Self::Astc4x4, false) => TextureFormat::Astc4x4RgbaUnorm,
+      (Self::Astc4x4, true) => TextureFormat::Astc4x4RgbaUnormSrgb,
+      (Self::Etc2Rgb, false) => TextureFormat::Etc2RgbUnorm,
+      (Self::Etc2Rgb, true) => TextureFormat::Etc2RgbUnormSrgb,
+      (Self::Rgba8, false) => TextureFormat::Rgba8Unorm,
+      (Self::Rgba8, true) => TextureFormat::Rgba8UnormSrgb,
+    }
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Transcodes each level of UASTC data to `target`
+fn transcode_uastc(
+  ktx2: &Ktx2,
+  levels: &[Vec<u8>],
+  target: BasisTarget,
+) -> Result<Vec<Vec<u8>>, TextureError> {
+  use basis_universal::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+  };
+  let block_format = match target {
+    BasisTarget::Bc7 => TranscoderBlockFormat::BC7,
+    BasisTarget::Astc4x4 => TranscoderBlockFormat::ASTC_4x4,
+    BasisTarget::Etc2Rgb => TranscoderBlockFormat::ETC1,
+    BasisTarget::Rgba8 => TranscoderBlockFormat::RGBA32,
+  };
+  let mut transcoded_levels = Vec::with_capacity(levels.len());
+  let transcoder = LowLevelUastcTranscoder::new();
+  for (level, data) in levels.iter().enumerate() {
+    let (width, height) = ktx2.level_size(level);
+    let transcoded = transcoder
+      .transcode_slice(
+        data,
+        SliceParametersUastc {
+          num_blocks_x: (width + 3) / 4,
+          num_blocks_y: (height + 3) / 4,
+          has_alpha: ktx2.uastc_has_alpha(),
+          original_width: width,
+          original_height: height,
+        },
+        DecodeFlags::HIGH_QUALITY,
+        block_format,
+      )
+      .map_err(|e| {
+        TextureError::Other(format!(
+          "could not transcode UASTC level {} to {:?}: {:?}",
+          level, block_format, e
+        ))
+      })?;
+    transcoded_levels.push(transcoded);
+  }
+  Ok(transcoded_levels)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Transcodes each level of a .basis file made by [basis_file] to `target`
+fn transcode_etc1s(
+  file: &[u8],
+  level_count: usize,
+  target: BasisTarget,
+) -> Result<Vec<Vec<u8>>, TextureError> {
+  use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+  let format = match target {
+    BasisTarget::Bc7 => TranscoderTextureFormat::BC7_RGBA,
+    BasisTarget::Astc4x4 => TranscoderTextureFormat::ASTC_4x4_RGBA,
+    BasisTarget::Etc2Rgb => TranscoderTextureFormat::ETC1_RGB,
+    BasisTarget::Rgba8 => TranscoderTextureFormat::RGBA32,
+  };
+  let mut transcoder = Transcoder::new();
+  // decodes the codebooks and Huffman tables shared by every level
+  transcoder
+    .prepare_transcoding(file)
+    .map_err(|_| TextureError::Other("invalid BasisLZ codebooks or tables".into()))?;
+  (0..level_count as u32)
+    .map(|level| {
+      transcoder
+        .transcode_image_level(
+          file,
+          format,
+          TranscodeParameters {
+            image_index: 0,
+            level_index: level,
+            ..Default::default()
+          },
+        )
+        .map_err(|e| {
+          TextureError::Other(format!(
+            "could not transcode ETC1S level {} to {:?}: {:?}",
+            level, format, e
+          ))
+        })
+    })
+    .collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn transcode_uastc(
+  _ktx2: &Ktx2,
+  _levels: &[Vec<u8>],
+  _target: BasisTarget,
+) -> Result<Vec<Vec<u8>>, TextureError> {
+  Err(TextureError::Other(
+    "UASTC transcoding is not available on wasm".into(),
+  ))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn transcode_etc1s(
+  _file: &[u8],
+  _level_count: usize,
+  _target: BasisTarget,
+) -> Result<Vec<Vec<u8>>, TextureError> {
+  Err(TextureError::Other(
+    "ETC1S transcoding is not available on wasm".into(),
+  ))
+}
+
+// DDS header flags
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DDS_HEADER_END: usize = 128;
+const DDS_DX10_HEADER_END: usize = DDS_HEADER_END + 20;
+
+/// wgpu format of a DXGI_FORMAT in a DX10 DDS header
+fn format_from_dxgi(dxgi_format: u32) -> Option<TextureFormat> {
+  use TextureFormat::*;
+  Some(match dxgi_format {
+    2 => Rgba32Float,
+    10 => Rgba16Float,
+    28 => Rgba8Unorm,
+    29 => Rgba8UnormSrgb,
+    71 => Bc1RgbaUnorm,
+    72 => Bc1RgbaUnormSrgb,
+    74 => Bc2RgbaUnorm,
+    75 => Bc2RgbaUnormSrgb,
+    77 => Bc3RgbaUnorm,
+    78 => Bc3RgbaUnormSrgb,
+    80 => Bc4RUnorm,
+    81 => Bc4RSnorm,
+    83 => Bc5RgUnorm,
+    84 => Bc5RgSnorm,
+    87 => Bgra8Unorm,
+    91 => Bgra8UnormSrgb,
+    95 => Bc6hRgbUfloat,
+    96 => Bc6hRgbSfloat,
+    98 => Bc7RgbaUnorm,
+    99 => Bc7RgbaUnormSrgb,
+    _ => return None,
+  })
+}
+
+///
+/// wgpu format of a legacy DDS pixel format, which doesn't store a color space.
+/// Color formats are returned as sRGB, and converted to how the texture is used on upload
+fn format_from_legacy_dds(bytes: &[u8]) -> Result<Option<TextureFormat>, TextureError> {
+  use TextureFormat::*;
+  let flags = read_u32(bytes, 80)?;
+  if flags & DDPF_FOURCC != 0 {
+    let four_cc = read_u32(bytes, 84)?.to_le_bytes();
+    return Ok(match &four_cc {
+      b"DXT1" => Some(Bc1RgbaUnormSrgb),
+      b"DXT2" | b"DXT3" => Some(Bc2RgbaUnormSrgb),
+      b"DXT4" | b"DXT5" => Some(Bc3RgbaUnormSrgb),
+      b"ATI1" | b"BC4U" => Some(Bc4RUnorm),
+      b"BC4S" => Some(Bc4RSnorm),
+      b"ATI2" | b"BC5U" => Some(Bc5RgUnorm),
+      b"BC5S" => Some(Bc5RgSnorm),
+      _ => None,
+    });
+  }
+  let bit_count = read_u32(bytes, 88)?;
+  let red_mask = read_u32(bytes, 92)?;
+  if flags & DDPF_RGB != 0 && bit_count == 32 {
+    return Ok(match red_mask {
+      0x0000_00ff => Some(Rgba8UnormSrgb),
+      0x00ff_0000 => Some(Bgra8UnormSrgb),
+      _ => None,
+    });
+  }
+  Ok(None)
+}
+
+pub fn decode_dds(
+  bytes: &[u8],
+  supported: CompressedFormats,
+) -> Result<DecodedTexture, TextureError> {
+  if !bytes.starts_with(&DDS_MAGIC) {
+    return Err(TextureError::Other("not a DDS file".into()));
+  }
+  let height = read_u32(bytes, 12)?;
+  let width = read_u32(bytes, 16)?;
+  let level_count = read_u32(bytes, 28)?.max(1);
+  let caps2 = read_u32(bytes, 112)?;
+  if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+    return Err(TextureError::Other(
+      "only 2D DDS textures are supported, not cubemaps or volumes".into(),
+    ));
+  }
+  let is_dx10 =
+    read_u32(bytes, 80)? & DDPF_FOURCC != 0 && read_u32(bytes, 84)?.to_le_bytes() == *b"DX10";
+  let (format, data_start) = if is_dx10 {
+    let dxgi_format = read_u32(bytes, DDS_HEADER_END)?;
+    if read_u32(bytes, DDS_HEADER_END + 12)? > 1 {
+      return Err(TextureError::Other(
+        "DDS texture arrays are not supported".into(),
+      ));
+    }
+    let format = format_from_dxgi(dxgi_format)
+      .ok_or_else(|| TextureError::Other(format!("unsupported DDS DXGI format {}", dxgi_format)))?;
+    (format, DDS_DX10_HEADER_END)
+  } else {
+    let format = format_from_legacy_dds(bytes)?
+      .ok_or_else(|| TextureError::Other("unsupported DDS pixel format".into()))?;
+    (format, DDS_HEADER_END)
+  };
+
+  let mut chain = MipChain {
+    format,
+    width,
+    height,
+    levels: Vec::with_capacity(level_count as usize),
+  };
+  let mut offset = data_start as u64;
+  for level in 0..level_count {
+    let (level_width, level_height) = chain.level_size(level);
+    let size = level_byte_size(format, level_width, level_height) as u64;
+    let range = byte_range(bytes, offset, size)?;
+    chain.levels.push(bytes[range].to_vec());
+    offset += size;
+  }
+  supported_or_decompressed(chain, supported)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use super::*;
+
+  /// A KTX2 file with one level per entry of `levels`
+  pub(crate) fn ktx2_file(vk_format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+    let level_index_end = 80 + 24 * levels.len();
+    let dfd_length = 44;
+    let mut bytes = KTX2_IDENTIFIER.to_vec();
+    for value in &[vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+      bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(level_index_end as u32).to_le_bytes());
+    bytes.extend_from_slice(&(dfd_length as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0; 24]);
+    let mut offset = (level_index_end + dfd_length) as u64;
+    for level in levels {
+      bytes.extend_from_slice(&offset.to_le_bytes());
+      bytes.extend_from_slice(&(level.len() as u64).to_le_bytes());
+      bytes.extend_from_slice(&(level.len() as u64).to_le_bytes());
+      offset += level.len() as u64;
+    }
+    // total size, block header, then an sRGB RGBSDA descriptor
+    bytes.extend_from_slice(&(dfd_length as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0, 0, 0, 0, 2, 0, 40, 0]);
+    bytes.extend_from_slice(&[1, 1, DF_TRANSFER_SRGB, 0]);
+    bytes.extend_from_slice(&[0; 28]);
+    for level in levels {
+      bytes.extend_from_slice(level);
+    }
+    bytes
+  }
+
+  /// A legacy DDS file, with the pixel format given by a four character code
+  fn dds_file(four_cc: &[u8; 4], width: u32, height: u32, data: &[u8], levels: u32) -> Vec<u8> {
+    let mut bytes = vec![0; DDS_HEADER_END];
+    bytes[..4].copy_from_slice(&DDS_MAGIC);
+    bytes[4..8].copy_from_slice(&124u32.to_le_bytes());
+    bytes[12..16].copy_from_slice(&height.to_le_bytes());
+    bytes[16..20].copy_from_slice(&width.to_le_bytes());
+    bytes[28..32].copy_from_slice(&levels.to_le_bytes());
+    bytes[80..84].copy_from_slice(&DDPF_FOURCC.to_le_bytes());
+    bytes[84..88].copy_from_slice(four_cc);
+    bytes.extend_from_slice(data);
+    bytes
+  }
+
+  #[test]
+  fn test_compressed_formats_from_features() {
+    let formats =
+      CompressedFormats::from_features(Features::TEXTURE_COMPRESSION_BC | Features::DEPTH_CLAMPING);
+    assert_eq!(formats, CompressedFormats::BC);
+    assert_eq!(formats.features(), Features::TEXTURE_COMPRESSION_BC);
+    assert!(formats.supports(TextureFormat::Bc7RgbaUnormSrgb));
+    assert!(formats.supports(TextureFormat::Rgba8UnormSrgb));
+    assert!(!formats.supports(TextureFormat::Astc4x4RgbaUnorm));
+  }
+
+  #[test]
+  fn test_level_byte_size_pads_partial_blocks() {
+    assert_eq!(level_byte_size(TextureFormat::Bc1RgbaUnorm, 8, 8), 4 * 8);
+    assert_eq!(level_byte_size(TextureFormat::Bc7RgbaUnorm, 2, 1), 16);
+    assert_eq!(level_byte_size(TextureFormat::Bc7RgbaUnorm, 5, 4), 2 * 16);
+    assert_eq!(level_byte_size(TextureFormat::Rgba8Unorm, 3, 2), 24);
+  }
+
+  #[test]
+  fn test_decode_ktx2_keeps_supported_format() {
+    let levels = vec![vec![1; 4 * 16], vec![2; 16], vec![3; 16]];
+    let file = ktx2_file(146, 8, 8, &levels);
+    match decode_compressed(&file, CompressedFormats::BC).unwrap() {
+      DecodedTexture::MipChain(chain) => {
+        assert_eq!(chain.format, TextureFormat::Bc7RgbaUnormSrgb);
+        assert_eq!((chain.width, chain.height), (8, 8));
+        assert_eq!(chain.levels, levels);
+        assert_eq!(chain.level_size(2), (2, 2));
+      }
+      DecodedTexture::Image(_) => panic!("expected the BC7 data to be kept"),
+    }
+    // BC7 can not be decompressed on the CPU
+    assert!(decode_compressed(&file, CompressedFormats::ASTC).is_err());
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  /// A .basis file with the ETC1S data and mip levels of `image`
+  fn etc1s_basis_file(image: &image::RgbaImage) -> Vec<u8> {
+    use basis_universal::{Compressor, CompressorParams};
+    let mut params = CompressorParams::new();
+    params.set_generate_mipmaps(true);
+    params
+      .source_image_mut(0)
+      .init(image.as_raw(), image.width(), image.height(), 4);
+    let mut compressor = Compressor::default();
+    unsafe {
+      assert!(compressor.init(&params));
+      compressor.process().expect("could not encode ETC1S");
+    }
+    compressor.basis_file().to_vec()
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  /// A BasisLZ KTX2 file with the slices and codebooks of a single image .basis file
+  fn ktx2_from_basis(basis: &[u8]) -> Vec<u8> {
+    let field = |offset: usize, size: usize| {
+      let mut value = [0; 8];
+      value[..size].copy_from_slice(&basis[offset..offset + size]);
+      u64::from_le_bytes(value) as usize
+    };
+    let slice_descs = field(65, 4);
+    let slice = |index: usize| {
+      let desc = slice_descs + index * BASIS_SLICE_DESC_SIZE;
+      let offset = field(desc + 13, 4);
+      &basis[offset..offset + field(desc + 17, 4)]
+    };
+    let has_alpha = field(21, 2) & BASIS_HEADER_FLAG_HAS_ALPHA_SLICES as usize != 0;
+    let slices_per_level = if has_alpha { 2 } else { 1 };
+
+    let mut levels = Vec::new();
+    let mut image_descs = Vec::new();
+    for level in 0..field(14, 3) / slices_per_level {
+      let rgb = slice(level * slices_per_level);
+      let alpha = if has_alpha { slice(level * 2 + 1) } else { &[] };
+      for value in &[0, 0, rgb.len(), rgb.len(), alpha.len()] {
+        image_descs.extend_from_slice(&(*value as u32).to_le_bytes());
+      }
+      levels.push([rgb, alpha].concat());
+    }
+    let codebooks = [(41, 45, 3), (50, 54, 3), (57, 61, 4)];
+    let mut global_data = ((field(39, 2) | field(48, 2) << 16) as u32)
+      .to_le_bytes()
+      .to_vec();
+    for (_, size, size_bytes) in &codebooks {
+      global_data.extend_from_slice(&(field(*size, *size_bytes) as u32).to_le_bytes());
+    }
+    global_data.extend_from_slice(&0u32.to_le_bytes());
+    global_data.extend_from_slice(&image_descs);
+    for (offset, size, size_bytes) in &codebooks {
+      let offset = field(*offset, 4);
+      global_data.extend_from_slice(&basis[offset..offset + field(*size, *size_bytes)]);
+    }
+
+    let width = field(slice_descs + 5, 2) as u32;
+    let height = field(slice_descs + 7, 2) as u32;
+    let mut file = ktx2_file(0, width, height, &levels);
+    file[44..48].copy_from_slice(&SUPERCOMPRESSION_BASIS_LZ.to_le_bytes());
+    let dfd_offset = read_u32(&file, 48).unwrap() as usize;
+    file[dfd_offset + 12] = DF_MODEL_ETC1S;
+    let global_data_offset = file.len() as u64;
+    file[64..72].copy_from_slice(&global_data_offset.to_le_bytes());
+    file[72..80].copy_from_slice(&(global_data.len() as u64).to_le_bytes());
+    file.extend_from_slice(&global_data);
+    file
+  }
+
+  #[test]
+  fn test_check_ktx2_basis_lz() {
+    let mut file = ktx2_file(146, 4, 4, &[vec![0; 16]]);
+    assert!(check_ktx2(&file).is_ok());
+    // BasisLZ only holds ETC1S data
+    file[44..48].copy_from_slice(&SUPERCOMPRESSION_BASIS_LZ.to_le_bytes());
+    assert!(check_ktx2(&file).is_err());
+    assert!(decode_compressed(&file, CompressedFormats::all()).is_err());
+    // and needs the codebooks in its global data
+    let mut file = ktx2_file(0, 4, 4, &[vec![0; 16]]);
+    file[44..48].copy_from_slice(&SUPERCOMPRESSION_BASIS_LZ.to_le_bytes());
+    let dfd_offset = read_u32(&file, 48).unwrap() as usize;
+    file[dfd_offset + 12] = DF_MODEL_ETC1S;
+    assert!(check_ktx2(&file).is_err());
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  #[test]
+  fn test_decode_etc1s_ktx2() {
+    let image = image::RgbaImage::from_fn(16, 16, |x, y| {
+      image::Rgba([x as u8 * 16, y as u8 * 16, 128, 255 - x as u8 * 8])
+    });
+    let file = ktx2_from_basis(&etc1s_basis_file(&image));
+    assert!(check_ktx2(&file).is_ok());
+
+    match decode_compressed(&file, CompressedFormats::empty()).unwrap() {
+      DecodedTexture::MipChain(chain) => {
+        assert_eq!(chain.format, TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(chain.levels.len(), 5);
+        assert_eq!(chain.levels[0].len(), 16 * 16 * 4);
+        assert_eq!(chain.levels[4].len(), 4);
+        // ETC1S is lossy, but keeps the gradients and the alpha slice
+        let max_delta = chain.levels[0]
+          .iter()
+          .zip(image.as_raw())
+          .map(|(a, b)| (*a as i16 - *b as i16).abs())
+          .max()
+          .unwrap();
+        assert!(max_delta < 48, "max channel delta {}", max_delta);
+      }
+      DecodedTexture::Image(_) => panic!("expected ETC1S to be transcoded"),
+    }
+    match decode_compressed(&file, CompressedFormats::BC).unwrap() {
+      DecodedTexture::MipChain(chain) => {
+        assert_eq!(chain.format, TextureFormat::Bc7RgbaUnormSrgb);
+        for (level, data) in chain.levels.iter().enumerate() {
+          let (width, height) = chain.level_size(level as u32);
+          assert_eq!(data.len(), level_byte_size(chain.format, width, height));
+        }
+      }
+      DecodedTexture::Image(_) => panic!("expected ETC1S to be transcoded"),
+    }
+  }
+
+  #[test]
+  fn test_check_ktx2_rejects_unknown_formats() {
+    // a descriptor only format needs the UASTC color model
+    assert!(check_ktx2(&ktx2_file(0, 4, 4, &[vec![0; 16]])).is_err());
+    assert!(check_ktx2(&ktx2_file(1000, 4, 4, &[vec![0; 16]])).is_err());
+  }
+
+  #[test]
+  fn test_decode_dds_decompresses_unsupported_bc1() {
+    // one opaque white DXT1 block: both endpoints white, all indices 0
+    let block = [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+    let file = dds_file(b"DXT1", 4, 4, &block, 1);
+
+    match decode_compressed(&file, CompressedFormats::BC).unwrap() {
+      DecodedTexture::MipChain(chain) => {
+        assert_eq!(chain.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(chain.levels, vec![block.to_vec()]);
+      }
+      DecodedTexture::Image(_) => panic!("expected the BC1 data to be kept"),
+    }
+    match decode_compressed(&file, CompressedFormats::empty()).unwrap() {
+      DecodedTexture::Image(image) => {
+        let rgba = image.to_rgba8();
+        assert_eq!(rgba.dimensions(), (4, 4));
+        assert!(rgba.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+      }
+      DecodedTexture::MipChain(_) => panic!("expected BC1 to be decompressed"),
+    }
+  }
+
+  #[test]
+  fn test_decode_dds_rejects_truncated_levels() {
+    let file = dds_file(b"DXT5", 8, 8, &[0; 16], 1);
+    assert!(decode_compressed(&file, CompressedFormats::BC).is_err());
+  }
+
+  #[test]
+  fn test_basis_target() {
+    let all = CompressedFormats::all();
+    assert_eq!(BasisTarget::select(all, true), BasisTarget::Bc7);
+    assert_eq!(
+      BasisTarget::select(CompressedFormats::ETC2 | CompressedFormats::ASTC, true),
+      BasisTarget::Astc4x4
+    );
+    assert_eq!(
+      BasisTarget::select(CompressedFormats::ETC2, false),
+      BasisTarget::Etc2Rgb
+    );
+    assert_eq!(
+      BasisTarget::select(CompressedFormats::ETC2, true),
+      BasisTarget::Rgba8
+    );
+    assert_eq!(
+      BasisTarget::Bc7.format(true),
+      TextureFormat::Bc7RgbaUnormSrgb
+    );
+  }
+}
@@ -1,7 +1,10 @@
 use std::{
+  borrow::Cow,
+  collections::HashSet,
   fmt,
   fmt::Formatter,
   num::NonZeroU64,
+  path::Path,
   sync::{Arc, RwLock},
 };
 
@@ -17,27 +20,48 @@ use wgpu::{
 use wgpu::Buffer;
 
 use crate::{
+  camera::Camera,
   error::Error,
   game::{
-    components::{LightSource, RenderModel, Transform3D},
-    resources::Scene,
+    components::{select_lod, LightSource, RenderModel},
+    debug_draw::DebugDraw,
+    resources::{PickRequest, PickResult, RenderSettings, Scene},
     GameState,
   },
+  nalgebra_glm::{distance2, Vec4},
   renderer_common::{
     allocator::ResourceManager,
     geometry::Vertex,
     handle::{Handle, HandleIndex},
-    render_context::DrawModel,
+    images::RawImageRbga,
     RenderContext,
   },
+  scene_graph::components::LocalToWorld,
   wgpu::{BindGroupLayout, Device, PipelineLayout, TextureFormat},
   wgpu_renderer::{
-    material::{Material, RenderMaterial, WgpuMaterial},
+    compressed_texture::CompressedFormats,
+    debug_lines::DebugLines,
+    environment::{bake_brdf_lut, create_environment_bind_group, EnvironmentMap},
+    gpu_culling::{plan_indirect_draws, supports_gpu_culling, GpuCulling},
+    instance_cache::InstanceCache,
+    material::{AlphaMode, Material, RenderMaterial, WgpuMaterial},
     model::{Model, StreamingMesh},
-    pipeline_state::{create_render_pipeline, RendererPipelines},
+    model_instance::{
+      append_instances_by_model, sort_back_to_front, sort_draws_back_to_front, InstanceRange,
+      LodGroup, ModelLod, SortedDraw,
+    },
+    picking::{Picking, PickingPass},
+    pipeline_state::{supported_sample_counts, RendererPipelines, ShadingModel},
+    post_process::PostProcess,
+    reflection::create_bind_group_layout,
+    render_graph::{RenderGraph, RenderNode, RenderNodeQueue, HDR_FORMAT, SHADOW_MAPS, SURFACE},
+    render_hooks::OnRenderUiClosure,
+    render_passes::default_render_graph,
+    render_target::RenderTarget,
     resource_view::ResourceContext,
-    textures::{BindTexture, TextureResource},
-    uniforms::{make_light_bind_group_layout, PointLightUniform},
+    shadows::{ShadowMaps, ShadowUniformBuilder},
+    textures::{BindTexture, ColorSpace, Sampler, SamplerCache, TextureResource},
+    uniforms::{LightArrayUniform, ShadowUniform, LIGHT_TYPE_POINT},
     ModelInstance,
   },
   window::AsWindow,
@@ -45,23 +69,55 @@ use crate::{
 
 use super::{mesh::Mesh, uniforms::Uniforms};
 use crate::wgpu_renderer::pipeline_state::ShaderInfo;
-use std::{
-  borrow::{Borrow, BorrowMut},
-  ops::Deref,
-};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::wgpu_renderer::{reflection::ShaderReflection, shader_reload::ShaderWatcher};
+use std::borrow::BorrowMut;
 
 pub struct Context {
   pub instance: wgpu::Instance,
-  pub surface: wgpu::Surface,
+  /// the window surface or offscreen texture rendered into
+  pub render_target: RenderTarget,
   pub adapter: wgpu::Adapter,
   pub device: wgpu::Device,
   pub queue: wgpu::Queue,
   pub pipeline_layout: wgpu::PipelineLayout,
 
-  pipelines: RendererPipelines,
+  pub(crate) pipelines: RendererPipelines,
+  /// passes run by `render`, in the order of the resources they share
+  render_graph: RenderGraph,
+  /// MSAA sample counts the adapter can render the surface and depth buffer with
+  supported_sample_counts: Vec<u32>,
+  /// None if the adapter can't cull on the GPU
+  gpu_culling: Option<GpuCulling>,
+  /// whether the opaque pass draws the instances culled by `gpu_culling`
+  gpu_culling_enabled: bool,
+  /// the entities drawn with GPU culling, packed into the instance buffer when they change
+  instance_cache: InstanceCache,
+  /// the PBR instances culled on the GPU, whose level of detail is picked by the culling pass
+  lod_groups: Vec<LodGroup>,
+  /// the blended meshes of each of the `lod_groups`, sorted on the CPU every frame
+  blended_meshes: Vec<Vec<Handle<Mesh>>>,
+  /// allocations of the models, meshes and materials `gpu_culling` was planned with,
+  /// or None if the instances changed since
+  planned_resources: Option<[(u32, usize); 3]>,
+  /// renders and reads back picks requested by the game
+  pub(crate) picking: Picking,
+  /// whether PickRequests are rendered
+  picking_enabled: bool,
+  /// lines of the game's DebugDraw resource, drawn by the debug draw node
+  pub(crate) debug_lines: DebugLines,
+  pub(crate) clear_color: wgpu::Color,
 
   // scene resources
-  pub models_to_draw: Vec<Handle<StreamingMesh>>,
+  /// per-model instance ranges, gathered from the world each frame,
+  /// or when entities change with GPU culling
+  models_to_draw: Vec<InstanceRange>,
+  /// instance ranges of models with the DebugLight shading model
+  pub(crate) debug_lights_to_draw: Vec<InstanceRange>,
+  /// blended meshes, drawn after the opaque ones
+  pub(crate) transparent_draws: Vec<SortedDraw>,
+  /// instance ranges drawn into the shadow maps, after the main instances
+  pub(crate) shadow_casters_to_draw: Vec<InstanceRange>,
   uniforms: Uniforms,
   uniform_buffer: wgpu::Buffer,
 
@@ -70,24 +126,39 @@ pub struct Context {
   pub main_tex_handle: Option<Handle<TextureResource>>,
   pub(crate) fallback_texture: Handle<TextureResource>,
   pub texture_bind_group_layout: BindGroupLayout,
+  pub material_bind_group_layout: BindGroupLayout,
   pub diffuse_bind_group: BindGroup,
   pub uniform_bind_group_layout: BindGroupLayout,
   pub light_bind_group_layout: BindGroupLayout,
   pub light_bind_group: BindGroup,
   pub light_uniform_buffer: Buffer,
+  pub(crate) shadow_maps: ShadowMaps,
+  /// the shadow uniform written for the current frame
+  pub(crate) shadow_uniform: ShadowUniform,
+  /// pipelines of the bloom, tonemapping and FXAA nodes
+  pub(crate) post_process: PostProcess,
+
+  // image based lighting
+  pub environment_bind_group_layout: BindGroupLayout,
+  pub(crate) environment_bind_group: BindGroup,
+  brdf_lut: TextureResource,
+  /// flat ambient light, used when no environment is set
+  default_environment: EnvironmentMap,
+  pub(crate) environment: Option<EnvironmentMap>,
 
   pub(crate) default_material: Handle<WgpuMaterial>,
 
-  uniform_bind_group: wgpu::BindGroup,
+  pub(crate) uniform_bind_group: wgpu::BindGroup,
 
   /// Buffer storing instance state for render
-  instance_buffer: wgpu::Buffer,
+  pub(crate) instance_buffer: wgpu::Buffer,
   n_instances: usize,
-  instance_buffer_view: Vec<u8>,
+  /// the contents of `instance_buffer`
+  instances: Vec<ModelInstance>,
 
-  // Depth/stencil buffers
-  depth_stencil_texture: TextureResource,
-  pub surface_config: wgpu::SurfaceConfiguration,
+  /// recompiles shaders when their sources change, once `watch_shaders` is called
+  #[cfg(not(target_arch = "wasm32"))]
+  shader_watcher: Option<ShaderWatcher>,
 }
 
 impl fmt::Debug for Context {
@@ -104,17 +175,29 @@ impl Context {
       window,
       instance: None,
       backends: None,
+      sample_count: 1,
     }
   }
 
-  pub fn on_resize(&mut self, size: (u32, u32)) {
-    let (width, height) = size;
-    self.surface_config.width = width;
-    self.surface_config.height = height;
-    self.surface.configure(&self.device, &self.surface_config);
+  ///
+  /// Creates a builder for a context without a window, which renders into an
+  /// offscreen texture of the given size and color format
+  pub fn headless(size: (u32, u32), format: TextureFormat) -> HeadlessBuilder {
+    HeadlessBuilder {
+      size,
+      format,
+      instance: None,
+      backends: None,
+      power_preference: wgpu::PowerPreference::default(),
+      software_adapter: false,
+      sample_count: 1,
+    }
+  }
 
-    self.depth_stencil_texture =
-      TextureResource::new_depth_stencil_texture(&self.device, size, "depth_stencil_texture");
+  pub fn on_resize(&mut self, size: (u32, u32)) {
+    // the render graph's depth and multisampled color textures follow the
+    // render target's size, and are reallocated when the next frame is rendered
+    self.render_target.resize(&self.device, size);
     // self.swapchain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
     //
     // self
@@ -123,14 +206,183 @@ impl Context {
     //   .unwrap();
   }
 
-  pub fn update(&mut self) {}
+  pub fn update(&mut self) {
+    #[cfg(not(target_arch = "wasm32"))]
+    self.reload_changed_shaders();
+  }
 
-  pub fn render(&mut self, game: &mut GameState) -> Result<(), anyhow::Error> {
-    self.update_instance_state(game);
-    let pbr_pipeline = match self.pipelines.pbr_model_pipeline.as_ref() {
-      None => return Ok(()),
-      Some(x) => x,
+  ///
+  /// Recompiles shaders whose GLSL sources in `dir` change, checked on each `update`.
+  /// `shader_reload::SHADER_SOURCE_DIR` is the crate's own shader directory.
+  ///
+  /// Only the shaders of `RendererPipelines::shader_sources` are watched,
+  /// the debug light, main and skybox shaders. The others, like the post processing,
+  /// shadow and picking shaders, still need a rebuild to change
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn watch_shaders<P: AsRef<Path>>(&mut self, dir: P) -> anyhow::Result<()> {
+    let mut watcher = ShaderWatcher::default();
+    for (source, handle) in self.pipelines.shader_sources() {
+      watcher.watch(dir.as_ref().join(source), handle)?;
+    }
+    self.shader_watcher = Some(watcher);
+    Ok(())
+  }
+
+  ///
+  /// Replaces the modules of changed shaders, and rebuilds the pipelines using them.
+  /// If the new shaders are rejected, the previous modules and pipelines are kept
+  #[cfg(not(target_arch = "wasm32"))]
+  fn reload_changed_shaders(&mut self) {
+    let compiled = match self.shader_watcher.as_mut() {
+      Some(watcher) => watcher.compile_changed(),
+      None => return,
+    };
+    if compiled.is_empty() {
+      return;
+    }
+    let sources = self.pipelines.shader_sources();
+    let reflections: Result<Vec<_>, _> = compiled
+      .iter()
+      .map(|(handle, words)| {
+        let label = sources
+          .iter()
+          .find(|(_, source)| source == handle)
+          .map_or("reloaded shader", |(name, _)| *name);
+        ShaderReflection::from_spirv(label, words, "main").map(|reflection| (*handle, reflection))
+      })
+      .collect();
+    let reflections = match reflections {
+      Ok(reflections) => reflections,
+      Err(e) => {
+        log::error!("could not reflect reloaded shaders: {}", e);
+        return;
+      }
     };
+    self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let modules: Vec<_> = compiled
+      .iter()
+      .map(|(handle, words)| {
+        let module = self
+          .device
+          .create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::SpirV(Cow::Borrowed(words.as_slice())),
+          });
+        (*handle, module)
+      })
+      .collect();
+    if let Some(error) = self.pop_validation_error() {
+      log::error!("reloaded shaders are invalid: {}", error);
+      return;
+    }
+
+    let previous = self.swap_shader_modules(modules);
+    let previous_reflections = self.pipelines.swap_reflections(reflections);
+    match self.try_rebuild_render_pipeline() {
+      Ok(()) => log::info!("reloaded {} shaders", previous.len()),
+      Err(e) => {
+        log::error!("could not rebuild pipelines with reloaded shaders: {:?}", e);
+        self.swap_shader_modules(previous);
+        self.pipelines.swap_reflections(previous_reflections);
+      }
+    }
+  }
+
+  /// Stores each module under its handle, and returns the modules they replaced
+  #[cfg(not(target_arch = "wasm32"))]
+  fn swap_shader_modules(
+    &self,
+    modules: Vec<(Handle<wgpu::ShaderModule>, wgpu::ShaderModule)>,
+  ) -> Vec<(Handle<wgpu::ShaderModule>, wgpu::ShaderModule)> {
+    let mut shaders = self.resources.shaders.write().unwrap();
+    modules
+      .into_iter()
+      .filter_map(|(handle, module)| {
+        let current = shaders.try_mut_ref(handle).ok()?;
+        Some((handle, std::mem::replace(current, module)))
+      })
+      .collect()
+  }
+
+  /// The environment used for image based lighting and the skybox, if one is set
+  pub fn environment(&self) -> Option<&EnvironmentMap> {
+    self.environment.as_ref()
+  }
+
+  ///
+  /// Sets the environment used for image based lighting, and drawn by the skybox.
+  /// With None, ambient light is a flat color and no skybox is drawn
+  pub fn set_environment(&mut self, environment: Option<EnvironmentMap>) {
+    self.environment = environment;
+    self.environment_bind_group = create_environment_bind_group(
+      &self.device,
+      &self.environment_bind_group_layout,
+      self
+        .environment
+        .as_ref()
+        .unwrap_or(&self.default_environment),
+      &self.brdf_lut,
+    );
+  }
+
+  /// Loads an equirectangular .hdr image, and sets it as the environment
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn load_environment_hdr<P: AsRef<std::path::Path>>(&mut self, path: P) -> anyhow::Result<()> {
+    let image = super::environment::HdrImage::open(path)?;
+    let environment = EnvironmentMap::from_hdr(&self.device, &self.queue, &image)?;
+    self.set_environment(Some(environment));
+    Ok(())
+  }
+
+  /// Models drawn in the last call to `render`, along with their
+  /// range in the instance buffer
+  #[inline]
+  pub fn models_to_draw(&self) -> &[InstanceRange] {
+    &self.models_to_draw
+  }
+
+  /// The passes run each frame. Starts with the nodes of `default_render_graph`
+  pub fn render_graph(&self) -> &RenderGraph {
+    &self.render_graph
+  }
+
+  pub fn render_graph_mut(&mut self) -> &mut RenderGraph {
+    &mut self.render_graph
+  }
+
+  pub fn render(&mut self, game: &mut GameState) -> Result<(), anyhow::Error> {
+    self.render_frame(game, None)
+  }
+
+  ///
+  /// Renders a frame with `ui_hook` drawn over it by the ui node.
+  /// The hook is dropped without running if the frame isn't rendered,
+  /// e.g. because there is no camera
+  pub fn render_with_ui(
+    &mut self,
+    game: &mut GameState,
+    ui_hook: OnRenderUiClosure,
+  ) -> Result<(), anyhow::Error> {
+    self.render_frame(game, Some(ui_hook))
+  }
+
+  fn render_frame(
+    &mut self,
+    game: &mut GameState,
+    ui_hook: Option<OnRenderUiClosure>,
+  ) -> Result<(), anyhow::Error> {
+    if let Some(mut queue) = game.resources_mut().get_mut::<RenderNodeQueue>() {
+      for node in queue.0.drain(..) {
+        self.render_graph.add_boxed_node(node)?;
+      }
+    }
+    self.apply_render_settings(game);
+    if let Some(result) = self.picking.poll(&self.device, false) {
+      game.resources_mut().insert(result);
+    }
+    if self.gpu_culling_enabled {
+      self.update_culled_instances(game.world_mut());
+    }
     let camera = game
       .resources()
       .get::<Scene>()
@@ -144,132 +396,367 @@ impl Context {
       }
       Some(camera) => camera,
     };
+    if self.gpu_culling_enabled {
+      self.prepare_gpu_culling(camera);
+      self.sort_culled_transparent_draws(camera);
+    } else {
+      self.update_instance_state(game, camera);
+    }
+    self.prepare_pipelines()?;
+    self.prepare_picking(game, camera);
+    let sample_count = self.sample_count();
+    self.debug_lines.prepare(
+      &self.device,
+      &self.queue,
+      game.resources().get::<DebugDraw>().as_deref(),
+      sample_count,
+    );
     self.uniforms.update_from_camera(camera);
     self.queue.write_buffer(
       &self.uniform_buffer,
       0,
       bytemuck::cast_slice(&[self.uniforms]),
     );
+    self.shadow_uniform = self.bind_light_sources(game, camera);
 
-    let frame = self
-      .surface
-      .get_current_frame()
-      .map_err(|e| anyhow!("swapchain error {:?}", e))?
-      .output;
+    let frame = self.render_target.acquire_frame()?;
 
     let mut encoder = self
       .device
       .create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("Render Encoder"),
       });
-    let mesh_allocator = self.resources.meshes.read().unwrap();
-    let model_allocator = self.resources.models.read().unwrap();
-    let material_allocator = self.resources.materials.read().unwrap();
+    // the graph is taken out of the context so its nodes can borrow the context
+    let mut graph = std::mem::take(&mut self.render_graph);
+    let result = graph.execute(
+      self,
+      game,
+      &[
+        (SURFACE, &frame.view),
+        (SHADOW_MAPS, self.shadow_maps.maps().view()),
+      ],
+      ui_hook,
+      &mut encoder,
+    );
+    self.render_graph = graph;
+    result?;
+    self.queue.submit(std::iter::once(encoder.finish()));
+    self.picking.on_submitted();
+    Ok(())
+  }
 
-    {
-      let frame_view = frame
-        .texture
-        .create_view(&wgpu::TextureViewDescriptor::default());
-      let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-        label: Some("render pass"),
-        color_attachments: &[wgpu::RenderPassColorAttachment {
-          view: &frame_view,
-          resolve_target: None,
-          ops: wgpu::Operations {
-            load: wgpu::LoadOp::Clear(wgpu::Color {
-              r: 0.1,
-              g: 0.2,
-              b: 0.3,
-              a: 1.0,
-            }),
-            store: true,
-          },
-        }],
-        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-          view: self.depth_stencil_texture.view(),
-          depth_ops: Some(wgpu::Operations {
-            load: wgpu::LoadOp::Clear(1.0),
-            store: true,
-          }),
-          stencil_ops: None,
-        }),
-      });
+  /// MSAA samples per pixel of the scene passes
+  pub fn sample_count(&self) -> u32 {
+    self.pipelines.sample_count()
+  }
 
-      render_pass.set_pipeline(pbr_pipeline);
+  pub fn supported_sample_counts(&self) -> &[u32] {
+    &self.supported_sample_counts
+  }
 
-      render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+  ///
+  /// Rebuilds the pipelines for a new MSAA sample count. The render graph creates
+  /// attachments with the new count on the next frame. If the device rejects any
+  /// of the pipelines, the previous ones and their sample count are kept
+  pub fn set_sample_count(&mut self, sample_count: u32) -> anyhow::Result<()> {
+    if !self.supported_sample_counts.contains(&sample_count) {
+      return Err(anyhow!(
+        "sample count {} is not supported, expected one of {:?}",
+        sample_count,
+        self.supported_sample_counts
+      ));
+    }
+    if sample_count == self.sample_count() {
+      return Ok(());
+    }
+    let shaders = self.resources.shaders.read().unwrap();
+    self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let previous = self
+      .pipelines
+      .replace_sample_count(&self.device, &*shaders, sample_count);
+    let error = self.pop_validation_error();
+    match (previous, error) {
+      (Ok(_), None) => Ok(()),
+      (Ok(previous), Some(error)) => {
+        self.pipelines.restore_sample_count(previous);
+        Err(anyhow!("{}", error))
+      }
+      (Err(e), _) => Err(e),
+    }
+  }
 
-      for m in &self.models_to_draw {
-        let model = match model_allocator.try_get_ref(m.into_typed()) {
-          Ok(e) => e,
-          Err(_) => continue,
-        };
+  /// Whether the adapter supports culling instances on the GPU
+  pub fn gpu_culling_supported(&self) -> bool {
+    self.gpu_culling.is_some()
+  }
 
-        for mesh_handle in model.primitives() {
-          let mesh = match mesh_handle.read(mesh_allocator.deref()) {
-            Some(m) => m,
-            None => {
-              log::info!("mesh {:?} not found", mesh_handle);
-              continue;
-            }
-          };
-          mesh
-            .material()
-            .and_then(|handle| match material_allocator.try_get_ref(handle) {
-              Ok(material) => Some(material),
-              Err(e) => {
-                log::warn!("could not access material: {:?}", e);
-                None
-              }
-            })
-            .map(|material| {
-              let material_bg = match &material.bind_group {
-                None => panic!("material does not have bind group attached"),
-                Some(bg) => bg,
-              };
-
-              render_pass.draw_mesh_instanced(
-                mesh,
-                material_bg,
-                &self.uniform_bind_group,
-                0..(self.n_instances as u32),
-              );
-            });
-        }
-      }
+  pub fn gpu_culling_enabled(&self) -> bool {
+    self.gpu_culling_enabled
+  }
+
+  ///
+  /// Switches between culling instances on the CPU, and culling them in a compute
+  /// pass whose output is drawn indirectly. Fails if the adapter can't cull on the GPU,
+  /// in which case the CPU path is kept
+  pub fn set_gpu_culling(&mut self, enabled: bool) -> anyhow::Result<()> {
+    if enabled && self.gpu_culling.is_none() {
+      return Err(anyhow!(
+        "the adapter does not support compute shaders and indirect draws"
+      ));
     }
-    self.queue.submit(std::iter::once(encoder.finish()));
+    self.gpu_culling_enabled = enabled;
+    // the CPU path packs its own instances, so the cache starts over once culling is back on the GPU
+    self.instance_cache = InstanceCache::default();
+    self.planned_resources = None;
     Ok(())
   }
 
+  /// The culling pass drawn by the opaque pass this frame, if GPU culling is enabled
+  pub(crate) fn enabled_gpu_culling(&self) -> Option<&GpuCulling> {
+    self
+      .gpu_culling
+      .as_ref()
+      .filter(|_| self.gpu_culling_enabled)
+  }
+
+  pub fn picking_enabled(&self) -> bool {
+    self.picking_enabled
+  }
+
+  ///
+  /// Renders the entity ids under requested PickRequest pixels, published as PickResults.
+  /// While disabled, requests are left in the game's resources
+  pub fn set_picking(&mut self, enabled: bool) {
+    self.picking_enabled = enabled;
+  }
+
+  ///
+  /// Blocks until the pick in flight has been read back, and returns it.
+  /// `render` publishes results as a PickResult resource without blocking
+  pub fn wait_for_pick(&mut self) -> Option<PickResult> {
+    self.picking.poll(&self.device, true)
+  }
+
+  ///
+  /// Takes the game's PickRequest, and adds the picking node to render it this frame.
+  /// The node is removed again on frames without a pick, so its targets aren't kept.
+  /// Requests made while another pick is being read back wait for a later frame
+  fn prepare_picking(&mut self, game: &GameState, camera: &Camera) {
+    self.picking.cancel_frame();
+    self.render_graph.remove_node(PickingPass.name());
+    if !self.picking_enabled || !self.picking.is_idle() {
+      return;
+    }
+    let pixel = match game.resources().get_mut::<PickRequest>() {
+      Some(mut request) => request.pixel.take(),
+      None => None,
+    };
+    let pixel = match pixel {
+      Some(pixel) => pixel,
+      None => return,
+    };
+    let models = self.resources.models.read().unwrap();
+    let prepared = self.picking.prepare(
+      &self.device,
+      &self.queue,
+      game.world(),
+      &models,
+      camera,
+      pixel,
+      self.render_target.size(),
+    );
+    if !prepared {
+      log::warn!("pick at {:?} is outside of the render target", pixel);
+      return;
+    }
+    if let Err(e) = self.render_graph.add_node(PickingPass) {
+      log::error!("could not add the picking node: {:?}", e);
+      self.picking.cancel_frame();
+    }
+  }
+
+  ///
+  /// Applies changes to the game's RenderSettings, inserting them if the game doesn't have any.
+  /// Settings which can't be applied are reset to the current ones
+  fn apply_render_settings(&mut self, game: &mut GameState) {
+    let resources = game.resources_mut();
+    if !resources.contains::<RenderSettings>() {
+      resources.insert(RenderSettings {
+        sample_count: self.sample_count(),
+        supported_sample_counts: self.supported_sample_counts.clone(),
+        gpu_culling: self.gpu_culling_enabled,
+        gpu_culling_supported: self.gpu_culling_supported(),
+        picking: self.picking_enabled,
+      });
+      return;
+    }
+    let mut settings = resources.get_mut::<RenderSettings>().unwrap();
+    if settings.supported_sample_counts != self.supported_sample_counts {
+      settings.supported_sample_counts = self.supported_sample_counts.clone();
+    }
+    settings.gpu_culling_supported = self.gpu_culling_supported();
+    if settings.gpu_culling != self.gpu_culling_enabled {
+      if let Err(e) = self.set_gpu_culling(settings.gpu_culling) {
+        log::error!("could not change gpu culling: {:?}", e);
+        settings.gpu_culling = self.gpu_culling_enabled;
+      }
+    }
+    if settings.picking != self.picking_enabled {
+      self.set_picking(settings.picking);
+    }
+    if settings.sample_count != self.sample_count() {
+      if let Err(e) = self.set_sample_count(settings.sample_count) {
+        log::error!(
+          "could not use {} samples per pixel: {:?}",
+          settings.sample_count,
+          e
+        );
+        settings.sample_count = self.sample_count();
+      }
+    }
+  }
+
   pub fn rebuild_render_pipeline(&mut self) {
+    self
+      .try_rebuild_render_pipeline()
+      .unwrap_or_else(|e| log::error!("could not rebuild pipelines {:?}", e));
+  }
+
+  ///
+  /// Rebuilds the pipelines with the current shader modules.
+  /// If the device rejects any of them, the previous pipelines are kept
+  fn try_rebuild_render_pipeline(&mut self) -> anyhow::Result<()> {
     let shaders = self.resources.shaders.read().unwrap();
-    if let Some(format) = self.surface.get_preferred_format(&self.adapter) {
-      self.pipelines.color_target = format.clone().into();
-      self
-        .pipelines
-        .build_pipelines(&self.device, &*shaders)
-        .unwrap_or_else(|e| log::error!("could not rebuild pipelines {:?}", e));
+    self.pipelines.color_target = HDR_FORMAT.into();
+    self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let previous = self.pipelines.replace_pipelines(&self.device, &*shaders);
+    let error = self.pop_validation_error();
+    match (previous, error) {
+      (Ok(_), None) => Ok(()),
+      (Ok(previous), Some(error)) => {
+        self.pipelines.restore_pipelines(previous);
+        Err(anyhow!("{}", error))
+      }
+      (Err(e), _) => Err(e),
     }
   }
 
-  /// get instance data from game state
-  fn update_instance_state(&mut self, game: &GameState) {
+  #[cfg(not(target_arch = "wasm32"))]
+  fn pop_validation_error(&self) -> Option<wgpu::Error> {
+    pollster::block_on(self.device.pop_error_scope())
+  }
+
+  /// errors are reported asynchronously by the browser, and not checked
+  #[cfg(target_arch = "wasm32")]
+  fn pop_validation_error(&self) -> Option<wgpu::Error> {
+    let _ = self.device.pop_error_scope();
+    None
+  }
+
+  ///
+  /// Copies the last rendered frame back to the CPU.
+  /// Only available for headless contexts
+  pub async fn read_back_rgba(&self) -> anyhow::Result<RawImageRbga> {
+    self
+      .render_target
+      .read_back_rgba(&self.device, &self.queue)
+      .await
+  }
+
+  /// get instance data from game state.
+  /// Instances outside of the camera's frustum are only kept as shadow casters,
+  /// which get their own instances, placed after the shown models'.
+  /// Each instance is drawn at the level of detail its model picks for its size on screen.
+  /// Meshes with blended materials are sorted back to front from the camera
+  fn update_instance_state(&mut self, game: &GameState, camera: &Camera) {
     use legion::*;
-    let mut query = <(&Transform3D, &RenderModel)>::query();
-    let mut instances: Vec<ModelInstance> = Vec::with_capacity(10);
-    for item in query.iter(game.world()) {
-      let (xform, model): (&Transform3D, &RenderModel) = item;
-      if model.is_shown {
-        instances.push(xform.into());
+    let eye = &camera.position;
+    let frustum = camera.frustum();
+    let mut query = <(&LocalToWorld, &RenderModel)>::query();
+    let mut pbr: Vec<(ModelLod, ModelInstance)> = Vec::with_capacity(10);
+    let mut debug_lights: Vec<(ModelLod, ModelInstance)> = Vec::new();
+    let mut casters: Vec<(ModelLod, ModelInstance)> = Vec::new();
+    {
+      let models = self.resources.models.read().unwrap();
+      for item in query.iter(game.world()) {
+        let (local_to_world, model): (&LocalToWorld, &RenderModel) = item;
+        match model.model {
+          Some(handle) if model.is_shown => {
+            let instance = ModelInstance::from(local_to_world);
+            // models without bounds aren't loaded yet, and are kept to be safe
+            let bounds = models
+              .try_get_ref(handle)
+              .ok()
+              .and_then(|streaming_mesh| streaming_mesh.bounds())
+              .map(|bounds| bounds.transformed(&local_to_world.0));
+            let is_visible = bounds
+              .as_ref()
+              .map_or(true, |bounds| frustum.intersects_bounds(bounds));
+            // shadows are cast with the shown level, so they match the drawn surface
+            let key = ModelLod {
+              model: handle,
+              lod: bounds.map_or(0, |bounds| {
+                model.select_lod(camera.screen_size(&bounds.sphere))
+              }),
+            };
+            match model.shading_model {
+              ShadingModel::Pbr if is_visible => {
+                pbr.push((key, instance.with_receives_shadows(model.receives_shadows)))
+              }
+              ShadingModel::DebugLight if is_visible => debug_lights.push((key, instance)),
+              _ => {}
+            }
+            if model.casts_shadows {
+              casters.push((key, instance));
+            }
+          }
+          _ => {}
+        }
       }
     }
-    let binding = self.instance_buffer.as_entire_buffer_binding();
-    let buffer_data: &[u8] = bytemuck::cast_slice(&instances);
-    if self.instance_buffer_view == buffer_data {
+    let mut instances = Vec::new();
+    self.models_to_draw = append_instances_by_model(&mut instances, pbr);
+    self.debug_lights_to_draw = append_instances_by_model(&mut instances, debug_lights);
+    self.shadow_casters_to_draw = append_instances_by_model(&mut instances, casters);
+    self.transparent_draws = {
+      let models = self.resources.models.read().unwrap();
+      let meshes = self.resources.meshes.read().unwrap();
+      let materials = self.resources.materials.read().unwrap();
+      sort_back_to_front(&instances, &self.models_to_draw, eye, |model| {
+        blended_meshes(&models, &meshes, &materials, model)
+      })
+    };
+    self.upload_instances(instances);
+  }
+
+  ///
+  /// Packs the instances culled on the GPU again if any of the world's entities changed,
+  /// and has the culling pass planned again
+  fn update_culled_instances(&mut self, world: &mut legion::World) {
+    let packed = match self.instance_cache.update(world) {
+      Some(packed) => packed,
+      None => return,
+    };
+    self.models_to_draw = packed
+      .models
+      .iter()
+      .map(|group| group.range.clone())
+      .collect();
+    self.lod_groups = packed.models;
+    self.debug_lights_to_draw = packed.debug_lights;
+    self.shadow_casters_to_draw = packed.shadow_casters;
+    self.upload_instances(packed.instances);
+    self.planned_resources = None;
+  }
+
+  /// Writes `instances` to the instance buffer, unless they're already there
+  fn upload_instances(&mut self, instances: Vec<ModelInstance>) {
+    if self.instances == instances {
       // if instances haven't changed, don't update buffers
       return;
     }
+    let binding = self.instance_buffer.as_entire_buffer_binding();
+    let buffer_data: &[u8] = bytemuck::cast_slice(&instances);
 
     let old_buffer_size: usize = binding
       .size
@@ -279,7 +766,7 @@ impl Context {
       let new_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
         label: Some("Instance Buffer"),
         contents: buffer_data,
-        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        usage: instance_buffer_usage(self.gpu_culling.is_some()),
       });
       self.instance_buffer = new_buffer;
     } else {
@@ -288,75 +775,159 @@ impl Context {
         .write_buffer(&self.instance_buffer, 0_u64, buffer_data);
     }
 
-    self
-      .queue
-      .write_buffer(&self.instance_buffer, 0, &bytemuck::cast_slice(&instances));
     self.n_instances = instances.len();
-    self.instance_buffer_view = buffer_data.to_vec();
+    self.instances = instances;
   }
 
-  fn bind_light_sources(&mut self, game_state: &GameState) {
-    use legion::*;
-    let mut query = <(&LightSource, &Transform3D)>::query();
-    query.for_each(game_state.world(), |(_a, _b)| {});
+  ///
+  /// Plans the indirect draws of the opaque meshes, and uploads them for the culling pass,
+  /// if the instances, models, meshes or materials changed since they were planned.
+  /// Then uploads the camera to cull with
+  fn prepare_gpu_culling(&mut self, camera: &Camera) {
+    let culling = match self.gpu_culling.as_mut() {
+      Some(culling) => culling,
+      None => return,
+    };
+    let models = self.resources.models.read().unwrap();
+    let meshes = self.resources.meshes.read().unwrap();
+    let materials = self.resources.materials.read().unwrap();
+    let resources = [
+      allocations(&models),
+      allocations(&meshes),
+      allocations(&materials),
+    ];
+    if self.planned_resources != Some(resources) {
+      let plan = plan_indirect_draws(&self.lod_groups, |model, lod| {
+        let model = match models.try_get_ref(model) {
+          Ok(model) => model,
+          Err(_) => return (None, Vec::new()),
+        };
+        let opaque_meshes = model
+          .primitives()
+          .iter()
+          .filter_map(|handle| {
+            let mesh = handle.read(&*meshes)?;
+            let buffers = mesh.buffers()?;
+            let material = materials.try_get_ref(mesh.material()?).ok()?;
+            if material.alpha_mode == AlphaMode::Blend {
+              return None;
+            }
+            Some((*handle, buffers.lod_range(lod)))
+          })
+          .collect();
+        (model.bounds().map(|bounds| bounds.sphere), opaque_meshes)
+      });
+      culling.set_plan(&self.device, &self.queue, &self.instance_buffer, plan);
+      self.blended_meshes = self
+        .lod_groups
+        .iter()
+        .map(|group| blended_meshes(&models, &meshes, &materials, group.range.model))
+        .collect();
+      self.planned_resources = Some(resources);
+    }
+    culling.prepare(&self.queue, camera);
   }
-}
 
-pub struct Builder<'a, W: AsWindow + HasRawWindowHandle> {
-  window: &'a W,
-  instance: Option<wgpu::Instance>,
-  backends: Option<wgpu::Backends>,
-}
-
-impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
-  pub async fn build(self) -> Result<Context, Error> {
-    #[cfg(not(target_os = "linux"))]
-    let backends = self.backends.unwrap_or(wgpu::Backends::VULKAN);
-    #[cfg(target_os = "linux")]
-    let backends = self.backends.unwrap_or(wgpu::Backends::VULKAN);
-
-    log::info!("backend is {:?}", backends);
-
-    let instance = self
-      .instance
-      .unwrap_or_else(|| wgpu::Instance::new(backends));
-
-    let (width, height) = self.window.size();
-
-    let surface = unsafe { instance.create_surface(self.window) };
-
-    let mut resources = ResourceContext::default();
+  ///
+  /// Sorts the blended meshes of the instances culled on the GPU back to front,
+  /// each drawn at the level of detail its model picks for its size on screen
+  fn sort_culled_transparent_draws(&mut self, camera: &Camera) {
+    let models = self.resources.models.read().unwrap();
+    let mut draws = Vec::new();
+    for (group, meshes) in self.lod_groups.iter().zip(&self.blended_meshes) {
+      if meshes.is_empty() {
+        continue;
+      }
+      let bounds = models
+        .try_get_ref(group.range.model)
+        .ok()
+        .and_then(|model| model.bounds());
+      for instance in group.range.instances.clone() {
+        let data = match self.instances.get(instance as usize) {
+          Some(data) => data,
+          None => continue,
+        };
+        let lod = bounds.map_or(0, |bounds| {
+          let bounds = bounds.transformed(&data.model_matrix());
+          select_lod(&group.lod_thresholds, camera.screen_size(&bounds.sphere))
+        });
+        let distance2 = distance2(&camera.position, &data.position());
+        draws.extend(meshes.iter().map(|&mesh| SortedDraw {
+          mesh,
+          lod,
+          instance,
+          distance2,
+        }));
+      }
+    }
+    sort_draws_back_to_front(&mut draws);
+    self.transparent_draws = draws;
+  }
 
-    let adapter = instance
-      .request_adapter(&wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::default(),
-        compatible_surface: Some(&surface),
-      })
-      .await
-      .ok_or_else(|| Error::Create {
-        reason: "could not create adapter".into(),
-      })?;
+  ///
+  /// Builds the pipeline variants needed by the meshes drawn this frame
+  fn prepare_pipelines(&mut self) -> anyhow::Result<()> {
+    let shaders = self.resources.shaders.read().unwrap();
+    let models = self.resources.models.read().unwrap();
+    let meshes = self.resources.meshes.read().unwrap();
+    let materials = self.resources.materials.read().unwrap();
+    let mut keys = HashSet::new();
+    if !self.debug_lights_to_draw.is_empty() {
+      keys.insert(
+        self
+          .pipelines
+          .key(ShadingModel::DebugLight)
+          .with_cull_mode(None),
+      );
+    }
+    let pbr_key = self.pipelines.key(ShadingModel::Pbr);
+    for draw in &self.models_to_draw {
+      let model = match models.try_get_ref(draw.model) {
+        Ok(model) => model,
+        Err(_) => continue,
+      };
+      let model_materials = model
+        .primitives()
+        .iter()
+        .filter_map(|mesh| mesh.read(&*meshes))
+        .filter_map(|mesh| mesh.material())
+        .filter_map(|material| materials.try_get_ref(material).ok());
+      for material in model_materials {
+        keys.insert(pbr_key.with_material(material));
+      }
+    }
+    for key in &keys {
+      self
+        .pipelines
+        .prepare_variant(&self.device, &shaders, key)?;
+    }
+    Ok(())
+  }
 
-    let (device, queue) = adapter
-      .request_device(
-        &wgpu::DeviceDescriptor {
-          label: None,
-          features: wgpu::Features::empty(),
-          limits: wgpu::Limits::default(),
-        },
-        None,
-      )
-      .await
-      .map_err(|e| crate::Error::from_error(Box::new(e)))?;
-    let surface_config = wgpu::SurfaceConfiguration {
-      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-      format: surface.get_preferred_format(&adapter).unwrap(),
-      width,
-      height,
-      present_mode: wgpu::PresentMode::Immediate,
+  /// Creates the renderer's resources once a device and render target
+  /// have been set up by one of the builders
+  fn from_parts(
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    render_target: RenderTarget,
+    sample_count: u32,
+  ) -> Result<Self, Error> {
+    let supported_sample_counts = supported_sample_counts(&adapter, HDR_FORMAT);
+    if !supported_sample_counts.contains(&sample_count) {
+      return Err(Error::Create {
+        reason: format!(
+          "sample count {} is not supported, expected one of {:?}",
+          sample_count, supported_sample_counts
+        ),
+      });
+    }
+    let resources = ResourceContext {
+      samplers: Arc::new(RwLock::new(SamplerCache::for_adapter(&adapter))),
+      ..Default::default()
     };
-    surface.configure(&device, &surface_config);
-    /// uniform buffer setup
+    // uniform buffer setup
     let uniforms = Uniforms::default();
     // let camera = self.camera;
     // uniforms.update_from_camera(&camera);
@@ -367,19 +938,46 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
       usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
     });
 
-    let ubo_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-      label: Some("ubo_layout"),
-      entries: &[wgpu::BindGroupLayoutEntry {
-        binding: 0,
-        visibility: wgpu::ShaderStages::VERTEX,
-        ty: wgpu::BindingType::Buffer {
-          ty: wgpu::BufferBindingType::Uniform,
-          has_dynamic_offset: false,
-          min_binding_size: None,
-        },
-        count: None,
-      }],
-    });
+    // the bind group layouts are derived from the shaders that read them,
+    // so the shaders are loaded and reflected first
+    let (debug_light_shaders, pbr_model_shaders, skybox_shaders) = {
+      let mut shaders = resources.shaders.write().unwrap();
+      let debug_light_shaders = ShaderInfo::from_shader_descriptors(
+        &device,
+        shaders.borrow_mut(),
+        &include_shader!("debug_light.vert"),
+        &include_shader!("debug_light.frag"),
+      )?
+      .with_sources("debug_light.vert", "debug_light.frag");
+
+      let pbr_model_shaders = ShaderInfo::from_shader_descriptors(
+        &device,
+        shaders.borrow_mut(),
+        &include_shader!("main.vert"),
+        &include_shader!("main.frag"),
+      )?
+      .with_sources("main.vert", "main.frag");
+
+      let skybox_shaders = ShaderInfo::from_shader_descriptors(
+        &device,
+        shaders.borrow_mut(),
+        &include_shader!("skybox.vert"),
+        &include_shader!("skybox.frag"),
+      )?
+      .with_sources("skybox.vert", "skybox.frag");
+      (debug_light_shaders, pbr_model_shaders, skybox_shaders)
+    };
+
+    let ubo_layout = create_bind_group_layout(
+      &device,
+      "ubo_layout",
+      &[
+        pbr_model_shaders.group(0),
+        debug_light_shaders.group(0),
+        skybox_shaders.group(0),
+      ]
+      .concat(),
+    )?;
     let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
       layout: &ubo_layout,
       entries: &[wgpu::BindGroupEntry {
@@ -389,15 +987,30 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
       label: Some("ubo_bind_group"),
     });
     // default diffuse texture setup
-    let model_texture_bind_group_layout =
-      super::textures::create_texture_bind_group_layout(&device);
+    let model_texture_bind_group_layout = create_bind_group_layout(
+      &device,
+      "texture_bind_group_layout",
+      &debug_light_shaders.group(1),
+    )?;
+    let material_bind_group_layout = create_bind_group_layout(
+      &device,
+      "material_bind_group_layout",
+      &pbr_model_shaders.group(1),
+    )?;
     let (fallback_texture, diffuse_bind_group) = {
       let mut textures = resources.textures.write().unwrap();
       use super::textures::*;
       let img = image::load_from_memory(super::textures::DEFAULT_TEX_JPEG)
         .map_err(|e| Error::from_other(format!("{:?}", e)))?;
-      let tex_resource = TextureResource::from_image(&img, &queue, &device)
-        .map_err(|e| Error::from_other(format!("{:?}", e)))?;
+      let tex_resource = TextureResource::from_image(
+        &img,
+        ColorSpace::Srgb,
+        &Sampler::default(),
+        &queue,
+        &device,
+        &resources,
+      )
+      .map_err(|e| Error::from_other(format!("{:?}", e)))?;
       let bg = super::textures::basic_texture_bind_group(
         &tex_resource,
         &model_texture_bind_group_layout,
@@ -410,44 +1023,55 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
 
     let pipeline_layout =
       create_pipeline_layout(&device, &[&ubo_layout, &model_texture_bind_group_layout]);
-    let (w_width, w_height) = self.window.size();
-    let debug_light_vert_shader =
-      device.create_shader_module(&wgpu::include_spirv!("../shaders/debug_light.vert.spv"));
-    let debug_light_frag_shader =
-      device.create_shader_module(&wgpu::include_spirv!("../shaders/debug_light.frag.spv"));
-    let depth_stencil_texture =
-      TextureResource::new_depth_stencil_texture(&device, (w_width, w_height), "depth_stencil_tex");
-    let preferred_format = surface
-      .get_preferred_format(&adapter)
-      .ok_or_else(|| anyhow::anyhow!("could not get preferred texture format for surface"))?;
+    let debug_light_vert_shader = device.create_shader_module(&include_shader!("debug_light.vert"));
+    let debug_light_frag_shader = device.create_shader_module(&include_shader!("debug_light.frag"));
+    let shadow_maps = ShadowMaps::new(&device);
+    let picking = Picking::new(&device, &ubo_layout);
+    let debug_lines = DebugLines::new(&device, &ubo_layout, sample_count);
+    let post_process = PostProcess::new(&device, render_target.format());
+    let light_bind_group_layout = create_bind_group_layout(
+      &device,
+      "light_bind_group_layout",
+      &pbr_model_shaders.group(2),
+    )?;
+    let (light_uniform_buffer, light_bind_group) =
+      Self::create_light_bindings(&device, &light_bind_group_layout, &shadow_maps);
+    let brdf_lut = bake_brdf_lut(&device, &queue);
+    let default_environment = EnvironmentMap::uniform_color(&device, &queue, [0.03, 0.03, 0.03]);
+    let environment_bind_group_layout = create_bind_group_layout(
+      &device,
+      "environment_bind_group_layout",
+      &[pbr_model_shaders.group(3), skybox_shaders.group(1)].concat(),
+    )?;
+    let environment_bind_group = create_environment_bind_group(
+      &device,
+      &environment_bind_group_layout,
+      &default_environment,
+      &brdf_lut,
+    );
     // create render pipeline
 
     let pipelines = {
-      let mut shaders = resources.shaders.write().unwrap();
-      let debug_light_shaders = ShaderInfo::from_shader_descriptors(
-        &device,
-        shaders.borrow_mut(),
-        &wgpu::include_spirv!("../shaders/debug_light.vert.spv"),
-        &wgpu::include_spirv!("../shaders/debug_light.frag.spv"),
-      );
-
-      let pbr_model_shaders = ShaderInfo::from_shader_descriptors(
-        &device,
-        shaders.borrow_mut(),
-        &wgpu::include_spirv!("../shaders/main.vert.spv"),
-        &wgpu::include_spirv!("../shaders/main.frag.spv"),
-      );
-
+      let shaders = resources.shaders.read().unwrap();
       let mut pipelines = RendererPipelines::new(
         &device,
         &[&ubo_layout, &model_texture_bind_group_layout],
         debug_light_shaders,
-        &[&ubo_layout, &model_texture_bind_group_layout],
+        &[
+          &ubo_layout,
+          &material_bind_group_layout,
+          &light_bind_group_layout,
+          &environment_bind_group_layout,
+        ],
         pbr_model_shaders,
-        preferred_format.into(),
+        &[&ubo_layout, &environment_bind_group_layout],
+        skybox_shaders,
+        // the scene is drawn in HDR, and tonemapped into the render target
+        HDR_FORMAT.into(),
+        sample_count,
       );
 
-      pipelines.build_pipelines(&device, shaders.borrow())?;
+      pipelines.build_pipelines(&device, &shaders)?;
 
       pipelines
     };
@@ -456,12 +1080,18 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
 
     // create default mesh to draw
 
+    let gpu_culling = if supports_gpu_culling(&adapter, &device.limits()) {
+      Some(GpuCulling::new(&device))
+    } else {
+      log::info!("compute shaders or indirect draws are unsupported, culling on the cpu");
+      None
+    };
     let instance_buffer = {
       let instance_data: &[ModelInstance] = &[];
       device.create_buffer_init(&BufferInitDescriptor {
         label: Some("Instance Buffer"),
         contents: bytemuck::cast_slice(&instance_data),
-        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        usage: instance_buffer_usage(gpu_culling.is_some()),
       })
     };
     let default_material = {
@@ -470,7 +1100,8 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
         &Material::default(),
         &queue,
         &device,
-        &model_texture_bind_group_layout,
+        &material_bind_group_layout,
+        &resources,
         &mut textures,
         fallback_texture,
       )
@@ -480,41 +1111,66 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
       materials.insert(default_material)
     };
 
-    let (light_uniform_buffer, light_bind_group, light_bind_group_layout) =
-      Self::create_light_bindings(&device);
-
     let mut result = Context {
-      surface,
-      surface_config,
+      render_target,
       instance,
       adapter,
       device,
       queue,
       pipeline_layout,
       pipelines,
+      render_graph: default_render_graph(),
+      supported_sample_counts,
+      gpu_culling,
+      gpu_culling_enabled: false,
+      instance_cache: InstanceCache::default(),
+      lod_groups: Vec::new(),
+      blended_meshes: Vec::new(),
+      planned_resources: None,
+      picking,
+      picking_enabled: false,
+      debug_lines,
+      clear_color: wgpu::Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+      },
       models_to_draw: Vec::new(),
+      debug_lights_to_draw: Vec::new(),
+      transparent_draws: Vec::new(),
+      shadow_casters_to_draw: Vec::new(),
 
       uniforms,
       uniform_buffer,
       uniform_bind_group_layout: ubo_layout,
       uniform_bind_group,
       texture_bind_group_layout: model_texture_bind_group_layout,
+      material_bind_group_layout,
       diffuse_bind_group,
       resources,
       main_tex_handle: None,
       fallback_texture,
 
       instance_buffer,
-      instance_buffer_view: Vec::new(),
+      instances: Vec::new(),
+      #[cfg(not(target_arch = "wasm32"))]
+      shader_watcher: None,
 
       n_instances: 0,
 
-      depth_stencil_texture,
-
       default_material,
       light_uniform_buffer,
       light_bind_group,
       light_bind_group_layout,
+      shadow_maps,
+      shadow_uniform: ShadowUniform::default(),
+      post_process,
+      environment_bind_group_layout,
+      environment_bind_group,
+      brdf_lut,
+      default_environment,
+      environment: None,
     };
     result
       .bind_texture(*fallback_texture)
@@ -522,6 +1178,144 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
     Ok(result)
   }
 
+  fn create_light_bindings(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_maps: &ShadowMaps,
+  ) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let light_uniform = LightArrayUniform::default();
+
+    // lights are rewritten every frame, so we use COPY_DST
+    let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Light UBO"),
+      contents: bytemuck::cast_slice(&[light_uniform]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: None,
+      layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: light_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: shadow_maps.uniform_buffer().as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: wgpu::BindingResource::TextureView(shadow_maps.maps().view()),
+        },
+        wgpu::BindGroupEntry {
+          binding: 3,
+          resource: wgpu::BindingResource::Sampler(shadow_maps.maps().sampler()),
+        },
+      ],
+    });
+    (light_buffer, bind_group)
+  }
+
+  ///
+  /// Writes every LightSource in the world to the light uniform buffer,
+  /// and assigns shadow map layers to shadow casting lights.
+  /// Returns the shadow uniform, for rendering the shadow maps
+  fn bind_light_sources(&self, game_state: &GameState, camera: &Camera) -> ShadowUniform {
+    use legion::*;
+    let mut shadows = ShadowUniformBuilder::new(camera);
+    let mut query = <(&LightSource, &LocalToWorld)>::query();
+    let lights = query
+      .iter(game_state.world())
+      .map(|(light, local_to_world)| {
+        let mut uniform = light.as_uniform(local_to_world);
+        if light.casts_shadows
+          && uniform.light_type != LIGHT_TYPE_POINT
+          && !shadows.add_light(&mut uniform)
+        {
+          log::warn!("no shadow map layers left for light {:?}", light.light_type);
+        }
+        uniform
+      });
+    let (light_array, dropped) = LightArrayUniform::from_lights(lights);
+    if dropped > 0 {
+      log::warn!(
+        "{} lights exceed the maximum of {}, and were not drawn",
+        dropped,
+        light_array.count
+      );
+    }
+    self.queue.write_buffer(
+      &self.light_uniform_buffer,
+      0,
+      bytemuck::cast_slice(&[light_array]),
+    );
+    let shadows = shadows.build();
+    self.shadow_maps.write(&self.queue, &shadows);
+    shadows
+  }
+}
+
+pub struct Builder<'a, W: AsWindow + HasRawWindowHandle> {
+  window: &'a W,
+  instance: Option<wgpu::Instance>,
+  backends: Option<wgpu::Backends>,
+  sample_count: u32,
+}
+
+impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
+  pub async fn build(self) -> Result<Context, Error> {
+    #[cfg(not(target_os = "linux"))]
+    let backends = self.backends.unwrap_or(wgpu::Backends::VULKAN);
+    #[cfg(target_os = "linux")]
+    let backends = self.backends.unwrap_or(wgpu::Backends::VULKAN);
+
+    log::info!("backend is {:?}", backends);
+
+    let instance = self
+      .instance
+      .unwrap_or_else(|| wgpu::Instance::new(backends));
+
+    let (width, height) = self.window.size();
+
+    let surface = unsafe { instance.create_surface(self.window) };
+
+    let adapter = instance
+      .request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: Some(&surface),
+      })
+      .await
+      .ok_or_else(|| Error::Create {
+        reason: "could not create adapter".into(),
+      })?;
+
+    let (device, queue) = request_device(&adapter).await?;
+    let preferred_format = surface
+      .get_preferred_format(&adapter)
+      .ok_or_else(|| anyhow::anyhow!("could not get preferred texture format for surface"))?;
+    let surface_config = wgpu::SurfaceConfiguration {
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+      // shaders output linear color, which the surface encodes to sRGB
+      format: ColorSpace::Srgb.format(preferred_format),
+      width,
+      height,
+      present_mode: wgpu::PresentMode::Immediate,
+    };
+    surface.configure(&device, &surface_config);
+    let render_target = RenderTarget::Surface {
+      surface,
+      config: surface_config,
+    };
+    Context::from_parts(
+      instance,
+      adapter,
+      device,
+      queue,
+      render_target,
+      self.sample_count,
+    )
+  }
+
   pub fn with_instance(mut self, instance: wgpu::Instance) -> Self {
     self.instance = Some(instance);
     self
@@ -532,31 +1326,165 @@ impl<'a, W: AsWindow + HasRawWindowHandle> Builder<'a, W> {
     self
   }
 
-  fn create_light_bindings(
-    device: &wgpu::Device,
-  ) -> (wgpu::Buffer, wgpu::BindGroup, wgpu::BindGroupLayout) {
-    let light_uniform = PointLightUniform {
-      position: [2.0, 2.0, 2.0],
-      _padding: 0,
-      color: [1.0, 1.0, 1.0],
-    };
+  ///
+  /// MSAA samples per pixel. Building fails if the adapter
+  /// doesn't support the count, see `Context::supported_sample_counts`
+  pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+    self.sample_count = sample_count;
+    self
+  }
+}
 
-    // We'll want to update our lights position, so we use COPY_DST
-    let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Light VB"),
-      contents: bytemuck::cast_slice(&[light_uniform]),
-      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
-    let layout = make_light_bind_group_layout(device);
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-      label: None,
-      layout: &layout,
-      entries: &[wgpu::BindGroupEntry {
-        binding: 0,
-        resource: light_buffer.as_entire_binding(),
-      }],
-    });
-    (light_buffer, bind_group, layout)
+pub struct HeadlessBuilder {
+  size: (u32, u32),
+  format: TextureFormat,
+  instance: Option<wgpu::Instance>,
+  backends: Option<wgpu::Backends>,
+  power_preference: wgpu::PowerPreference,
+  software_adapter: bool,
+  sample_count: u32,
+}
+
+impl HeadlessBuilder {
+  pub async fn build(self) -> Result<Context, Error> {
+    let backends = self
+      .backends
+      .or_else(wgpu::util::backend_bits_from_env)
+      .unwrap_or(wgpu::Backends::PRIMARY);
+    log::info!("backend is {:?}", backends);
+
+    let instance = self
+      .instance
+      .unwrap_or_else(|| wgpu::Instance::new(backends));
+    let adapter = if self.software_adapter {
+      find_software_adapter(&instance, backends)
+    } else {
+      instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+          power_preference: self.power_preference,
+          compatible_surface: None,
+        })
+        .await
+    }
+    .ok_or_else(|| Error::Create {
+      reason: "could not create adapter".into(),
+    })?;
+    let (device, queue) = request_device(&adapter).await?;
+    let render_target = RenderTarget::new_texture(&device, self.size, self.format);
+    Context::from_parts(
+      instance,
+      adapter,
+      device,
+      queue,
+      render_target,
+      self.sample_count,
+    )
+  }
+
+  pub fn with_instance(mut self, instance: wgpu::Instance) -> Self {
+    self.instance = Some(instance);
+    self
+  }
+
+  pub fn with_backends(mut self, backends: Option<wgpu::Backends>) -> Self {
+    self.backends = backends;
+    self
+  }
+
+  pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+    self.power_preference = power_preference;
+    self
+  }
+
+  ///
+  /// If true, only use a software (CPU) adapter, such as llvmpipe/lavapipe or WARP.
+  /// Building fails if none is available. Useful for deterministic output on CI machines
+  pub fn with_software_adapter(mut self, software_adapter: bool) -> Self {
+    self.software_adapter = software_adapter;
+    self
+  }
+
+  ///
+  /// MSAA samples per pixel. Building fails if the adapter
+  /// doesn't support the count, see `Context::supported_sample_counts`
+  pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+    self.sample_count = sample_count;
+    self
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn find_software_adapter(
+  instance: &wgpu::Instance,
+  backends: wgpu::Backends,
+) -> Option<wgpu::Adapter> {
+  instance
+    .enumerate_adapters(backends)
+    .find(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn find_software_adapter(
+  _instance: &wgpu::Instance,
+  _backends: wgpu::Backends,
+) -> Option<wgpu::Adapter> {
+  None
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), Error> {
+  adapter
+    .request_device(
+      &wgpu::DeviceDescriptor {
+        label: None,
+        // compressed texture formats are used where available
+        features: adapter.features() & CompressedFormats::all().features(),
+        limits: wgpu::Limits::default(),
+      },
+      None,
+    )
+    .await
+    .map_err(|e| crate::Error::from_error(Box::new(e)))
+}
+
+/// The meshes of `model` with blended materials
+fn blended_meshes(
+  models: &ResourceManager<StreamingMesh>,
+  meshes: &ResourceManager<Mesh>,
+  materials: &ResourceManager<WgpuMaterial>,
+  model: Handle<StreamingMesh>,
+) -> Vec<Handle<Mesh>> {
+  models
+    .try_get_ref(model)
+    .map(|model| {
+      model
+        .primitives()
+        .iter()
+        .copied()
+        .filter(|mesh| {
+          mesh
+            .read(meshes)
+            .and_then(|mesh| mesh.material())
+            .and_then(|material| materials.try_get_ref(material).ok())
+            .map_or(false, |material| material.alpha_mode == AlphaMode::Blend)
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+///
+/// The allocator's generation and size, which change whenever
+/// a resource is added or removed
+fn allocations<T>(manager: &ResourceManager<T>) -> (u32, usize) {
+  (manager.generation_count(), manager.len())
+}
+
+/// The instance buffer is also read by the culling pass, when the adapter supports it
+fn instance_buffer_usage(gpu_culling: bool) -> BufferUsages {
+  if gpu_culling {
+    BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::STORAGE
+  } else {
+    BufferUsages::VERTEX | BufferUsages::COPY_DST
   }
 }
 
@@ -580,6 +1508,15 @@ pub fn create_bind_group(device: &Device) {
 }
 
 impl RenderContext for Context {
+  fn set_clear_color(&mut self, color: Vec4) {
+    self.clear_color = wgpu::Color {
+      r: color.x as f64,
+      g: color.y as f64,
+      b: color.z as f64,
+      a: color.w as f64,
+    };
+  }
+
   fn on_render(&mut self, game: &mut GameState) -> Result<(), Error> {
     self.render(game).map_err(crate::Error::Render)
   }
@@ -0,0 +1,278 @@
+//! GPU batching of the lines in the game's [DebugDraw] resource.
+//!
+//! Every line is written into a single vertex buffer each frame, with the depth tested
+//! lines first and the overlay lines after them. Each half is drawn as a line list,
+//! with its own pipeline, by the debug draw node.
+use std::ops::Range;
+
+use wgpu::{
+  util::DeviceExt, BindGroupLayout, Buffer, BufferUsages, Device, PipelineLayout, Queue,
+  RenderPass, RenderPipeline, ShaderModule, VertexBufferLayout, VertexStepMode,
+};
+
+use crate::{
+  game::debug_draw::{DebugDraw, DebugLine},
+  wgpu_renderer::{render_graph::HDR_FORMAT, textures::TextureResource},
+};
+
+/// Vertex of a debug line
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+  position: [f32; 3],
+  color: [f32; 4],
+}
+
+const VERTEX_ATTR_ARRAY: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+  0 => Float32x3,
+  1 => Float32x4
+];
+
+impl DebugVertex {
+  pub fn desc<'a>() -> VertexBufferLayout<'a> {
+    VertexBufferLayout {
+      array_stride: std::mem::size_of::<DebugVertex>() as _,
+      step_mode: VertexStepMode::Vertex,
+      attributes: &VERTEX_ATTR_ARRAY,
+    }
+  }
+}
+
+///
+/// Line list vertices of `lines`, with the depth tested lines first.
+/// Returns the vertex ranges of the depth tested and overlay lines
+pub fn line_vertices(lines: &[DebugLine]) -> (Vec<DebugVertex>, Range<u32>, Range<u32>) {
+  let mut vertices = Vec::with_capacity(lines.len() * 2);
+  let depth_tested = lines.iter().filter(|line| line.depth_test);
+  let overlay = lines.iter().filter(|line| !line.depth_test);
+  for line in depth_tested.chain(overlay) {
+    let color = line.color.into();
+    vertices.push(DebugVertex {
+      position: line.start.into(),
+      color,
+    });
+    vertices.push(DebugVertex {
+      position: line.end.into(),
+      color,
+    });
+  }
+  let split = 2 * lines.iter().filter(|line| line.depth_test).count() as u32;
+  let len = vertices.len() as u32;
+  (vertices, 0..split, split..len)
+}
+
+///
+/// The depth tested and overlay line pipelines, and the vertex buffer
+/// holding the current frame's lines
+#[derive(Debug)]
+pub struct DebugLines {
+  layout: PipelineLayout,
+  vertex_shader: ShaderModule,
+  fragment_shader: ShaderModule,
+  /// sample count of `pipelines`, which follow the scene's
+  sample_count: u32,
+  /// depth tested and overlay pipelines
+  pipelines: [RenderPipeline; 2],
+  vertex_buffer: Buffer,
+  /// size of `vertex_buffer` in bytes
+  vertex_buffer_size: usize,
+  depth_tested: Range<u32>,
+  overlay: Range<u32>,
+}
+
+impl DebugLines {
+  /// `uniform_layout` is the layout of the context's camera uniforms
+  pub fn new(device: &Device, uniform_layout: &BindGroupLayout, sample_count: u32) -> Self {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("debug_lines_pipeline_layout"),
+      bind_group_layouts: &[uniform_layout],
+      push_constant_ranges: &[],
+    });
+    let vertex_shader = device.create_shader_module(&include_shader!("debug_lines.vert"));
+    let fragment_shader = device.create_shader_module(&include_shader!("debug_lines.frag"));
+    let pipelines = [true, false].map(|depth_test| {
+      create_pipeline(
+        device,
+        &layout,
+        &vertex_shader,
+        &fragment_shader,
+        depth_test,
+        sample_count,
+      )
+    });
+    let vertex_buffer_size = 2 * std::mem::size_of::<DebugVertex>();
+    let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Debug Line Buffer"),
+      size: vertex_buffer_size as wgpu::BufferAddress,
+      usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    Self {
+      layout,
+      vertex_shader,
+      fragment_shader,
+      sample_count,
+      pipelines,
+      vertex_buffer,
+      vertex_buffer_size,
+      depth_tested: 0..0,
+      overlay: 0..0,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.depth_tested.is_empty() && self.overlay.is_empty()
+  }
+
+  ///
+  /// Uploads the lines to draw this frame, and rebuilds the pipelines
+  /// if the scene's sample count has changed
+  pub(crate) fn prepare(
+    &mut self,
+    device: &Device,
+    queue: &Queue,
+    debug_draw: Option<&DebugDraw>,
+    sample_count: u32,
+  ) {
+    let lines = debug_draw
+      .filter(|debug_draw| debug_draw.enabled)
+      .map_or(&[][..], |debug_draw| debug_draw.lines());
+    let (vertices, depth_tested, overlay) = line_vertices(lines);
+    self.depth_tested = depth_tested;
+    self.overlay = overlay;
+    if vertices.is_empty() {
+      return;
+    }
+    if sample_count != self.sample_count {
+      let (layout, vertex_shader, fragment_shader) =
+        (&self.layout, &self.vertex_shader, &self.fragment_shader);
+      self.pipelines = [true, false].map(|depth_test| {
+        create_pipeline(
+          device,
+          layout,
+          vertex_shader,
+          fragment_shader,
+          depth_test,
+          sample_count,
+        )
+      });
+      self.sample_count = sample_count;
+    }
+    let data: &[u8] = bytemuck::cast_slice(&vertices);
+    if data.len() > self.vertex_buffer_size {
+      self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Debug Line Buffer"),
+        contents: data,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      });
+      self.vertex_buffer_size = data.len();
+    } else {
+      queue.write_buffer(&self.vertex_buffer, 0, data);
+    }
+  }
+
+  /// Draws the lines into a pass with the scene's color and depth attachments
+  pub fn draw<'a>(&'a self, pass: &mut RenderPass<'a>, uniforms: &'a wgpu::BindGroup) {
+    pass.set_bind_group(0, uniforms, &[]);
+    pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+    for (pipeline, vertices) in self
+      .pipelines
+      .iter()
+      .zip([self.depth_tested.clone(), self.overlay.clone()])
+    {
+      if !vertices.is_empty() {
+        pass.set_pipeline(pipeline);
+        pass.draw(vertices, 0..1);
+      }
+    }
+  }
+}
+
+///
+/// Line list pipeline drawing into the HDR scene color. Lines are blended,
+/// and never write depth. Overlay lines pass the depth test everywhere
+fn create_pipeline(
+  device: &Device,
+  layout: &PipelineLayout,
+  vertex_shader: &ShaderModule,
+  fragment_shader: &ShaderModule,
+  depth_test: bool,
+  sample_count: u32,
+) -> RenderPipeline {
+  device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    label: Some(if depth_test {
+      "Debug Line Pipeline"
+    } else {
+      "Debug Line Overlay Pipeline"
+    }),
+    layout: Some(layout),
+    vertex: wgpu::VertexState {
+      module: vertex_shader,
+      entry_point: "main",
+      buffers: &[DebugVertex::desc()],
+    },
+    fragment: Some(wgpu::FragmentState {
+      module: fragment_shader,
+      entry_point: "main",
+      targets: &[wgpu::ColorTargetState {
+        format: HDR_FORMAT,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+      }],
+    }),
+    primitive: wgpu::PrimitiveState {
+      topology: wgpu::PrimitiveTopology::LineList,
+      ..Default::default()
+    },
+    depth_stencil: Some(wgpu::DepthStencilState {
+      format: TextureResource::DEPTH_TEXTURE_FORMAT,
+      depth_write_enabled: false,
+      depth_compare: if depth_test {
+        wgpu::CompareFunction::LessEqual
+      } else {
+        wgpu::CompareFunction::Always
+      },
+      stencil: Default::default(),
+      bias: Default::default(),
+    }),
+    multisample: wgpu::MultisampleState {
+      count: sample_count,
+      ..Default::default()
+    },
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::game::debug_draw::DebugStyle;
+  use nalgebra_glm::{vec3, vec4};
+
+  #[test]
+  fn test_line_vertices_overlay_last() {
+    let mut debug_draw = DebugDraw::default();
+    let red = vec4(1.0, 0.0, 0.0, 1.0);
+    debug_draw
+      .line(
+        vec3(0.0, 0.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        DebugStyle::new(red).overlay(),
+      )
+      .line(vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 0.0), red)
+      .line(vec3(0.0, 2.0, 0.0), vec3(1.0, 2.0, 0.0), red);
+    let (vertices, depth_tested, overlay) = line_vertices(debug_draw.lines());
+    assert_eq!(vertices.len(), 6);
+    assert_eq!(depth_tested, 0..4);
+    assert_eq!(overlay, 4..6);
+    assert_eq!(vertices[0].position, [0.0, 1.0, 0.0]);
+    assert_eq!(vertices[5].position, [1.0, 0.0, 0.0]);
+    assert_eq!(vertices[5].color, [1.0, 0.0, 0.0, 1.0]);
+  }
+
+  #[test]
+  fn test_line_vertices_empty() {
+    let (vertices, depth_tested, overlay) = line_vertices(&[]);
+    assert!(vertices.is_empty());
+    assert!(depth_tested.is_empty() && overlay.is_empty());
+  }
+}
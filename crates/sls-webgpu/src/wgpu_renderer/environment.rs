@@ -0,0 +1,659 @@
+//! Image based lighting.
+//!
+//! An equirectangular HDR image is projected onto a cubemap, which is then baked into
+//! a diffuse irradiance cubemap and a GGX prefiltered specular cubemap with one roughness
+//! level per mip. Together with the BRDF lookup table, these are sampled by main.frag
+//! for ambient lighting, using the split sum approximation.
+use std::{io::BufRead, num::NonZeroU32};
+
+use image::codecs::hdr::HdrDecoder;
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupLayout, Device, Queue, RenderPipeline, ShaderModule, ShaderStages,
+  TextureFormat, TextureSampleType, TextureView, TextureViewDimension,
+};
+
+use crate::wgpu_renderer::textures::{TextureError, TextureResource};
+
+/// Format of the environment, irradiance and prefiltered cubemaps
+pub const ENVIRONMENT_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const BRDF_LUT_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+pub const ENVIRONMENT_SIZE: u32 = 512;
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Number of roughness levels in the prefiltered cubemap.
+/// Must match PREFILTERED_MIP_LEVELS in main.frag
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 256;
+
+const PREFILTER_SAMPLE_COUNT: u32 = 512;
+const BRDF_LUT_SAMPLE_COUNT: u32 = 512;
+const CUBE_FACES: u32 = 6;
+
+///
+/// A decoded Radiance HDR image, in linear RGB
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+  pub width: u32,
+  pub height: u32,
+  pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+  pub fn from_memory(bytes: &[u8]) -> Result<Self, TextureError> {
+    Self::from_reader(bytes)
+  }
+
+  pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, TextureError> {
+    let decoder = HdrDecoder::new(reader)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+      .read_image_hdr()?
+      .into_iter()
+      .map(|pixel| pixel.0)
+      .collect();
+    Ok(Self {
+      width: metadata.width,
+      height: metadata.height,
+      pixels,
+    })
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, TextureError> {
+    let file = std::fs::File::open(path)
+      .map_err(|e| TextureError::Other(format!("could not open hdr image: {}", e)))?;
+    Self::from_reader(std::io::BufReader::new(file))
+  }
+
+  /// Pixels as half float RGBA, the layout of `ENVIRONMENT_FORMAT`
+  fn to_rgba_f16(&self) -> Vec<u16> {
+    self
+      .pixels
+      .iter()
+      .flat_map(|[r, g, b]| {
+        [
+          f32_to_f16(*r),
+          f32_to_f16(*g),
+          f32_to_f16(*b),
+          f32_to_f16(1.0),
+        ]
+      })
+      .collect()
+  }
+}
+
+///
+/// Converts to the bits of an IEEE 754 half float, rounding to nearest even.
+/// Values too large for a half become infinity
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+  let bits = value.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exponent = ((bits >> 23) & 0xff) as i32;
+  let mantissa = bits & 0x007f_ffff;
+
+  if exponent == 0xff {
+    // infinity or NaN
+    let nan_bit = if mantissa != 0 { 0x0200 } else { 0 };
+    return sign | 0x7c00 | nan_bit;
+  }
+  let half_exponent = exponent - 127 + 15;
+  if half_exponent >= 0x1f {
+    return sign | 0x7c00;
+  }
+  if half_exponent <= 0 {
+    if half_exponent < -10 {
+      return sign;
+    }
+    // subnormal half, including the implicit leading bit
+    let mantissa = mantissa | 0x0080_0000;
+    let shift = (14 - half_exponent) as u32;
+    let half_mantissa = mantissa >> shift;
+    let remainder = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round_up = remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1);
+    return sign | (half_mantissa + round_up as u32) as u16;
+  }
+  let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+  let remainder = mantissa & 0x1fff;
+  let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+  // a carry out of the mantissa correctly increments the exponent
+  sign | (half + round_up as u32) as u16
+}
+
+/// Roughness which a mip level of the prefiltered cubemap is convolved for
+pub fn prefiltered_mip_roughness(mip_level: u32, mip_level_count: u32) -> f32 {
+  if mip_level_count <= 1 {
+    return 0.0;
+  }
+  mip_level as f32 / (mip_level_count - 1) as f32
+}
+
+///
+/// Per draw parameters for the bake shaders.
+/// Matches the std140 `BakeParams` block in the bake fragment shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeParams {
+  face: u32,
+  roughness: f32,
+  face_size: f32,
+  sample_count: u32,
+}
+
+///
+/// Cubemaps used for image based lighting
+#[derive(Debug)]
+pub struct EnvironmentMap {
+  /// the source environment, drawn by the skybox
+  pub environment: TextureResource,
+  /// cosine weighted convolution of the environment, for diffuse light
+  pub irradiance: TextureResource,
+  /// GGX convolutions of the environment, with roughness increasing per mip level
+  pub prefiltered: TextureResource,
+}
+
+impl EnvironmentMap {
+  ///
+  /// Projects an equirectangular HDR image onto a cubemap,
+  /// and bakes its irradiance and prefiltered specular maps
+  pub fn from_hdr(device: &Device, queue: &Queue, image: &HdrImage) -> Result<Self, TextureError> {
+    if image.width == 0 || image.height == 0 {
+      return Err(TextureError::Other("hdr image is empty".into()));
+    }
+    let equirect = upload_equirect(device, queue, image);
+    let environment = TextureResource::new_cube_texture(
+      device,
+      ENVIRONMENT_SIZE,
+      1,
+      ENVIRONMENT_FORMAT,
+      "environment_cubemap",
+    );
+    let irradiance = TextureResource::new_cube_texture(
+      device,
+      IRRADIANCE_SIZE,
+      1,
+      ENVIRONMENT_FORMAT,
+      "irradiance_cubemap",
+    );
+    let prefiltered = TextureResource::new_cube_texture(
+      device,
+      PREFILTERED_SIZE,
+      PREFILTERED_MIP_LEVELS,
+      ENVIRONMENT_FORMAT,
+      "prefiltered_cubemap",
+    );
+
+    let baker = Baker::new(device);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("environment_bake"),
+    });
+    let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+    let equirect_pass = baker.fullscreen_pass(
+      &include_shader!("equirect_to_cube.frag"),
+      Some(TextureViewDimension::D2),
+      ENVIRONMENT_FORMAT,
+    );
+    baker.bake_cube(
+      &mut encoder,
+      &equirect_pass,
+      &equirect_view,
+      environment.texture(),
+      ENVIRONMENT_SIZE,
+      0,
+      0.0,
+      0,
+    );
+
+    let irradiance_pass = baker.fullscreen_pass(
+      &include_shader!("irradiance.frag"),
+      Some(TextureViewDimension::Cube),
+      ENVIRONMENT_FORMAT,
+    );
+    baker.bake_cube(
+      &mut encoder,
+      &irradiance_pass,
+      environment.view(),
+      irradiance.texture(),
+      IRRADIANCE_SIZE,
+      0,
+      0.0,
+      0,
+    );
+
+    let prefilter_pass = baker.fullscreen_pass(
+      &include_shader!("prefilter.frag"),
+      Some(TextureViewDimension::Cube),
+      ENVIRONMENT_FORMAT,
+    );
+    for mip_level in 0..PREFILTERED_MIP_LEVELS {
+      baker.bake_cube(
+        &mut encoder,
+        &prefilter_pass,
+        environment.view(),
+        prefiltered.texture(),
+        (PREFILTERED_SIZE >> mip_level).max(1),
+        mip_level,
+        prefiltered_mip_roughness(mip_level, PREFILTERED_MIP_LEVELS),
+        PREFILTER_SAMPLE_COUNT,
+      );
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+
+    Ok(Self {
+      environment,
+      irradiance,
+      prefiltered,
+    })
+  }
+
+  ///
+  /// An environment which is the same color in every direction.
+  /// Used for ambient light when no environment map is loaded
+  pub fn uniform_color(device: &Device, queue: &Queue, color: [f32; 3]) -> Self {
+    let texel = [
+      f32_to_f16(color[0]),
+      f32_to_f16(color[1]),
+      f32_to_f16(color[2]),
+      f32_to_f16(1.0),
+    ];
+    let data: Vec<u16> = (0..CUBE_FACES).flat_map(|_| texel).collect();
+    let create = |label: &str| {
+      let cube = TextureResource::new_cube_texture(device, 1, 1, ENVIRONMENT_FORMAT, label);
+      queue.write_texture(
+        wgpu::ImageCopyTexture {
+          texture: cube.texture(),
+          mip_level: 0,
+          origin: wgpu::Origin3d::ZERO,
+          aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(&data),
+        wgpu::ImageDataLayout {
+          offset: 0,
+          bytes_per_row: NonZeroU32::new(8),
+          rows_per_image: NonZeroU32::new(1),
+        },
+        wgpu::Extent3d {
+          width: 1,
+          height: 1,
+          depth_or_array_layers: CUBE_FACES,
+        },
+      );
+      cube
+    };
+    Self {
+      environment: create("uniform_environment_cubemap"),
+      irradiance: create("uniform_irradiance_cubemap"),
+      prefiltered: create("uniform_prefiltered_cubemap"),
+    }
+  }
+}
+
+///
+/// Bakes the split sum BRDF lookup table. It does not depend on
+/// the environment, so one table is shared by every EnvironmentMap
+pub fn bake_brdf_lut(device: &Device, queue: &Queue) -> TextureResource {
+  let lut = TextureResource::new_render_target_texture(
+    device,
+    (BRDF_LUT_SIZE, BRDF_LUT_SIZE),
+    BRDF_LUT_FORMAT,
+    "brdf_lut",
+  );
+  let baker = Baker::new(device);
+  let pass = baker.fullscreen_pass(&include_shader!("brdf_lut.frag"), None, BRDF_LUT_FORMAT);
+  let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+    label: Some("brdf_lut_bake"),
+  });
+  let bind_group = baker.bind_group(
+    &pass,
+    None,
+    BakeParams {
+      face: 0,
+      roughness: 0.0,
+      face_size: BRDF_LUT_SIZE as f32,
+      sample_count: BRDF_LUT_SAMPLE_COUNT,
+    },
+  );
+  baker.draw(&mut encoder, &pass, &bind_group, lut.view());
+  queue.submit(std::iter::once(encoder.finish()));
+  lut
+}
+
+pub fn create_environment_bind_group(
+  device: &Device,
+  layout: &BindGroupLayout,
+  environment: &EnvironmentMap,
+  brdf_lut: &TextureResource,
+) -> BindGroup {
+  device.create_bind_group(&wgpu::BindGroupDescriptor {
+    label: Some("environment_bind_group"),
+    layout,
+    entries: &[
+      wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::TextureView(environment.environment.view()),
+      },
+      wgpu::BindGroupEntry {
+        binding: 1,
+        resource: wgpu::BindingResource::TextureView(environment.irradiance.view()),
+      },
+      wgpu::BindGroupEntry {
+        binding: 2,
+        resource: wgpu::BindingResource::TextureView(environment.prefiltered.view()),
+      },
+      wgpu::BindGroupEntry {
+        binding: 3,
+        resource: wgpu::BindingResource::TextureView(brdf_lut.view()),
+      },
+      wgpu::BindGroupEntry {
+        binding: 4,
+        resource: wgpu::BindingResource::Sampler(environment.prefiltered.sampler()),
+      },
+    ],
+  })
+}
+
+fn upload_equirect(device: &Device, queue: &Queue, image: &HdrImage) -> wgpu::Texture {
+  let size = wgpu::Extent3d {
+    width: image.width,
+    height: image.height,
+    depth_or_array_layers: 1,
+  };
+  let texture = device.create_texture(&wgpu::TextureDescriptor {
+    label: Some("equirect_environment"),
+    size,
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: wgpu::TextureDimension::D2,
+    format: ENVIRONMENT_FORMAT,
+    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+  });
+  queue.write_texture(
+    wgpu::ImageCopyTexture {
+      texture: &texture,
+      mip_level: 0,
+      origin: wgpu::Origin3d::ZERO,
+      aspect: wgpu::TextureAspect::All,
+    },
+    bytemuck::cast_slice(&image.to_rgba_f16()),
+    wgpu::ImageDataLayout {
+      offset: 0,
+      bytes_per_row: NonZeroU32::new(8 * image.width),
+      rows_per_image: NonZeroU32::new(image.height),
+    },
+    size,
+  );
+  texture
+}
+
+/// A fullscreen bake pass, and the layout of its bind group
+struct BakePass {
+  pipeline: RenderPipeline,
+  layout: BindGroupLayout,
+}
+
+/// Creates the fullscreen render passes used to bake IBL textures
+struct Baker<'a> {
+  device: &'a Device,
+  vertex_shader: ShaderModule,
+  sampler: wgpu::Sampler,
+}
+
+impl<'a> Baker<'a> {
+  fn new(device: &'a Device) -> Self {
+    let vertex_shader = device.create_shader_module(&include_shader!("fullscreen.vert"));
+    // repeat horizontally, so lookups across the equirect seam wrap around
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("environment_bake_sampler"),
+      address_mode_u: wgpu::AddressMode::Repeat,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      mipmap_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+    Self {
+      device,
+      vertex_shader,
+      sampler,
+    }
+  }
+
+  /// `source` is the view dimension of the texture the pass reads, if any
+  fn fullscreen_pass(
+    &self,
+    fragment: &wgpu::ShaderModuleDescriptor,
+    source: Option<TextureViewDimension>,
+    format: TextureFormat,
+  ) -> BakePass {
+    let mut entries = vec![wgpu::BindGroupLayoutEntry {
+      binding: 0,
+      visibility: ShaderStages::FRAGMENT,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<BakeParams>() as _),
+      },
+      count: None,
+    }];
+    if let Some(view_dimension) = source {
+      entries.push(wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+          multisampled: false,
+          view_dimension,
+          sample_type: TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+      });
+      entries.push(wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler {
+          comparison: false,
+          filtering: true,
+        },
+        count: None,
+      });
+    }
+    let layout = self
+      .device
+      .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("environment_bake_layout"),
+        entries: &entries,
+      });
+    let pipeline_layout = self
+      .device
+      .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("environment_bake_pipeline_layout"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+      });
+    let fragment_shader = self.device.create_shader_module(fragment);
+    let pipeline = self
+      .device
+      .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("environment_bake_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+          module: &self.vertex_shader,
+          entry_point: "main",
+          buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+          module: &fragment_shader,
+          entry_point: "main",
+          targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+      });
+    BakePass { pipeline, layout }
+  }
+
+  fn bind_group(
+    &self,
+    pass: &BakePass,
+    source: Option<&TextureView>,
+    params: BakeParams,
+  ) -> BindGroup {
+    let params_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("environment_bake_params"),
+      contents: bytemuck::cast_slice(&[params]),
+      usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let mut entries = vec![wgpu::BindGroupEntry {
+      binding: 0,
+      resource: params_buffer.as_entire_binding(),
+    }];
+    if let Some(source) = source {
+      entries.push(wgpu::BindGroupEntry {
+        binding: 1,
+        resource: wgpu::BindingResource::TextureView(source),
+      });
+      entries.push(wgpu::BindGroupEntry {
+        binding: 2,
+        resource: wgpu::BindingResource::Sampler(&self.sampler),
+      });
+    }
+    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("environment_bake_bind_group"),
+      layout: &pass.layout,
+      entries: &entries,
+    })
+  }
+
+  /// Renders every face of one mip level of `target`
+  #[allow(clippy::too_many_arguments)]
+  fn bake_cube(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    pass: &BakePass,
+    source: &TextureView,
+    target: &wgpu::Texture,
+    face_size: u32,
+    mip_level: u32,
+    roughness: f32,
+    sample_count: u32,
+  ) {
+    for face in 0..CUBE_FACES {
+      let face_view = target.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("environment_bake_face"),
+        dimension: Some(TextureViewDimension::D2),
+        base_mip_level: mip_level,
+        mip_level_count: NonZeroU32::new(1),
+        base_array_layer: face,
+        array_layer_count: NonZeroU32::new(1),
+        ..Default::default()
+      });
+      let bind_group = self.bind_group(
+        pass,
+        Some(source),
+        BakeParams {
+          face,
+          roughness,
+          face_size: face_size as f32,
+          sample_count,
+        },
+      );
+      self.draw(encoder, pass, &bind_group, &face_view);
+    }
+  }
+
+  fn draw(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    pass: &BakePass,
+    bind_group: &BindGroup,
+    target: &TextureView,
+  ) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("environment_bake_pass"),
+      color_attachments: &[wgpu::RenderPassColorAttachment {
+        view: target,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+          store: true,
+        },
+      }],
+      depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(&pass.pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use image::{codecs::hdr::HdrEncoder, Rgb};
+
+  #[test]
+  fn test_f32_to_f16() {
+    assert_eq!(f32_to_f16(0.0), 0x0000);
+    assert_eq!(f32_to_f16(-0.0), 0x8000);
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(0.5), 0x3800);
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    // largest half, and overflow to infinity
+    assert_eq!(f32_to_f16(1.0e6), 0x7c00);
+    assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+    assert_eq!(f32_to_f16(f32::NAN) & 0x7c00, 0x7c00);
+    assert_ne!(f32_to_f16(f32::NAN) & 0x03ff, 0);
+    // smallest subnormal half, and underflow to zero
+    assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001);
+    assert_eq!(f32_to_f16(1.0e-10), 0x0000);
+  }
+
+  #[test]
+  fn test_f32_to_f16_rounds_to_nearest_even() {
+    // 1 + 2^-11 is halfway between 1.0 and the next half, and rounds down to even
+    assert_eq!(f32_to_f16(1.0 + 2.0f32.powi(-11)), 0x3c00);
+    // 1 + 3 * 2^-11 is halfway, and rounds up to even
+    assert_eq!(f32_to_f16(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+  }
+
+  #[test]
+  fn test_prefiltered_mip_roughness() {
+    assert_eq!(prefiltered_mip_roughness(0, PREFILTERED_MIP_LEVELS), 0.0);
+    assert_eq!(
+      prefiltered_mip_roughness(PREFILTERED_MIP_LEVELS - 1, PREFILTERED_MIP_LEVELS),
+      1.0
+    );
+    assert_eq!(prefiltered_mip_roughness(0, 1), 0.0);
+  }
+
+  #[test]
+  fn test_bake_params_std140_layout() {
+    assert_eq!(std::mem::size_of::<BakeParams>(), 16);
+  }
+
+  #[test]
+  fn test_hdr_image_from_memory() {
+    let pixels = vec![
+      Rgb([0.0f32, 0.5, 1.0]),
+      Rgb([2.0, 4.0, 8.0]),
+      Rgb([1.0, 1.0, 1.0]),
+      Rgb([0.25, 0.125, 0.0]),
+      Rgb([16.0, 0.0, 0.0]),
+      Rgb([0.0, 0.0, 0.0]),
+    ];
+    let mut bytes = Vec::new();
+    HdrEncoder::new(&mut bytes).encode(&pixels, 3, 2).unwrap();
+
+    let image = HdrImage::from_memory(&bytes).unwrap();
+    assert_eq!((image.width, image.height), (3, 2));
+    assert_eq!(image.pixels.len(), 6);
+    // RGBE has an 8 bit mantissa per channel, so these values round trip exactly
+    assert_eq!(image.pixels[1], [2.0, 4.0, 8.0]);
+    assert_eq!(image.pixels[4], [16.0, 0.0, 0.0]);
+    assert_eq!(image.to_rgba_f16()[4..8], [0x4000, 0x4400, 0x4800, 0x3c00]);
+  }
+}
@@ -2,8 +2,8 @@ use crate::{renderer_common::handle::Handle, wgpu_renderer::model::StreamingMesh
 use nalgebra_glm::Mat4;
 use std::sync::{Arc, RwLock, Weak};
 
-use super::uniforms::PointLightUniform;
-use crate::game::components::{LightSource, LightType, Transform3D};
+use super::uniforms::LightUniform;
+use crate::{game::components::LightSource, scene_graph::components::LocalToWorld};
 
 #[derive(Debug)]
 pub enum DrawCommand {
@@ -16,38 +16,24 @@ pub enum DrawCommand {
 #[derive(Debug)]
 pub struct WgpuFrame {
   context: Weak<RwLock<Context>>,
-  point_lights: RwLock<Vec<PointLightUniform>>,
+  lights: RwLock<Vec<LightUniform>>,
 }
 
 impl WgpuFrame {
   pub fn new(context: &Arc<RwLock<Context>>) -> Self {
     Self {
       context: Arc::downgrade(context),
-      point_lights: Default::default(),
+      lights: Default::default(),
     }
   }
-  pub fn push_light<I>(&mut self, light: &LightSource, transform: &Transform3D) {
-    let mut point_lights = self.point_lights.write().unwrap();
-    match light.light_type {
-      LightType::Point => {
-        let uniform = PointLightUniform {
-          position: (*transform.position()).into(),
-          _padding: 0,
-          color: light.color.into(),
-        };
-        point_lights.push(uniform);
-      }
-      _ => {}
-    }
+  pub fn push_light(&mut self, light: &LightSource, local_to_world: &LocalToWorld) {
+    let mut lights = self.lights.write().unwrap();
+    lights.push(light.as_uniform(local_to_world));
   }
 
   pub fn clear(&mut self) {
     // self.draw_list.clear();
-    if let Err(e) = self
-      .point_lights
-      .write()
-      .map(|mut point_lights| point_lights.clear())
-    {
+    if let Err(e) = self.lights.write().map(|mut lights| lights.clear()) {
       panic!("render resources are poisoned! {:?}", e);
     }
   }
@@ -0,0 +1,277 @@
+//! glTF import with `KHR_texture_basisu` support.
+//!
+//! `gltf::import` can't decode KTX2 images, and the gltf crate doesn't
+//! expose the extension, so textures that use it are found in the raw json.
+//! Their KTX2 files are kept as bytes and uploaded through `compressed_texture`.
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Arc,
+};
+
+use serde_json::Value;
+
+pub const KHR_TEXTURE_BASISU: &str = "KHR_texture_basisu";
+
+///
+/// KTX2 files of the textures that use `KHR_texture_basisu`,
+/// keyed by gltf texture index
+#[derive(Debug, Clone, Default)]
+pub struct BasisuImages {
+  images: HashMap<usize, Arc<[u8]>>,
+  /// textures whose `source` is only a placeholder for their KTX2 image
+  without_fallback: HashSet<usize>,
+}
+
+impl BasisuImages {
+  pub fn get(&self, texture_index: usize) -> Option<&Arc<[u8]>> {
+    self.images.get(&texture_index)
+  }
+
+  pub fn insert(&mut self, texture_index: usize, ktx2: Arc<[u8]>, has_fallback: bool) {
+    self.images.insert(texture_index, ktx2);
+    if !has_fallback {
+      self.without_fallback.insert(texture_index);
+    }
+  }
+
+  /// Whether the texture's `source` is an image that can replace its KTX2 file
+  pub fn has_fallback(&self, texture_index: usize) -> bool {
+    !self.without_fallback.contains(&texture_index)
+  }
+
+  pub fn len(&self) -> usize {
+    self.images.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.images.is_empty()
+  }
+}
+
+///
+/// Maps the index of each texture that uses `KHR_texture_basisu`
+/// to the index of its KTX2 image
+pub fn basisu_sources(json: &Value) -> HashMap<usize, usize> {
+  let textures = match json.get("textures").and_then(Value::as_array) {
+    Some(textures) => textures,
+    None => return HashMap::new(),
+  };
+  textures
+    .iter()
+    .enumerate()
+    .filter_map(|(texture_index, texture)| {
+      let source = texture
+        .get("extensions")?
+        .get(KHR_TEXTURE_BASISU)?
+        .get("source")?
+        .as_u64()?;
+      Some((texture_index, source as usize))
+    })
+    .collect()
+}
+
+///
+/// Rewrites the json so the gltf crate accepts it. Textures without a
+/// fallback `source` get their KTX2 image, which is only used as a placeholder,
+/// and the extension is dropped from `extensionsRequired`.
+/// Returns the textures which got a placeholder
+fn patch_json(json: &mut Value, sources: &HashMap<usize, usize>) -> HashSet<usize> {
+  let mut placeholders = HashSet::new();
+  if let Some(textures) = json.get_mut("textures").and_then(Value::as_array_mut) {
+    for (texture_index, texture) in textures.iter_mut().enumerate() {
+      if let (Some(source), Some(texture)) = (sources.get(&texture_index), texture.as_object_mut())
+      {
+        texture.entry("source").or_insert_with(|| {
+          placeholders.insert(texture_index);
+          Value::from(*source)
+        });
+      }
+    }
+  }
+  if let Some(required) = json
+    .get_mut("extensionsRequired")
+    .and_then(Value::as_array_mut)
+  {
+    required.retain(|ext| ext.as_str() != Some(KHR_TEXTURE_BASISU));
+  }
+  placeholders
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+  use super::*;
+  use crate::wgpu_renderer::compressed_texture::is_compressed_container;
+  use anyhow::{anyhow, Context as _};
+  use gltf::{buffer, image, Document};
+  use std::{borrow::Cow, path::Path};
+
+  ///
+  /// Like `gltf::import`, but also returns the KTX2 files of `KHR_texture_basisu` textures.
+  /// KTX2 images are replaced with a 1x1 white placeholder in the decoded images
+  pub fn import<P: AsRef<Path>>(
+    path: P,
+  ) -> anyhow::Result<(Document, Vec<buffer::Data>, Vec<image::Data>, BasisuImages)> {
+    let path = path.as_ref();
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+    let bytes = std::fs::read(path).with_context(|| format!("could not read {:?}", path))?;
+
+    let (json_bytes, blob): (Cow<[u8]>, _) = if bytes.starts_with(b"glTF") {
+      let glb = gltf::Glb::from_slice(&bytes)?;
+      (glb.json, glb.bin.map(|bin| bin.into_owned()))
+    } else {
+      (Cow::Borrowed(&bytes[..]), None)
+    };
+    let mut json: Value = serde_json::from_slice(&json_bytes)?;
+    let sources = basisu_sources(&json);
+    let placeholders = patch_json(&mut json, &sources);
+
+    let document = Document::from_json(serde_json::from_value(json)?)?;
+    let buffers = gltf::import_buffers(&document, Some(base), blob)?;
+
+    let mut images = Vec::new();
+    let mut ktx2_images: HashMap<usize, Arc<[u8]>> = HashMap::new();
+    for image in document.images() {
+      let data = image_bytes(&image, &buffers, base)?;
+      if is_compressed_container(&data) {
+        images.push(placeholder_image());
+        ktx2_images.insert(image.index(), Arc::from(data.into_owned()));
+      } else {
+        images.push(decode_image(&data)?);
+      }
+    }
+
+    let mut basisu = BasisuImages::default();
+    for (texture_index, image_index) in sources {
+      let data = ktx2_images.get(&image_index).ok_or_else(|| {
+        anyhow!(
+          "{} image {} is not a KTX2 file",
+          KHR_TEXTURE_BASISU,
+          image_index
+        )
+      })?;
+      basisu.insert(
+        texture_index,
+        data.clone(),
+        !placeholders.contains(&texture_index),
+      );
+    }
+    Ok((document, buffers, images, basisu))
+  }
+
+  fn image_bytes<'a>(
+    image: &gltf::Image,
+    buffers: &'a [buffer::Data],
+    base: &Path,
+  ) -> anyhow::Result<Cow<'a, [u8]>> {
+    match image.source() {
+      image::Source::View { view, .. } => {
+        let buffer = &buffers[view.buffer().index()];
+        let range = view.offset()..view.offset() + view.length();
+        buffer
+          .get(range)
+          .map(Cow::Borrowed)
+          .ok_or_else(|| anyhow!("image {} is out of its buffer's range", image.index()))
+      }
+      image::Source::Uri { uri, .. } => {
+        if let Some(data) = uri.strip_prefix("data:") {
+          let encoded = data
+            .split_once(";base64,")
+            .map(|(_, encoded)| encoded)
+            .ok_or_else(|| anyhow!("image {} has an unsupported data uri", image.index()))?;
+          Ok(Cow::Owned(base64::decode(encoded)?))
+        } else {
+          let path = base.join(uri);
+          Ok(Cow::Owned(
+            std::fs::read(&path).with_context(|| format!("could not read {:?}", path))?,
+          ))
+        }
+      }
+    }
+  }
+
+  /// Decodes to RGBA, keeping the precision of 16 bit images
+  fn decode_image(data: &[u8]) -> anyhow::Result<image::Data> {
+    use ::image::DynamicImage::*;
+    let decoded = ::image::load_from_memory(data)?;
+    Ok(match decoded {
+      ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => {
+        let rgba = decoded.into_rgba16();
+        image::Data {
+          width: rgba.width(),
+          height: rgba.height(),
+          format: image::Format::R16G16B16A16,
+          pixels: rgba
+            .into_raw()
+            .into_iter()
+            .flat_map(u16::to_ne_bytes)
+            .collect(),
+        }
+      }
+      _ => {
+        let rgba = decoded.into_rgba8();
+        image::Data {
+          width: rgba.width(),
+          height: rgba.height(),
+          format: image::Format::R8G8B8A8,
+          pixels: rgba.into_raw(),
+        }
+      }
+    })
+  }
+
+  fn placeholder_image() -> image::Data {
+    image::Data {
+      width: 1,
+      height: 1,
+      format: image::Format::R8G8B8A8,
+      pixels: vec![255; 4],
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const BASISU_GLTF: &str = r#"{
+    "asset": { "version": "2.0" },
+    "extensionsUsed": ["KHR_texture_basisu"],
+    "extensionsRequired": ["KHR_texture_basisu"],
+    "images": [
+      { "uri": "albedo.ktx2", "mimeType": "image/ktx2" },
+      { "uri": "normal.png" },
+      { "uri": "normal.ktx2", "mimeType": "image/ktx2" }
+    ],
+    "textures": [
+      { "extensions": { "KHR_texture_basisu": { "source": 0 } } },
+      { "source": 1 },
+      { "source": 1, "extensions": { "KHR_texture_basisu": { "source": 2 } } }
+    ]
+  }"#;
+
+  #[test]
+  fn test_basisu_sources() {
+    let json: Value = serde_json::from_str(BASISU_GLTF).unwrap();
+    let sources = basisu_sources(&json);
+    assert_eq!(sources.len(), 2);
+    assert_eq!(sources.get(&0), Some(&0));
+    assert_eq!(sources.get(&1), None);
+    assert_eq!(sources.get(&2), Some(&2));
+  }
+
+  #[test]
+  fn test_patch_json() {
+    let mut json: Value = serde_json::from_str(BASISU_GLTF).unwrap();
+    let sources = basisu_sources(&json);
+    let placeholders = patch_json(&mut json, &sources);
+    // textures without a fallback get the KTX2 image, and fallbacks are kept
+    assert_eq!(placeholders.into_iter().collect::<Vec<_>>(), vec![0]);
+    assert_eq!(json["textures"][0]["source"], 0);
+    assert_eq!(json["textures"][2]["source"], 1);
+    assert_eq!(json["extensionsRequired"].as_array().unwrap().len(), 0);
+    assert!(serde_json::from_value::<gltf::json::Root>(json).is_ok());
+  }
+}
@@ -1,13 +1,27 @@
-use crate::gltf::buffer::Target;
+use crate::{
+  game::components::RenderModel,
+  gltf::buffer::Target,
+  na::{Matrix4, Quaternion, UnitQuaternion},
+  renderer_common::handle::Handle,
+  scene_graph::components::*,
+  util::anyhow_from_poisoned,
+  wgpu_renderer::{gltf_import::BasisuImages, model::StreamingMesh},
+  Context,
+};
+use anyhow::anyhow;
 use gltf::Document;
+use legion::{systems::CommandBuffer, Entity};
 
 #[derive(Debug)]
 pub struct GltfScene {
   pub(crate) scene_id: usize,
+  pub(crate) path: String,
 
   pub(crate) document: Document,
   pub(crate) buffers: Vec<gltf::buffer::Data>,
   pub(crate) images: Vec<gltf::image::Data>,
+  /// KTX2 files of `KHR_texture_basisu` textures
+  pub(crate) basisu_images: BasisuImages,
   pub(crate) wgpu_resources: Option<WgpuResources>,
 }
 
@@ -20,14 +34,78 @@ impl GltfScene {
   ) -> Self {
     let instance = Self {
       scene_id,
+      path: String::new(),
       document,
       buffers,
       images,
+      basisu_images: BasisuImages::default(),
       wgpu_resources: None,
     };
     instance
   }
 
+  /// Imports the document's default scene, or its first scene if none is set
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn import<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+    let (document, buffers, images, basisu_images) =
+      crate::wgpu_renderer::gltf_import::import(path.as_ref())?;
+    let scene_id = document.default_scene().map(|s| s.index()).unwrap_or(0);
+    let mut scene = Self::from_import(document, scene_id, buffers, images);
+    scene.basisu_images = basisu_images;
+    scene.path = path.as_ref().display().to_string();
+    Ok(scene)
+  }
+
+  pub fn scene(&self) -> anyhow::Result<gltf::Scene> {
+    self
+      .document
+      .scenes()
+      .nth(self.scene_id)
+      .ok_or_else(|| anyhow!("document does not have a scene {}", self.scene_id))
+  }
+
+  ///
+  /// Loads every mesh in the document as a StreamingMesh.
+  /// The returned handles are indexed by gltf mesh index
+  pub fn load_models(&self, context: &mut Context) -> anyhow::Result<Vec<Handle<StreamingMesh>>> {
+    let mut handles = Vec::new();
+    for mesh in self.document.meshes() {
+      let mut model = StreamingMesh::new_with_index(self.path.clone(), mesh.index());
+      model.load_from_gltf(
+        context,
+        &self.document,
+        &self.buffers,
+        &self.images,
+        &self.basisu_images,
+      )?;
+      let handle = context
+        .resources
+        .models
+        .write()
+        .map_err(anyhow_from_poisoned)?
+        .insert(model);
+      handles.push(handle);
+    }
+    Ok(handles)
+  }
+
+  ///
+  /// Queues an entity for each node in the scene, returning the root entities.
+  /// `models` is indexed by gltf mesh index, as returned by `load_models`.
+  /// Mesh nodes without an entry get a RenderModel with no model
+  pub fn spawn(
+    &self,
+    commands: &mut CommandBuffer,
+    models: &[Handle<StreamingMesh>],
+  ) -> anyhow::Result<Vec<Entity>> {
+    let scene = self.scene()?;
+    let roots = scene
+      .nodes()
+      .map(|node| spawn_node(&node, None, &Matrix4::identity(), commands, models))
+      .collect();
+    Ok(roots)
+  }
+
   pub fn init_buffers(
     &mut self,
     _queue: &wgpu::Queue,
@@ -49,7 +127,154 @@ impl GltfScene {
   }
 }
 
+fn spawn_node(
+  node: &gltf::Node,
+  parent: Option<Entity>,
+  parent_to_world: &Matrix4<f32>,
+  commands: &mut CommandBuffer,
+  models: &[Handle<StreamingMesh>],
+) -> Entity {
+  let (t, r, s) = node.transform().decomposed();
+  let local_to_parent = Matrix4::from(node.transform().matrix());
+  let local_to_world = parent_to_world * local_to_parent;
+  let entity = commands.push((
+    Translation::new(t[0], t[1], t[2]),
+    Rotation(UnitQuaternion::from_quaternion(Quaternion::new(
+      r[3], r[0], r[1], r[2],
+    ))),
+    NonUniformScale::new(s[0], s[1], s[2]),
+    LocalToWorld(local_to_world),
+  ));
+  if let Some(parent) = parent {
+    // PreviousParent is already up to date, so ParentUpdateSystem
+    // won't append this entity to Children a second time
+    commands.add_component(entity, Parent(parent));
+    commands.add_component(entity, PreviousParent(Some(parent)));
+    commands.add_component(entity, LocalToParent(local_to_parent));
+  }
+  if let Some(mesh) = node.mesh() {
+    let model_id = node
+      .name()
+      .map(String::from)
+      .unwrap_or_else(|| format!("node_{}", node.index()));
+    commands.add_component(
+      entity,
+      RenderModel::new(models.get(mesh.index()).copied(), true, model_id),
+    );
+  }
+  let children: Vec<Entity> = node
+    .children()
+    .map(|child| spawn_node(&child, Some(entity), &local_to_world, commands, models))
+    .collect();
+  if !children.is_empty() {
+    commands.add_component(entity, Children::with(&children));
+  }
+  entity
+}
+
 #[derive(Default, Debug)]
 pub struct WgpuResources {
   buffers: Vec<wgpu::Buffer>,
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    game::systems::DynParallelRunnable, na::Vector3, scene_graph::transform_system_bundle,
+  };
+  use legion::{Resources, Schedule, World};
+
+  const NESTED_GLTF: &str = r#"{
+    "asset": { "version": "2.0" },
+    "scene": 0,
+    "scenes": [{ "nodes": [0] }],
+    "nodes": [
+      { "translation": [1.0, 0.0, 0.0], "children": [1] },
+      { "name": "middle", "translation": [0.0, 2.0, 0.0], "children": [2] },
+      { "translation": [0.0, 0.0, 3.0], "scale": [2.0, 2.0, 2.0], "mesh": 0 }
+    ],
+    "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+    "accessors": [{
+      "componentType": 5126, "count": 3, "type": "VEC3",
+      "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 1.0]
+    }]
+  }"#;
+
+  fn world_position(world: &World, entity: Entity) -> Vector3<f32> {
+    let entry = world.entry_ref(entity).unwrap();
+    let local_to_world = entry.get_component::<LocalToWorld>().unwrap();
+    local_to_world.fixed_slice::<3, 1>(0, 3).into_owned()
+  }
+
+  fn children(world: &World, entity: Entity) -> Vec<Entity> {
+    let entry = world.entry_ref(entity).unwrap();
+    entry.get_component::<Children>().unwrap().0.to_vec()
+  }
+
+  #[test]
+  fn test_spawn_nested_nodes() {
+    let gltf = gltf::Gltf::from_slice(NESTED_GLTF.as_bytes()).unwrap();
+    let scene = GltfScene::from_import(gltf.document, 0, vec![], vec![]);
+    let mut world = World::default();
+    let mut resources = Resources::default();
+
+    let mut commands = CommandBuffer::new(&world);
+    let roots = scene.spawn(&mut commands, &[]).unwrap();
+    commands.flush(&mut world, &mut resources);
+
+    assert_eq!(roots.len(), 1);
+    let root = roots[0];
+    let middle = children(&world, root);
+    assert_eq!(middle.len(), 1);
+    let leaf = children(&world, middle[0]);
+    assert_eq!(leaf.len(), 1);
+    let leaf = leaf[0];
+
+    let leaf_entry = world.entry_ref(leaf).unwrap();
+    assert_eq!(
+      *leaf_entry.get_component::<Parent>().unwrap(),
+      Parent(middle[0])
+    );
+    let model = leaf_entry.get_component::<RenderModel>().unwrap();
+    assert!(model.model.is_none());
+    assert_eq!(model.model_id, "node_2");
+    assert!(world
+      .entry_ref(root)
+      .unwrap()
+      .get_component::<Parent>()
+      .is_err());
+
+    assert_eq!(world_position(&world, leaf), Vector3::new(1.0, 2.0, 3.0));
+    assert_eq!(
+      world_position(&world, middle[0]),
+      Vector3::new(1.0, 2.0, 0.0)
+    );
+  }
+
+  #[test]
+  fn test_spawned_hierarchy_is_stable_under_transform_systems() {
+    let gltf = gltf::Gltf::from_slice(NESTED_GLTF.as_bytes()).unwrap();
+    let scene = GltfScene::from_import(gltf.document, 0, vec![], vec![]);
+    let mut world = World::default();
+    let mut resources = Resources::default();
+
+    let mut commands = CommandBuffer::new(&world);
+    let roots = scene.spawn(&mut commands, &[]).unwrap();
+    commands.flush(&mut world, &mut resources);
+
+    let mut builder = Schedule::builder();
+    for system in transform_system_bundle::build() {
+      builder.add_system(DynParallelRunnable::new(system)).flush();
+    }
+    let mut schedule = builder.build();
+    schedule.execute(&mut world, &mut resources);
+    schedule.execute(&mut world, &mut resources);
+
+    let middle = children(&world, roots[0]);
+    assert_eq!(middle.len(), 1);
+    let leaf = children(&world, middle[0]);
+    assert_eq!(leaf.len(), 1);
+    assert_eq!(world_position(&world, leaf[0]), Vector3::new(1.0, 2.0, 3.0));
+  }
+}
@@ -0,0 +1,639 @@
+//! GPU-driven frustum culling of the opaque scene.
+//!
+//! On the CPU path, the Context culls instances against the camera every frame, so the
+//! instance buffer is repacked and uploaded whenever the camera moves. With GPU culling,
+//! the instance buffer keeps every PBR instance, and is only uploaded when they change,
+//! as tracked by the [InstanceCache](super::instance_cache::InstanceCache).
+//! The batches and indirect draws are planned again when the instances, models or meshes
+//! change, so a frame only uploads the camera's frustum.
+//!
+//! Each frame, a compute pass tests the instances' bounding spheres against the frustum,
+//! picks their level of detail from their size on screen, and compacts the visible
+//! instances of each model and level into the culled instance buffer, counting them into
+//! the indirect arguments of the level's meshes.
+//! The opaque pass then draws each mesh with `draw_indexed_indirect`.
+//!
+//! Blended meshes are still sorted and drawn from the CPU, and aren't culled.
+use std::{borrow::Cow, ops::Range};
+
+use wgpu::{
+  Adapter, BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferUsages, ComputePipeline,
+  Device, DownlevelFlags, Limits, Queue, ShaderStages,
+};
+
+use crate::{
+  camera::Camera,
+  math::{Frustum, Sphere},
+  renderer_common::handle::Handle,
+  wgpu_renderer::{
+    mesh::Mesh, model::StreamingMesh, model_instance::LodGroup,
+    pipeline_state::create_compute_pipeline, ModelInstance,
+  },
+};
+
+/// Invocations per workgroup of cull_instances.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+/// Storage buffers bound by cull_instances.wgsl
+const STORAGE_BUFFER_COUNT: u32 = 4;
+/// Level of detail thresholds a batch holds. Further levels aren't drawn by the culling pass
+pub const MAX_LOD_THRESHOLDS: usize = 4;
+
+///
+/// Whether the adapter can run the culling compute pass and draw its output indirectly.
+/// Downlevel adapters, like WebGL2's, fall back to culling on the CPU
+pub fn supports_gpu_culling(adapter: &Adapter, limits: &Limits) -> bool {
+  adapter
+    .get_downlevel_properties()
+    .flags
+    .contains(DownlevelFlags::COMPUTE_SHADERS | DownlevelFlags::INDIRECT_EXECUTION)
+    && limits.max_storage_buffers_per_shader_stage >= STORAGE_BUFFER_COUNT
+}
+
+/// The `Cull` uniform block in cull_instances.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+  planes: [[f32; 4]; 6],
+  /// the camera's position, and the tangent of half its vertical field of view
+  eye: [f32; 4],
+  instance_count: u32,
+  batch_count: u32,
+  _padding: [u32; 2],
+}
+
+impl CullUniform {
+  fn new(camera: &Camera, frustum: &Frustum, instance_count: u32, batch_count: u32) -> Self {
+    let mut planes = [[0.0; 4]; 6];
+    for (coefficients, plane) in planes.iter_mut().zip(frustum.planes.iter()) {
+      *coefficients = [
+        plane.normal.x,
+        plane.normal.y,
+        plane.normal.z,
+        plane.distance,
+      ];
+    }
+    let position = camera.position;
+    Self {
+      planes,
+      eye: [
+        position.x,
+        position.y,
+        position.z,
+        (camera.fovy * 0.5).tan(),
+      ],
+      instance_count,
+      batch_count,
+      _padding: [0; 2],
+    }
+  }
+}
+
+///
+/// The instances of a model, as a `Batch` in cull_instances.wgsl.
+/// Its visible instances are counted into the `draw_count` indirect draws of their
+/// level of detail, which follow the previous level's from `first_draw`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullBatch {
+  /// model space bounding sphere, with a negative radius if the model has no bounds
+  pub sphere: [f32; 4],
+  /// the first `lod_count - 1` are the instances' level of detail thresholds
+  pub lod_thresholds: [f32; MAX_LOD_THRESHOLDS],
+  pub first_instance: u32,
+  pub instance_end: u32,
+  pub first_draw: u32,
+  /// indirect draws of each level of detail
+  pub draw_count: u32,
+  pub lod_count: u32,
+  /// start of the batch in the culled instance buffer,
+  /// where each level of detail has room for all of the batch's instances
+  pub first_culled: u32,
+  pub _padding: [u32; 2],
+}
+
+/// wgpu's argument layout for `draw_indexed_indirect`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirect {
+  pub index_count: u32,
+  /// counted by the culling pass
+  pub instance_count: u32,
+  pub first_index: u32,
+  pub base_vertex: i32,
+  pub first_instance: u32,
+}
+
+/// A mesh drawn with the culled instances of its model
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndirectDraw {
+  pub mesh: Handle<Mesh>,
+  /// offset of the model's instances in the culled instance buffer
+  pub instance_offset: BufferAddress,
+  /// offset of the draw's arguments in the indirect buffer
+  pub args_offset: BufferAddress,
+}
+
+/// The batches and draws of the instance buffer, before they're uploaded
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndirectPlan {
+  pub batches: Vec<CullBatch>,
+  pub args: Vec<DrawIndexedIndirect>,
+  pub draws: Vec<IndirectDraw>,
+}
+
+///
+/// Plans a batch for each of the `groups`. `meshes` returns the model's bounding sphere,
+/// and the meshes to draw with the indices of a level of detail, which each get an
+/// indirect draw. It must return the same meshes, in the same order, for every level.
+/// The culled instances of a level are compacted to the start of its range
+/// in the culled instance buffer
+pub fn plan_indirect_draws<F>(groups: &[LodGroup], mut meshes: F) -> IndirectPlan
+where
+  F: FnMut(Handle<StreamingMesh>, usize) -> (Option<Sphere>, Vec<(Handle<Mesh>, Range<u32>)>),
+{
+  let instance_size = std::mem::size_of::<ModelInstance>() as BufferAddress;
+  let args_size = std::mem::size_of::<DrawIndexedIndirect>() as BufferAddress;
+  let mut plan = IndirectPlan::default();
+  let mut culled_end = 0;
+  for group in groups {
+    let range = &group.range;
+    let thresholds = &group.lod_thresholds[..group.lod_thresholds.len().min(MAX_LOD_THRESHOLDS)];
+    let mut lod_thresholds = [0.0; MAX_LOD_THRESHOLDS];
+    lod_thresholds[..thresholds.len()].copy_from_slice(thresholds);
+    let lod_count = thresholds.len() as u32 + 1;
+    let instance_count = range.instances.end - range.instances.start;
+    let first_culled = culled_end;
+    culled_end += instance_count * lod_count;
+
+    let (bounds, model_meshes) = meshes(range.model, 0);
+    let sphere = bounds.map_or([0.0, 0.0, 0.0, -1.0], |sphere| {
+      [
+        sphere.center.x,
+        sphere.center.y,
+        sphere.center.z,
+        sphere.radius,
+      ]
+    });
+    plan.batches.push(CullBatch {
+      sphere,
+      lod_thresholds,
+      first_instance: range.instances.start,
+      instance_end: range.instances.end,
+      first_draw: plan.args.len() as u32,
+      draw_count: model_meshes.len() as u32,
+      lod_count,
+      first_culled,
+      _padding: [0; 2],
+    });
+    let mut model_meshes = Some(model_meshes);
+    for lod in 0..lod_count {
+      let lod_meshes = match model_meshes.take() {
+        Some(lod_meshes) => lod_meshes,
+        None => meshes(range.model, lod as usize).1,
+      };
+      let culled = (first_culled + lod * instance_count) as BufferAddress;
+      for (mesh, indices) in lod_meshes {
+        plan.draws.push(IndirectDraw {
+          mesh,
+          instance_offset: culled * instance_size,
+          args_offset: plan.args.len() as BufferAddress * args_size,
+        });
+        plan.args.push(DrawIndexedIndirect {
+          index_count: indices.end - indices.start,
+          instance_count: 0,
+          first_index: indices.start,
+          base_vertex: 0,
+          first_instance: 0,
+        });
+      }
+    }
+  }
+  plan
+}
+
+///
+/// A buffer which is replaced by a larger one when its contents outgrow it.
+/// Never empty, as empty buffers can't be bound
+#[derive(Debug)]
+struct GrowableBuffer {
+  label: &'static str,
+  usage: BufferUsages,
+  buffer: Buffer,
+  size: BufferAddress,
+}
+
+impl GrowableBuffer {
+  fn new(device: &Device, label: &'static str, usage: BufferUsages) -> Self {
+    let size = 256;
+    Self {
+      label,
+      usage,
+      buffer: Self::create(device, label, usage, size),
+      size,
+    }
+  }
+
+  fn create(device: &Device, label: &str, usage: BufferUsages, size: BufferAddress) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some(label),
+      size,
+      usage,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn reserve(&mut self, device: &Device, size: BufferAddress) {
+    if size > self.size {
+      self.size = size.next_power_of_two();
+      self.buffer = Self::create(device, self.label, self.usage, self.size);
+    }
+  }
+
+  fn write(&mut self, device: &Device, queue: &Queue, data: &[u8]) {
+    self.reserve(device, data.len() as BufferAddress);
+    queue.write_buffer(&self.buffer, 0, data);
+  }
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+  wgpu::BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::COMPUTE,
+    ty: wgpu::BindingType::Buffer {
+      ty: wgpu::BufferBindingType::Storage { read_only },
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    },
+    count: None,
+  }
+}
+
+///
+/// The culling compute pipeline, and the buffers it reads and writes.
+/// `set_plan` uploads the batches of the instances, `prepare` a frame's frustum,
+/// and `cull` records the compute pass
+#[derive(Debug)]
+pub struct GpuCulling {
+  layout: BindGroupLayout,
+  pipeline: ComputePipeline,
+  uniform_buffer: Buffer,
+  batches: GrowableBuffer,
+  /// the planned arguments, copied over `args` before each culling pass
+  initial_args: GrowableBuffer,
+  /// read as storage by the compute pass, and as indirect arguments by the opaque pass
+  args: GrowableBuffer,
+  /// read as storage by the compute pass, and as instances by the opaque pass
+  culled_instances: GrowableBuffer,
+  bind_group: Option<BindGroup>,
+  draws: Vec<IndirectDraw>,
+  /// size of the planned arguments in bytes
+  args_size: BufferAddress,
+  instance_count: u32,
+  batch_count: u32,
+}
+
+impl GpuCulling {
+  pub fn new(device: &Device) -> Self {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("cull_layout"),
+      entries: &[
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<CullUniform>() as _),
+          },
+          count: None,
+        },
+        storage_entry(1, true),
+        storage_entry(2, true),
+        storage_entry(3, false),
+        storage_entry(4, false),
+      ],
+    });
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+      label: Some("cull_instances.wgsl"),
+      source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(concat!(
+        env!("OUT_DIR"),
+        "/shaders/cull_instances.wgsl"
+      )))),
+    });
+    let pipeline = create_compute_pipeline(device, "cull_pipeline", &[&layout], &shader);
+    let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("cull_uniform_buffer"),
+      size: std::mem::size_of::<CullUniform>() as _,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    Self {
+      layout,
+      pipeline,
+      uniform_buffer,
+      batches: GrowableBuffer::new(
+        device,
+        "cull_batch_buffer",
+        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+      ),
+      initial_args: GrowableBuffer::new(
+        device,
+        "initial_indirect_buffer",
+        BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+      ),
+      args: GrowableBuffer::new(
+        device,
+        "indirect_buffer",
+        BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+      ),
+      culled_instances: GrowableBuffer::new(
+        device,
+        "culled_instance_buffer",
+        BufferUsages::STORAGE | BufferUsages::VERTEX,
+      ),
+      bind_group: None,
+      draws: Vec::new(),
+      args_size: 0,
+      instance_count: 0,
+      batch_count: 0,
+    }
+  }
+
+  ///
+  /// Uploads the plan of the instances in `instances`, which needs the STORAGE usage.
+  /// Only needed when the instances or their models change
+  pub(crate) fn set_plan(
+    &mut self,
+    device: &Device,
+    queue: &Queue,
+    instances: &Buffer,
+    plan: IndirectPlan,
+  ) {
+    let instance_count = plan
+      .batches
+      .iter()
+      .map(|batch| batch.instance_end)
+      .max()
+      .unwrap_or(0);
+    let culled_count = plan
+      .batches
+      .iter()
+      .map(|batch| {
+        batch.first_culled + (batch.instance_end - batch.first_instance) * batch.lod_count
+      })
+      .max()
+      .unwrap_or(0);
+    self.instance_count = instance_count;
+    self.batch_count = plan.batches.len() as u32;
+    self.draws = plan.draws;
+    if instance_count == 0 || self.draws.is_empty() {
+      self.bind_group = None;
+      return;
+    }
+
+    self
+      .batches
+      .write(device, queue, bytemuck::cast_slice(&plan.batches));
+    let args: &[u8] = bytemuck::cast_slice(&plan.args);
+    self.initial_args.write(device, queue, args);
+    self.args.reserve(device, args.len() as BufferAddress);
+    self.args_size = args.len() as BufferAddress;
+    self.culled_instances.reserve(
+      device,
+      culled_count as BufferAddress * std::mem::size_of::<ModelInstance>() as BufferAddress,
+    );
+    self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("cull_bind_group"),
+      layout: &self.layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: self.uniform_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: instances.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: self.batches.buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 3,
+          resource: self.args.buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+          binding: 4,
+          resource: self.culled_instances.buffer.as_entire_binding(),
+        },
+      ],
+    }));
+  }
+
+  /// Uploads the camera the planned instances are culled with this frame
+  pub(crate) fn prepare(&self, queue: &Queue, camera: &Camera) {
+    if self.bind_group.is_none() {
+      return;
+    }
+    queue.write_buffer(
+      &self.uniform_buffer,
+      0,
+      bytemuck::cast_slice(&[CullUniform::new(
+        camera,
+        &camera.frustum(),
+        self.instance_count,
+        self.batch_count,
+      )]),
+    );
+  }
+
+  /// Records the culling pass, which must run before the draws are made
+  pub(crate) fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+    let bind_group = match &self.bind_group {
+      Some(bind_group) => bind_group,
+      None => return,
+    };
+    // instance counts start at zero every frame
+    encoder.copy_buffer_to_buffer(
+      &self.initial_args.buffer,
+      0,
+      &self.args.buffer,
+      0,
+      self.args_size,
+    );
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+      label: Some("cull pass"),
+    });
+    pass.set_pipeline(&self.pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    let workgroups = (self.instance_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+    pass.dispatch(workgroups, 1, 1);
+  }
+
+  /// The meshes drawn by the opaque pass, as planned by the last `set_plan`
+  pub fn draws(&self) -> &[IndirectDraw] {
+    if self.bind_group.is_some() {
+      &self.draws
+    } else {
+      &[]
+    }
+  }
+
+  pub fn culled_instances(&self) -> &Buffer {
+    &self.culled_instances.buffer
+  }
+
+  pub fn indirect_buffer(&self) -> &Buffer {
+    &self.args.buffer
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{renderer_common::handle::HandleIndex, wgpu_renderer::model_instance::InstanceRange};
+  use nalgebra_glm::vec3;
+
+  fn group(
+    model: Handle<StreamingMesh>,
+    instances: Range<u32>,
+    lod_thresholds: &[f32],
+  ) -> LodGroup {
+    LodGroup {
+      range: InstanceRange {
+        model,
+        lod: 0,
+        instances,
+      },
+      lod_thresholds: lod_thresholds.to_vec(),
+    }
+  }
+
+  #[test]
+  fn test_plan_indirect_draws() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let b: Handle<StreamingMesh> = HandleIndex::new(1, 0).into_typed();
+    let c: Handle<StreamingMesh> = HandleIndex::new(2, 0).into_typed();
+    let mesh_0: Handle<Mesh> = HandleIndex::new(0, 0).into_typed();
+    let mesh_1: Handle<Mesh> = HandleIndex::new(1, 0).into_typed();
+    let mesh_2: Handle<Mesh> = HandleIndex::new(2, 0).into_typed();
+    let groups = vec![
+      group(a, 0..3, &[]),
+      group(b, 3..4, &[0.5]),
+      group(c, 4..6, &[]),
+    ];
+    let plan = plan_indirect_draws(&groups, |model, lod| {
+      if model == a {
+        assert_eq!(lod, 0);
+        (
+          Some(Sphere::new(vec3(1.0, 2.0, 3.0), 0.5)),
+          vec![(mesh_0, 0..36), (mesh_1, 0..6)],
+        )
+      } else if model == b {
+        (
+          None,
+          vec![(mesh_2, (lod as u32 * 12)..(lod as u32 * 12 + 6))],
+        )
+      } else {
+        // models without opaque meshes still get a batch, so every instance has one
+        (None, vec![])
+      }
+    });
+
+    assert_eq!(
+      plan.batches,
+      vec![
+        CullBatch {
+          sphere: [1.0, 2.0, 3.0, 0.5],
+          lod_thresholds: [0.0; 4],
+          first_instance: 0,
+          instance_end: 3,
+          first_draw: 0,
+          draw_count: 2,
+          lod_count: 1,
+          first_culled: 0,
+          _padding: [0; 2],
+        },
+        CullBatch {
+          sphere: [0.0, 0.0, 0.0, -1.0],
+          lod_thresholds: [0.5, 0.0, 0.0, 0.0],
+          first_instance: 3,
+          instance_end: 4,
+          first_draw: 2,
+          draw_count: 1,
+          lod_count: 2,
+          first_culled: 3,
+          _padding: [0; 2],
+        },
+        CullBatch {
+          sphere: [0.0, 0.0, 0.0, -1.0],
+          lod_thresholds: [0.0; 4],
+          first_instance: 4,
+          instance_end: 6,
+          first_draw: 4,
+          draw_count: 0,
+          lod_count: 1,
+          first_culled: 5,
+          _padding: [0; 2],
+        },
+      ]
+    );
+    let indices: Vec<(u32, u32)> = plan
+      .args
+      .iter()
+      .map(|args| (args.first_index, args.index_count))
+      .collect();
+    assert_eq!(indices, vec![(0, 36), (0, 6), (0, 6), (12, 6)]);
+    assert!(plan.args.iter().all(|args| args.instance_count == 0));
+
+    let instance_size = std::mem::size_of::<ModelInstance>() as BufferAddress;
+    let args_size = std::mem::size_of::<DrawIndexedIndirect>() as BufferAddress;
+    assert_eq!(
+      plan.draws,
+      vec![
+        IndirectDraw {
+          mesh: mesh_0,
+          instance_offset: 0,
+          args_offset: 0,
+        },
+        IndirectDraw {
+          mesh: mesh_1,
+          instance_offset: 0,
+          args_offset: args_size,
+        },
+        IndirectDraw {
+          mesh: mesh_2,
+          instance_offset: 3 * instance_size,
+          args_offset: 2 * args_size,
+        },
+        // each level of detail has room for all of the batch's instances
+        IndirectDraw {
+          mesh: mesh_2,
+          instance_offset: 4 * instance_size,
+          args_offset: 3 * args_size,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_plan_caps_lod_thresholds() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let mesh: Handle<Mesh> = HandleIndex::new(0, 0).into_typed();
+    let groups = vec![group(a, 0..1, &[0.5, 0.4, 0.3, 0.2, 0.1])];
+    let plan = plan_indirect_draws(&groups, |_, _| (None, vec![(mesh, 0..3)]));
+    assert_eq!(plan.batches[0].lod_count, MAX_LOD_THRESHOLDS as u32 + 1);
+    assert_eq!(plan.batches[0].lod_thresholds, [0.5, 0.4, 0.3, 0.2]);
+    assert_eq!(plan.args.len(), MAX_LOD_THRESHOLDS + 1);
+  }
+
+  #[test]
+  fn test_gpu_layouts_match_shader() {
+    // sizes of the structs in cull_instances.wgsl
+    assert_eq!(std::mem::size_of::<CullUniform>(), 128);
+    assert_eq!(std::mem::size_of::<CullBatch>(), 64);
+    assert_eq!(std::mem::size_of::<DrawIndexedIndirect>(), 20);
+    // the shader's Instance struct
+    assert_eq!(std::mem::size_of::<ModelInstance>(), 17 * 4);
+  }
+}
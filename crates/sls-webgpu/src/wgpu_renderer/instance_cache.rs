@@ -0,0 +1,364 @@
+//! The instances drawn with GPU culling, kept between frames.
+//!
+//! The culling pass tests every instance of the scene, so its instance buffer only needs
+//! to change when the entities do. [InstanceCache] finds the entities whose `LocalToWorld`
+//! or `RenderModel` may have changed with a `maybe_changed` query, which keeps its state
+//! between frames, and hears of removed entities through a world subscription.
+//! Instances are only packed again once one of them was added, removed or changed.
+use std::{
+  collections::HashMap,
+  fmt,
+  sync::{Arc, Mutex, Weak},
+};
+
+use legion::{
+  component, maybe_changed,
+  world::{Event, EventSender, WorldId},
+  Entity, EntityStore, IntoQuery, World,
+};
+use nalgebra_glm::Mat4;
+
+use crate::{
+  game::components::RenderModel,
+  renderer_common::handle::Handle,
+  scene_graph::components::LocalToWorld,
+  wgpu_renderer::{
+    model::StreamingMesh,
+    model_instance::{append_instances_by_model, InstanceRange, LodGroup},
+    pipeline_state::ShadingModel,
+    ModelInstance,
+  },
+};
+
+/// The render state of a shown entity
+#[derive(Debug, Clone, PartialEq)]
+struct CachedInstance {
+  model: Handle<StreamingMesh>,
+  shading_model: ShadingModel,
+  casts_shadows: bool,
+  receives_shadows: bool,
+  lod_thresholds: Vec<f32>,
+  local_to_world: Mat4,
+}
+
+impl CachedInstance {
+  /// None if the entity isn't drawn
+  fn new(local_to_world: &LocalToWorld, model: &RenderModel) -> Option<Self> {
+    match model.model {
+      Some(handle) if model.is_shown => Some(Self {
+        model: handle,
+        shading_model: model.shading_model,
+        casts_shadows: model.casts_shadows,
+        receives_shadows: model.receives_shadows,
+        lod_thresholds: model.lod_thresholds.clone(),
+        local_to_world: local_to_world.0,
+      }),
+      _ => None,
+    }
+  }
+
+  fn instance(&self) -> ModelInstance {
+    ModelInstance::from(&LocalToWorld(self.local_to_world))
+  }
+}
+
+/// The instance buffer's contents, and the ranges drawn from it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PackedInstances {
+  pub instances: Vec<ModelInstance>,
+  /// PBR instances, culled and drawn at the level of detail picked by the culling pass
+  pub models: Vec<LodGroup>,
+  pub debug_lights: Vec<InstanceRange>,
+  /// drawn into the shadow maps at their finest level of detail
+  pub shadow_casters: Vec<InstanceRange>,
+}
+
+/// Runs the persistent query for the entities which may have changed since its last run
+type ChangedQuery =
+  Box<dyn FnMut(&World, &mut dyn FnMut(Entity, &LocalToWorld, &RenderModel)) + Send + Sync>;
+
+fn changed_query() -> ChangedQuery {
+  let mut query = <(Entity, &LocalToWorld, &RenderModel)>::query()
+    .filter(maybe_changed::<LocalToWorld>() | maybe_changed::<RenderModel>());
+  Box::new(move |world, visit| {
+    for (entity, local_to_world, model) in query.iter(world) {
+      visit(*entity, local_to_world, model);
+    }
+  })
+}
+
+///
+/// Collects the entities removed from the world's archetypes.
+/// The world drops the subscription once the cache holding the list is gone
+struct RemovedEntities(Weak<Mutex<Vec<Entity>>>);
+
+impl EventSender for RemovedEntities {
+  fn send(&self, event: Event) -> bool {
+    let removed = match self.0.upgrade() {
+      Some(removed) => removed,
+      None => return false,
+    };
+    if let Event::EntityRemoved(entity, _) = event {
+      removed
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(entity);
+    }
+    true
+  }
+}
+
+///
+/// The shown entities of a world, and whether they changed since they were last packed.
+/// Entities are packed in the order they were first seen in
+#[derive(Default)]
+pub struct InstanceCache {
+  /// the world `changed` and `removed` follow
+  world: Option<WorldId>,
+  changed: Option<ChangedQuery>,
+  removed: Arc<Mutex<Vec<Entity>>>,
+  entities: Vec<(Entity, CachedInstance)>,
+  /// index of each entity in `entities`
+  indices: HashMap<Entity, usize>,
+  dirty: bool,
+}
+
+impl fmt::Debug for InstanceCache {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("InstanceCache")
+      .field("world", &self.world)
+      .field("entities", &self.entities.len())
+      .field("dirty", &self.dirty)
+      .finish()
+  }
+}
+
+impl InstanceCache {
+  ///
+  /// Applies the changes made to `world` since the last update.
+  /// Returns the packed instances if any of them changed
+  pub fn update(&mut self, world: &mut World) -> Option<PackedInstances> {
+    if self.world != Some(world.id()) {
+      self.follow(world);
+    }
+    let Self {
+      changed,
+      entities,
+      indices,
+      dirty,
+      ..
+    } = self;
+    if let Some(changed) = changed.as_mut() {
+      changed(&*world, &mut |entity, local_to_world, model| {
+        // archetypes are flagged as a whole, so most entities are unchanged
+        let instance = CachedInstance::new(local_to_world, model);
+        *dirty |= set_entity(entities, indices, entity, instance);
+      });
+    }
+    let removed = std::mem::take(&mut *self.removed.lock().unwrap_or_else(|e| e.into_inner()));
+    for entity in removed {
+      // entities moved to another archetype were seen by the query
+      let still_drawn = world.entry_ref(entity).map_or(false, |entry| {
+        entry.get_component::<LocalToWorld>().is_ok()
+          && entry.get_component::<RenderModel>().is_ok()
+      });
+      if !still_drawn {
+        self.dirty |= set_entity(&mut self.entities, &mut self.indices, entity, None);
+      }
+    }
+    if !self.dirty {
+      return None;
+    }
+    self.dirty = false;
+    Some(self.pack())
+  }
+
+  /// Starts over with the entities of `world`
+  fn follow(&mut self, world: &mut World) {
+    // the previous subscription ends with its list
+    self.removed = Arc::default();
+    world.subscribe(
+      RemovedEntities(Arc::downgrade(&self.removed)),
+      component::<LocalToWorld>() & component::<RenderModel>(),
+    );
+    self.world = Some(world.id());
+    self.changed = Some(changed_query());
+    self.entities.clear();
+    self.indices.clear();
+    self.dirty = true;
+  }
+
+  ///
+  /// Groups the PBR instances by model and level of detail thresholds, followed by
+  /// the debug lights, and the shadow casters, which get their own instances
+  fn pack(&self) -> PackedInstances {
+    let mut pbr: Vec<&CachedInstance> = self
+      .entities
+      .iter()
+      .map(|(_, cached)| cached)
+      .filter(|cached| cached.shading_model == ShadingModel::Pbr)
+      .collect();
+    pbr.sort_by(|a, b| {
+      (a.model.0, &a.lod_thresholds)
+        .partial_cmp(&(b.model.0, &b.lod_thresholds))
+        .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut packed = PackedInstances::default();
+    for cached in pbr {
+      let end = packed.instances.len() as u32 + 1;
+      packed.instances.push(
+        cached
+          .instance()
+          .with_receives_shadows(cached.receives_shadows),
+      );
+      match packed.models.last_mut() {
+        Some(group)
+          if group.range.model == cached.model && group.lod_thresholds == cached.lod_thresholds =>
+        {
+          group.range.instances.end = end
+        }
+        _ => packed.models.push(LodGroup {
+          range: InstanceRange {
+            model: cached.model,
+            lod: 0,
+            instances: (end - 1)..end,
+          },
+          lod_thresholds: cached.lod_thresholds.clone(),
+        }),
+      }
+    }
+    let debug_lights = self
+      .entities
+      .iter()
+      .filter(|(_, cached)| cached.shading_model == ShadingModel::DebugLight)
+      .map(|(_, cached)| (cached.model, cached.instance()))
+      .collect();
+    packed.debug_lights = append_instances_by_model(&mut packed.instances, debug_lights);
+    let casters = self
+      .entities
+      .iter()
+      .filter(|(_, cached)| cached.casts_shadows)
+      .map(|(_, cached)| (cached.model, cached.instance()))
+      .collect();
+    packed.shadow_casters = append_instances_by_model(&mut packed.instances, casters);
+    packed
+  }
+}
+
+///
+/// Sets or removes the entity's cached instance.
+/// Returns whether that changed the cached instances
+fn set_entity(
+  entities: &mut Vec<(Entity, CachedInstance)>,
+  indices: &mut HashMap<Entity, usize>,
+  entity: Entity,
+  instance: Option<CachedInstance>,
+) -> bool {
+  match (indices.get(&entity).copied(), instance) {
+    (Some(index), Some(instance)) => {
+      let cached = &mut entities[index].1;
+      if *cached == instance {
+        return false;
+      }
+      *cached = instance;
+    }
+    (None, Some(instance)) => {
+      indices.insert(entity, entities.len());
+      entities.push((entity, instance));
+    }
+    (Some(index), None) => {
+      indices.remove(&entity);
+      entities.swap_remove(index);
+      if let Some((moved, _)) = entities.get(index) {
+        indices.insert(*moved, index);
+      }
+    }
+    (None, None) => return false,
+  }
+  true
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{na::Matrix4, renderer_common::handle::HandleIndex};
+  use nalgebra_glm::vec3;
+
+  fn model(index: u32, shading_model: ShadingModel) -> RenderModel {
+    RenderModel {
+      shading_model,
+      ..RenderModel::new(
+        Some(HandleIndex::new(index, 0).into_typed()),
+        true,
+        String::new(),
+      )
+    }
+  }
+
+  fn at(x: f32) -> LocalToWorld {
+    LocalToWorld(Matrix4::new_translation(&vec3(x, 0.0, 0.0)))
+  }
+
+  #[test]
+  fn test_static_scene_is_not_repacked() {
+    let mut world = World::default();
+    world.push((at(0.0), model(0, ShadingModel::Pbr)));
+    world.push((at(1.0), model(1, ShadingModel::Pbr)));
+    world.push((at(2.0), model(0, ShadingModel::Pbr)));
+    let mut cache = InstanceCache::default();
+    let packed = cache
+      .update(&mut world)
+      .expect("first update packs the scene");
+    let ranges: Vec<std::ops::Range<u32>> = packed
+      .models
+      .iter()
+      .map(|group| group.range.instances.clone())
+      .collect();
+    assert_eq!(ranges, vec![0..2, 2..3]);
+    // every instance casts shadows
+    assert_eq!(packed.instances.len(), 6);
+    assert!(cache.update(&mut world).is_none());
+
+    // rewriting the same values flags the archetype, but doesn't change any instance
+    for local_to_world in <&mut LocalToWorld>::query().iter_mut(&mut world) {
+      *local_to_world = LocalToWorld(local_to_world.0);
+    }
+    assert!(cache.update(&mut world).is_none());
+  }
+
+  #[test]
+  fn test_changed_entities_are_repacked() {
+    let mut world = World::default();
+    let moved = world.push((at(0.0), model(0, ShadingModel::Pbr)));
+    let removed = world.push((at(1.0), model(0, ShadingModel::DebugLight)));
+    let mut cache = InstanceCache::default();
+    assert!(cache.update(&mut world).is_some());
+
+    *world
+      .entry(moved)
+      .unwrap()
+      .get_component_mut::<LocalToWorld>()
+      .unwrap() = at(3.0);
+    let packed = cache.update(&mut world).expect("moved entity is repacked");
+    assert_eq!(packed.instances[0].position(), vec3(3.0, 0.0, 0.0));
+
+    world.remove(removed);
+    let packed = cache
+      .update(&mut world)
+      .expect("removed entity is repacked");
+    assert!(packed.debug_lights.is_empty());
+
+    // moving to another archetype doesn't remove the entity
+    world.entry(moved).unwrap().add_component(0_u32);
+    assert!(cache.update(&mut world).is_none());
+
+    world
+      .entry(moved)
+      .unwrap()
+      .remove_component::<RenderModel>();
+    let packed = cache
+      .update(&mut world)
+      .expect("entity without a model is removed");
+    assert!(packed.instances.is_empty());
+  }
+}
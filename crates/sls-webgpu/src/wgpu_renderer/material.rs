@@ -1,14 +1,24 @@
 use crate::{
   renderer_common::{allocator::ResourceManager, handle::Handle},
-  wgpu_renderer::textures::{basic_texture_bind_group, TextureResource},
+  wgpu_renderer::{
+    compressed_texture::check_ktx2,
+    gltf_import::BasisuImages,
+    resource_view::ResourceContext,
+    textures::{ColorSpace, Sampler, TextureResource},
+  },
 };
+use bitflags::bitflags;
 use gltf::image::Format;
 use image::{Bgr, DynamicImage, ImageBuffer};
 use nalgebra_glm::{vec3, vec4, Vec3, Vec4};
+use std::sync::Arc;
 
-use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, Device, Queue};
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, Device, Queue,
+};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum AlphaMode {
   Opaque,
   Mask,
@@ -25,18 +35,13 @@ impl From<gltf::material::AlphaMode> for AlphaMode {
   }
 }
 
-#[derive(Debug)]
-pub struct Sampler {}
-
-impl Sampler {
-  pub fn from_gltf(_gltf_sampler: &gltf::texture::Sampler) -> Self {
-    Self {}
-  }
-}
-
 #[derive(Debug)]
 pub struct TextureInfoData {
   pub rgba: Option<DynamicImage>,
+  /// KTX2 file of a `KHR_texture_basisu` texture. Used instead of `rgba` when set
+  pub compressed: Option<Arc<[u8]>>,
+  /// sRGB for color textures, linear for data textures
+  pub color_space: ColorSpace,
   pub tex_coord_index: u32,
   pub name: Option<String>,
   pub sampler: Sampler,
@@ -49,19 +54,50 @@ impl TextureInfoData {
   pub fn load_texture(
     &mut self,
     textures: &mut ResourceManager<TextureResource>,
+    resources: &ResourceContext,
     queue: &Queue,
     device: &Device,
   ) -> anyhow::Result<()> {
-    match (self.texture_resource_handle, self.rgba.as_ref()) {
-      (Some(_handle), _) => Ok(()),
-      (None, Some(rbga)) => {
-        let texture = TextureResource::from_image(&rbga, queue, device)?;
-        let texture_handle = textures.insert(texture);
-        self.texture_resource_handle = Some(texture_handle);
-        Ok(())
-      }
-      (None, None) => anyhow::bail!("rgba data is missing!"),
+    if self.texture_resource_handle.is_some() {
+      return Ok(());
     }
+    let texture = self
+      .create_texture(queue, device, resources)?
+      .ok_or_else(|| anyhow::anyhow!("rgba data is missing!"))?;
+    let texture_handle = textures.insert(texture);
+    self.texture_resource_handle = Some(texture_handle);
+    Ok(())
+  }
+
+  ///
+  /// Uploads the compressed texture if there is one, or else the rgba image.
+  /// Returns None if the texture has neither
+  pub fn create_texture(
+    &self,
+    queue: &Queue,
+    device: &Device,
+    resources: &ResourceContext,
+  ) -> anyhow::Result<Option<TextureResource>> {
+    let texture = match (self.compressed.as_ref(), self.rgba.as_ref()) {
+      (Some(compressed), _) => TextureResource::from_compressed(
+        compressed,
+        self.color_space,
+        &self.sampler,
+        queue,
+        device,
+        resources,
+      )?,
+      (None, Some(rbga)) => TextureResource::from_image(
+        &rbga,
+        self.color_space,
+        &self.sampler,
+        queue,
+        device,
+        resources,
+      )?,
+      (None, None) => return Ok(None),
+    };
+    Ok(Some(texture))
   }
 }
 
@@ -105,7 +141,7 @@ impl Default for Material {
       albedo_tex: None,
       normal_tex: None,
       metallic_factor: 0.0,
-      roughness_factor: 0.0,
+      roughness_factor: 1.0,
       metallic_roughness_tex: None,
       occlusion_tex: None,
       ior: None,
@@ -121,10 +157,11 @@ impl Material {
   pub fn from_gltf(
     document: &gltf::Document,
     images: &[gltf::image::Data],
+    basisu: &BasisuImages,
   ) -> anyhow::Result<Vec<Self>> {
     let mut materials = Vec::new();
     for i in document.materials() {
-      materials.push(Self::from_gltf_material(&i, images)?);
+      materials.push(Self::from_gltf_material(&i, images, basisu)?);
     }
     Ok(materials)
   }
@@ -132,10 +169,11 @@ impl Material {
   pub fn from_gltf_material(
     material: &gltf::Material,
     images: &[gltf::image::Data],
+    basisu: &BasisuImages,
   ) -> anyhow::Result<Self> {
     let pbr = material.pbr_metallic_roughness();
     let mut new_mat: Self = Self {
-      double_sided: false,
+      double_sided: material.double_sided(),
       index: material.index().unwrap_or(0),
       name: material.name().map(|s| s.to_owned()),
       alpha_cutoff: material.alpha_cutoff(),
@@ -157,17 +195,34 @@ impl Material {
     };
     // if let Some(tx) = material.transmission() {
     //   new_mat.transmission_factor = tx.transmission_factor();
-    //   new_mat.transmission_tex = texture_from_info(tx.transmission_tex().as_ref(), images)?;
+    //   new_mat.transmission_tex = texture_from_info(tx.transmission_tex().as_ref(), ColorSpace::Linear, images, basisu)?;
     // }
 
-    new_mat.albedo_tex = texture_from_info(pbr.base_color_texture().as_ref(), images)?;
-    new_mat.metallic_roughness_tex =
-      texture_from_info(pbr.metallic_roughness_texture().as_ref(), images)?;
-    new_mat.emissive_tex = texture_from_info(material.emissive_texture().as_ref(), images)?;
+    new_mat.albedo_tex = texture_from_info(
+      pbr.base_color_texture().as_ref(),
+      ColorSpace::Srgb,
+      images,
+      basisu,
+    )?;
+    new_mat.metallic_roughness_tex = texture_from_info(
+      pbr.metallic_roughness_texture().as_ref(),
+      ColorSpace::Linear,
+      images,
+      basisu,
+    )?;
+    new_mat.emissive_tex = texture_from_info(
+      material.emissive_texture().as_ref(),
+      ColorSpace::Srgb,
+      images,
+      basisu,
+    )?;
     if let Some(occlusion) = material.occlusion_texture() {
       let tex = occlusion.texture();
+      let (rgba, compressed) = texture_data(&tex, images, basisu)?;
       new_mat.occlusion_tex = Some(TextureInfoData {
-        rgba: Some(rgba_from_texture(&tex, images)?),
+        rgba,
+        compressed,
+        color_space: ColorSpace::Linear,
         tex_coord_index: occlusion.tex_coord(),
         name: tex.name().map(&str::to_owned),
         sampler: Sampler::from_gltf(&tex.sampler()),
@@ -179,8 +234,11 @@ impl Material {
 
     if let Some(normal) = material.normal_texture() {
       let tex = normal.texture();
-      new_mat.occlusion_tex = Some(TextureInfoData {
-        rgba: Some(rgba_from_texture(&tex, images)?),
+      let (rgba, compressed) = texture_data(&tex, images, basisu)?;
+      new_mat.normal_tex = Some(TextureInfoData {
+        rgba,
+        compressed,
+        color_space: ColorSpace::Linear,
         tex_coord_index: normal.tex_coord(),
         name: tex.name().map(&str::to_owned),
         sampler: Sampler::from_gltf(&tex.sampler()),
@@ -196,7 +254,9 @@ impl Material {
 
 fn texture_from_info(
   info: Option<&gltf::texture::Info>,
+  color_space: ColorSpace,
   images: &[gltf::image::Data],
+  basisu: &BasisuImages,
 ) -> anyhow::Result<Option<TextureInfoData>> {
   match info {
     None => Ok(None),
@@ -206,11 +266,12 @@ fn texture_from_info(
       let index = tex.index();
       let tex_coord_index = info.tex_coord();
       let name: Option<String> = tex.name().map(&str::to_owned);
-      let rgba = rgba_from_texture(&tex, images)?;
+      let (rgba, compressed) = texture_data(&tex, images, basisu)?;
 
-      let _sampler = Sampler {};
       Ok(Some(TextureInfoData {
-        rgba: Some(rgba),
+        rgba,
+        compressed,
+        color_space,
         index,
         tex_coord_index,
         name,
@@ -222,12 +283,44 @@ fn texture_from_info(
   }
 }
 
+///
+/// The KTX2 file of a `KHR_texture_basisu` texture, or the decoded image of any other texture.
+/// KTX2 files which can't be decoded fall back to the texture's `source` image,
+/// or fail if the texture has none
+fn texture_data(
+  tex: &gltf::Texture,
+  images: &[gltf::image::Data],
+  basisu: &BasisuImages,
+) -> anyhow::Result<(Option<DynamicImage>, Option<Arc<[u8]>>)> {
+  match basisu.get(tex.index()) {
+    Some(compressed) => match check_ktx2(compressed) {
+      Ok(()) => Ok((None, Some(compressed.clone()))),
+      Err(e) if !basisu.has_fallback(tex.index()) => Err(anyhow::anyhow!(
+        "texture {} {:?} has no fallback image, and its KTX2 file can not be decoded: {}",
+        tex.index(),
+        tex.name(),
+        e
+      )),
+      Err(e) => {
+        log::warn!(
+          "texture {} {:?} uses its source image, its KTX2 file can not be decoded: {}",
+          tex.index(),
+          tex.name(),
+          e
+        );
+        Ok((Some(rgba_from_texture(tex, images)?), None))
+      }
+    },
+    None => Ok((Some(rgba_from_texture(tex, images)?), None)),
+  }
+}
+
 fn rgba_from_texture(
   tex: &gltf::Texture,
   images: &[gltf::image::Data],
 ) -> anyhow::Result<DynamicImage> {
   let img_index = tex.source().index();
-  if img_index > images.len() {
+  if img_index >= images.len() {
     anyhow::bail!(
       "image index {} exceded images loaded {}",
       img_index,
@@ -252,19 +345,64 @@ fn rgba_from_texture(
       ImageBuffer::<image::Bgra<u8>, Vec<u8>>::from_raw(img.width, img.height, img.pixels.clone())
         .map(DynamicImage::ImageBgra8)
     }
-    fmt => anyhow::bail!(
-      "format {:?} not supported. Only supports 8 bit images right now",
-      fmt
-    ), // Format::R16 => {
-       // }
-       // Format::R16G16 => {None}
-       // Format::R16G16B16 => {None}
-       // Format::R16G16B16A16 => {None}
+    Format::R16 => image::ImageBuffer::from_raw(img.width, img.height, pixels_u16(&img.pixels))
+      .map(DynamicImage::ImageLuma16),
+    Format::R16G16 => image::ImageBuffer::from_raw(img.width, img.height, pixels_u16(&img.pixels))
+      .map(DynamicImage::ImageLumaA16),
+    Format::R16G16B16 => {
+      image::ImageBuffer::from_raw(img.width, img.height, pixels_u16(&img.pixels))
+        .map(DynamicImage::ImageRgb16)
+    }
+    Format::R16G16B16A16 => {
+      image::ImageBuffer::from_raw(img.width, img.height, pixels_u16(&img.pixels))
+        .map(DynamicImage::ImageRgba16)
+    }
   }
   .ok_or_else(|| anyhow::anyhow!("could not create image buffer for image"))?;
   Ok(dyn_image)
 }
 
+/// 16 bit glTF image data, which is stored as native endian bytes
+fn pixels_u16(pixels: &[u8]) -> Vec<u16> {
+  pixels
+    .chunks_exact(2)
+    .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+    .collect()
+}
+
+bitflags! {
+  /// Textures sampled by a material.
+  /// Mirrors the MATERIAL_HAS_* defines in main.frag
+  #[derive(Default)]
+  pub struct MaterialTextures: u32 {
+    const ALBEDO = 1 << 0;
+    const METALLIC_ROUGHNESS = 1 << 1;
+    const NORMAL = 1 << 2;
+    const OCCLUSION = 1 << 3;
+    const EMISSIVE = 1 << 4;
+  }
+}
+
+/// Number of texture/sampler pairs in the material bind group
+pub const MATERIAL_TEXTURE_COUNT: u32 = 5;
+
+///
+/// Material factors, laid out to match the std140 `MaterialUniform` block in main.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+  pub albedo_factor: [f32; 4],
+  pub emissive_factor: [f32; 3],
+  pub metallic_factor: f32,
+  pub roughness_factor: f32,
+  pub normal_scale: f32,
+  pub occlusion_strength: f32,
+  /// fragments with alpha below the cutoff are discarded. 0 unless alpha mode is Mask
+  pub alpha_cutoff: f32,
+  pub texture_flags: u32,
+  pub _padding: [u32; 3],
+}
+
 #[derive(Debug)]
 pub struct RenderMaterial<TextureT: 'static> {
   pub double_sided: bool,
@@ -278,11 +416,13 @@ pub struct RenderMaterial<TextureT: 'static> {
   pub albedo_tex: Option<Handle<TextureT>>,
 
   pub normal_tex: Option<Handle<TextureT>>,
+  pub normal_scale: f32,
   pub metallic_factor: f32,
   pub roughness_factor: f32,
   pub metallic_roughness_tex: Option<Handle<TextureT>>,
 
   pub occlusion_tex: Option<Handle<TextureT>>,
+  pub occlusion_strength: f32,
 
   /// index of reflection
   pub ior: Option<f32>,
@@ -292,20 +432,56 @@ pub struct RenderMaterial<TextureT: 'static> {
   pub emissive_factor: Vec3,
   pub emissive_tex: Option<Handle<TextureT>>,
 
+  pub uniform_buffer: Option<wgpu::Buffer>,
   pub bind_group: Option<wgpu::BindGroup>,
 }
 
 pub type WgpuMaterial = RenderMaterial<TextureResource>;
 
+impl<TextureT: 'static> RenderMaterial<TextureT> {
+  pub fn texture_flags(&self) -> MaterialTextures {
+    let mut flags = MaterialTextures::empty();
+    flags.set(MaterialTextures::ALBEDO, self.albedo_tex.is_some());
+    flags.set(
+      MaterialTextures::METALLIC_ROUGHNESS,
+      self.metallic_roughness_tex.is_some(),
+    );
+    flags.set(MaterialTextures::NORMAL, self.normal_tex.is_some());
+    flags.set(MaterialTextures::OCCLUSION, self.occlusion_tex.is_some());
+    flags.set(MaterialTextures::EMISSIVE, self.emissive_tex.is_some());
+    flags
+  }
+
+  pub fn uniform(&self) -> MaterialUniform {
+    let alpha_cutoff = match self.alpha_mode {
+      AlphaMode::Mask => self.alpha_cutoff.unwrap_or(0.5),
+      AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+    };
+    MaterialUniform {
+      albedo_factor: self.albedo_factor.into(),
+      emissive_factor: self.emissive_factor.into(),
+      metallic_factor: self.metallic_factor,
+      roughness_factor: self.roughness_factor,
+      normal_scale: self.normal_scale,
+      occlusion_strength: self.occlusion_strength,
+      alpha_cutoff,
+      texture_flags: self.texture_flags().bits(),
+      _padding: [0; 3],
+    }
+  }
+}
+
 impl RenderMaterial<TextureResource> {
   ///
   /// @param default_texture. Texture handle to use for bind groups if
   /// material does not have defined texture.
+  /// @param resources. Shares samplers and mipmap pipelines between textures
   pub fn from_material<'ax>(
     material: &Material,
     queue: &Queue,
     device: &Device,
     bind_group_layout: &BindGroupLayout,
+    resources: &ResourceContext,
     textures: &mut ResourceManager<TextureResource>,
     default_texture: Handle<TextureResource>,
   ) -> anyhow::Result<Self> {
@@ -318,15 +494,24 @@ impl RenderMaterial<TextureResource> {
       albedo_factor: material.albedo_factor,
       albedo_tex: None,
       normal_tex: None,
+      normal_scale: material
+        .normal_tex
+        .as_ref()
+        .map_or(1.0, |info| info.scale_or_strength),
       metallic_factor: material.metallic_factor,
       roughness_factor: material.roughness_factor,
       metallic_roughness_tex: None,
       occlusion_tex: None,
+      occlusion_strength: material
+        .occlusion_tex
+        .as_ref()
+        .map_or(1.0, |info| info.scale_or_strength),
       ior: material.ior,
       transmission_factor: material.transmission_factor,
       transmission_tex: None,
       emissive_factor: material.emissive_factor,
       emissive_tex: None,
+      uniform_buffer: None,
       bind_group: None,
     };
     let mut texture_infos = [
@@ -335,27 +520,31 @@ impl RenderMaterial<TextureResource> {
         &material.metallic_roughness_tex,
         &mut gpu_resource.metallic_roughness_tex,
       ),
-      // &material.metallic_roughness_tex,
+      (&material.normal_tex, &mut gpu_resource.normal_tex),
+      (&material.occlusion_tex, &mut gpu_resource.occlusion_tex),
+      (&material.emissive_tex, &mut gpu_resource.emissive_tex),
       // &material.transmission_tex,
-      // &material.emissive_tex,
-      // &material.occlusion_tex,
-      // &material.normal_tex
     ];
     for (info_opt, gpu_tex) in texture_infos.iter_mut() {
-      let get_tex = info_opt.as_ref().map(|info| (info, &info.rgba));
-      match get_tex {
-        Some((_info, Some(rgba))) => {
-          let resource = TextureResource::from_image(rgba, queue, device)?;
+      if let Some(info) = info_opt.as_ref() {
+        if let Some(resource) = info.create_texture(queue, device, resources)? {
           let handle = textures.insert(resource);
           **gpu_tex = Some(handle)
         }
-        _ => (),
       }
     }
 
     gpu_resource.init_bind_group(queue, device, textures, default_texture, bind_group_layout)?;
     Ok(gpu_resource)
   }
+
+  /// Uploads the material's factors, after they have been changed
+  pub fn write_uniform(&self, queue: &Queue) {
+    if let Some(buffer) = &self.uniform_buffer {
+      queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[self.uniform()]));
+    }
+  }
+
   fn init_bind_group(
     &mut self,
     _queue: &Queue,
@@ -364,9 +553,202 @@ impl RenderMaterial<TextureResource> {
     default_texture: Handle<TextureResource>,
     layout: &BindGroupLayout,
   ) -> anyhow::Result<()> {
-    let albedo_tex = textures.try_get_ref(self.albedo_tex.unwrap_or(default_texture))?;
-    let bind_group = basic_texture_bind_group(albedo_tex, layout, device);
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("material_uniform_buffer"),
+      contents: bytemuck::cast_slice(&[self.uniform()]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    // unused slots are bound to the default texture, and skipped by the shader
+    let texture_handles = [
+      self.albedo_tex,
+      self.metallic_roughness_tex,
+      self.normal_tex,
+      self.occlusion_tex,
+      self.emissive_tex,
+    ];
+    let mut bound_textures = Vec::with_capacity(texture_handles.len());
+    for handle in texture_handles.iter() {
+      bound_textures.push(textures.try_get_ref(handle.unwrap_or(default_texture))?);
+    }
+
+    let mut entries = vec![BindGroupEntry {
+      binding: 0,
+      resource: uniform_buffer.as_entire_binding(),
+    }];
+    for (i, texture) in bound_textures.iter().enumerate() {
+      let binding = 1 + 2 * i as u32;
+      entries.push(BindGroupEntry {
+        binding,
+        resource: BindingResource::TextureView(texture.view()),
+      });
+      entries.push(BindGroupEntry {
+        binding: binding + 1,
+        resource: BindingResource::Sampler(texture.sampler()),
+      });
+    }
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("material_bind_group"),
+      layout,
+      entries: &entries,
+    });
     self.bind_group = Some(bind_group);
+    self.uniform_buffer = Some(uniform_buffer);
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    renderer_common::handle::HandleIndex, wgpu_renderer::compressed_texture::test::ktx2_file,
+  };
+  use memoffset::offset_of;
+
+  #[test]
+  fn test_rgba_from_16_bit_texture() {
+    let document = gltf::Gltf::from_slice(
+      br#"{
+        "asset": { "version": "2.0" },
+        "images": [{ "uri": "data.png" }],
+        "textures": [{ "source": 0 }]
+      }"#,
+    )
+    .unwrap()
+    .document;
+    let pixels: Vec<u8> = [1u16, 2, 3, u16::MAX]
+      .iter()
+      .flat_map(|channel| channel.to_ne_bytes())
+      .collect();
+    let images = vec![gltf::image::Data {
+      pixels,
+      format: Format::R16G16B16A16,
+      width: 1,
+      height: 1,
+    }];
+    let texture = document.textures().next().unwrap();
+    match rgba_from_texture(&texture, &images).unwrap() {
+      DynamicImage::ImageRgba16(img) => assert_eq!(img.get_pixel(0, 0).0, [1, 2, 3, u16::MAX]),
+      other => panic!("expected a 16 bit image, got {:?}", other.color()),
+    }
+  }
+
+  /// A document with one texture, whose source is a 1x1 image
+  fn texture_document() -> (gltf::Document, Vec<gltf::image::Data>) {
+    let document = gltf::Gltf::from_slice(
+      br#"{
+        "asset": { "version": "2.0" },
+        "images": [{ "uri": "fallback.png" }],
+        "textures": [{ "source": 0 }]
+      }"#,
+    )
+    .unwrap()
+    .document;
+    let images = vec![gltf::image::Data {
+      pixels: vec![1, 2, 3, 4],
+      format: Format::R8G8B8A8,
+      width: 1,
+      height: 1,
+    }];
+    (document, images)
+  }
+
+  #[test]
+  fn test_texture_data_uses_ktx2() {
+    let (document, images) = texture_document();
+    let mut basisu = BasisuImages::default();
+    // one BC7 block
+    basisu.insert(0, Arc::from(ktx2_file(146, 4, 4, &[vec![0; 16]])), true);
+    let texture = document.textures().next().unwrap();
+    let (rgba, compressed) = texture_data(&texture, &images, &basisu).unwrap();
+    assert!(rgba.is_none());
+    assert_eq!(compressed.as_ref(), basisu.get(0));
+  }
+
+  #[test]
+  fn test_texture_data_falls_back_from_unsupported_ktx2() {
+    let (document, images) = texture_document();
+    let mut file = ktx2_file(146, 4, 4, &[vec![0; 16]]);
+    // an unknown supercompression scheme
+    file[44..48].copy_from_slice(&7u32.to_le_bytes());
+    let texture = document.textures().next().unwrap();
+
+    let mut basisu = BasisuImages::default();
+    basisu.insert(0, Arc::from(file.clone()), true);
+    let (rgba, compressed) = texture_data(&texture, &images, &basisu).unwrap();
+    assert!(compressed.is_none());
+    assert_eq!(rgba.unwrap().to_rgba8().get_pixel(0, 0).0, [1, 2, 3, 4]);
+
+    // the source is only a placeholder
+    let mut basisu = BasisuImages::default();
+    basisu.insert(0, Arc::from(file), false);
+    assert!(texture_data(&texture, &images, &basisu).is_err());
+  }
+
+  #[test]
+  fn test_material_uniform_std140_layout() {
+    assert_eq!(offset_of!(MaterialUniform, albedo_factor), 0);
+    assert_eq!(offset_of!(MaterialUniform, emissive_factor), 16);
+    assert_eq!(offset_of!(MaterialUniform, metallic_factor), 28);
+    assert_eq!(offset_of!(MaterialUniform, roughness_factor), 32);
+    assert_eq!(offset_of!(MaterialUniform, normal_scale), 36);
+    assert_eq!(offset_of!(MaterialUniform, occlusion_strength), 40);
+    assert_eq!(offset_of!(MaterialUniform, alpha_cutoff), 44);
+    assert_eq!(offset_of!(MaterialUniform, texture_flags), 48);
+    assert_eq!(std::mem::size_of::<MaterialUniform>(), 64);
+  }
+
+  fn material_with_textures(
+    albedo: bool,
+    normal: bool,
+    alpha_mode: AlphaMode,
+  ) -> RenderMaterial<()> {
+    let handle: Handle<()> = HandleIndex::new(0, 0).into_typed();
+    RenderMaterial {
+      double_sided: false,
+      index: 0,
+      name: None,
+      alpha_cutoff: Some(0.25),
+      alpha_mode,
+      albedo_factor: vec4(1.0, 0.5, 0.25, 1.0),
+      albedo_tex: if albedo { Some(handle) } else { None },
+      normal_tex: if normal { Some(handle) } else { None },
+      normal_scale: 0.5,
+      metallic_factor: 1.0,
+      roughness_factor: 0.75,
+      metallic_roughness_tex: None,
+      occlusion_tex: None,
+      occlusion_strength: 1.0,
+      ior: None,
+      transmission_factor: None,
+      transmission_tex: None,
+      emissive_factor: vec3(0.0, 0.0, 0.0),
+      emissive_tex: None,
+      uniform_buffer: None,
+      bind_group: None,
+    }
+  }
+
+  #[test]
+  fn test_material_uniform_texture_flags() {
+    let uniform = material_with_textures(true, true, AlphaMode::Opaque).uniform();
+    assert_eq!(
+      MaterialTextures::from_bits(uniform.texture_flags),
+      Some(MaterialTextures::ALBEDO | MaterialTextures::NORMAL)
+    );
+    assert_eq!(uniform.albedo_factor, [1.0, 0.5, 0.25, 1.0]);
+    assert_eq!(uniform.roughness_factor, 0.75);
+    assert_eq!(uniform.normal_scale, 0.5);
+
+    let uniform = material_with_textures(false, false, AlphaMode::Opaque).uniform();
+    assert_eq!(uniform.texture_flags, 0);
+  }
+
+  #[test]
+  fn test_material_uniform_alpha_cutoff() {
+    let opaque = material_with_textures(false, false, AlphaMode::Opaque).uniform();
+    assert_eq!(opaque.alpha_cutoff, 0.0);
+    let mask = material_with_textures(false, false, AlphaMode::Mask).uniform();
+    assert_eq!(mask.alpha_cutoff, 0.25);
+  }
+}
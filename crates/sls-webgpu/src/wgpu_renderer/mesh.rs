@@ -4,6 +4,7 @@ use std::ops::Range;
 
 use crate::{
   error::Error,
+  math::Bounds,
   renderer_common::{
     allocator::ResourceManager,
     handle::{Handle, HandleIndex, ResourceStore},
@@ -21,6 +22,8 @@ use std::sync::{Arc, RwLock};
 #[derive(Debug)]
 pub struct Mesh {
   geometry: MeshGeometry,
+  /// model space bounds of the geometry, computed once when the mesh is created
+  bounds: Option<Bounds>,
   buffers: Option<MeshBuffers>,
   material: Option<Handle<WgpuMaterial>>,
 }
@@ -28,6 +31,7 @@ pub struct Mesh {
 impl Mesh {
   pub fn new(geometry: MeshGeometry, buffers: Option<MeshBuffers>) -> Self {
     Self {
+      bounds: geometry.bounds(),
       geometry,
       buffers,
       material: None,
@@ -36,11 +40,7 @@ impl Mesh {
 
   pub fn from_geometry(geometry: MeshGeometry, device: &wgpu::Device) -> Result<Self, Error> {
     let buffers = Some(geometry.create_buffers(device)?);
-    Ok(Self {
-      buffers,
-      geometry,
-      material: None,
-    })
+    Ok(Self::new(geometry, buffers))
   }
 
   #[inline]
@@ -51,10 +51,20 @@ impl Mesh {
   pub fn geometry(&self) -> &MeshGeometry {
     &self.geometry
   }
+  /// None if the mesh has no vertices
+  #[inline]
+  pub fn bounds(&self) -> Option<&Bounds> {
+    self.bounds.as_ref()
+  }
   #[inline]
   pub fn n_elements(&self) -> usize {
     self.geometry.indices.len()
   }
+  /// Number of levels of detail, including the full mesh
+  #[inline]
+  pub fn lod_count(&self) -> usize {
+    self.geometry.lod_count()
+  }
 
   #[inline]
   pub fn material(&self) -> Option<Handle<WgpuMaterial>> {
@@ -70,6 +80,22 @@ impl Mesh {
 pub struct MeshBuffers {
   pub index_buffer: wgpu::Buffer,
   pub vertex_buffer: wgpu::Buffer,
+  /// format of the indices in `index_buffer`
+  pub index_format: wgpu::IndexFormat,
+  /// ranges of `index_buffer` holding each level of detail, starting with the full mesh
+  pub lod_ranges: Vec<Range<u32>>,
+}
+
+impl MeshBuffers {
+  /// The indices of level `lod`, or of the coarsest level if there are fewer levels
+  pub fn lod_range(&self, lod: usize) -> Range<u32> {
+    self
+      .lod_ranges
+      .get(lod)
+      .or_else(|| self.lod_ranges.last())
+      .cloned()
+      .unwrap_or(0..0)
+  }
 }
 
 impl<'a, 'b> DrawModel<'a, 'b> for wgpu::RenderPass<'a>
@@ -123,16 +149,26 @@ where
     material: &'a Self::Material,
     uniforms: &'a Self::Uniforms,
     instances: Range<u32>,
+  ) {
+    self.draw_mesh_lod_instanced(model, 0, material, uniforms, instances);
+  }
+
+  fn draw_mesh_lod_instanced(
+    &mut self,
+    model: &'b Self::Mesh,
+    lod: usize,
+    material: &'a Self::Material,
+    uniforms: &'a Self::Uniforms,
+    instances: Range<u32>,
   ) {
     match model.buffers.as_ref() {
       Some(mesh) => {
-        let n_indices = model.geometry().indices.len() as u32;
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         // instance matrix data
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(1, material, &[]);
         self.set_bind_group(0, uniforms, &[]);
-        self.draw_indexed(0..n_indices, 0, instances);
+        self.draw_indexed(mesh.lod_range(lod), 0, instances);
       }
       None => {
         log::error!("missing gpu resources");
@@ -0,0 +1,193 @@
+//! Mip chain generation.
+//!
+//! Each mip level is rendered from the level above it with a fullscreen
+//! linear blit, so textures only need their base level uploaded.
+use std::{collections::HashMap, num::NonZeroU32};
+
+use wgpu::{
+  BindGroupLayout, Device, Queue, RenderPipeline, ShaderModule, ShaderStages, Texture,
+  TextureFormat, TextureSampleType, TextureViewDimension,
+};
+
+/// Number of mip levels in a full chain for a texture of the given size,
+/// down to a 1x1 level
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+  32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Blit resources shared by the pipelines of every format
+#[derive(Debug)]
+struct Blit {
+  vertex_shader: ShaderModule,
+  fragment_shader: ShaderModule,
+  layout: BindGroupLayout,
+  sampler: wgpu::Sampler,
+}
+
+impl Blit {
+  fn new(device: &Device) -> Self {
+    let vertex_shader = device.create_shader_module(&include_shader!("fullscreen.vert"));
+    let fragment_shader = device.create_shader_module(&include_shader!("blit.frag"));
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("mipmap_blit_layout"),
+      entries: &[
+        wgpu::BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: TextureViewDimension::D2,
+            sample_type: TextureSampleType::Float { filterable: true },
+          },
+          count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Sampler {
+            comparison: false,
+            filtering: true,
+          },
+          count: None,
+        },
+      ],
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("mipmap_blit_sampler"),
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      mipmap_filter: wgpu::FilterMode::Nearest,
+      ..Default::default()
+    });
+    Self {
+      vertex_shader,
+      fragment_shader,
+      layout,
+      sampler,
+    }
+  }
+
+  fn pipeline(&self, device: &Device, format: TextureFormat) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("mipmap_blit_pipeline_layout"),
+      bind_group_layouts: &[&self.layout],
+      push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("mipmap_blit_pipeline"),
+      layout: Some(&pipeline_layout),
+      vertex: wgpu::VertexState {
+        module: &self.vertex_shader,
+        entry_point: "main",
+        buffers: &[],
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &self.fragment_shader,
+        entry_point: "main",
+        targets: &[format.into()],
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: None,
+      multisample: wgpu::MultisampleState::default(),
+    })
+  }
+}
+
+///
+/// Fills in the mip levels of 2D textures from their base level.
+/// The shaders and per format pipelines are created on first use
+#[derive(Debug, Default)]
+pub struct MipmapGenerator {
+  blit: Option<Blit>,
+  pipelines: HashMap<TextureFormat, RenderPipeline>,
+}
+
+impl MipmapGenerator {
+  ///
+  /// Renders levels `1..mip_level_count` of `texture`, each from the one above it.
+  /// The texture must be a filterable color format, created with the
+  /// `RENDER_ATTACHMENT` and `TEXTURE_BINDING` usages
+  pub fn generate(
+    &mut self,
+    device: &Device,
+    queue: &Queue,
+    texture: &Texture,
+    format: TextureFormat,
+    mip_level_count: u32,
+  ) {
+    if mip_level_count <= 1 {
+      return;
+    }
+    let blit = self.blit.get_or_insert_with(|| Blit::new(device));
+    let pipeline = self
+      .pipelines
+      .entry(format)
+      .or_insert_with(|| blit.pipeline(device, format));
+
+    let level_view = |mip_level: u32| {
+      texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("mipmap_level"),
+        dimension: Some(TextureViewDimension::D2),
+        base_mip_level: mip_level,
+        mip_level_count: NonZeroU32::new(1),
+        ..Default::default()
+      })
+    };
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("mipmap_generation"),
+    });
+    for mip_level in 1..mip_level_count {
+      let source = level_view(mip_level - 1);
+      let target = level_view(mip_level);
+      let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("mipmap_blit_bind_group"),
+        layout: &blit.layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&source),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&blit.sampler),
+          },
+        ],
+      });
+      let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("mipmap_blit_pass"),
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+          view: &target,
+          resolve_target: None,
+          ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            store: true,
+          },
+        }],
+        depth_stencil_attachment: None,
+      });
+      pass.set_pipeline(pipeline);
+      pass.set_bind_group(0, &bind_group, &[]);
+      pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_mip_level_count() {
+    assert_eq!(mip_level_count(1, 1), 1);
+    assert_eq!(mip_level_count(2, 2), 2);
+    assert_eq!(mip_level_count(256, 256), 9);
+    // non square and non power of two sizes round down at each level
+    assert_eq!(mip_level_count(300, 17), 9);
+    assert_eq!(mip_level_count(1, 1024), 11);
+    assert_eq!(mip_level_count(0, 0), 1);
+  }
+}
@@ -1,16 +1,58 @@
 pub use model_instance::ModelInstance;
 
+/// Shader module descriptor for a shader in src/shaders, compiled by build.rs.
+/// Native builds load the SPIR-V, and wasm loads the WGSL translated from it
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! include_shader {
+  ($name:literal) => {
+    wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/shaders/", $name, ".spv"))
+  };
+}
+
+#[cfg(target_arch = "wasm32")]
+macro_rules! include_shader {
+  ($name:literal) => {
+    wgpu::ShaderModuleDescriptor {
+      label: Some($name),
+      source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(concat!(
+        env!("OUT_DIR"),
+        "/shaders/",
+        $name,
+        ".wgsl"
+      )))),
+    }
+  };
+}
+
+pub mod compressed_texture;
 pub mod context;
+pub mod debug_lines;
 
+pub mod environment;
 pub mod frame;
+pub mod gltf_import;
 pub mod gltf_scene;
+pub mod gpu_culling;
+pub mod instance_cache;
 pub mod material;
 pub mod mesh;
+pub mod mipmaps;
 pub mod model;
 pub mod model_instance;
+pub mod picking;
 pub mod pipeline_state;
+pub mod post_process;
+pub mod reflection;
+pub mod render_graph;
 pub mod render_hooks;
+pub mod render_passes;
+pub mod render_target;
 pub mod resource_view;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shader_compiler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shader_reload;
+pub mod shadows;
 pub mod textures;
 pub mod uniforms;
 
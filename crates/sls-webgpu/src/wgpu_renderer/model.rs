@@ -1,11 +1,13 @@
 use super::{material::Material, mesh::Mesh};
 use crate::{
   anyhow::Error,
+  math::Bounds,
   renderer_common::{
     allocator::ResourceManager,
     handle::{Handle, HandleIndex, ResourceStore},
   },
   wgpu_renderer::{
+    gltf_import::BasisuImages,
     material::{RenderMaterial, WgpuMaterial},
     mesh::MeshGeometry,
     resource_view::{ReadWriteResources, ResourceView},
@@ -21,6 +23,9 @@ use std::{
   sync::{Arc, RwLock, Weak},
 };
 
+/// levels of detail simplified from each primitive when a model loads, besides the full mesh
+pub const LOD_LEVELS: usize = 3;
+
 #[derive(Debug)]
 pub struct Model {
   pub meshes: Vec<HandleIndex>,
@@ -61,6 +66,8 @@ pub struct StreamingMesh {
   pub(crate) state: ModelLoadState,
   pub(crate) primitives: Vec<Handle<Mesh>>,
   pub(crate) materials: Option<Weak<RwLock<ResourceManager<WgpuMaterial>>>>,
+  /// union of the primitives' bounds, None until the model is loaded
+  pub(crate) bounds: Option<Bounds>,
 }
 
 /// accessor implementations
@@ -81,6 +88,10 @@ impl StreamingMesh {
   pub fn primitives(&self) -> &Vec<Handle<Mesh>> {
     &self.primitives
   }
+  #[inline]
+  pub fn bounds(&self) -> Option<&Bounds> {
+    self.bounds.as_ref()
+  }
 
   #[inline]
   pub fn set_path(&mut self, path: String) {
@@ -109,6 +120,7 @@ impl StreamingMesh {
       primitives: Vec::new(),
       mesh_index: index,
       materials: None,
+      bounds: None,
     }
   }
 
@@ -118,16 +130,21 @@ impl StreamingMesh {
     document: &Document,
     buffers: &Vec<gltf::buffer::Data>,
     images: &Vec<gltf::image::Data>,
+    basisu: &BasisuImages,
   ) -> anyhow::Result<()> {
     let mesh = document
       .meshes()
       .nth(self.mesh_index)
       .ok_or(anyhow!("Document does not have a mesh"))?;
 
-    let geometry = MeshGeometry::from_gltf_mesh(&mesh, buffers)?;
-    let materials = Material::from_gltf(document, images)?;
+    let mut geometry = MeshGeometry::from_gltf_mesh(&mesh, buffers)?;
+    for mesh_geom in geometry.iter_mut() {
+      mesh_geom.build_lods(LOD_LEVELS);
+    }
+    let materials = Material::from_gltf(document, images, basisu)?;
     let mut material_handles: HashMap<usize, _> = HashMap::default();
     let mut meshes: Vec<Handle<Mesh>> = Vec::with_capacity(geometry.len());
+    let mut bounds: Option<Bounds> = None;
     {
       let mut mesh_loader = context
         .resources
@@ -142,7 +159,8 @@ impl StreamingMesh {
           &mat,
           &context.queue,
           &context.device,
-          &context.texture_bind_group_layout,
+          &context.material_bind_group_layout,
+          &context.resources,
           &mut *tex_loader,
           context.fallback_texture,
         )?;
@@ -151,6 +169,12 @@ impl StreamingMesh {
       }
       for mesh_geom in geometry.into_iter() {
         let mut mesh = Mesh::from_geometry(mesh_geom, &context.device)?;
+        if let Some(mesh_bounds) = mesh.bounds() {
+          bounds = Some(match bounds {
+            Some(bounds) => mesh_bounds.union(&bounds),
+            None => *mesh_bounds,
+          });
+        }
         match mesh.geometry().gltf_mat_index {
           Some(material_idx) => {
             let handle = material_handles.get(&material_idx).unwrap_or_else(|| {
@@ -168,6 +192,7 @@ impl StreamingMesh {
       }
     }
     self.primitives = meshes;
+    self.bounds = bounds;
     self.state = ModelLoadState::Loaded;
     self.materials = Some(Arc::downgrade(&context.resources.materials));
 
@@ -180,8 +205,9 @@ impl StreamingMesh {
     document: &Document,
     buffers: &Vec<gltf::buffer::Data>,
     images: &Vec<gltf::image::Data>,
+    basisu: &BasisuImages,
   ) -> anyhow::Result<()> {
-    match self.load_from_gltf_impl(context, document, buffers, images) {
+    match self.load_from_gltf_impl(context, document, buffers, images, basisu) {
       Err(e) => {
         self.state = ModelLoadState::Failed(format!("{:?}", e));
         Err(e)
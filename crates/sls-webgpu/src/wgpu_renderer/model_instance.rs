@@ -1,19 +1,28 @@
-use crate::game::components::Transform3D;
+use crate::{
+  renderer_common::handle::Handle,
+  scene_graph::components::LocalToWorld,
+  wgpu_renderer::{mesh::Mesh, model::StreamingMesh},
+};
+use nalgebra_glm::{distance2, vec3, Mat4, Vec3};
+use std::{cmp::Ordering, ops::Range};
 
 use wgpu::{VertexBufferLayout, VertexStepMode};
 
 /// Instance data for model
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelInstance {
   model: [[f32; 4]; 4],
+  /// 0 if shadow maps are ignored when shading the instance
+  receives_shadows: u32,
 }
 
-const VERTEX_ATTR_ARRAY: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+const VERTEX_ATTR_ARRAY: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
   7 => Float32x4,
   8=> Float32x4,
   9=> Float32x4,
-  10 => Float32x4
+  10 => Float32x4,
+  11 => Uint32
 ];
 
 impl ModelInstance {
@@ -24,13 +33,312 @@ impl ModelInstance {
       attributes: &VERTEX_ATTR_ARRAY,
     }
   }
+
+  /// Translation of the model matrix
+  #[inline]
+  pub fn position(&self) -> Vec3 {
+    let [x, y, z, _] = self.model[3];
+    vec3(x, y, z)
+  }
+
+  #[inline]
+  pub fn model_matrix(&self) -> Mat4 {
+    self.model.into()
+  }
+
+  #[inline]
+  pub fn with_receives_shadows(mut self, receives_shadows: bool) -> Self {
+    self.receives_shadows = receives_shadows as u32;
+    self
+  }
 }
 
-impl From<&Transform3D> for ModelInstance {
-  fn from(xform: &Transform3D) -> Self {
-    let model = xform.matrix();
+impl From<&LocalToWorld> for ModelInstance {
+  fn from(local_to_world: &LocalToWorld) -> Self {
     Self {
-      model: model.into(),
+      model: local_to_world.0.into(),
+      receives_shadows: 1,
+    }
+  }
+}
+
+/// A model, and the level of detail its instance is drawn at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelLod {
+  pub model: Handle<StreamingMesh>,
+  pub lod: usize,
+}
+
+impl From<Handle<StreamingMesh>> for ModelLod {
+  fn from(model: Handle<StreamingMesh>) -> Self {
+    Self { model, lod: 0 }
+  }
+}
+
+/// A contiguous run of instances in the instance buffer
+/// which are all drawn with the same model, at the same level of detail
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceRange {
+  pub model: Handle<StreamingMesh>,
+  pub lod: usize,
+  pub instances: Range<u32>,
+}
+
+///
+/// A contiguous run of instances of the same model, each drawn at the
+/// level of detail `lod_thresholds` picks for its size on screen.
+/// The range's `lod` is always 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodGroup {
+  pub range: InstanceRange,
+  pub lod_thresholds: Vec<f32>,
+}
+
+///
+/// Groups instance data by model handle and level of detail, so that every group's
+/// instances occupy a single contiguous range of the returned instance array.
+/// Instances keep their relative order within a group's range.
+pub fn group_instances_by_model<K: Into<ModelLod>, T>(
+  instances: Vec<(K, T)>,
+) -> (Vec<T>, Vec<InstanceRange>) {
+  let mut instances: Vec<(ModelLod, T)> = instances
+    .into_iter()
+    .map(|(key, instance)| (key.into(), instance))
+    .collect();
+  instances.sort_by_key(|(key, _)| (key.model.0, key.lod));
+  let mut ranges: Vec<InstanceRange> = Vec::new();
+  let mut data: Vec<T> = Vec::with_capacity(instances.len());
+  for (i, (key, instance)) in instances.into_iter().enumerate() {
+    let i = i as u32;
+    match ranges.last_mut() {
+      Some(range) if range.model == key.model && range.lod == key.lod => {
+        range.instances.end = i + 1
+      }
+      _ => ranges.push(InstanceRange {
+        model: key.model,
+        lod: key.lod,
+        instances: i..(i + 1),
+      }),
     }
+    data.push(instance);
+  }
+  (data, ranges)
+}
+
+///
+/// Groups instances by model and level of detail, and appends them to `data`.
+/// Returns the groups' ranges, which are offset by the instances already in `data`
+pub fn append_instances_by_model<K: Into<ModelLod>, T>(
+  data: &mut Vec<T>,
+  instances: Vec<(K, T)>,
+) -> Vec<InstanceRange> {
+  let offset = data.len() as u32;
+  let (grouped, mut ranges) = group_instances_by_model(instances);
+  for range in ranges.iter_mut() {
+    range.instances = (range.instances.start + offset)..(range.instances.end + offset);
+  }
+  data.extend(grouped);
+  ranges
+}
+
+/// A single instance of a mesh, drawn on its own so it can be depth sorted
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortedDraw {
+  pub mesh: Handle<Mesh>,
+  pub lod: usize,
+  pub instance: u32,
+  /// squared distance from the eye to the instance's origin
+  pub distance2: f32,
+}
+
+///
+/// Expands `ranges` into a draw for each instance of the meshes returned by `meshes`,
+/// sorted back to front from `eye`. Meshes of the same instance keep their order
+pub fn sort_back_to_front<F: FnMut(Handle<StreamingMesh>) -> Vec<Handle<Mesh>>>(
+  instances: &[ModelInstance],
+  ranges: &[InstanceRange],
+  eye: &Vec3,
+  mut meshes: F,
+) -> Vec<SortedDraw> {
+  let mut draws = Vec::new();
+  for range in ranges {
+    let range_meshes = meshes(range.model);
+    if range_meshes.is_empty() {
+      continue;
+    }
+    for instance in range.instances.clone() {
+      let distance2 = match instances.get(instance as usize) {
+        Some(data) => distance2(eye, &data.position()),
+        None => continue,
+      };
+      draws.extend(range_meshes.iter().map(|&mesh| SortedDraw {
+        mesh,
+        lod: range.lod,
+        instance,
+        distance2,
+      }));
+    }
+  }
+  sort_draws_back_to_front(&mut draws);
+  draws
+}
+
+/// Sorts `draws` by decreasing distance, keeping the order of draws at the same distance
+pub fn sort_draws_back_to_front(draws: &mut [SortedDraw]) {
+  draws.sort_by(|a, b| {
+    b.distance2
+      .partial_cmp(&a.distance2)
+      .unwrap_or(Ordering::Equal)
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{na::Matrix4, renderer_common::handle::HandleIndex};
+  use nalgebra_glm::vec3;
+
+  fn instance_at(x: f32) -> ModelInstance {
+    (&LocalToWorld(Matrix4::new_translation(&vec3(x, 0.0, 0.0)))).into()
+  }
+
+  #[test]
+  fn test_group_instances_by_model() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(1, 0).into_typed();
+    let b: Handle<StreamingMesh> = HandleIndex::new(0, 1).into_typed();
+    let input = vec![
+      (a, instance_at(0.0)),
+      (b, instance_at(1.0)),
+      (a, instance_at(2.0)),
+      (b, instance_at(3.0)),
+      (a, instance_at(4.0)),
+    ];
+    let (data, ranges) = group_instances_by_model(input);
+    assert_eq!(
+      ranges,
+      vec![
+        InstanceRange {
+          model: a,
+          lod: 0,
+          instances: 0..3
+        },
+        InstanceRange {
+          model: b,
+          lod: 0,
+          instances: 3..5
+        },
+      ]
+    );
+    let expected = vec![
+      instance_at(0.0),
+      instance_at(2.0),
+      instance_at(4.0),
+      instance_at(1.0),
+      instance_at(3.0),
+    ];
+    assert_eq!(data, expected);
+  }
+
+  #[test]
+  fn test_append_instances_by_model() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let mut data = vec![instance_at(0.0), instance_at(1.0)];
+    let ranges = append_instances_by_model(
+      &mut data,
+      vec![(a, instance_at(2.0)), (a, instance_at(3.0))],
+    );
+    assert_eq!(
+      ranges,
+      vec![InstanceRange {
+        model: a,
+        lod: 0,
+        instances: 2..4
+      }]
+    );
+    assert_eq!(data.len(), 4);
+    assert_eq!(data[3], instance_at(3.0));
+  }
+
+  #[test]
+  fn test_group_instances_empty() {
+    let (data, ranges) = group_instances_by_model::<ModelLod, ModelInstance>(Vec::new());
+    assert!(data.is_empty());
+    assert!(ranges.is_empty());
+  }
+
+  #[test]
+  fn test_group_instances_by_lod() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let lod = |lod| ModelLod { model: a, lod };
+    let input = vec![
+      (lod(1), instance_at(0.0)),
+      (lod(0), instance_at(1.0)),
+      (lod(1), instance_at(2.0)),
+    ];
+    let (data, ranges) = group_instances_by_model(input);
+    assert_eq!(
+      ranges,
+      vec![
+        InstanceRange {
+          model: a,
+          lod: 0,
+          instances: 0..1
+        },
+        InstanceRange {
+          model: a,
+          lod: 1,
+          instances: 1..3
+        },
+      ]
+    );
+    assert_eq!(
+      data,
+      vec![instance_at(1.0), instance_at(0.0), instance_at(2.0)]
+    );
+  }
+
+  #[test]
+  fn test_sort_back_to_front() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let b: Handle<StreamingMesh> = HandleIndex::new(1, 0).into_typed();
+    let mesh_a: Handle<Mesh> = HandleIndex::new(0, 0).into_typed();
+    let mesh_b: Handle<Mesh> = HandleIndex::new(1, 0).into_typed();
+    let data = vec![instance_at(1.0), instance_at(5.0), instance_at(3.0)];
+    let ranges = vec![
+      InstanceRange {
+        model: a,
+        lod: 0,
+        instances: 0..2,
+      },
+      InstanceRange {
+        model: b,
+        lod: 0,
+        instances: 2..3,
+      },
+    ];
+    let draws = sort_back_to_front(&data, &ranges, &vec3(0.0, 0.0, 0.0), |model| {
+      if model == a {
+        vec![mesh_a]
+      } else {
+        vec![mesh_b]
+      }
+    });
+    let order: Vec<(Handle<Mesh>, u32)> = draws.iter().map(|d| (d.mesh, d.instance)).collect();
+    assert_eq!(order, vec![(mesh_a, 1), (mesh_b, 2), (mesh_a, 0)]);
+    assert_eq!(draws[0].distance2, 25.0);
+  }
+
+  #[test]
+  fn test_sort_back_to_front_skips_models_without_meshes() {
+    let a: Handle<StreamingMesh> = HandleIndex::new(0, 0).into_typed();
+    let ranges = vec![InstanceRange {
+      model: a,
+      lod: 0,
+      instances: 0..1,
+    }];
+    let draws = sort_back_to_front(&[instance_at(1.0)], &ranges, &vec3(0.0, 0.0, 0.0), |_| {
+      Vec::new()
+    });
+    assert!(draws.is_empty());
   }
 }
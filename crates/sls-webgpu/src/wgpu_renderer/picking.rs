@@ -0,0 +1,643 @@
+//! Object-ID picking, which finds the entity under a pixel of the render target.
+//!
+//! When a pick is requested, every shown RenderModel is drawn with the id of its entity
+//! into an [OBJECT_ID] target, along with its depth. Ids index a table of the entities
+//! drawn by that pick, so they stay valid however legion allocates entities.
+//! The targets are a single pixel, which the camera's projection is offset to cover
+//! with the picked pixel. The [PickingPass] is only in the render graph on frames
+//! which render a pick. Its pixel is copied into a readback buffer which is
+//! mapped without blocking. The Context checks the mapping on each following frame,
+//! and publishes a [PickResult] once it completes.
+use std::{
+  future::Future,
+  num::NonZeroU32,
+  pin::Pin,
+  sync::Mutex,
+  task::{Context as TaskContext, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use anyhow::anyhow;
+use legion::{Entity, IntoQuery, World};
+use nalgebra_glm::{vec4, Mat4, Vec3};
+use wgpu::{
+  util::DeviceExt, BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferAsyncError,
+  BufferUsages, Device, Queue, RenderPipeline, TextureFormat, TextureUsages, VertexBufferLayout,
+  VertexStepMode,
+};
+
+use crate::{
+  camera::Camera,
+  game::{components::RenderModel, resources::PickResult},
+  renderer_common::{allocator::ResourceManager, geometry::Vertex},
+  scene_graph::components::LocalToWorld,
+  wgpu_renderer::{
+    model::StreamingMesh,
+    model_instance::{group_instances_by_model, InstanceRange, ModelLod},
+    render_graph::{NodeContext, NodeSlots, RenderNode, SlotSize, TextureSlotDesc},
+    textures::TextureResource,
+    uniforms::Uniforms,
+  },
+};
+
+/// Ids of the entities drawn by a pick, 0 where nothing was drawn. Holds the picked pixel only
+pub const OBJECT_ID: &str = "object_id";
+/// Depth of the picked surfaces, written as a color so it can be copied back
+pub const OBJECT_DEPTH: &str = "object_depth";
+/// Depth buffer of the picking pass
+pub const PICK_DEPTH: &str = "pick_depth";
+
+pub const OBJECT_ID_FORMAT: TextureFormat = TextureFormat::R32Uint;
+const OBJECT_DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+/// Offset of the depth in the readback buffer, after the id.
+/// Texture copies into a buffer must start at a multiple of the row alignment
+const DEPTH_OFFSET: BufferAddress = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as BufferAddress;
+
+/// Instance data of the picking pass
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PickInstance {
+  model: [[f32; 4]; 4],
+  /// the instance's id in its PickTable
+  id: u32,
+}
+
+const VERTEX_ATTR_ARRAY: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+  7 => Float32x4,
+  8 => Float32x4,
+  9 => Float32x4,
+  10 => Float32x4,
+  11 => Uint32
+];
+
+impl PickInstance {
+  pub fn new(local_to_world: &LocalToWorld, id: u32) -> Self {
+    Self {
+      model: local_to_world.0.into(),
+      id,
+    }
+  }
+
+  pub fn desc<'a>() -> VertexBufferLayout<'a> {
+    VertexBufferLayout {
+      array_stride: std::mem::size_of::<PickInstance>() as _,
+      step_mode: VertexStepMode::Instance,
+      attributes: &VERTEX_ATTR_ARRAY,
+    }
+  }
+}
+
+///
+/// Entities drawn by a pick. Ids start at 1, leaving 0 for pixels without an entity
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PickTable {
+  entities: Vec<Entity>,
+}
+
+impl PickTable {
+  /// Adds an entity, and returns its id
+  pub fn push(&mut self, entity: Entity) -> u32 {
+    self.entities.push(entity);
+    self.entities.len() as u32
+  }
+
+  /// The entity with the given id, None for 0 or an unknown id
+  pub fn entity(&self, id: u32) -> Option<Entity> {
+    let index = id.checked_sub(1)?;
+    self.entities.get(index as usize).copied()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entities.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entities.is_empty()
+  }
+}
+
+///
+/// World space position of a pixel's surface, given the depth buffer value there.
+/// Pixels are sampled at their centers, with y pointing down as in the render target
+pub fn unproject(
+  inverse_view_projection: &Mat4,
+  pixel: (u32, u32),
+  target_size: (u32, u32),
+  depth: f32,
+) -> Vec3 {
+  let x = 2.0 * (pixel.0 as f32 + 0.5) / target_size.0 as f32 - 1.0;
+  let y = 1.0 - 2.0 * (pixel.1 as f32 + 0.5) / target_size.1 as f32;
+  let position = inverse_view_projection * vec4(x, y, depth, 1.0);
+  position.xyz() / position.w
+}
+
+///
+/// Scales and offsets clip space so the pixel covers the whole viewport, for rendering
+/// it into a 1x1 target. Depth is unchanged, so it can be unprojected with the full view
+#[rustfmt::skip]
+pub fn pick_projection(pixel: (u32, u32), target_size: (u32, u32)) -> Mat4 {
+  let (width, height) = (target_size.0 as f32, target_size.1 as f32);
+  let x = 2.0 * (pixel.0 as f32 + 0.5) / width - 1.0;
+  let y = 1.0 - 2.0 * (pixel.1 as f32 + 0.5) / height;
+  // Mat4::new takes its elements in row-major order
+  Mat4::new(
+    width, 0.0, 0.0, -width * x,
+    0.0, height, 0.0, -height * y,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+  )
+}
+
+/// What a pick needs to turn the read back pixel into a PickResult
+#[derive(Debug, Clone)]
+struct PickTarget {
+  pixel: (u32, u32),
+  target_size: (u32, u32),
+  inverse_view_projection: Mat4,
+  table: PickTable,
+}
+
+impl PickTarget {
+  fn result(&self, id: u32, depth: f32) -> PickResult {
+    let entity = self.table.entity(id);
+    PickResult {
+      pixel: self.pixel,
+      entity,
+      world_position: match entity {
+        Some(_) => unproject(
+          &self.inverse_view_projection,
+          self.pixel,
+          self.target_size,
+          depth,
+        ),
+        None => Vec3::zeros(),
+      },
+      depth,
+    }
+  }
+}
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), BufferAsyncError>> + Send>>;
+
+/// A pick whose pixel is being copied back from the GPU
+struct PendingPick {
+  target: PickTarget,
+  mapping: MapFuture,
+}
+
+///
+/// GPU resources of the picking pass, and the state of the pick in flight.
+/// Only one pick is rendered at a time, later requests wait until it has been read back
+pub struct Picking {
+  pipeline: RenderPipeline,
+  /// camera uniforms of the pick, projected onto its pixel
+  uniform_buffer: Buffer,
+  uniform_bind_group: BindGroup,
+  instance_buffer: Buffer,
+  /// size of `instance_buffer` in bytes
+  instance_buffer_size: usize,
+  readback_buffer: Buffer,
+  /// draws of the pick rendered this frame
+  draws: Vec<InstanceRange>,
+  /// the pick rendered this frame, mapped once the frame is submitted
+  frame: Option<PickTarget>,
+  pending: Mutex<Option<PendingPick>>,
+}
+
+impl std::fmt::Debug for Picking {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Picking")
+      .field("draws", &self.draws)
+      .field("frame", &self.frame)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Picking {
+  /// `uniform_layout` is the layout of the context's camera uniforms
+  pub fn new(device: &Device, uniform_layout: &BindGroupLayout) -> Self {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("picking_pipeline_layout"),
+      bind_group_layouts: &[uniform_layout],
+      push_constant_ranges: &[],
+    });
+    let vertex_shader = device.create_shader_module(&include_shader!("pick.vert"));
+    let fragment_shader = device.create_shader_module(&include_shader!("pick.frag"));
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Picking Pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &vertex_shader,
+        entry_point: "main",
+        buffers: &[Vertex::desc(), PickInstance::desc()],
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &fragment_shader,
+        entry_point: "main",
+        targets: &[OBJECT_ID_FORMAT.into(), OBJECT_DEPTH_FORMAT.into()],
+      }),
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: Some(wgpu::DepthStencilState {
+        format: TextureResource::DEPTH_TEXTURE_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: Default::default(),
+        bias: Default::default(),
+      }),
+      multisample: wgpu::MultisampleState::default(),
+    });
+    let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pick Uniform Buffer"),
+      size: std::mem::size_of::<Uniforms>() as BufferAddress,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: uniform_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: uniform_buffer.as_entire_binding(),
+      }],
+      label: Some("pick_ubo_bind_group"),
+    });
+    let instance_buffer_size = std::mem::size_of::<PickInstance>();
+    let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pick Instance Buffer"),
+      size: instance_buffer_size as BufferAddress,
+      usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pick Readback Buffer"),
+      size: DEPTH_OFFSET + std::mem::size_of::<f32>() as BufferAddress,
+      usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    Self {
+      pipeline,
+      uniform_buffer,
+      uniform_bind_group,
+      instance_buffer,
+      instance_buffer_size,
+      readback_buffer,
+      draws: Vec::new(),
+      frame: None,
+      pending: Mutex::new(None),
+    }
+  }
+
+  /// False while a pick is being read back
+  pub fn is_idle(&mut self) -> bool {
+    self.pending.get_mut().unwrap().is_none()
+  }
+
+  /// Drops the pick prepared for a frame which failed to render
+  pub(crate) fn cancel_frame(&mut self) {
+    self.frame = None;
+  }
+
+  ///
+  /// Gathers every shown RenderModel in the world, and uploads their instances and
+  /// the camera projected onto the pixel, for the picking pass to draw this frame.
+  /// Returns false if the pixel is outside
+  /// the render target, or another pick hasn't finished
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn prepare(
+    &mut self,
+    device: &Device,
+    queue: &Queue,
+    world: &World,
+    models: &ResourceManager<StreamingMesh>,
+    camera: &Camera,
+    pixel: (u32, u32),
+    target_size: (u32, u32),
+  ) -> bool {
+    if !self.is_idle() || pixel.0 >= target_size.0 || pixel.1 >= target_size.1 {
+      return false;
+    }
+    let mut table = PickTable::default();
+    let mut instances: Vec<(ModelLod, PickInstance)> = Vec::new();
+    let mut query = <(Entity, &LocalToWorld, &RenderModel)>::query();
+    for (entity, local_to_world, model) in query.iter(world) {
+      let handle = match model.model {
+        Some(handle) if model.is_shown => handle,
+        _ => continue,
+      };
+      // drawn at the same level of detail as the frame, so the surfaces match
+      let lod = models
+        .try_get_ref(handle)
+        .ok()
+        .and_then(|streaming_mesh| streaming_mesh.bounds())
+        .map_or(0, |bounds| {
+          let bounds = bounds.transformed(&local_to_world.0);
+          model.select_lod(camera.screen_size(&bounds.sphere))
+        });
+      let id = table.push(*entity);
+      instances.push((
+        ModelLod { model: handle, lod },
+        PickInstance::new(local_to_world, id),
+      ));
+    }
+    let (instances, draws) = group_instances_by_model(instances);
+    let data: &[u8] = bytemuck::cast_slice(&instances);
+    if data.len() > self.instance_buffer_size {
+      self.instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Pick Instance Buffer"),
+        contents: data,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      });
+      self.instance_buffer_size = data.len();
+    } else if !data.is_empty() {
+      queue.write_buffer(&self.instance_buffer, 0, data);
+    }
+    self.draws = draws;
+    let mut uniforms = Uniforms::default();
+    uniforms.update_from_camera(camera);
+    uniforms.view_projection =
+      (pick_projection(pixel, target_size) * camera.view_projection()).into();
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    self.frame = Some(PickTarget {
+      pixel,
+      target_size,
+      inverse_view_projection: camera
+        .view_projection()
+        .try_inverse()
+        .unwrap_or_else(Mat4::identity),
+      table,
+    });
+    true
+  }
+
+  ///
+  /// Starts mapping the readback buffer, once the frame which rendered the pick
+  /// has been submitted
+  pub(crate) fn on_submitted(&mut self) {
+    if let Some(target) = self.frame.take() {
+      let mapping = self
+        .readback_buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read);
+      *self.pending.get_mut().unwrap() = Some(PendingPick {
+        target,
+        mapping: Box::pin(mapping),
+      });
+    }
+  }
+
+  ///
+  /// Checks whether the pick in flight has been read back, without blocking.
+  /// With `wait`, blocks until the GPU has finished it instead
+  pub fn poll(&mut self, device: &Device, wait: bool) -> Option<PickResult> {
+    let pending = self.pending.get_mut().unwrap();
+    let mapped = {
+      let mapping = &mut pending.as_mut()?.mapping;
+      device.poll(if wait {
+        wgpu::Maintain::Wait
+      } else {
+        wgpu::Maintain::Poll
+      });
+      let waker = noop_waker();
+      match mapping.as_mut().poll(&mut TaskContext::from_waker(&waker)) {
+        Poll::Pending => return None,
+        Poll::Ready(mapped) => mapped,
+      }
+    };
+    let target = pending.take()?.target;
+    if let Err(e) = mapped {
+      log::error!("could not read back the picked pixel: {:?}", e);
+      return None;
+    }
+    let (id, depth) = {
+      let data = self.readback_buffer.slice(..).get_mapped_range();
+      let offset = DEPTH_OFFSET as usize;
+      let id: u32 = *bytemuck::from_bytes(&data[..4]);
+      let depth: f32 = *bytemuck::from_bytes(&data[offset..offset + 4]);
+      (id, depth)
+    };
+    self.readback_buffer.unmap();
+    Some(target.result(id, depth))
+  }
+
+  /// Draws the pick's instances into its pixel, and copies the pixel into the readback buffer
+  fn render(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let target = match self.frame.as_ref() {
+      Some(target) => target,
+      None => return Ok(()),
+    };
+    let context = ctx.context;
+    let models = context
+      .resources
+      .models
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let meshes = context
+      .resources
+      .meshes
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    {
+      let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("picking pass"),
+        color_attachments: &[
+          wgpu::RenderPassColorAttachment {
+            view: ctx.texture_view(OBJECT_ID)?,
+            resolve_target: None,
+            ops: wgpu::Operations {
+              load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+              store: true,
+            },
+          },
+          wgpu::RenderPassColorAttachment {
+            view: ctx.texture_view(OBJECT_DEPTH)?,
+            resolve_target: None,
+            ops: wgpu::Operations {
+              load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+              store: true,
+            },
+          },
+        ],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+          view: ctx.texture_view(PICK_DEPTH)?,
+          depth_ops: Some(wgpu::Operations {
+            load: wgpu::LoadOp::Clear(1.0),
+            store: true,
+          }),
+          stencil_ops: None,
+        }),
+      });
+      pass.set_pipeline(&self.pipeline);
+      pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+      pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+      for draw in &self.draws {
+        let model = match models.try_get_ref(draw.model) {
+          Ok(model) => model,
+          Err(_) => continue,
+        };
+        for buffers in model
+          .primitives()
+          .iter()
+          .filter_map(|handle| handle.read(&*meshes))
+          .filter_map(|mesh| mesh.buffers())
+        {
+          pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+          pass.set_index_buffer(buffers.index_buffer.slice(..), buffers.index_format);
+          pass.draw_indexed(buffers.lod_range(draw.lod), 0, draw.instances.clone());
+        }
+      }
+    }
+    for (slot, offset) in [(OBJECT_ID, 0), (OBJECT_DEPTH, DEPTH_OFFSET)] {
+      encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+          texture: ctx.texture(slot)?,
+          mip_level: 0,
+          origin: wgpu::Origin3d::ZERO,
+          aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+          buffer: &self.readback_buffer,
+          layout: wgpu::ImageDataLayout {
+            offset,
+            bytes_per_row: NonZeroU32::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+            rows_per_image: None,
+          },
+        },
+        wgpu::Extent3d {
+          width: 1,
+          height: 1,
+          depth_or_array_layers: 1,
+        },
+      );
+    }
+    Ok(())
+  }
+}
+
+/// Map futures are driven by `Device::poll`, so nothing needs to be woken
+fn noop_waker() -> Waker {
+  fn clone(_: *const ()) -> RawWaker {
+    RawWaker::new(std::ptr::null(), &VTABLE)
+  }
+  fn noop(_: *const ()) {}
+  static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+  unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+///
+/// Renders entity ids under the pixel of a pending pick. The Context only adds it
+/// to the render graph for the frames which render a pick
+#[derive(Debug, Default)]
+pub struct PickingPass;
+
+impl RenderNode for PickingPass {
+  fn name(&self) -> &str {
+    "picking"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    let copied = |format| TextureSlotDesc {
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+      ..TextureSlotDesc::new(format, SlotSize::Fixed(1, 1))
+    };
+    slots
+      .create_texture(OBJECT_ID, copied(OBJECT_ID_FORMAT))
+      .create_texture(OBJECT_DEPTH, copied(OBJECT_DEPTH_FORMAT))
+      .create_texture(
+        PICK_DEPTH,
+        TextureSlotDesc::new(TextureResource::DEPTH_TEXTURE_FORMAT, SlotSize::Fixed(1, 1)),
+      );
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    ctx.context.picking.render(ctx, encoder)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use nalgebra_glm::vec3;
+
+  #[test]
+  fn test_pick_table() {
+    let mut world = World::default();
+    let a = world.push((0u32,));
+    let b = world.push((1u32,));
+    let mut table = PickTable::default();
+    assert_eq!(table.push(a), 1);
+    assert_eq!(table.push(b), 2);
+    assert_eq!(table.entity(0), None);
+    assert_eq!(table.entity(1), Some(a));
+    assert_eq!(table.entity(2), Some(b));
+    assert_eq!(table.entity(3), None);
+  }
+
+  #[test]
+  fn test_unproject_pixel_center() {
+    let identity = Mat4::identity();
+    let position = unproject(&identity, (0, 0), (2, 2), 0.25);
+    assert!((position - vec3(-0.5, 0.5, 0.25)).norm() < 1e-6);
+    let position = unproject(&identity, (1, 1), (2, 2), 1.0);
+    assert!((position - vec3(0.5, -0.5, 1.0)).norm() < 1e-6);
+  }
+
+  #[test]
+  fn test_unproject_camera() {
+    let camera = Camera::new(1.0);
+    let point = camera.position + camera.front().normalize() * 5.0;
+    let clip = camera.view_projection() * vec4(point.x, point.y, point.z, 1.0);
+    let depth = clip.z / clip.w;
+    let inverse = camera.view_projection().try_inverse().unwrap();
+    // the center pixel of an odd sized target is on the camera's axis
+    let position = unproject(&inverse, (50, 50), (101, 101), depth);
+    assert!((position - point).norm() < 1e-3, "{:?}", position);
+  }
+
+  #[test]
+  fn test_pick_projection() {
+    let projection = pick_projection((3, 0), (4, 2));
+    // the pixel's center and edges, in clip space with and without perspective
+    let center = projection * vec4(0.75, 0.5, 0.25, 1.0);
+    assert!(
+      (center - vec4(0.0, 0.0, 0.25, 1.0)).norm() < 1e-6,
+      "{:?}",
+      center
+    );
+    let center = projection * vec4(1.5, 1.0, 0.5, 2.0);
+    assert!(
+      (center - vec4(0.0, 0.0, 0.5, 2.0)).norm() < 1e-6,
+      "{:?}",
+      center
+    );
+    let corner = projection * vec4(0.5, 1.0, 0.0, 1.0);
+    assert!(
+      (corner - vec4(-1.0, 1.0, 0.0, 1.0)).norm() < 1e-6,
+      "{:?}",
+      corner
+    );
+    let corner = projection * vec4(1.0, 0.0, 0.0, 1.0);
+    assert!(
+      (corner - vec4(1.0, -1.0, 0.0, 1.0)).norm() < 1e-6,
+      "{:?}",
+      corner
+    );
+  }
+
+  #[test]
+  fn test_pick_target_result() {
+    let mut world = World::default();
+    let entity = world.push((0u32,));
+    let mut table = PickTable::default();
+    table.push(entity);
+    let target = PickTarget {
+      pixel: (1, 1),
+      target_size: (2, 2),
+      inverse_view_projection: Mat4::identity(),
+      table,
+    };
+    let hit = target.result(1, 0.5);
+    assert_eq!(hit.entity, Some(entity));
+    assert!((hit.world_position - vec3(0.5, -0.5, 0.5)).norm() < 1e-6);
+    let miss = target.result(0, 1.0);
+    assert_eq!(miss.entity, None);
+    assert_eq!(miss.depth, 1.0);
+  }
+}
@@ -1,13 +1,41 @@
 // Manager for RenderPipeline state, layouts, and shader loading
 use crate::{
   renderer_common::{allocator::ResourceManager, geometry::Vertex, handle::Handle},
-  wgpu_renderer::{textures::TextureResource, ModelInstance},
+  wgpu_renderer::{
+    material::{AlphaMode, RenderMaterial},
+    reflection::{ReflectionError, ShaderReflection},
+    textures::TextureResource,
+    ModelInstance,
+  },
 };
 use atomic_refcell::AtomicRefCell;
 use serde::{Deserialize, Serialize};
-use std::ops::Deref;
+use std::{collections::HashMap, ops::Deref};
 use wgpu::*;
 
+///
+/// Sample counts the renderer can use for MSAA.
+/// wgpu only accepts 1 or 4 samples per pixel in render passes
+pub const SUPPORTED_SAMPLE_COUNTS: [u32; 2] = [1, 4];
+
+///
+/// The counts of `SUPPORTED_SAMPLE_COUNTS` the adapter can use
+/// for render attachments of `color_format` and the depth buffer
+pub fn supported_sample_counts(adapter: &Adapter, color_format: TextureFormat) -> Vec<u32> {
+  let renderable = |format| {
+    adapter
+      .get_texture_format_features(format)
+      .allowed_usages
+      .contains(TextureUsages::RENDER_ATTACHMENT)
+  };
+  let multisampled = renderable(color_format) && renderable(TextureResource::DEPTH_TEXTURE_FORMAT);
+  SUPPORTED_SAMPLE_COUNTS
+    .iter()
+    .copied()
+    .filter(|&count| count == 1 || multisampled)
+    .collect()
+}
+
 #[derive(Debug)]
 pub struct AllocedRenderPipelineDescriptor {}
 
@@ -25,6 +53,12 @@ pub struct ShaderInfo {
   pub frag_entrypoint: Option<String>,
   pub vert_shader: Handle<ShaderModule>,
   pub vert_entrypoint: Option<String>,
+  /// file names of the GLSL sources, relative to the shader directory
+  pub vert_source: Option<String>,
+  pub frag_source: Option<String>,
+  /// interfaces of the shaders, if they were created from descriptors
+  pub vert_reflection: Option<ShaderReflection>,
+  pub frag_reflection: Option<ShaderReflection>,
 }
 
 impl ShaderInfo {
@@ -34,21 +68,61 @@ impl ShaderInfo {
       vert_shader,
       frag_entrypoint: None,
       vert_entrypoint: None,
+      vert_source: None,
+      frag_source: None,
+      vert_reflection: None,
+      frag_reflection: None,
     }
   }
+
+  /// Sets the GLSL sources of the shaders, for reloading them when they change
+  pub fn with_sources(mut self, vert_source: &str, frag_source: &str) -> Self {
+    self.vert_source = Some(vert_source.to_owned());
+    self.frag_source = Some(frag_source.to_owned());
+    self
+  }
   /**
    * Given shader module descriptors, compiles and loads the shaders into the resorce manager, and creates the shaderInfo
-   * object
+   * object, with the shaders' reflected interfaces
    */
   pub fn from_shader_descriptors(
     device: &Device,
     shaders: &mut ResourceManager<ShaderModule>,
     vert_descriptor: &wgpu::ShaderModuleDescriptor,
     frag_descriptor: &wgpu::ShaderModuleDescriptor,
-  ) -> Self {
+  ) -> Result<Self, ReflectionError> {
+    let vert_reflection = ShaderReflection::from_descriptor(vert_descriptor, "main")?;
+    let frag_reflection = ShaderReflection::from_descriptor(frag_descriptor, "main")?;
     let vert_shader = shaders.insert(device.create_shader_module(vert_descriptor));
     let frag_shader = shaders.insert(device.create_shader_module(frag_descriptor));
-    Self::new(frag_shader, vert_shader)
+    Ok(Self {
+      vert_reflection: Some(vert_reflection),
+      frag_reflection: Some(frag_reflection),
+      ..Self::new(frag_shader, vert_shader)
+    })
+  }
+
+  ///
+  /// The reflected shaders paired with `group`, for `reflection::bind_group_layout_entries`.
+  /// Used to derive the layout these shaders bind at index `group`
+  pub fn group(&self, group: u32) -> Vec<(&ShaderReflection, u32)> {
+    self
+      .vert_reflection
+      .iter()
+      .chain(self.frag_reflection.iter())
+      .map(|reflection| (reflection, group))
+      .collect()
+  }
+
+  /// Checks that `buffers` provide the vertex shader's inputs
+  pub fn validate_vertex_buffers(
+    &self,
+    buffers: &[VertexBufferLayout],
+  ) -> Result<(), ReflectionError> {
+    match &self.vert_reflection {
+      Some(reflection) => reflection.validate_vertex_buffers(buffers),
+      None => Ok(()),
+    }
   }
   pub fn frag_entrypoint_name(&self) -> &str {
     self.frag_entrypoint.as_deref().unwrap_or("main")
@@ -59,17 +133,111 @@ impl ShaderInfo {
   }
 }
 
+/// The vertex buffers a pipeline reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+  /// `Vertex` in slot 0, and `ModelInstance` in slot 1
+  MeshInstanced,
+}
+
+impl VertexLayout {
+  pub fn buffers(&self) -> Vec<VertexBufferLayout<'static>> {
+    match self {
+      Self::MeshInstanced => vec![Vertex::desc(), ModelInstance::desc()],
+    }
+  }
+}
+
+///
+/// The render state a mesh pipeline is built for.
+/// Every distinct key gets its own pipeline in `RendererPipelines`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+  pub shading_model: ShadingModel,
+  pub alpha_mode: AlphaMode,
+  pub cull_mode: Option<Face>,
+  pub vertex_layout: VertexLayout,
+  pub color_format: TextureFormat,
+  pub depth_format: TextureFormat,
+  pub sample_count: u32,
+}
+
+impl PipelineKey {
+  /// An opaque, back face culled pipeline
+  pub fn new(shading_model: ShadingModel, color_format: TextureFormat) -> Self {
+    Self {
+      shading_model,
+      alpha_mode: AlphaMode::Opaque,
+      cull_mode: Some(Face::Back),
+      vertex_layout: VertexLayout::MeshInstanced,
+      color_format,
+      depth_format: TextureResource::DEPTH_TEXTURE_FORMAT,
+      sample_count: 1,
+    }
+  }
+
+  /// Takes the alpha mode and culling from a material
+  pub fn with_material<T>(self, material: &RenderMaterial<T>) -> Self {
+    Self {
+      alpha_mode: material.alpha_mode,
+      cull_mode: if material.double_sided {
+        None
+      } else {
+        Some(Face::Back)
+      },
+      ..self
+    }
+  }
+
+  pub fn with_cull_mode(self, cull_mode: Option<Face>) -> Self {
+    Self { cull_mode, ..self }
+  }
+
+  pub fn with_sample_count(self, sample_count: u32) -> Self {
+    Self {
+      sample_count,
+      ..self
+    }
+  }
+
+  pub fn blend_state(&self) -> Option<BlendState> {
+    match self.alpha_mode {
+      AlphaMode::Blend => Some(BlendState::ALPHA_BLENDING),
+      AlphaMode::Opaque | AlphaMode::Mask => None,
+    }
+  }
+
+  /// blended meshes are sorted instead, and don't hide the meshes behind them
+  pub fn depth_write_enabled(&self) -> bool {
+    !matches!(self.alpha_mode, AlphaMode::Blend)
+  }
+}
+
 #[derive(Debug)]
 pub struct RendererPipelines {
-  pub(crate) debug_light_pipeline: Option<RenderPipeline>,
   pub(crate) debug_light_layout: PipelineLayout,
   pub(crate) debug_light_shaders: ShaderInfo,
 
-  pub(crate) pbr_model_pipeline: Option<RenderPipeline>,
   pub(crate) pbr_model_layout: PipelineLayout,
   pub(crate) pbr_model_shaders: ShaderInfo,
 
+  pub(crate) skybox_pipeline: Option<RenderPipeline>,
+  pub(crate) skybox_layout: PipelineLayout,
+  pub(crate) skybox_shaders: ShaderInfo,
+
+  /// mesh pipelines, built the first time their key is drawn
+  variants: HashMap<PipelineKey, RenderPipeline>,
+
   pub(crate) color_target: wgpu::ColorTargetState,
+  /// MSAA samples per pixel of every pipeline
+  pub(crate) sample_count: u32,
+}
+
+/// Pipelines replaced by `RendererPipelines::replace_pipelines`
+#[derive(Debug)]
+pub(crate) struct BuiltPipelines {
+  skybox_pipeline: Option<RenderPipeline>,
+  variants: HashMap<PipelineKey, RenderPipeline>,
 }
 
 impl RendererPipelines {
@@ -79,7 +247,10 @@ impl RendererPipelines {
     debug_light_shaders: ShaderInfo,
     pbr_model_layouts: &[&BindGroupLayout],
     pbr_model_shaders: ShaderInfo,
+    skybox_layouts: &[&BindGroupLayout],
+    skybox_shaders: ShaderInfo,
     color_target: ColorTargetState,
+    sample_count: u32,
   ) -> Self {
     let debug_light_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
       label: Some("debug_light renderer"),
@@ -92,49 +263,202 @@ impl RendererPipelines {
       bind_group_layouts: pbr_model_layouts,
       push_constant_ranges: &[],
     });
+
+    let skybox_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("skybox renderer"),
+      bind_group_layouts: skybox_layouts,
+      push_constant_ranges: &[],
+    });
     Self {
-      debug_light_pipeline: None,
       debug_light_layout,
-      pbr_model_pipeline: None,
       pbr_model_layout,
+      skybox_pipeline: None,
+      skybox_layout,
+      variants: HashMap::new(),
       color_target,
+      sample_count,
       debug_light_shaders,
       pbr_model_shaders,
+      skybox_shaders,
     }
   }
 
+  /// Builds the skybox pipeline, and rebuilds the mesh pipelines with the current shaders
   pub fn build_pipelines(
     &mut self,
     device: &wgpu::Device,
     shaders: &ResourceManager<ShaderModule>,
   ) -> anyhow::Result<()> {
-    self.pbr_model_pipeline = {
-      let vert_shader = shaders.try_get_ref(self.pbr_model_shaders.vert_shader)?;
-      let frag_shader = shaders.try_get_ref(self.pbr_model_shaders.frag_shader)?;
-      Some(create_render_pipeline(
-        device,
-        &self.pbr_model_layout,
-        vert_shader,
-        frag_shader,
-        self.color_target.clone(),
-      ))
-    };
-    self.debug_light_pipeline = {
-      let vert_shader = shaders.try_get_ref(self.debug_light_shaders.vert_shader)?;
-      let frag_shader = shaders.try_get_ref(self.debug_light_shaders.frag_shader)?;
-      Some(create_render_pipeline(
+    self.replace_pipelines(device, shaders).map(|_| ())
+  }
+
+  ///
+  /// Rebuilds the skybox and every mesh pipeline built so far,
+  /// with the current color target and sample count.
+  /// Returns the previous pipelines, which can be put back with `restore_pipelines`
+  pub(crate) fn replace_pipelines(
+    &mut self,
+    device: &wgpu::Device,
+    shaders: &ResourceManager<ShaderModule>,
+  ) -> anyhow::Result<BuiltPipelines> {
+    let skybox_pipeline = {
+      self.skybox_shaders.validate_vertex_buffers(&[])?;
+      let vert_shader = shaders.try_get_ref(self.skybox_shaders.vert_shader)?;
+      let frag_shader = shaders.try_get_ref(self.skybox_shaders.frag_shader)?;
+      create_skybox_pipeline(
         device,
-        &self.debug_light_layout,
+        &self.skybox_layout,
         vert_shader,
         frag_shader,
         self.color_target.clone(),
-      ))
+        self.sample_count,
+      )
     };
+    let mut variants = HashMap::with_capacity(self.variants.len());
+    for key in self.variants.keys() {
+      let key = PipelineKey {
+        color_format: self.color_target.format,
+        sample_count: self.sample_count,
+        ..*key
+      };
+      if !variants.contains_key(&key) {
+        variants.insert(key, self.create_variant(device, shaders, &key)?);
+      }
+    }
+    Ok(BuiltPipelines {
+      skybox_pipeline: std::mem::replace(&mut self.skybox_pipeline, Some(skybox_pipeline)),
+      variants: std::mem::replace(&mut self.variants, variants),
+    })
+  }
+
+  pub(crate) fn restore_pipelines(&mut self, previous: BuiltPipelines) {
+    self.skybox_pipeline = previous.skybox_pipeline;
+    self.variants = previous.variants;
+  }
+
+  /// The GLSL source file of each shader module, for those with known sources
+  pub fn shader_sources(&self) -> Vec<(&str, Handle<ShaderModule>)> {
+    let mut sources = Vec::new();
+    for info in [
+      &self.debug_light_shaders,
+      &self.pbr_model_shaders,
+      &self.skybox_shaders,
+    ]
+    .iter()
+    {
+      if let Some(source) = &info.vert_source {
+        sources.push((source.as_str(), info.vert_shader));
+      }
+      if let Some(source) = &info.frag_source {
+        sources.push((source.as_str(), info.frag_shader));
+      }
+    }
+    sources
+  }
+
+  ///
+  /// Replaces the reflection of each shader module with the given handle,
+  /// and returns the reflections that were replaced
+  pub(crate) fn swap_reflections(
+    &mut self,
+    reflections: Vec<(Handle<ShaderModule>, ShaderReflection)>,
+  ) -> Vec<(Handle<ShaderModule>, ShaderReflection)> {
+    let mut previous = Vec::new();
+    for (handle, reflection) in reflections {
+      for info in [
+        &mut self.debug_light_shaders,
+        &mut self.pbr_model_shaders,
+        &mut self.skybox_shaders,
+      ] {
+        let slot = if info.vert_shader == handle {
+          &mut info.vert_reflection
+        } else if info.frag_shader == handle {
+          &mut info.frag_reflection
+        } else {
+          continue;
+        };
+        if let Some(replaced) = slot.replace(reflection.clone()) {
+          previous.push((handle, replaced));
+        }
+      }
+    }
+    previous
+  }
+
+  /// Key for drawing with the given shading model into the color target
+  pub fn key(&self, shading_model: ShadingModel) -> PipelineKey {
+    PipelineKey::new(shading_model, self.color_target.format).with_sample_count(self.sample_count)
+  }
+
+  pub fn sample_count(&self) -> u32 {
+    self.sample_count
+  }
 
+  ///
+  /// Rebuilds every pipeline for a new sample count.
+  /// Returns the previous pipelines, which can be put back with `restore_sample_count`
+  pub(crate) fn replace_sample_count(
+    &mut self,
+    device: &wgpu::Device,
+    shaders: &ResourceManager<ShaderModule>,
+    sample_count: u32,
+  ) -> anyhow::Result<(u32, BuiltPipelines)> {
+    let previous_count = std::mem::replace(&mut self.sample_count, sample_count);
+    match self.replace_pipelines(device, shaders) {
+      Ok(previous) => Ok((previous_count, previous)),
+      Err(e) => {
+        self.sample_count = previous_count;
+        Err(e)
+      }
+    }
+  }
+
+  pub(crate) fn restore_sample_count(&mut self, (sample_count, previous): (u32, BuiltPipelines)) {
+    self.sample_count = sample_count;
+    self.restore_pipelines(previous);
+  }
+
+  /// Builds the pipeline for `key`, if it hasn't been already
+  pub fn prepare_variant(
+    &mut self,
+    device: &wgpu::Device,
+    shaders: &ResourceManager<ShaderModule>,
+    key: &PipelineKey,
+  ) -> anyhow::Result<()> {
+    if self.variants.contains_key(key) {
+      return Ok(());
+    }
+    let pipeline = self.create_variant(device, shaders, key)?;
+    self.variants.insert(*key, pipeline);
     Ok(())
   }
 
-  pub fn resize(&mut self, (width, height): (u32, u32)) {}
+  fn create_variant(
+    &self,
+    device: &wgpu::Device,
+    shaders: &ResourceManager<ShaderModule>,
+    key: &PipelineKey,
+  ) -> anyhow::Result<RenderPipeline> {
+    let (layout, shader_info) = match key.shading_model {
+      ShadingModel::Pbr => (&self.pbr_model_layout, &self.pbr_model_shaders),
+      ShadingModel::DebugLight => (&self.debug_light_layout, &self.debug_light_shaders),
+    };
+    shader_info.validate_vertex_buffers(&key.vertex_layout.buffers())?;
+    let vert_shader = shaders.try_get_ref(shader_info.vert_shader)?;
+    let frag_shader = shaders.try_get_ref(shader_info.frag_shader)?;
+    Ok(create_render_pipeline(
+      device,
+      layout,
+      vert_shader,
+      frag_shader,
+      key,
+    ))
+  }
+
+  /// The pipeline for `key`, if `prepare_variant` has built it
+  pub fn variant(&self, key: &PipelineKey) -> Option<&RenderPipeline> {
+    self.variants.get(key)
+  }
 }
 
 pub fn create_render_pipeline(
@@ -142,39 +466,108 @@ pub fn create_render_pipeline(
   layout: &wgpu::PipelineLayout,
   vert_shader: &wgpu::ShaderModule,
   frag_shader: &wgpu::ShaderModule,
-  color_target: ColorTargetState,
+  key: &PipelineKey,
 ) -> RenderPipeline {
+  let buffers = key.vertex_layout.buffers();
   let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
     label: Some("Render Pipeline"),
     layout: Some(layout),
     vertex: wgpu::VertexState {
       module: vert_shader,
       entry_point: "main",
-      buffers: &[Vertex::desc(), ModelInstance::desc()],
+      buffers: &buffers,
     },
     fragment: Some(wgpu::FragmentState {
       module: &frag_shader,
       entry_point: "main",
-      targets: &[color_target],
+      targets: &[ColorTargetState {
+        format: key.color_format,
+        blend: key.blend_state(),
+        write_mask: ColorWrites::ALL,
+      }],
     }),
     primitive: wgpu::PrimitiveState {
-      cull_mode: None,
-      // cull_mode: Some(Face::Back),
+      cull_mode: key.cull_mode,
       ..wgpu::PrimitiveState::default()
     },
     depth_stencil: Some(wgpu::DepthStencilState {
-      format: TextureResource::DEPTH_TEXTURE_FORMAT,
-      depth_write_enabled: true,
+      format: key.depth_format,
+      depth_write_enabled: key.depth_write_enabled(),
       depth_compare: wgpu::CompareFunction::Less,
       stencil: Default::default(),
       bias: Default::default(),
     }),
-    multisample: wgpu::MultisampleState::default(),
+    multisample: wgpu::MultisampleState {
+      count: key.sample_count,
+      ..wgpu::MultisampleState::default()
+    },
   });
   render_pipeline
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+///
+/// Pipeline for drawing the environment behind the scene.
+/// Draws a fullscreen triangle on the far plane without vertex buffers,
+/// so it is only visible where no geometry was drawn
+pub fn create_skybox_pipeline(
+  device: &wgpu::Device,
+  layout: &wgpu::PipelineLayout,
+  vert_shader: &wgpu::ShaderModule,
+  frag_shader: &wgpu::ShaderModule,
+  color_target: ColorTargetState,
+  sample_count: u32,
+) -> RenderPipeline {
+  device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    label: Some("Skybox Pipeline"),
+    layout: Some(layout),
+    vertex: wgpu::VertexState {
+      module: vert_shader,
+      entry_point: "main",
+      buffers: &[],
+    },
+    fragment: Some(wgpu::FragmentState {
+      module: frag_shader,
+      entry_point: "main",
+      targets: &[color_target],
+    }),
+    primitive: wgpu::PrimitiveState::default(),
+    depth_stencil: Some(wgpu::DepthStencilState {
+      format: TextureResource::DEPTH_TEXTURE_FORMAT,
+      depth_write_enabled: false,
+      depth_compare: wgpu::CompareFunction::LessEqual,
+      stencil: Default::default(),
+      bias: Default::default(),
+    }),
+    multisample: wgpu::MultisampleState {
+      count: sample_count,
+      ..wgpu::MultisampleState::default()
+    },
+  })
+}
+
+///
+/// Compute pipeline running the `main` entry point of `shader`,
+/// with a layout of `bind_group_layouts`
+pub fn create_compute_pipeline(
+  device: &wgpu::Device,
+  label: &str,
+  bind_group_layouts: &[&BindGroupLayout],
+  shader: &wgpu::ShaderModule,
+) -> ComputePipeline {
+  let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+    label: Some(label),
+    bind_group_layouts,
+    push_constant_ranges: &[],
+  });
+  device.create_compute_pipeline(&ComputePipelineDescriptor {
+    label: Some(label),
+    layout: Some(&layout),
+    module: shader,
+    entry_point: "main",
+  })
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum ShadingModel {
   Pbr,
   DebugLight,
@@ -185,3 +578,40 @@ impl Default for ShadingModel {
     Self::Pbr
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_blended_keys_disable_depth_writes() {
+    let opaque = PipelineKey::new(ShadingModel::Pbr, TextureFormat::Bgra8UnormSrgb);
+    assert!(opaque.depth_write_enabled());
+    assert_eq!(opaque.blend_state(), None);
+
+    let masked = PipelineKey {
+      alpha_mode: AlphaMode::Mask,
+      ..opaque
+    };
+    assert!(masked.depth_write_enabled());
+    assert_eq!(masked.blend_state(), None);
+    assert_ne!(masked, opaque);
+
+    let blended = PipelineKey {
+      alpha_mode: AlphaMode::Blend,
+      ..opaque
+    };
+    assert!(!blended.depth_write_enabled());
+    assert_eq!(blended.blend_state(), Some(BlendState::ALPHA_BLENDING));
+  }
+
+  #[test]
+  fn test_sample_count_keys() {
+    let key = PipelineKey::new(ShadingModel::Pbr, TextureFormat::Bgra8UnormSrgb);
+    assert_eq!(key.sample_count, 1);
+    let multisampled = key.with_sample_count(4);
+    assert_eq!(multisampled.sample_count, 4);
+    assert_ne!(multisampled, key);
+    assert_eq!(multisampled.with_sample_count(1), key);
+  }
+}
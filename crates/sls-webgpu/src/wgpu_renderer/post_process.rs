@@ -0,0 +1,686 @@
+//! Post-processing of the HDR scene.
+//!
+//! After the scene passes have drawn into [HDR_COLOR], the bloom node blurs its bright
+//! areas through a chain of downsampled textures, the tonemap node mixes the bloom in and
+//! maps the result to the surface's range, and the FXAA node antialiases it into the surface.
+//! Each effect is configured every frame from the game's [PostProcessSettings].
+use wgpu::{
+  BindGroupLayout, Buffer, Device, RenderPipeline, Sampler, ShaderModule, ShaderStages,
+  TextureFormat, TextureSampleType, TextureView, TextureViewDimension,
+};
+
+use crate::{
+  game::{
+    resources::{BloomSettings, FxaaSettings, PostProcessSettings, Tonemapper},
+    GameState,
+  },
+  wgpu_renderer::render_graph::{
+    NodeContext, NodeSlots, RenderNode, SlotSize, TextureSlotDesc, HDR_COLOR, HDR_FORMAT,
+    LDR_COLOR, SURFACE,
+  },
+};
+
+/// Number of textures in the bloom chain, each half the size of the one before it
+pub const BLOOM_MIP_COUNT: usize = 6;
+/// Slots of the bloom chain. The first, at half the surface's size, holds the finished bloom
+pub const BLOOM_SLOTS: [&str; BLOOM_MIP_COUNT] = [
+  "bloom_0", "bloom_1", "bloom_2", "bloom_3", "bloom_4", "bloom_5",
+];
+
+/// Offset between the bloom pass uniforms, which satisfies the default
+/// min_uniform_buffer_offset_alignment
+const BLOOM_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+/// Bloom uniform used by the first downsample, which applies the Karis average
+const BLOOM_FIRST_DOWNSAMPLE: u32 = 0;
+/// Bloom uniform used by every other pass
+const BLOOM_CHAIN: u32 = 1;
+
+/// The `BloomPass` uniform block in bloom_downsample.frag and bloom_upsample.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomPassUniform {
+  filter_radius: f32,
+  karis_average: u32,
+  _padding: [u32; 2],
+}
+
+/// The `Tonemap` uniform block in tonemap.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+  exposure: f32,
+  bloom_intensity: f32,
+  tonemapper: u32,
+  _padding: u32,
+}
+
+impl TonemapUniform {
+  fn new(settings: &PostProcessSettings) -> Self {
+    Self {
+      exposure: settings.exposure,
+      // the bloom chain is only cleared when bloom is disabled
+      bloom_intensity: if settings.bloom.enabled {
+        settings.bloom.intensity
+      } else {
+        0.0
+      },
+      tonemapper: match settings.tonemapper {
+        Tonemapper::None => 0,
+        Tonemapper::Reinhard => 1,
+        Tonemapper::Aces => 2,
+      },
+      _padding: 0,
+    }
+  }
+}
+
+/// The `Fxaa` uniform block in fxaa.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct FxaaUniform {
+  enabled: u32,
+  edge_threshold: f32,
+  edge_threshold_min: f32,
+  span_max: f32,
+}
+
+impl From<&FxaaSettings> for FxaaUniform {
+  fn from(settings: &FxaaSettings) -> Self {
+    Self {
+      enabled: settings.enabled as u32,
+      edge_threshold: settings.edge_threshold,
+      edge_threshold_min: settings.edge_threshold_min,
+      span_max: settings.span_max,
+    }
+  }
+}
+
+/// The game's post-processing settings, or the defaults if it has none
+fn post_process_settings(game: &GameState) -> PostProcessSettings {
+  game
+    .resources()
+    .get::<PostProcessSettings>()
+    .map(|settings| *settings)
+    .unwrap_or_default()
+}
+
+fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+  wgpu::BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Texture {
+      multisampled: false,
+      view_dimension: TextureViewDimension::D2,
+      sample_type: TextureSampleType::Float { filterable: true },
+    },
+    count: None,
+  }
+}
+
+fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+  wgpu::BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Sampler {
+      comparison: false,
+      filtering: true,
+    },
+    count: None,
+  }
+}
+
+fn uniform_entry<T>(binding: u32, has_dynamic_offset: bool) -> wgpu::BindGroupLayoutEntry {
+  wgpu::BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+      ty: wgpu::BufferBindingType::Uniform,
+      has_dynamic_offset,
+      min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<T>() as _),
+    },
+    count: None,
+  }
+}
+
+fn uniform_buffer<T>(device: &Device, label: &str, size: wgpu::BufferAddress) -> Buffer {
+  device.create_buffer(&wgpu::BufferDescriptor {
+    label: Some(label),
+    size: size.max(std::mem::size_of::<T>() as _),
+    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    mapped_at_creation: false,
+  })
+}
+
+/// Pipeline drawing a fullscreen triangle, without vertex buffers or depth
+fn fullscreen_pipeline(
+  device: &Device,
+  label: &str,
+  layout: &BindGroupLayout,
+  vertex_shader: &ShaderModule,
+  fragment_shader: &ShaderModule,
+  target: wgpu::ColorTargetState,
+) -> RenderPipeline {
+  let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+    label: Some(label),
+    bind_group_layouts: &[layout],
+    push_constant_ranges: &[],
+  });
+  device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    label: Some(label),
+    layout: Some(&pipeline_layout),
+    vertex: wgpu::VertexState {
+      module: vertex_shader,
+      entry_point: "main",
+      buffers: &[],
+    },
+    fragment: Some(wgpu::FragmentState {
+      module: fragment_shader,
+      entry_point: "main",
+      targets: &[target],
+    }),
+    primitive: wgpu::PrimitiveState::default(),
+    depth_stencil: None,
+    multisample: wgpu::MultisampleState::default(),
+  })
+}
+
+/// Begins a pass drawing into a single color target
+fn begin_fullscreen_pass<'a>(
+  encoder: &'a mut wgpu::CommandEncoder,
+  label: &str,
+  target: &'a TextureView,
+  load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu::RenderPass<'a> {
+  encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+    label: Some(label),
+    color_attachments: &[wgpu::RenderPassColorAttachment {
+      view: target,
+      resolve_target: None,
+      ops: wgpu::Operations { load, store: true },
+    }],
+    depth_stencil_attachment: None,
+  })
+}
+
+///
+/// Pipelines and uniform buffers of the post-processing nodes.
+/// The tonemap and FXAA pipelines are built for the render target's format
+#[derive(Debug)]
+pub struct PostProcess {
+  sampler: Sampler,
+
+  bloom_layout: BindGroupLayout,
+  bloom_buffer: Buffer,
+  bloom_downsample: RenderPipeline,
+  bloom_upsample: RenderPipeline,
+
+  tonemap_layout: BindGroupLayout,
+  tonemap_buffer: Buffer,
+  tonemap: RenderPipeline,
+
+  fxaa_layout: BindGroupLayout,
+  fxaa_buffer: Buffer,
+  fxaa: RenderPipeline,
+}
+
+impl PostProcess {
+  pub fn new(device: &Device, surface_format: TextureFormat) -> Self {
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      label: Some("post_process_sampler"),
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      mipmap_filter: wgpu::FilterMode::Nearest,
+      ..Default::default()
+    });
+    let fullscreen = device.create_shader_module(&include_shader!("fullscreen.vert"));
+
+    let bloom_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("bloom_layout"),
+      entries: &[
+        texture_entry(0),
+        sampler_entry(1),
+        uniform_entry::<BloomPassUniform>(2, true),
+      ],
+    });
+    let bloom_buffer =
+      uniform_buffer::<BloomPassUniform>(device, "bloom_uniform_buffer", 2 * BLOOM_UNIFORM_STRIDE);
+    let bloom_downsample = fullscreen_pipeline(
+      device,
+      "bloom_downsample_pipeline",
+      &bloom_layout,
+      &fullscreen,
+      &device.create_shader_module(&include_shader!("bloom_downsample.frag")),
+      HDR_FORMAT.into(),
+    );
+    // each upsampled level is added to the level above it
+    let additive = wgpu::BlendComponent {
+      src_factor: wgpu::BlendFactor::One,
+      dst_factor: wgpu::BlendFactor::One,
+      operation: wgpu::BlendOperation::Add,
+    };
+    let bloom_upsample = fullscreen_pipeline(
+      device,
+      "bloom_upsample_pipeline",
+      &bloom_layout,
+      &fullscreen,
+      &device.create_shader_module(&include_shader!("bloom_upsample.frag")),
+      wgpu::ColorTargetState {
+        format: HDR_FORMAT,
+        blend: Some(wgpu::BlendState {
+          color: additive,
+          alpha: additive,
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+      },
+    );
+
+    let tonemap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("tonemap_layout"),
+      entries: &[
+        texture_entry(0),
+        texture_entry(1),
+        sampler_entry(2),
+        uniform_entry::<TonemapUniform>(3, false),
+      ],
+    });
+    let tonemap_buffer = uniform_buffer::<TonemapUniform>(device, "tonemap_uniform_buffer", 0);
+    let tonemap = fullscreen_pipeline(
+      device,
+      "tonemap_pipeline",
+      &tonemap_layout,
+      &fullscreen,
+      &device.create_shader_module(&include_shader!("tonemap.frag")),
+      surface_format.into(),
+    );
+
+    let fxaa_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("fxaa_layout"),
+      entries: &[
+        texture_entry(0),
+        sampler_entry(1),
+        uniform_entry::<FxaaUniform>(2, false),
+      ],
+    });
+    let fxaa_buffer = uniform_buffer::<FxaaUniform>(device, "fxaa_uniform_buffer", 0);
+    let fxaa = fullscreen_pipeline(
+      device,
+      "fxaa_pipeline",
+      &fxaa_layout,
+      &fullscreen,
+      &device.create_shader_module(&include_shader!("fxaa.frag")),
+      surface_format.into(),
+    );
+
+    Self {
+      sampler,
+      bloom_layout,
+      bloom_buffer,
+      bloom_downsample,
+      bloom_upsample,
+      tonemap_layout,
+      tonemap_buffer,
+      tonemap,
+      fxaa_layout,
+      fxaa_buffer,
+      fxaa,
+    }
+  }
+
+  fn write_bloom(&self, queue: &wgpu::Queue, settings: &BloomSettings) {
+    for (index, karis_average) in [(BLOOM_FIRST_DOWNSAMPLE, 1), (BLOOM_CHAIN, 0)].iter() {
+      let uniform = BloomPassUniform {
+        filter_radius: settings.filter_radius,
+        karis_average: *karis_average,
+        _padding: [0; 2],
+      };
+      queue.write_buffer(
+        &self.bloom_buffer,
+        *index as wgpu::BufferAddress * BLOOM_UNIFORM_STRIDE,
+        bytemuck::cast_slice(&[uniform]),
+      );
+    }
+  }
+
+  ///
+  /// Draws `source` into `target`, using the bloom uniform at `uniform_index`.
+  /// Downsampling replaces the target, and upsampling adds to it
+  fn bloom_pass(
+    &self,
+    device: &Device,
+    encoder: &mut wgpu::CommandEncoder,
+    upsample: bool,
+    source: &TextureView,
+    target: &TextureView,
+    uniform_index: u32,
+  ) {
+    let (pipeline, load) = if upsample {
+      (&self.bloom_upsample, wgpu::LoadOp::Load)
+    } else {
+      (
+        &self.bloom_downsample,
+        wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+      )
+    };
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("bloom_bind_group"),
+      layout: &self.bloom_layout,
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(source),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(&self.sampler),
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.bloom_buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(std::mem::size_of::<BloomPassUniform>() as _),
+          }),
+        },
+      ],
+    });
+    let mut pass = begin_fullscreen_pass(encoder, "bloom pass", target, load);
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(
+      0,
+      &bind_group,
+      &[(uniform_index as wgpu::BufferAddress * BLOOM_UNIFORM_STRIDE) as u32],
+    );
+    pass.draw(0..3, 0..1);
+  }
+}
+
+///
+/// Blurs the bright parts of HDR_COLOR. Each level of the chain is downsampled from the one
+/// above it, then the levels are blurred and added back up the chain into the first one
+#[derive(Debug, Default)]
+pub struct BloomPass;
+
+impl RenderNode for BloomPass {
+  fn name(&self) -> &str {
+    "bloom"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(HDR_COLOR);
+    for (level, slot) in BLOOM_SLOTS.iter().enumerate() {
+      slots.create_texture(
+        slot,
+        TextureSlotDesc::new(HDR_FORMAT, SlotSize::Scaled(0.5f32.powi(level as i32 + 1))),
+      );
+    }
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let post_process = &context.post_process;
+    let settings = post_process_settings(ctx.game).bloom;
+    if !settings.enabled {
+      // the tonemap node still samples the bloom, so it is cleared
+      begin_fullscreen_pass(
+        encoder,
+        "bloom clear pass",
+        ctx.texture_view(BLOOM_SLOTS[0])?,
+        wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+      );
+      return Ok(());
+    }
+    post_process.write_bloom(&context.queue, &settings);
+
+    for level in 0..BLOOM_MIP_COUNT {
+      let (source, uniform_index) = match level {
+        0 => (ctx.texture_view(HDR_COLOR)?, BLOOM_FIRST_DOWNSAMPLE),
+        _ => (ctx.texture_view(BLOOM_SLOTS[level - 1])?, BLOOM_CHAIN),
+      };
+      post_process.bloom_pass(
+        &context.device,
+        encoder,
+        false,
+        source,
+        ctx.texture_view(BLOOM_SLOTS[level])?,
+        uniform_index,
+      );
+    }
+    for level in (1..BLOOM_MIP_COUNT).rev() {
+      post_process.bloom_pass(
+        &context.device,
+        encoder,
+        true,
+        ctx.texture_view(BLOOM_SLOTS[level])?,
+        ctx.texture_view(BLOOM_SLOTS[level - 1])?,
+        BLOOM_CHAIN,
+      );
+    }
+    Ok(())
+  }
+}
+
+///
+/// Mixes the bloom into HDR_COLOR, applies exposure,
+/// and tonemaps the result into LDR_COLOR
+#[derive(Debug, Default)]
+pub struct TonemapPass;
+
+impl RenderNode for TonemapPass {
+  fn name(&self) -> &str {
+    "tonemap"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    let surface_format = slots.config().surface_format;
+    slots.read(HDR_COLOR).read(BLOOM_SLOTS[0]).create_texture(
+      LDR_COLOR,
+      TextureSlotDesc::new(surface_format, SlotSize::Surface),
+    );
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let post_process = &context.post_process;
+    let uniform = TonemapUniform::new(&post_process_settings(ctx.game));
+    context.queue.write_buffer(
+      &post_process.tonemap_buffer,
+      0,
+      bytemuck::cast_slice(&[uniform]),
+    );
+    let bind_group = context
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("tonemap_bind_group"),
+        layout: &post_process.tonemap_layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(ctx.texture_view(HDR_COLOR)?),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::TextureView(ctx.texture_view(BLOOM_SLOTS[0])?),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: wgpu::BindingResource::Sampler(&post_process.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 3,
+            resource: post_process.tonemap_buffer.as_entire_binding(),
+          },
+        ],
+      });
+    let mut pass = begin_fullscreen_pass(
+      encoder,
+      "tonemap pass",
+      ctx.texture_view(LDR_COLOR)?,
+      wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+    );
+    pass.set_pipeline(&post_process.tonemap);
+    pass.set_bind_group(0, &bind_group, &[]);
+    pass.draw(0..3, 0..1);
+    Ok(())
+  }
+}
+
+///
+/// Antialiases LDR_COLOR into the surface.
+/// Copies it unchanged when FXAA is disabled
+#[derive(Debug, Default)]
+pub struct FxaaPass;
+
+impl RenderNode for FxaaPass {
+  fn name(&self) -> &str {
+    "fxaa"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(LDR_COLOR).write(SURFACE);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let post_process = &context.post_process;
+    let uniform = FxaaUniform::from(&post_process_settings(ctx.game).fxaa);
+    context.queue.write_buffer(
+      &post_process.fxaa_buffer,
+      0,
+      bytemuck::cast_slice(&[uniform]),
+    );
+    let bind_group = context
+      .device
+      .create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("fxaa_bind_group"),
+        layout: &post_process.fxaa_layout,
+        entries: &[
+          wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(ctx.texture_view(LDR_COLOR)?),
+          },
+          wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&post_process.sampler),
+          },
+          wgpu::BindGroupEntry {
+            binding: 2,
+            resource: post_process.fxaa_buffer.as_entire_binding(),
+          },
+        ],
+      });
+    let mut pass = begin_fullscreen_pass(
+      encoder,
+      "fxaa pass",
+      ctx.texture_view(SURFACE)?,
+      ctx.color_load_op(SURFACE, wgpu::Color::BLACK),
+    );
+    pass.set_pipeline(&post_process.fxaa);
+    pass.set_bind_group(0, &bind_group, &[]);
+    pass.draw(0..3, 0..1);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::wgpu_renderer::{
+    reflection::ShaderReflection,
+    render_graph::{GraphPlan, DEPTH, SHADOW_MAPS},
+  };
+  use std::collections::HashSet;
+
+  #[test]
+  fn test_tonemap_uniform() {
+    let uniform = TonemapUniform::new(&PostProcessSettings {
+      exposure: 2.0,
+      ..PostProcessSettings::default()
+    });
+    assert_eq!(uniform.exposure, 2.0);
+    assert_eq!(uniform.tonemapper, 2);
+    assert_eq!(uniform.bloom_intensity, BloomSettings::default().intensity);
+
+    let uniform = TonemapUniform::new(&PostProcessSettings::disabled());
+    assert_eq!(uniform.tonemapper, 0);
+    // disabled bloom isn't mixed in, whatever its intensity
+    assert_eq!(uniform.bloom_intensity, 0.0);
+  }
+
+  #[test]
+  fn test_uniforms_match_shaders() {
+    for (descriptor, binding, size) in [
+      (
+        include_shader!("bloom_downsample.frag"),
+        2,
+        std::mem::size_of::<BloomPassUniform>(),
+      ),
+      (
+        include_shader!("bloom_upsample.frag"),
+        2,
+        std::mem::size_of::<BloomPassUniform>(),
+      ),
+      (
+        include_shader!("tonemap.frag"),
+        3,
+        std::mem::size_of::<TonemapUniform>(),
+      ),
+      (
+        include_shader!("fxaa.frag"),
+        2,
+        std::mem::size_of::<FxaaUniform>(),
+      ),
+    ] {
+      let reflection = ShaderReflection::from_descriptor(&descriptor, "main").unwrap();
+      let declared = reflection
+        .group(0)
+        .find(|declared| declared.binding == binding)
+        .unwrap();
+      match declared.ty {
+        wgpu::BindingType::Buffer {
+          min_binding_size: Some(declared_size),
+          ..
+        } => assert!(declared_size.get() as usize <= size),
+        ty => panic!("binding {} is not a buffer: {:?}", binding, ty),
+      }
+    }
+  }
+
+  #[test]
+  fn test_post_process_nodes_follow_the_scene() {
+    let names = ["bloom", "tonemap", "fxaa", "opaque"];
+    let mut slots = vec![NodeSlots::default(); 4];
+    BloomPass.declare(&mut slots[0]);
+    TonemapPass.declare(&mut slots[1]);
+    FxaaPass.declare(&mut slots[2]);
+    slots[3]
+      .read(SHADOW_MAPS)
+      .create_texture(
+        HDR_COLOR,
+        TextureSlotDesc::new(HDR_FORMAT, SlotSize::Surface),
+      )
+      .create_texture(
+        DEPTH,
+        TextureSlotDesc::new(TextureFormat::Depth32Float, SlotSize::Surface),
+      );
+    let imports: HashSet<String> = [SURFACE, SHADOW_MAPS]
+      .iter()
+      .map(|s| s.to_string())
+      .collect();
+    let plan = GraphPlan::compile(&names, &slots, &imports).unwrap();
+    assert_eq!(plan.order, vec![3, 0, 1, 2]);
+
+    // every level of the bloom chain is alive at once, so none of them share a texture
+    let bloom_textures: HashSet<usize> = BLOOM_SLOTS
+      .iter()
+      .map(|slot| plan.textures[*slot])
+      .collect();
+    assert_eq!(bloom_textures.len(), BLOOM_MIP_COUNT);
+    assert_eq!(
+      plan.physical_textures[plan.textures[BLOOM_SLOTS[1]]].size,
+      SlotSize::Scaled(0.25)
+    );
+  }
+}
@@ -0,0 +1,622 @@
+//! Reflection of shader module interfaces with naga,
+//! for deriving bind group layouts and checking vertex buffers against the shaders that read them
+use naga::{
+  Binding, ImageClass, ImageDimension, Module, ScalarKind, ShaderStage, StorageAccess,
+  StorageClass, TypeInner,
+};
+use std::num::NonZeroU64;
+use thiserror::Error;
+use wgpu::{
+  BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
+  Device, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType,
+  TextureViewDimension, VertexBufferLayout, VertexFormat,
+};
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum ReflectionError {
+  #[error("could not parse shader {shader}: {reason}")]
+  Parse { shader: String, reason: String },
+  #[error("shader {shader} has no entry point named {entry_point}")]
+  MissingEntryPoint { shader: String, entry_point: String },
+  #[error("shader {shader} declares set={group}, binding={binding}, which has no bind group layout equivalent: {reason}")]
+  UnsupportedBinding {
+    shader: String,
+    group: u32,
+    binding: u32,
+    reason: String,
+  },
+  #[error("set={group}, binding={binding} is {first:?} in shader {first_shader}, but {second:?} in shader {second_shader}")]
+  ConflictingBinding {
+    group: u32,
+    binding: u32,
+    first_shader: String,
+    first: BindingType,
+    second_shader: String,
+    second: BindingType,
+  },
+  #[error("shader {shader} reads vertex input {name} at location {location}, which no vertex buffer provides")]
+  MissingVertexAttribute {
+    shader: String,
+    name: String,
+    location: u32,
+  },
+  #[error("shader {shader} reads vertex input {name} at location {location} as {expected}, but the vertex buffer provides {format:?}")]
+  VertexFormatMismatch {
+    shader: String,
+    name: String,
+    location: u32,
+    expected: NumericType,
+    format: VertexFormat,
+  },
+}
+
+/// A scalar or vector type read by a shader, or provided by a vertex format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericType {
+  pub kind: ScalarKind,
+  pub components: u8,
+}
+
+impl NumericType {
+  fn from_type(inner: &TypeInner) -> Option<Self> {
+    match *inner {
+      TypeInner::Scalar { kind, .. } => Some(Self {
+        kind,
+        components: 1,
+      }),
+      TypeInner::Vector { kind, size, .. } => Some(Self {
+        kind,
+        components: size as u8,
+      }),
+      _ => None,
+    }
+  }
+
+  /// The type a shader sees when reading an attribute of this format.
+  /// Normalized formats are read as floats
+  pub fn from_vertex_format(format: VertexFormat) -> Self {
+    use ScalarKind::*;
+    use VertexFormat as Vf;
+    let (kind, components) = match format {
+      Vf::Uint32 => (Uint, 1),
+      Vf::Uint8x2 | Vf::Uint16x2 | Vf::Uint32x2 => (Uint, 2),
+      Vf::Uint32x3 => (Uint, 3),
+      Vf::Uint8x4 | Vf::Uint16x4 | Vf::Uint32x4 => (Uint, 4),
+      Vf::Sint32 => (Sint, 1),
+      Vf::Sint8x2 | Vf::Sint16x2 | Vf::Sint32x2 => (Sint, 2),
+      Vf::Sint32x3 => (Sint, 3),
+      Vf::Sint8x4 | Vf::Sint16x4 | Vf::Sint32x4 => (Sint, 4),
+      Vf::Float32 | Vf::Float64 => (Float, 1),
+      Vf::Unorm8x2
+      | Vf::Snorm8x2
+      | Vf::Unorm16x2
+      | Vf::Snorm16x2
+      | Vf::Float16x2
+      | Vf::Float32x2
+      | Vf::Float64x2 => (Float, 2),
+      Vf::Float32x3 | Vf::Float64x3 => (Float, 3),
+      Vf::Unorm8x4
+      | Vf::Snorm8x4
+      | Vf::Unorm16x4
+      | Vf::Snorm16x4
+      | Vf::Float16x4
+      | Vf::Float32x4
+      | Vf::Float64x4 => (Float, 4),
+    };
+    Self { kind, components }
+  }
+}
+
+impl std::fmt::Display for NumericType {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let prefix = match self.kind {
+      ScalarKind::Float => "",
+      ScalarKind::Sint => "i",
+      ScalarKind::Uint => "u",
+      ScalarKind::Bool => "b",
+    };
+    match (self.kind, self.components) {
+      (ScalarKind::Float, 1) => write!(f, "float"),
+      (ScalarKind::Sint, 1) => write!(f, "int"),
+      (ScalarKind::Uint, 1) => write!(f, "uint"),
+      (ScalarKind::Bool, 1) => write!(f, "bool"),
+      (_, n) => write!(f, "{}vec{}", prefix, n),
+    }
+  }
+}
+
+/// A resource declared by a shader
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedBinding {
+  pub group: u32,
+  pub binding: u32,
+  pub name: Option<String>,
+  pub ty: BindingType,
+}
+
+/// A vertex attribute read by a vertex shader
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexInput {
+  pub location: u32,
+  pub name: Option<String>,
+  pub ty: NumericType,
+}
+
+///
+/// The resources and vertex inputs of one shader entry point.
+/// Every declared resource is included, whether or not the entry point uses it,
+/// so that a shader's declarations describe the bind groups it is drawn with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderReflection {
+  /// the module's label, for error messages
+  pub label: String,
+  pub stage: ShaderStages,
+  pub bindings: Vec<ReflectedBinding>,
+  pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl ShaderReflection {
+  /// Reflects the module a descriptor creates
+  pub fn from_descriptor(
+    descriptor: &ShaderModuleDescriptor,
+    entry_point: &str,
+  ) -> Result<Self, ReflectionError> {
+    let label = descriptor.label.unwrap_or("unlabeled");
+    match &descriptor.source {
+      ShaderSource::SpirV(words) => Self::from_spirv(label, words, entry_point),
+      ShaderSource::Wgsl(source) => {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| ReflectionError::Parse {
+          shader: label.to_owned(),
+          reason: e.emit_to_string(source),
+        })?;
+        Self::from_module(label, &module, entry_point)
+      }
+    }
+  }
+
+  pub fn from_spirv(
+    label: &str,
+    words: &[u32],
+    entry_point: &str,
+  ) -> Result<Self, ReflectionError> {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let module = naga::front::spv::parse_u8_slice(&bytes, &naga::front::spv::Options::default())
+      .map_err(|e| ReflectionError::Parse {
+        shader: label.to_owned(),
+        reason: e.to_string(),
+      })?;
+    Self::from_module(label, &module, entry_point)
+  }
+
+  pub fn from_module(
+    label: &str,
+    module: &Module,
+    entry_point: &str,
+  ) -> Result<Self, ReflectionError> {
+    let entry = module
+      .entry_points
+      .iter()
+      .find(|entry| entry.name == entry_point)
+      .ok_or_else(|| ReflectionError::MissingEntryPoint {
+        shader: label.to_owned(),
+        entry_point: entry_point.to_owned(),
+      })?;
+
+    let mut bindings = Vec::new();
+    for (_, global) in module.global_variables.iter() {
+      let resource = match &global.binding {
+        Some(resource) => resource,
+        None => continue,
+      };
+      let ty =
+        binding_type(module, global.class, &module.types[global.ty].inner).map_err(|reason| {
+          ReflectionError::UnsupportedBinding {
+            shader: label.to_owned(),
+            group: resource.group,
+            binding: resource.binding,
+            reason,
+          }
+        })?;
+      bindings.push(ReflectedBinding {
+        group: resource.group,
+        binding: resource.binding,
+        name: global.name.clone(),
+        ty,
+      });
+    }
+    bindings.sort_by_key(|binding| (binding.group, binding.binding));
+
+    let mut vertex_inputs = Vec::new();
+    if entry.stage == ShaderStage::Vertex {
+      for argument in &entry.function.arguments {
+        match (&argument.binding, &module.types[argument.ty].inner) {
+          (Some(binding), inner) => {
+            push_vertex_input(&mut vertex_inputs, binding, &argument.name, inner)
+          }
+          (None, TypeInner::Struct { members, .. }) => {
+            for member in members {
+              if let Some(binding) = &member.binding {
+                let inner = &module.types[member.ty].inner;
+                push_vertex_input(&mut vertex_inputs, binding, &member.name, inner);
+              }
+            }
+          }
+          (None, _) => {}
+        }
+      }
+      vertex_inputs.sort_by_key(|input| input.location);
+    }
+
+    Ok(Self {
+      label: label.to_owned(),
+      stage: match entry.stage {
+        ShaderStage::Vertex => ShaderStages::VERTEX,
+        ShaderStage::Fragment => ShaderStages::FRAGMENT,
+        ShaderStage::Compute => ShaderStages::COMPUTE,
+      },
+      bindings,
+      vertex_inputs,
+    })
+  }
+
+  /// Bindings the shader declares in bind group `group`
+  pub fn group(&self, group: u32) -> impl Iterator<Item = &ReflectedBinding> {
+    self
+      .bindings
+      .iter()
+      .filter(move |binding| binding.group == group)
+  }
+
+  ///
+  /// Checks that `buffers` provide every vertex input the shader reads,
+  /// with the same scalar kind and number of components
+  pub fn validate_vertex_buffers(
+    &self,
+    buffers: &[VertexBufferLayout],
+  ) -> Result<(), ReflectionError> {
+    for input in &self.vertex_inputs {
+      let name = input
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("#{}", input.location));
+      let attribute = buffers
+        .iter()
+        .flat_map(|buffer| buffer.attributes.iter())
+        .find(|attribute| attribute.shader_location == input.location)
+        .ok_or_else(|| ReflectionError::MissingVertexAttribute {
+          shader: self.label.clone(),
+          name: name.clone(),
+          location: input.location,
+        })?;
+      if NumericType::from_vertex_format(attribute.format) != input.ty {
+        return Err(ReflectionError::VertexFormatMismatch {
+          shader: self.label.clone(),
+          name,
+          location: input.location,
+          expected: input.ty,
+          format: attribute.format,
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+fn push_vertex_input(
+  inputs: &mut Vec<VertexInput>,
+  binding: &Binding,
+  name: &Option<String>,
+  inner: &TypeInner,
+) {
+  if let (Binding::Location { location, .. }, Some(ty)) = (binding, NumericType::from_type(inner)) {
+    inputs.push(VertexInput {
+      location: *location,
+      name: name.clone(),
+      ty,
+    });
+  }
+}
+
+fn binding_type(
+  module: &Module,
+  class: StorageClass,
+  inner: &TypeInner,
+) -> Result<BindingType, String> {
+  let ty = match (class, inner) {
+    (StorageClass::Uniform, _) => BindingType::Buffer {
+      ty: BufferBindingType::Uniform,
+      has_dynamic_offset: false,
+      min_binding_size: NonZeroU64::new(inner.span(&module.constants) as u64),
+    },
+    (StorageClass::Storage { access }, _) => BindingType::Buffer {
+      ty: BufferBindingType::Storage {
+        read_only: !access.contains(StorageAccess::STORE),
+      },
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    },
+    (StorageClass::Handle, TypeInner::Sampler { comparison }) => BindingType::Sampler {
+      filtering: true,
+      comparison: *comparison,
+    },
+    (
+      StorageClass::Handle,
+      TypeInner::Image {
+        dim,
+        arrayed,
+        class,
+      },
+    ) => {
+      let view_dimension = match (dim, arrayed) {
+        (ImageDimension::D1, false) => TextureViewDimension::D1,
+        (ImageDimension::D2, false) => TextureViewDimension::D2,
+        (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+        (ImageDimension::D3, false) => TextureViewDimension::D3,
+        (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+        (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+        (dim, true) => return Err(format!("{:?} textures can't be arrayed", dim)),
+      };
+      let (sample_type, multisampled) = match *class {
+        ImageClass::Sampled { kind, multi } => {
+          let sample_type = match kind {
+            ScalarKind::Float => TextureSampleType::Float { filterable: true },
+            ScalarKind::Sint => TextureSampleType::Sint,
+            ScalarKind::Uint => TextureSampleType::Uint,
+            ScalarKind::Bool => return Err("boolean textures are not supported".to_owned()),
+          };
+          (sample_type, multi)
+        }
+        ImageClass::Depth { multi } => (TextureSampleType::Depth, multi),
+        ImageClass::Storage { .. } => {
+          return Err("storage textures are not supported yet".to_owned())
+        }
+      };
+      BindingType::Texture {
+        sample_type,
+        view_dimension,
+        multisampled,
+      }
+    }
+    (class, inner) => return Err(format!("{:?} of {:?}", class, inner)),
+  };
+  Ok(ty)
+}
+
+/// Whether two declarations of the same binding can share a layout entry
+fn compatible(a: &BindingType, b: &BindingType) -> bool {
+  match (a, b) {
+    (
+      BindingType::Buffer {
+        ty: a_ty,
+        has_dynamic_offset: a_dynamic,
+        ..
+      },
+      BindingType::Buffer {
+        ty: b_ty,
+        has_dynamic_offset: b_dynamic,
+        ..
+      },
+    ) => a_ty == b_ty && a_dynamic == b_dynamic,
+    (a, b) => a == b,
+  }
+}
+
+///
+/// Derives the entries of one bind group layout from the shaders that read it.
+/// Each `(shader, group)` pair names the group index the layout is bound to in that shader,
+/// since a layout can be bound at different indices by different pipelines.
+///
+/// Entries are visible to every stage that declares them,
+/// and buffers are sized for the largest declaration
+pub fn bind_group_layout_entries(
+  groups: &[(&ShaderReflection, u32)],
+) -> Result<Vec<BindGroupLayoutEntry>, ReflectionError> {
+  let mut entries: Vec<(BindGroupLayoutEntry, &str)> = Vec::new();
+  for &(shader, group) in groups {
+    for declared in shader.group(group) {
+      let existing = entries
+        .iter_mut()
+        .find(|(entry, _)| entry.binding == declared.binding);
+      let (entry, first_shader) = match existing {
+        Some(existing) => existing,
+        None => {
+          entries.push((
+            BindGroupLayoutEntry {
+              binding: declared.binding,
+              visibility: shader.stage,
+              ty: declared.ty,
+              count: None,
+            },
+            &shader.label,
+          ));
+          continue;
+        }
+      };
+      if !compatible(&entry.ty, &declared.ty) {
+        return Err(ReflectionError::ConflictingBinding {
+          group,
+          binding: declared.binding,
+          first_shader: first_shader.to_string(),
+          first: entry.ty,
+          second_shader: shader.label.clone(),
+          second: declared.ty,
+        });
+      }
+      entry.visibility |= shader.stage;
+      if let (
+        BindingType::Buffer {
+          min_binding_size: Some(size),
+          ..
+        },
+        BindingType::Buffer {
+          min_binding_size: Some(declared_size),
+          ..
+        },
+      ) = (&mut entry.ty, &declared.ty)
+      {
+        *size = (*size).max(*declared_size);
+      }
+    }
+  }
+  let mut entries: Vec<BindGroupLayoutEntry> =
+    entries.into_iter().map(|(entry, _)| entry).collect();
+  entries.sort_by_key(|entry| entry.binding);
+  Ok(entries)
+}
+
+/// Creates a bind group layout from the shaders that read it, with `bind_group_layout_entries`
+pub fn create_bind_group_layout(
+  device: &Device,
+  label: &str,
+  groups: &[(&ShaderReflection, u32)],
+) -> Result<BindGroupLayout, ReflectionError> {
+  let entries = bind_group_layout_entries(groups)?;
+  Ok(device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+    label: Some(label),
+    entries: &entries,
+  }))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{renderer_common::geometry::Vertex, wgpu_renderer::ModelInstance};
+
+  fn reflect(descriptor: ShaderModuleDescriptor) -> ShaderReflection {
+    ShaderReflection::from_descriptor(&descriptor, "main").unwrap()
+  }
+
+  #[test]
+  fn test_mesh_shaders_match_vertex_buffers() {
+    let buffers = [Vertex::desc(), ModelInstance::desc()];
+    for shader in [
+      reflect(include_shader!("main.vert")),
+      reflect(include_shader!("debug_light.vert")),
+      reflect(include_shader!("shadow.vert")),
+    ]
+    .iter()
+    {
+      assert_eq!(shader.stage, ShaderStages::VERTEX);
+      assert!(!shader.vertex_inputs.is_empty());
+      shader.validate_vertex_buffers(&buffers).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_vertex_format_mismatch() {
+    let shader = reflect(include_shader!("main.vert"));
+    let attributes = wgpu::vertex_attr_array![0 => Float32x4];
+    let buffers = [VertexBufferLayout {
+      array_stride: 16,
+      step_mode: wgpu::VertexStepMode::Vertex,
+      attributes: &attributes,
+    }];
+    match shader.validate_vertex_buffers(&buffers) {
+      Err(ReflectionError::VertexFormatMismatch {
+        location,
+        expected,
+        format,
+        ..
+      }) => {
+        assert_eq!(location, 0);
+        assert_eq!(
+          expected,
+          NumericType {
+            kind: ScalarKind::Float,
+            components: 3
+          }
+        );
+        assert_eq!(format, VertexFormat::Float32x4);
+      }
+      other => panic!("expected a format mismatch, got {:?}", other),
+    }
+
+    let buffers = [Vertex::desc()];
+    assert!(matches!(
+      shader.validate_vertex_buffers(&buffers),
+      Err(ReflectionError::MissingVertexAttribute { location: 7, .. })
+    ));
+  }
+
+  #[test]
+  fn test_light_bind_group_entries() {
+    let vert = reflect(include_shader!("main.vert"));
+    let frag = reflect(include_shader!("main.frag"));
+    let entries = bind_group_layout_entries(&[(&vert, 2), (&frag, 2)]).unwrap();
+    let types: Vec<(u32, ShaderStages, BindingType)> = entries
+      .iter()
+      .map(|entry| (entry.binding, entry.visibility, entry.ty))
+      .collect();
+    assert_eq!(
+      types,
+      vec![
+        (
+          0,
+          ShaderStages::FRAGMENT,
+          BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+              crate::wgpu_renderer::uniforms::LightArrayUniform,
+            >() as _),
+          }
+        ),
+        (
+          1,
+          ShaderStages::FRAGMENT,
+          BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+              crate::wgpu_renderer::uniforms::ShadowUniform,
+            >() as _),
+          }
+        ),
+        (
+          2,
+          ShaderStages::FRAGMENT,
+          BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false,
+          }
+        ),
+        (
+          3,
+          ShaderStages::FRAGMENT,
+          BindingType::Sampler {
+            filtering: true,
+            comparison: true,
+          }
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_shared_bindings_merge_visibility() {
+    let vert = reflect(include_shader!("main.vert"));
+    let frag = reflect(include_shader!("main.frag"));
+    let entries = bind_group_layout_entries(&[(&vert, 0), (&frag, 0)]).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+      entries[0].visibility,
+      ShaderStages::VERTEX | ShaderStages::FRAGMENT
+    );
+  }
+
+  #[test]
+  fn test_conflicting_bindings() {
+    let pbr = reflect(include_shader!("main.frag"));
+    let debug = reflect(include_shader!("debug_light.frag"));
+    // the material uniform and the debug light texture are both at set=1, binding=0
+    assert!(matches!(
+      bind_group_layout_entries(&[(&pbr, 1), (&debug, 1)]),
+      Err(ReflectionError::ConflictingBinding {
+        group: 1,
+        binding: 0,
+        ..
+      })
+    ));
+  }
+}
@@ -0,0 +1,1040 @@
+//! A small render graph.
+//!
+//! Each frame is rendered by a list of [RenderNode]s. Nodes declare the named slots
+//! they read and write, and create the transient textures and buffers they need.
+//! The graph orders nodes so that every slot is written before it is read, with
+//! writers of the same slot running in the order they were added. Transient resources
+//! whose lifetimes don't overlap share the same texture or buffer, and are reallocated
+//! when the render target is resized.
+//!
+//! Slots which no node creates, such as the [SURFACE] being rendered into,
+//! are imported when the graph is executed.
+//!
+//! Nodes declare their slots against the current [FrameConfig], so changing the
+//! surface format or sample count recompiles the plan.
+use std::{
+  cell::RefCell,
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap, HashSet},
+  fmt,
+};
+
+use anyhow::anyhow;
+use thiserror::Error;
+use wgpu::{Buffer, BufferUsages, Device, Texture, TextureFormat, TextureUsages, TextureView};
+
+use crate::{game::GameState, wgpu_renderer::render_hooks::OnRenderUiClosure, Context};
+
+/// The frame's color target, imported from the context's render target
+pub const SURFACE: &str = "surface";
+/// Depth buffer of the main camera, created by the opaque pass
+pub const DEPTH: &str = "depth";
+/// The shadow map array, imported from the context's shadow maps
+pub const SHADOW_MAPS: &str = "shadow_maps";
+/// HDR color of the main camera, before post-processing. Created by the opaque pass
+pub const HDR_COLOR: &str = "hdr_color";
+/// Multisampled color target of the main camera, resolved into [HDR_COLOR].
+/// Only created when the sample count is greater than 1
+pub const MSAA_COLOR: &str = "msaa_color";
+/// The tonemapped frame, in the surface's format
+pub const LDR_COLOR: &str = "ldr_color";
+
+/// Format of [HDR_COLOR], and of the pipelines that draw the scene
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+///
+/// Render target settings shared by every node in a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameConfig {
+  pub surface_format: TextureFormat,
+  pub sample_count: u32,
+}
+
+impl Default for FrameConfig {
+  fn default() -> Self {
+    Self {
+      surface_format: TextureFormat::Bgra8UnormSrgb,
+      sample_count: 1,
+    }
+  }
+}
+
+impl FrameConfig {
+  pub fn is_multisampled(&self) -> bool {
+    self.sample_count > 1
+  }
+}
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum RenderGraphError {
+  #[error("a render node named {0:?} has already been added")]
+  DuplicateNode(String),
+  #[error("render nodes {0:?} depend on each other")]
+  Cycle(Vec<String>),
+  #[error("slot {slot:?} is created by both {first:?} and {second:?}")]
+  SlotCreatedTwice {
+    slot: String,
+    first: String,
+    second: String,
+  },
+  #[error("node {node:?} uses slot {slot:?}, which is neither created by a node nor imported")]
+  MissingSlot { node: String, slot: String },
+}
+
+///
+/// A pass in the render graph
+pub trait RenderNode: Send + Sync + 'static {
+  /// Unique name of the node within its graph
+  fn name(&self) -> &str;
+
+  /// Declares the slots the node reads, writes and creates
+  fn declare(&self, slots: &mut NodeSlots);
+
+  /// Records the node's commands
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()>;
+}
+
+/// Size of a transient texture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlotSize {
+  /// same size as the render target
+  Surface,
+  /// the render target's size multiplied by a factor
+  Scaled(f32),
+  Fixed(u32, u32),
+}
+
+impl SlotSize {
+  pub fn resolve(&self, (width, height): (u32, u32)) -> (u32, u32) {
+    match *self {
+      Self::Surface => (width, height),
+      Self::Scaled(factor) => (
+        ((width as f32 * factor) as u32).max(1),
+        ((height as f32 * factor) as u32).max(1),
+      ),
+      Self::Fixed(width, height) => (width, height),
+    }
+  }
+}
+
+/// Description of a transient texture created by a node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureSlotDesc {
+  pub format: TextureFormat,
+  pub size: SlotSize,
+  pub usage: TextureUsages,
+  pub sample_count: u32,
+}
+
+impl TextureSlotDesc {
+  /// A texture which can be rendered into, and sampled by later nodes
+  pub fn new(format: TextureFormat, size: SlotSize) -> Self {
+    Self {
+      format,
+      size,
+      usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+      sample_count: 1,
+    }
+  }
+
+  ///
+  /// A multisampled texture can only be rendered into and resolved,
+  /// so it loses `TEXTURE_BINDING` when `sample_count` is greater than 1
+  pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+    self.sample_count = sample_count;
+    if sample_count > 1 {
+      self.usage -= TextureUsages::TEXTURE_BINDING;
+    }
+    self
+  }
+}
+
+/// Description of a transient buffer created by a node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferSlotDesc {
+  pub size: wgpu::BufferAddress,
+  pub usage: BufferUsages,
+}
+
+///
+/// Slots declared by a node. Creating a slot also counts as writing to it
+#[derive(Debug, Clone, Default)]
+pub struct NodeSlots {
+  config: FrameConfig,
+  reads: Vec<String>,
+  writes: Vec<String>,
+  textures: Vec<(String, TextureSlotDesc)>,
+  buffers: Vec<(String, BufferSlotDesc)>,
+}
+
+impl NodeSlots {
+  pub fn new(config: FrameConfig) -> Self {
+    Self {
+      config,
+      ..Self::default()
+    }
+  }
+
+  /// Settings of the frames the node will run in
+  pub fn config(&self) -> &FrameConfig {
+    &self.config
+  }
+
+  pub fn read(&mut self, slot: &str) -> &mut Self {
+    self.reads.push(slot.to_owned());
+    self
+  }
+
+  pub fn write(&mut self, slot: &str) -> &mut Self {
+    self.writes.push(slot.to_owned());
+    self
+  }
+
+  pub fn create_texture(&mut self, slot: &str, desc: TextureSlotDesc) -> &mut Self {
+    self.textures.push((slot.to_owned(), desc));
+    self.write(slot)
+  }
+
+  pub fn create_buffer(&mut self, slot: &str, desc: BufferSlotDesc) -> &mut Self {
+    self.buffers.push((slot.to_owned(), desc));
+    self.write(slot)
+  }
+
+  fn writes(&self, slot: &str) -> bool {
+    self.writes.iter().any(|s| s == slot)
+  }
+
+  fn used(&self) -> impl Iterator<Item = &String> {
+    self.reads.iter().chain(self.writes.iter())
+  }
+}
+
+///
+/// Node order and transient resource assignments, compiled from the nodes' slots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphPlan {
+  /// indices of the nodes, in execution order
+  pub order: Vec<usize>,
+  /// transient texture slots, and the index of the physical texture backing them
+  pub textures: HashMap<String, usize>,
+  pub physical_textures: Vec<TextureSlotDesc>,
+  /// transient buffer slots, and the index of the physical buffer backing them
+  pub buffers: HashMap<String, usize>,
+  pub physical_buffers: Vec<BufferSlotDesc>,
+  /// (node, slot) pairs where the node is the first to write to the slot in a frame
+  first_writes: HashSet<(usize, String)>,
+}
+
+impl GraphPlan {
+  ///
+  /// Orders nodes and assigns transient resources.
+  /// `names` and `slots` are indexed by node, in the order the nodes were added
+  pub fn compile(
+    names: &[&str],
+    slots: &[NodeSlots],
+    imports: &HashSet<String>,
+  ) -> Result<Self, RenderGraphError> {
+    let mut creators: HashMap<&str, usize> = HashMap::new();
+    for (node, node_slots) in slots.iter().enumerate() {
+      let created = node_slots
+        .textures
+        .iter()
+        .map(|(slot, _)| slot)
+        .chain(node_slots.buffers.iter().map(|(slot, _)| slot));
+      for slot in created {
+        if let Some(&first) = creators.get(slot.as_str()) {
+          return Err(RenderGraphError::SlotCreatedTwice {
+            slot: slot.clone(),
+            first: names[first].to_owned(),
+            second: names[node].to_owned(),
+          });
+        }
+        creators.insert(slot.as_str(), node);
+      }
+    }
+    for (node, node_slots) in slots.iter().enumerate() {
+      if let Some(slot) = node_slots
+        .used()
+        .find(|slot| !creators.contains_key(slot.as_str()) && !imports.contains(*slot))
+      {
+        return Err(RenderGraphError::MissingSlot {
+          node: names[node].to_owned(),
+          slot: slot.clone(),
+        });
+      }
+    }
+
+    // the creator of a slot writes first, followed by other writers in the order they were added
+    let mut writers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (node, node_slots) in slots.iter().enumerate() {
+      for slot in &node_slots.writes {
+        let slot_writers = writers.entry(slot.as_str()).or_default();
+        if !slot_writers.contains(&node) {
+          slot_writers.push(node);
+        }
+      }
+    }
+    for (slot, slot_writers) in writers.iter_mut() {
+      if let Some(&creator) = creators.get(slot) {
+        slot_writers.sort_by_key(|&node| (node != creator, node));
+      }
+    }
+
+    let mut edges: Vec<HashSet<usize>> = vec![HashSet::new(); slots.len()];
+    for slot_writers in writers.values() {
+      for pair in slot_writers.windows(2) {
+        edges[pair[0]].insert(pair[1]);
+      }
+    }
+    for (node, node_slots) in slots.iter().enumerate() {
+      for slot in node_slots
+        .reads
+        .iter()
+        .filter(|slot| !node_slots.writes(slot))
+      {
+        for &writer in writers.get(slot.as_str()).into_iter().flatten() {
+          edges[writer].insert(node);
+        }
+      }
+    }
+
+    let order = topological_order(&edges).map_err(|remaining| {
+      RenderGraphError::Cycle(remaining.iter().map(|&i| names[i].to_owned()).collect())
+    })?;
+    let position: Vec<usize> = {
+      let mut position = vec![0; order.len()];
+      for (i, &node) in order.iter().enumerate() {
+        position[node] = i;
+      }
+      position
+    };
+
+    let first_writes = writers
+      .iter()
+      .filter_map(|(slot, slot_writers)| {
+        let first = slot_writers.iter().min_by_key(|&&node| position[node])?;
+        Some((*first, slot.to_string()))
+      })
+      .collect();
+
+    let lifetime = |slot: &str| {
+      let uses = slots
+        .iter()
+        .enumerate()
+        .filter(|(_, node_slots)| node_slots.used().any(|s| s == slot))
+        .map(|(node, _)| position[node]);
+      let (first, last) = uses.fold((usize::MAX, 0), |(first, last), p| {
+        (first.min(p), last.max(p))
+      });
+      (first, last)
+    };
+    let texture_slots = slots
+      .iter()
+      .flat_map(|node_slots| node_slots.textures.iter())
+      .map(|(slot, desc)| (slot.clone(), *desc, lifetime(slot)))
+      .collect();
+    let buffer_slots = slots
+      .iter()
+      .flat_map(|node_slots| node_slots.buffers.iter())
+      .map(|(slot, desc)| (slot.clone(), *desc, lifetime(slot)))
+      .collect();
+    let (textures, physical_textures) = alias_resources(texture_slots);
+    let (buffers, physical_buffers) = alias_resources(buffer_slots);
+
+    Ok(Self {
+      order,
+      textures,
+      physical_textures,
+      buffers,
+      physical_buffers,
+      first_writes,
+    })
+  }
+
+  /// True if `node` is the first node to write to `slot` in a frame
+  pub fn is_first_write(&self, node: usize, slot: &str) -> bool {
+    self.first_writes.contains(&(node, slot.to_owned()))
+  }
+}
+
+///
+/// Kahn's algorithm, preferring the node added first whenever more than one is ready.
+/// On a cycle, returns the nodes which could not be ordered
+fn topological_order(edges: &[HashSet<usize>]) -> Result<Vec<usize>, Vec<usize>> {
+  let mut in_degree = vec![0; edges.len()];
+  for targets in edges {
+    for &target in targets {
+      in_degree[target] += 1;
+    }
+  }
+  let mut ready: BinaryHeap<Reverse<usize>> = in_degree
+    .iter()
+    .enumerate()
+    .filter(|&(_, &degree)| degree == 0)
+    .map(|(node, _)| Reverse(node))
+    .collect();
+  let mut order = Vec::with_capacity(edges.len());
+  while let Some(Reverse(node)) = ready.pop() {
+    order.push(node);
+    for &target in &edges[node] {
+      in_degree[target] -= 1;
+      if in_degree[target] == 0 {
+        ready.push(Reverse(target));
+      }
+    }
+  }
+  if order.len() < edges.len() {
+    return Err(
+      (0..edges.len())
+        .filter(|node| in_degree[*node] > 0)
+        .collect(),
+    );
+  }
+  Ok(order)
+}
+
+///
+/// Greedily assigns slots to physical resources. A slot reuses a resource with the same
+/// description once every earlier slot assigned to it is no longer used.
+/// Lifetimes are (first, last) positions in the execution order
+fn alias_resources<D: Copy + PartialEq>(
+  mut slots: Vec<(String, D, (usize, usize))>,
+) -> (HashMap<String, usize>, Vec<D>) {
+  slots.sort_by(|a, b| (a.2 .0, &a.0).cmp(&(b.2 .0, &b.0)));
+  let mut assignments = HashMap::new();
+  let mut physical: Vec<(D, usize)> = Vec::new();
+  for (slot, desc, (first, last)) in slots {
+    let index = match physical
+      .iter()
+      .position(|(physical_desc, physical_last)| *physical_desc == desc && *physical_last < first)
+    {
+      Some(index) => {
+        physical[index].1 = last;
+        index
+      }
+      None => {
+        physical.push((desc, last));
+        physical.len() - 1
+      }
+    };
+    assignments.insert(slot, index);
+  }
+  (
+    assignments,
+    physical.into_iter().map(|(desc, _)| desc).collect(),
+  )
+}
+
+#[derive(Debug)]
+struct PooledTexture {
+  desc: TextureSlotDesc,
+  size: (u32, u32),
+  texture: Texture,
+  view: TextureView,
+}
+
+/// Physical textures and buffers backing transient slots, kept between frames
+#[derive(Debug, Default)]
+struct ResourcePool {
+  textures: Vec<PooledTexture>,
+  buffers: Vec<(BufferSlotDesc, Buffer)>,
+}
+
+impl ResourcePool {
+  fn prepare(&mut self, device: &Device, plan: &GraphPlan, surface_size: (u32, u32)) {
+    self.textures.truncate(plan.physical_textures.len());
+    for (i, desc) in plan.physical_textures.iter().enumerate() {
+      let size = desc.size.resolve(surface_size);
+      let matches = self.textures.get(i).map_or(false, |pooled| {
+        pooled.desc.format == desc.format
+          && pooled.desc.usage == desc.usage
+          && pooled.desc.sample_count == desc.sample_count
+          && pooled.size == size
+      });
+      if matches {
+        continue;
+      }
+      let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("render_graph_texture"),
+        size: wgpu::Extent3d {
+          width: size.0,
+          height: size.1,
+          depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: desc.sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: desc.format,
+        usage: desc.usage,
+      });
+      let pooled = PooledTexture {
+        desc: *desc,
+        size,
+        view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+        texture,
+      };
+      if i < self.textures.len() {
+        self.textures[i] = pooled;
+      } else {
+        self.textures.push(pooled);
+      }
+    }
+
+    self.buffers.truncate(plan.physical_buffers.len());
+    for (i, desc) in plan.physical_buffers.iter().enumerate() {
+      if matches!(self.buffers.get(i), Some((pooled, _)) if pooled == desc) {
+        continue;
+      }
+      let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("render_graph_buffer"),
+        size: desc.size,
+        usage: desc.usage,
+        mapped_at_creation: false,
+      });
+      if i < self.buffers.len() {
+        self.buffers[i] = (*desc, buffer);
+      } else {
+        self.buffers.push((*desc, buffer));
+      }
+    }
+  }
+}
+
+///
+/// Everything a node can access while it runs.
+/// `'ui` is the lifetime of the frame's ui hook, which may borrow the caller's ui data
+pub struct NodeContext<'a, 'ui> {
+  pub context: &'a Context,
+  pub game: &'a GameState,
+  node: usize,
+  plan: &'a GraphPlan,
+  config: &'a FrameConfig,
+  pool: &'a ResourcePool,
+  imports: &'a HashMap<&'a str, &'a TextureView>,
+  ui_hook: &'a RefCell<Option<OnRenderUiClosure<'ui>>>,
+}
+
+impl<'a, 'ui> NodeContext<'a, 'ui> {
+  /// Settings of the frame being rendered
+  pub fn config(&self) -> &'a FrameConfig {
+    self.config
+  }
+
+  /// View of a transient or imported texture slot
+  pub fn texture_view(&self, slot: &str) -> anyhow::Result<&'a TextureView> {
+    if let Some(view) = self.imports.get(slot) {
+      return Ok(*view);
+    }
+    self
+      .plan
+      .textures
+      .get(slot)
+      .and_then(|&index| self.pool.textures.get(index))
+      .map(|pooled| &pooled.view)
+      .ok_or_else(|| anyhow!("texture slot {:?} is not available", slot))
+  }
+
+  ///
+  /// Texture of a transient slot, for copies. Imported slots only provide a view
+  pub fn texture(&self, slot: &str) -> anyhow::Result<&'a Texture> {
+    self
+      .plan
+      .textures
+      .get(slot)
+      .and_then(|&index| self.pool.textures.get(index))
+      .map(|pooled| &pooled.texture)
+      .ok_or_else(|| anyhow!("texture slot {:?} is not a transient slot", slot))
+  }
+
+  /// A transient buffer slot
+  pub fn buffer(&self, slot: &str) -> anyhow::Result<&'a Buffer> {
+    self
+      .plan
+      .buffers
+      .get(slot)
+      .and_then(|&index| self.pool.buffers.get(index))
+      .map(|(_, buffer)| buffer)
+      .ok_or_else(|| anyhow!("buffer slot {:?} is not available", slot))
+  }
+
+  ///
+  /// Takes the ui hook passed to `Context::render_with_ui`, if no other node took it.
+  /// The hook isn't run after the graph, so a node that takes it should run it
+  pub fn take_ui_hook(&self) -> Option<OnRenderUiClosure<'ui>> {
+    self.ui_hook.borrow_mut().take()
+  }
+
+  /// Clears a color slot if this node is the first to write to it this frame
+  pub fn color_load_op(&self, slot: &str, clear: wgpu::Color) -> wgpu::LoadOp<wgpu::Color> {
+    if self.plan.is_first_write(self.node, slot) {
+      wgpu::LoadOp::Clear(clear)
+    } else {
+      wgpu::LoadOp::Load
+    }
+  }
+
+  /// Clears a depth slot if this node is the first to write to it this frame
+  pub fn depth_load_op(&self, slot: &str, clear: f32) -> wgpu::LoadOp<f32> {
+    if self.plan.is_first_write(self.node, slot) {
+      wgpu::LoadOp::Clear(clear)
+    } else {
+      wgpu::LoadOp::Load
+    }
+  }
+}
+
+///
+/// Nodes added from `GameStateBuilder::with_render_node`, which are
+/// moved into the context's render graph on the next render
+#[derive(Default)]
+pub struct RenderNodeQueue(pub Vec<Box<dyn RenderNode>>);
+
+///
+/// Ordered list of render nodes, with the transient resources they share
+#[derive(Default)]
+pub struct RenderGraph {
+  nodes: Vec<Box<dyn RenderNode>>,
+  imports: HashSet<String>,
+  config: FrameConfig,
+  plan: Option<GraphPlan>,
+  pool: ResourcePool,
+}
+
+impl fmt::Debug for RenderGraph {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RenderGraph")
+      .field("nodes", &self.node_names().collect::<Vec<_>>())
+      .field("imports", &self.imports)
+      .field("config", &self.config)
+      .field("plan", &self.plan)
+      .finish()
+  }
+}
+
+impl RenderGraph {
+  /// An empty graph, which imports the SURFACE slot
+  pub fn new() -> Self {
+    let mut graph = Self::default();
+    graph.import_slot(SURFACE);
+    graph
+  }
+
+  pub fn add_node<N: RenderNode>(&mut self, node: N) -> Result<(), RenderGraphError> {
+    self.add_boxed_node(Box::new(node))
+  }
+
+  pub fn add_boxed_node(&mut self, node: Box<dyn RenderNode>) -> Result<(), RenderGraphError> {
+    if self.node_names().any(|name| name == node.name()) {
+      return Err(RenderGraphError::DuplicateNode(node.name().to_owned()));
+    }
+    self.nodes.push(node);
+    self.plan = None;
+    Ok(())
+  }
+
+  pub fn remove_node(&mut self, name: &str) -> Option<Box<dyn RenderNode>> {
+    let index = self.nodes.iter().position(|node| node.name() == name)?;
+    self.plan = None;
+    Some(self.nodes.remove(index))
+  }
+
+  pub fn node_names(&self) -> impl Iterator<Item = &str> {
+    self.nodes.iter().map(|node| node.name())
+  }
+
+  ///
+  /// Declares a slot whose texture is provided each time the graph is executed,
+  /// rather than created by a node
+  pub fn import_slot(&mut self, slot: &str) {
+    self.imports.insert(slot.to_owned());
+    self.plan = None;
+  }
+
+  /// Recompiles the plan on the next execution, for nodes whose slots have changed
+  pub fn invalidate(&mut self) {
+    self.plan = None;
+  }
+
+  pub fn config(&self) -> &FrameConfig {
+    &self.config
+  }
+
+  /// Recompiles the plan if the config has changed, since nodes declare their slots from it
+  pub fn set_config(&mut self, config: FrameConfig) {
+    if self.config != config {
+      self.config = config;
+      self.plan = None;
+    }
+  }
+
+  /// Compiles the plan if nodes or the config have changed since the last call
+  pub fn plan(&mut self) -> Result<&GraphPlan, RenderGraphError> {
+    if self.plan.is_none() {
+      let names: Vec<&str> = self.node_names().collect();
+      let slots: Vec<NodeSlots> = self
+        .nodes
+        .iter()
+        .map(|node| {
+          let mut slots = NodeSlots::new(self.config);
+          node.declare(&mut slots);
+          slots
+        })
+        .collect();
+      self.plan = Some(GraphPlan::compile(&names, &slots, &self.imports)?);
+    }
+    Ok(self.plan.as_ref().unwrap())
+  }
+
+  ///
+  /// Runs every node in order. `imports` provides the views of imported slots,
+  /// and `ui_hook` is run by the first node which takes it.
+  /// The frame config is taken from the context's render target and sample count
+  pub fn execute(
+    &mut self,
+    context: &Context,
+    game: &GameState,
+    imports: &[(&str, &TextureView)],
+    ui_hook: Option<OnRenderUiClosure>,
+    encoder: &mut wgpu::CommandEncoder,
+  ) -> anyhow::Result<()> {
+    self.set_config(FrameConfig {
+      surface_format: context.render_target.format(),
+      sample_count: context.sample_count(),
+    });
+    self.plan()?;
+    let plan = self.plan.as_ref().unwrap();
+    self
+      .pool
+      .prepare(&context.device, plan, context.render_target.size());
+    let imports: HashMap<&str, &TextureView> = imports.iter().copied().collect();
+    let ui_hook = RefCell::new(ui_hook);
+    for &node in &plan.order {
+      let ctx = NodeContext {
+        context,
+        game,
+        node,
+        plan,
+        config: &self.config,
+        pool: &self.pool,
+        imports: &imports,
+        ui_hook: &ui_hook,
+      };
+      let node = &self.nodes[node];
+      node
+        .run(&ctx, encoder)
+        .map_err(|e| anyhow!("render node {:?} failed: {:?}", node.name(), e))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn depth_desc() -> TextureSlotDesc {
+    TextureSlotDesc::new(TextureFormat::Depth32Float, SlotSize::Surface)
+  }
+
+  fn color_desc() -> TextureSlotDesc {
+    TextureSlotDesc::new(TextureFormat::Rgba16Float, SlotSize::Surface)
+  }
+
+  fn compile(nodes: &[(&str, NodeSlots)]) -> Result<GraphPlan, RenderGraphError> {
+    let names: Vec<&str> = nodes.iter().map(|(name, _)| *name).collect();
+    let slots: Vec<NodeSlots> = nodes.iter().map(|(_, slots)| slots.clone()).collect();
+    let imports = [SURFACE, SHADOW_MAPS]
+      .iter()
+      .map(|s| s.to_string())
+      .collect();
+    GraphPlan::compile(&names, &slots, &imports)
+  }
+
+  fn slots<F: FnOnce(&mut NodeSlots)>(f: F) -> NodeSlots {
+    let mut slots = NodeSlots::default();
+    f(&mut slots);
+    slots
+  }
+
+  fn ordered_names<'a>(plan: &GraphPlan, nodes: &[(&'a str, NodeSlots)]) -> Vec<&'a str> {
+    plan.order.iter().map(|&i| nodes[i].0).collect()
+  }
+
+  #[test]
+  fn test_writers_run_before_readers() {
+    let nodes = vec![
+      (
+        "post",
+        slots(|s| {
+          s.read("hdr").write(SURFACE);
+        }),
+      ),
+      (
+        "opaque",
+        slots(|s| {
+          s.read(SHADOW_MAPS)
+            .create_texture("hdr", color_desc())
+            .create_texture(DEPTH, depth_desc());
+        }),
+      ),
+      (
+        "shadow",
+        slots(|s| {
+          s.write(SHADOW_MAPS);
+        }),
+      ),
+    ];
+    let plan = compile(&nodes).unwrap();
+    assert_eq!(
+      ordered_names(&plan, &nodes),
+      vec!["shadow", "opaque", "post"]
+    );
+  }
+
+  #[test]
+  fn test_writers_of_a_slot_keep_insertion_order() {
+    let nodes = vec![
+      (
+        "opaque",
+        slots(|s| {
+          s.write(SURFACE).create_texture(DEPTH, depth_desc());
+        }),
+      ),
+      (
+        "skybox",
+        slots(|s| {
+          s.read(DEPTH).write(SURFACE);
+        }),
+      ),
+      (
+        "transparent",
+        slots(|s| {
+          s.read(DEPTH).write(SURFACE);
+        }),
+      ),
+      (
+        "ui",
+        slots(|s| {
+          s.write(SURFACE);
+        }),
+      ),
+    ];
+    let plan = compile(&nodes).unwrap();
+    assert_eq!(
+      ordered_names(&plan, &nodes),
+      vec!["opaque", "skybox", "transparent", "ui"]
+    );
+    assert!(plan.is_first_write(0, SURFACE));
+    assert!(plan.is_first_write(0, DEPTH));
+    assert!(!plan.is_first_write(1, SURFACE));
+    assert!(!plan.is_first_write(3, SURFACE));
+  }
+
+  #[test]
+  fn test_creator_writes_first() {
+    let nodes = vec![
+      (
+        "decals",
+        slots(|s| {
+          s.write("gbuffer");
+        }),
+      ),
+      (
+        "geometry",
+        slots(|s| {
+          s.create_texture("gbuffer", color_desc());
+        }),
+      ),
+    ];
+    let plan = compile(&nodes).unwrap();
+    assert_eq!(ordered_names(&plan, &nodes), vec!["geometry", "decals"]);
+    assert!(plan.is_first_write(1, "gbuffer"));
+  }
+
+  #[test]
+  fn test_cycle() {
+    let nodes = vec![
+      (
+        "a",
+        slots(|s| {
+          s.read("y").create_texture("x", color_desc());
+        }),
+      ),
+      (
+        "b",
+        slots(|s| {
+          s.read("x").create_texture("y", color_desc());
+        }),
+      ),
+    ];
+    assert_eq!(
+      compile(&nodes),
+      Err(RenderGraphError::Cycle(vec!["a".into(), "b".into()]))
+    );
+  }
+
+  #[test]
+  fn test_missing_slot() {
+    let nodes = vec![(
+      "post",
+      slots(|s| {
+        s.read("hdr").write(SURFACE);
+      }),
+    )];
+    assert_eq!(
+      compile(&nodes),
+      Err(RenderGraphError::MissingSlot {
+        node: "post".into(),
+        slot: "hdr".into()
+      })
+    );
+  }
+
+  #[test]
+  fn test_slot_created_twice() {
+    let nodes = vec![
+      (
+        "a",
+        slots(|s| {
+          s.create_texture(DEPTH, depth_desc());
+        }),
+      ),
+      (
+        "b",
+        slots(|s| {
+          s.create_texture(DEPTH, depth_desc());
+        }),
+      ),
+    ];
+    assert!(matches!(
+      compile(&nodes),
+      Err(RenderGraphError::SlotCreatedTwice { .. })
+    ));
+  }
+
+  #[test]
+  fn test_transient_textures_alias() {
+    // ping-pong between textures: "a" is dead once "c" is created
+    let nodes = vec![
+      (
+        "first",
+        slots(|s| {
+          s.create_texture("a", color_desc());
+        }),
+      ),
+      (
+        "second",
+        slots(|s| {
+          s.read("a").create_texture("b", color_desc());
+        }),
+      ),
+      (
+        "third",
+        slots(|s| {
+          s.read("b").create_texture("c", color_desc());
+        }),
+      ),
+      (
+        "last",
+        slots(|s| {
+          s.read("c")
+            .create_texture(DEPTH, depth_desc())
+            .write(SURFACE);
+        }),
+      ),
+    ];
+    let plan = compile(&nodes).unwrap();
+    assert_eq!(plan.physical_textures.len(), 3);
+    assert_eq!(plan.textures["a"], plan.textures["c"]);
+    assert_ne!(plan.textures["a"], plan.textures["b"]);
+    // depth has a different format, so it gets its own texture
+    assert_eq!(plan.physical_textures[plan.textures[DEPTH]], depth_desc());
+  }
+
+  #[test]
+  fn test_slot_size_resolve() {
+    assert_eq!(SlotSize::Surface.resolve((640, 480)), (640, 480));
+    assert_eq!(SlotSize::Scaled(0.5).resolve((640, 480)), (320, 240));
+    assert_eq!(SlotSize::Scaled(0.0).resolve((640, 480)), (1, 1));
+    assert_eq!(SlotSize::Fixed(16, 8).resolve((640, 480)), (16, 8));
+  }
+
+  struct NamedNode(&'static str);
+
+  impl RenderNode for NamedNode {
+    fn name(&self) -> &str {
+      self.0
+    }
+    fn declare(&self, slots: &mut NodeSlots) {
+      slots.write(SURFACE);
+    }
+    fn run(&self, _ctx: &NodeContext, _encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_graph_nodes() {
+    let mut graph = RenderGraph::new();
+    graph.add_node(NamedNode("a")).unwrap();
+    graph.add_node(NamedNode("b")).unwrap();
+    assert_eq!(
+      graph.add_node(NamedNode("a")),
+      Err(RenderGraphError::DuplicateNode("a".into()))
+    );
+    assert_eq!(graph.plan().unwrap().order, vec![0, 1]);
+    assert!(graph.remove_node("a").is_some());
+    assert!(graph.remove_node("a").is_none());
+    assert_eq!(graph.node_names().collect::<Vec<_>>(), vec!["b"]);
+    assert_eq!(graph.plan().unwrap().order, vec![0]);
+  }
+
+  #[test]
+  fn test_multisampled_slot_desc() {
+    let desc = color_desc().with_sample_count(4);
+    assert_eq!(desc.sample_count, 4);
+    assert!(desc.usage.contains(TextureUsages::RENDER_ATTACHMENT));
+    assert!(!desc.usage.contains(TextureUsages::TEXTURE_BINDING));
+    assert_eq!(color_desc().with_sample_count(1), color_desc());
+  }
+
+  struct MsaaNode;
+
+  impl RenderNode for MsaaNode {
+    fn name(&self) -> &str {
+      "msaa"
+    }
+    fn declare(&self, slots: &mut NodeSlots) {
+      let sample_count = slots.config().sample_count;
+      slots
+        .write(SURFACE)
+        .create_texture(DEPTH, depth_desc().with_sample_count(sample_count));
+      if slots.config().is_multisampled() {
+        slots.create_texture(MSAA_COLOR, color_desc().with_sample_count(sample_count));
+      }
+    }
+    fn run(&self, _ctx: &NodeContext, _encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_config_change_recompiles_plan() {
+    let mut graph = RenderGraph::new();
+    graph.add_node(MsaaNode).unwrap();
+    let plan = graph.plan().unwrap();
+    assert!(!plan.textures.contains_key(MSAA_COLOR));
+    assert_eq!(plan.physical_textures[plan.textures[DEPTH]].sample_count, 1);
+
+    graph.set_config(FrameConfig {
+      sample_count: 4,
+      ..FrameConfig::default()
+    });
+    let plan = graph.plan().unwrap();
+    assert_eq!(plan.physical_textures[plan.textures[DEPTH]].sample_count, 4);
+    assert_eq!(
+      plan.physical_textures[plan.textures[MSAA_COLOR]].sample_count,
+      4
+    );
+  }
+}
@@ -1,6 +1,33 @@
-use crate::wgpu::{Device, Queue, RenderPass};
-/// Closure called per frame to render the UI.
+use crate::wgpu::{Color, CommandEncoder, Device, LoadOp, Queue, TextureView};
+/// Closure called per frame to render the UI, passed to `Context::render_with_ui`.
 /// Note, this is closure will need to be re-allocated per frame (since it will be
-/// handling non-persistent data like imgui UI frames)
-pub type OnRenderUiClosure =
-  Box<dyn FnOnce(&Queue, &Device, &mut RenderPass) -> Result<(), crate::Error>>;
+/// handling non-persistent data like imgui UI frames). It only has to live for that
+/// `render_with_ui` call, so it can borrow the frame's UI data, like imgui's draw data.
+///
+/// The closure begins its own render pass on the target, so the resources it binds,
+/// such as an imgui renderer's buffers, only need to outlive that pass.
+pub type OnRenderUiClosure<'ui> = Box<
+  dyn FnOnce(&Queue, &Device, &mut CommandEncoder, &UiRenderTarget) -> Result<(), crate::Error>
+    + 'ui,
+>;
+
+/// The finished frame the ui is drawn over
+#[derive(Debug)]
+pub struct UiRenderTarget<'a> {
+  pub view: &'a TextureView,
+  /// Clear if nothing else has drawn into the frame, Load otherwise
+  pub load: LoadOp<Color>,
+}
+
+impl<'a> UiRenderTarget<'a> {
+  pub fn color_attachment(&self) -> wgpu::RenderPassColorAttachment<'a> {
+    wgpu::RenderPassColorAttachment {
+      view: self.view,
+      resolve_target: None,
+      ops: wgpu::Operations {
+        load: self.load,
+        store: true,
+      },
+    }
+  }
+}
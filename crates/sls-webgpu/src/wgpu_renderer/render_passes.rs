@@ -0,0 +1,556 @@
+//! The built-in render graph nodes, which draw the scene gathered by the Context.
+//!
+//! The default graph runs them in the order: shadow, opaque, debug light, skybox,
+//! transparent, debug draw, the post-processing nodes, then ui.
+//!
+//! The scene passes draw into [HDR_COLOR], which post-processing tonemaps into the surface.
+//! When the frame is multisampled, they draw into [MSAA_COLOR] instead, and resolve
+//! it into HDR_COLOR at the end of each pass. The ui is drawn over the finished surface.
+use anyhow::anyhow;
+use wgpu::{BindGroup, RenderPass};
+
+use crate::{
+  renderer_common::{allocator::ResourceManager, render_context::DrawModel},
+  wgpu_renderer::{
+    material::{AlphaMode, WgpuMaterial},
+    mesh::Mesh,
+    pipeline_state::{PipelineKey, RendererPipelines, ShadingModel},
+    post_process::{BloomPass, FxaaPass, TonemapPass},
+    render_graph::{
+      NodeContext, NodeSlots, RenderGraph, RenderNode, SlotSize, TextureSlotDesc, DEPTH, HDR_COLOR,
+      HDR_FORMAT, MSAA_COLOR, SHADOW_MAPS, SURFACE,
+    },
+    render_hooks::UiRenderTarget,
+    textures::TextureResource,
+  },
+};
+
+/// A graph with every built-in node, which imports SURFACE and SHADOW_MAPS
+pub fn default_render_graph() -> RenderGraph {
+  let mut graph = RenderGraph::new();
+  graph.import_slot(SHADOW_MAPS);
+  let added = graph
+    .add_node(ShadowPass)
+    .and_then(|_| graph.add_node(OpaquePass))
+    .and_then(|_| graph.add_node(DebugLightPass))
+    .and_then(|_| graph.add_node(SkyboxPass))
+    .and_then(|_| graph.add_node(TransparentPass))
+    .and_then(|_| graph.add_node(DebugDrawPass))
+    .and_then(|_| graph.add_node(BloomPass))
+    .and_then(|_| graph.add_node(TonemapPass))
+    .and_then(|_| graph.add_node(FxaaPass))
+    .and_then(|_| graph.add_node(UiPass));
+  debug_assert!(added.is_ok(), "{:?}", added);
+  graph
+}
+
+/// Binds the pipeline variant for each material's key, when it changes between draws
+struct VariantBinder<'a> {
+  pipelines: &'a RendererPipelines,
+  key: PipelineKey,
+  bound: Option<PipelineKey>,
+}
+
+impl<'a> VariantBinder<'a> {
+  fn new(pipelines: &'a RendererPipelines, key: PipelineKey) -> Self {
+    Self {
+      pipelines,
+      key,
+      bound: None,
+    }
+  }
+
+  /// Returns false if the variant for the material was not prepared
+  fn bind(&mut self, pass: &mut RenderPass<'a>, material: &WgpuMaterial) -> bool {
+    let key = self.key.with_material(material);
+    if self.bound == Some(key) {
+      return true;
+    }
+    match self.pipelines.variant(&key) {
+      Some(pipeline) => {
+        pass.set_pipeline(pipeline);
+        self.bound = Some(key);
+        true
+      }
+      None => {
+        log::warn!("pipeline for {:?} was not prepared", key);
+        false
+      }
+    }
+  }
+}
+
+/// The material of a mesh, if it has one
+fn mesh_material<'a>(
+  mesh: &Mesh,
+  materials: &'a ResourceManager<WgpuMaterial>,
+) -> Option<&'a WgpuMaterial> {
+  match materials.try_get_ref(mesh.material()?) {
+    Ok(material) => Some(material),
+    Err(e) => {
+      log::warn!("could not access material: {:?}", e);
+      None
+    }
+  }
+}
+
+fn material_bind_group(material: &WgpuMaterial) -> &BindGroup {
+  match &material.bind_group {
+    None => panic!("material does not have bind group attached"),
+    Some(bg) => bg,
+  }
+}
+
+/// Declares the color slots written by a scene pass
+fn write_scene_color(slots: &mut NodeSlots) {
+  slots.write(HDR_COLOR);
+  if slots.config().is_multisampled() {
+    slots.write(MSAA_COLOR);
+  }
+}
+
+///
+/// The scene's color attachment. When multisampled, the pass draws into
+/// MSAA_COLOR and resolves into HDR_COLOR
+fn scene_color_attachment<'a>(
+  ctx: &NodeContext<'a, '_>,
+) -> anyhow::Result<wgpu::RenderPassColorAttachment<'a>> {
+  let clear_color = ctx.context.clear_color;
+  Ok(if ctx.config().is_multisampled() {
+    wgpu::RenderPassColorAttachment {
+      view: ctx.texture_view(MSAA_COLOR)?,
+      resolve_target: Some(ctx.texture_view(HDR_COLOR)?),
+      ops: wgpu::Operations {
+        load: ctx.color_load_op(MSAA_COLOR, clear_color),
+        store: true,
+      },
+    }
+  } else {
+    wgpu::RenderPassColorAttachment {
+      view: ctx.texture_view(HDR_COLOR)?,
+      resolve_target: None,
+      ops: wgpu::Operations {
+        load: ctx.color_load_op(HDR_COLOR, clear_color),
+        store: true,
+      },
+    }
+  })
+}
+
+/// Begins a pass with the pbr pipelines' light and environment bindings
+fn begin_pbr_pass<'a>(
+  ctx: &NodeContext<'a, '_>,
+  encoder: &'a mut wgpu::CommandEncoder,
+  label: &str,
+) -> anyhow::Result<RenderPass<'a>> {
+  let context = ctx.context;
+  let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+    label: Some(label),
+    color_attachments: &[scene_color_attachment(ctx)?],
+    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+      view: ctx.texture_view(DEPTH)?,
+      depth_ops: Some(wgpu::Operations {
+        load: ctx.depth_load_op(DEPTH, 1.0),
+        store: true,
+      }),
+      stencil_ops: None,
+    }),
+  });
+  pass.set_bind_group(2, &context.light_bind_group, &[]);
+  pass.set_bind_group(3, &context.environment_bind_group, &[]);
+  pass.set_vertex_buffer(1, context.instance_buffer.slice(..));
+  Ok(pass)
+}
+
+/// Renders shadow casters into the shadow map array
+#[derive(Debug, Default)]
+pub struct ShadowPass;
+
+impl RenderNode for ShadowPass {
+  fn name(&self) -> &str {
+    "shadow"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.write(SHADOW_MAPS);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let models = context
+      .resources
+      .models
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let meshes = context
+      .resources
+      .meshes
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    context.shadow_maps.render(
+      encoder,
+      &context.shadow_uniform,
+      &context.instance_buffer,
+      &context.shadow_casters_to_draw,
+      &models,
+      &meshes,
+    );
+    Ok(())
+  }
+}
+
+///
+/// Clears the scene's color and depth buffers, and draws models
+/// whose materials are opaque or alpha masked.
+/// With GPU culling, runs the culling pass first, and draws its output indirectly.
+/// Creates HDR_COLOR, the depth buffer, and the multisampled color target when MSAA is enabled
+#[derive(Debug, Default)]
+pub struct OpaquePass;
+
+impl RenderNode for OpaquePass {
+  fn name(&self) -> &str {
+    "opaque"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    let config = *slots.config();
+    slots
+      .read(SHADOW_MAPS)
+      .create_texture(
+        HDR_COLOR,
+        TextureSlotDesc::new(HDR_FORMAT, SlotSize::Surface),
+      )
+      .create_texture(
+        DEPTH,
+        TextureSlotDesc::new(TextureResource::DEPTH_TEXTURE_FORMAT, SlotSize::Surface)
+          .with_sample_count(config.sample_count),
+      );
+    if config.is_multisampled() {
+      slots.create_texture(
+        MSAA_COLOR,
+        TextureSlotDesc::new(HDR_FORMAT, SlotSize::Surface).with_sample_count(config.sample_count),
+      );
+    }
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let models = context
+      .resources
+      .models
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let meshes = context
+      .resources
+      .meshes
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let materials = context
+      .resources
+      .materials
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let culling = context.enabled_gpu_culling();
+    if let Some(culling) = culling {
+      culling.cull(encoder);
+    }
+    let mut pass = begin_pbr_pass(ctx, encoder, "opaque pass")?;
+    let mut binder =
+      VariantBinder::new(&context.pipelines, context.pipelines.key(ShadingModel::Pbr));
+    if let Some(culling) = culling {
+      for draw in culling.draws() {
+        let mesh = match draw.mesh.read(&*meshes) {
+          Some(mesh) => mesh,
+          None => continue,
+        };
+        let (material, buffers) = match (mesh_material(mesh, &materials), mesh.buffers()) {
+          (Some(material), Some(buffers)) => (material, buffers),
+          _ => continue,
+        };
+        if binder.bind(&mut pass, material) {
+          pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+          pass.set_vertex_buffer(1, culling.culled_instances().slice(draw.instance_offset..));
+          pass.set_index_buffer(buffers.index_buffer.slice(..), buffers.index_format);
+          pass.set_bind_group(1, material_bind_group(material), &[]);
+          pass.set_bind_group(0, &context.uniform_bind_group, &[]);
+          pass.draw_indexed_indirect(culling.indirect_buffer(), draw.args_offset);
+        }
+      }
+      return Ok(());
+    }
+    for draw in context.models_to_draw() {
+      let model = match models.try_get_ref(draw.model) {
+        Ok(model) => model,
+        Err(_) => continue,
+      };
+      for mesh_handle in model.primitives() {
+        let mesh = match mesh_handle.read(&*meshes) {
+          Some(mesh) => mesh,
+          None => {
+            log::info!("mesh {:?} not found", mesh_handle);
+            continue;
+          }
+        };
+        let material = match mesh_material(mesh, &materials) {
+          Some(material) if material.alpha_mode != AlphaMode::Blend => material,
+          _ => continue,
+        };
+        if binder.bind(&mut pass, material) {
+          pass.draw_mesh_lod_instanced(
+            mesh,
+            draw.lod,
+            material_bind_group(material),
+            &context.uniform_bind_group,
+            draw.instances.clone(),
+          );
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+///
+/// Draws meshes whose materials are alpha blended over the opaque scene,
+/// one instance at a time from back to front
+#[derive(Debug, Default)]
+pub struct TransparentPass;
+
+impl RenderNode for TransparentPass {
+  fn name(&self) -> &str {
+    "transparent"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(SHADOW_MAPS).read(DEPTH);
+    write_scene_color(slots);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    if context.transparent_draws.is_empty() {
+      return Ok(());
+    }
+    let meshes = context
+      .resources
+      .meshes
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let materials = context
+      .resources
+      .materials
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let mut pass = begin_pbr_pass(ctx, encoder, "transparent pass")?;
+    let mut binder =
+      VariantBinder::new(&context.pipelines, context.pipelines.key(ShadingModel::Pbr));
+    for draw in &context.transparent_draws {
+      let (mesh, material) = match draw.mesh.read(&*meshes) {
+        Some(mesh) => match mesh_material(mesh, &materials) {
+          Some(material) => (mesh, material),
+          None => continue,
+        },
+        None => continue,
+      };
+      if binder.bind(&mut pass, material) {
+        pass.draw_mesh_lod_instanced(
+          mesh,
+          draw.lod,
+          material_bind_group(material),
+          &context.uniform_bind_group,
+          draw.instance..(draw.instance + 1),
+        );
+      }
+    }
+    Ok(())
+  }
+}
+
+///
+/// Draws the environment map behind the scene, if the context has one
+#[derive(Debug, Default)]
+pub struct SkyboxPass;
+
+impl RenderNode for SkyboxPass {
+  fn name(&self) -> &str {
+    "skybox"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(DEPTH);
+    write_scene_color(slots);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let pipeline = match (
+      &context.environment,
+      context.pipelines.skybox_pipeline.as_ref(),
+    ) {
+      (Some(_), Some(pipeline)) => pipeline,
+      _ => return Ok(()),
+    };
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("skybox pass"),
+      color_attachments: &[scene_color_attachment(ctx)?],
+      // the depth test skips pixels covered by the scene
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: ctx.texture_view(DEPTH)?,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: true,
+        }),
+        stencil_ops: None,
+      }),
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, &context.uniform_bind_group, &[]);
+    pass.set_bind_group(1, &context.environment_bind_group, &[]);
+    pass.draw(0..3, 0..1);
+    Ok(())
+  }
+}
+
+///
+/// Draws models with the DebugLight shading model as flat, unlit shapes
+#[derive(Debug, Default)]
+pub struct DebugLightPass;
+
+impl RenderNode for DebugLightPass {
+  fn name(&self) -> &str {
+    "debug_light"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(DEPTH);
+    write_scene_color(slots);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    if context.debug_lights_to_draw.is_empty() {
+      return Ok(());
+    }
+    let key = context
+      .pipelines
+      .key(ShadingModel::DebugLight)
+      .with_cull_mode(None);
+    let pipeline = match context.pipelines.variant(&key) {
+      Some(pipeline) => pipeline,
+      None => return Ok(()),
+    };
+    let models = context
+      .resources
+      .models
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let meshes = context
+      .resources
+      .meshes
+      .read()
+      .map_err(|e| anyhow!("{:?}", e))?;
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("debug light pass"),
+      color_attachments: &[scene_color_attachment(ctx)?],
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: ctx.texture_view(DEPTH)?,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: true,
+        }),
+        stencil_ops: None,
+      }),
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_vertex_buffer(1, context.instance_buffer.slice(..));
+    for draw in &context.debug_lights_to_draw {
+      let model = match models.try_get_ref(draw.model) {
+        Ok(model) => model,
+        Err(_) => continue,
+      };
+      for mesh in model
+        .primitives()
+        .iter()
+        .filter_map(|handle| handle.read(&*meshes))
+      {
+        pass.draw_mesh_lod_instanced(
+          mesh,
+          draw.lod,
+          &context.diffuse_bind_group,
+          &context.uniform_bind_group,
+          draw.instances.clone(),
+        );
+      }
+    }
+    Ok(())
+  }
+}
+
+///
+/// Draws the lines of the game's DebugDraw resource over the scene,
+/// as uploaded by `Context::render`
+#[derive(Debug, Default)]
+pub struct DebugDrawPass;
+
+impl RenderNode for DebugDrawPass {
+  fn name(&self) -> &str {
+    "debug_draw"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.read(DEPTH);
+    write_scene_color(slots);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    if context.debug_lines.is_empty() {
+      return Ok(());
+    }
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+      label: Some("debug draw pass"),
+      color_attachments: &[scene_color_attachment(ctx)?],
+      depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+        view: ctx.texture_view(DEPTH)?,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Load,
+          store: true,
+        }),
+        stencil_ops: None,
+      }),
+    });
+    context
+      .debug_lines
+      .draw(&mut pass, &context.uniform_bind_group);
+    Ok(())
+  }
+}
+
+///
+/// Runs the hook passed to `Context::render_with_ui`, over the finished frame
+#[derive(Debug, Default)]
+pub struct UiPass;
+
+impl RenderNode for UiPass {
+  fn name(&self) -> &str {
+    "ui"
+  }
+
+  fn declare(&self, slots: &mut NodeSlots) {
+    slots.write(SURFACE);
+  }
+
+  fn run(&self, ctx: &NodeContext, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+    let context = ctx.context;
+    let hook = match ctx.take_ui_hook() {
+      Some(hook) => hook,
+      None => return Ok(()),
+    };
+    let target = UiRenderTarget {
+      view: ctx.texture_view(SURFACE)?,
+      load: ctx.color_load_op(SURFACE, context.clear_color),
+    };
+    hook(&context.queue, &context.device, encoder, &target)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,195 @@
+use crate::{renderer_common::images::RawImageRbga, wgpu_renderer::textures::TextureResource};
+use anyhow::anyhow;
+use std::num::NonZeroU32;
+use wgpu::{Device, Queue, TextureFormat};
+
+///
+/// The color attachment the Context renders into.
+/// Either a window surface, or an owned offscreen texture for headless rendering
+#[derive(Debug)]
+pub enum RenderTarget {
+  Surface {
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+  },
+  Texture {
+    texture: TextureResource,
+    format: TextureFormat,
+    size: (u32, u32),
+  },
+}
+
+/// A frame acquired from a render target.
+/// Surface frames are presented when dropped
+#[derive(Debug)]
+pub struct TargetFrame {
+  pub view: wgpu::TextureView,
+  surface_texture: Option<wgpu::SurfaceTexture>,
+}
+
+impl RenderTarget {
+  pub fn new_texture(device: &Device, size: (u32, u32), format: TextureFormat) -> Self {
+    Self::Texture {
+      texture: TextureResource::new_render_target_texture(
+        device,
+        size,
+        format,
+        "offscreen_render_target",
+      ),
+      format,
+      size,
+    }
+  }
+
+  #[inline]
+  pub fn format(&self) -> TextureFormat {
+    match self {
+      Self::Surface { config, .. } => config.format,
+      Self::Texture { format, .. } => *format,
+    }
+  }
+
+  #[inline]
+  pub fn size(&self) -> (u32, u32) {
+    match self {
+      Self::Surface { config, .. } => (config.width, config.height),
+      Self::Texture { size, .. } => *size,
+    }
+  }
+
+  #[inline]
+  pub fn surface(&self) -> Option<&wgpu::Surface> {
+    match self {
+      Self::Surface { surface, .. } => Some(surface),
+      Self::Texture { .. } => None,
+    }
+  }
+
+  #[inline]
+  pub fn texture(&self) -> Option<&TextureResource> {
+    match self {
+      Self::Surface { .. } => None,
+      Self::Texture { texture, .. } => Some(texture),
+    }
+  }
+
+  pub fn resize(&mut self, device: &Device, size: (u32, u32)) {
+    match self {
+      Self::Surface { surface, config } => {
+        config.width = size.0;
+        config.height = size.1;
+        surface.configure(device, config);
+      }
+      Self::Texture { format, .. } => {
+        *self = Self::new_texture(device, size, *format);
+      }
+    }
+  }
+
+  pub fn acquire_frame(&self) -> anyhow::Result<TargetFrame> {
+    match self {
+      Self::Surface { surface, .. } => {
+        let surface_texture = surface
+          .get_current_frame()
+          .map_err(|e| anyhow!("swapchain error {:?}", e))?
+          .output;
+        let view = surface_texture
+          .texture
+          .create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(TargetFrame {
+          view,
+          surface_texture: Some(surface_texture),
+        })
+      }
+      Self::Texture { texture, .. } => Ok(TargetFrame {
+        view: texture
+          .texture()
+          .create_view(&wgpu::TextureViewDescriptor::default()),
+        surface_texture: None,
+      }),
+    }
+  }
+
+  ///
+  /// Copies the contents of an offscreen target back to the CPU as 8 bit rgba.
+  /// Fails for surface targets, or texture formats other than 8 bit rgba/bgra
+  pub async fn read_back_rgba(
+    &self,
+    device: &Device,
+    queue: &Queue,
+  ) -> anyhow::Result<RawImageRbga> {
+    let (texture, format, (width, height)) = match self {
+      Self::Surface { .. } => anyhow::bail!("cannot read back from a window surface"),
+      Self::Texture {
+        texture,
+        format,
+        size,
+      } => (texture, *format, *size),
+    };
+    let is_bgra = match format {
+      TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+      TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+      fmt => anyhow::bail!("read back is not supported for format {:?}", fmt),
+    };
+    let unpadded_bytes_per_row = 4 * width;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("read_back_rgba buffer"),
+      size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+      usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("read_back_rgba encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+      wgpu::ImageCopyTexture {
+        texture: texture.texture(),
+        mip_level: 0,
+        origin: wgpu::Origin3d::ZERO,
+        aspect: wgpu::TextureAspect::All,
+      },
+      wgpu::ImageCopyBuffer {
+        buffer: &buffer,
+        layout: wgpu::ImageDataLayout {
+          offset: 0,
+          bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+          rows_per_image: NonZeroU32::new(height),
+        },
+      },
+      wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+      },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    mapping
+      .await
+      .map_err(|e| anyhow!("could not map read back buffer {:?}", e))?;
+
+    let mut data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+      let padded = slice.get_mapped_range();
+      for row in padded.chunks(padded_bytes_per_row as usize) {
+        data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+      }
+    }
+    buffer.unmap();
+    if is_bgra {
+      for pixel in data.chunks_mut(4) {
+        pixel.swap(0, 2);
+      }
+    }
+    Ok(RawImageRbga {
+      data,
+      size: (width as usize, height as usize),
+    })
+  }
+}
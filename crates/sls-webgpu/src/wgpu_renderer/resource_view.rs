@@ -6,9 +6,10 @@ use crate::{
   wgpu_renderer::{
     material::{Material, WgpuMaterial},
     mesh::Mesh,
+    mipmaps::MipmapGenerator,
     model::StreamingMesh,
     pipeline_state::PipelineProgram,
-    textures::TextureResource,
+    textures::{SamplerCache, TextureResource},
   },
 };
 use legion::any;
@@ -63,6 +64,10 @@ pub struct ResourceContext {
   pub textures: Arc<RwLock<ResourceManager<TextureResource>>>,
   pub render_pipelines: Arc<RwLock<HashMap<Uuid, PipelineProgram>>>,
   pub shaders: Arc<RwLock<ResourceManager<wgpu::ShaderModule>>>,
+  /// samplers shared between textures
+  pub samplers: Arc<RwLock<SamplerCache>>,
+  /// generates the mip chains of uploaded textures
+  pub mipmaps: Arc<RwLock<MipmapGenerator>>,
 }
 
 impl ReadWriteResources for ResourceContext {
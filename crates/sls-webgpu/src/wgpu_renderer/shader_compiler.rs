@@ -0,0 +1,90 @@
+//! Compiles GLSL shaders to SPIR-V with naga's GLSL frontend.
+//! Shared by build.rs, which includes this file as a module, and shader hot reload,
+//! so reloaded shaders compile exactly like the embedded ones.
+//! Only uses `naga` and `std`, as build.rs can't depend on the crate itself
+use naga::{
+  back::spv,
+  front::glsl,
+  valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+  Module, ShaderStage,
+};
+use std::path::Path;
+
+/// The stage of a GLSL source, from its extension
+pub fn shader_stage(path: &Path) -> Option<ShaderStage> {
+  match path.extension()?.to_str()? {
+    "vert" => Some(ShaderStage::Vertex),
+    "frag" => Some(ShaderStage::Fragment),
+    "comp" => Some(ShaderStage::Compute),
+    _ => None,
+  }
+}
+
+///
+/// Compiles and validates a GLSL source, `path` is only used in error messages.
+///
+/// The SPIR-V matches glslangValidator's output, with no coordinate space adjustment,
+/// and keeps debug names and labelled varyings
+pub fn glsl_to_spirv(path: &Path, source: &str, stage: ShaderStage) -> Result<Vec<u32>, String> {
+  let module = glsl::Parser::default()
+    .parse(&glsl::Options::from(stage), source)
+    .map_err(|errors| {
+      errors
+        .iter()
+        .map(|error| format_diagnostic(path, source, error))
+        .collect::<Vec<_>>()
+        .join("\n")
+    })?;
+  let info = validate(&module).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+  let spv_options = spv::Options {
+    flags: spv::WriterFlags::DEBUG | spv::WriterFlags::LABEL_VARYINGS,
+    ..spv::Options::default()
+  };
+  spv::write_vec(&module, &info, &spv_options, None)
+    .map_err(|e| format!("{}: could not write SPIR-V: {}", path.display(), e))
+}
+
+pub fn validate(module: &Module) -> Result<ModuleInfo, String> {
+  Validator::new(ValidationFlags::all(), Capabilities::empty())
+    .validate(module)
+    .map_err(|e| format!("validation error: {}", error_chain(&e)))
+}
+
+/// `path:line:column: error` for a GLSL parse error, followed by the offending line
+fn format_diagnostic(path: &Path, source: &str, error: &glsl::Error) -> String {
+  let start = error.meta.to_range().map_or(0, |range| range.start);
+  let (line, column, text) = line_at(source, start);
+  format!(
+    "{}:{}:{}: error: {}\n  {}\n  {:>width$}",
+    path.display(),
+    line,
+    column,
+    error.kind,
+    text,
+    "^",
+    width = column
+  )
+}
+
+/// 1-based line and column of a byte offset, and the text of its line
+fn line_at(source: &str, offset: usize) -> (usize, usize, &str) {
+  let offset = offset.min(source.len());
+  let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+  let line_end = source[offset..]
+    .find('\n')
+    .map_or(source.len(), |i| offset + i);
+  let line = source[..offset].matches('\n').count() + 1;
+  (line, offset - line_start + 1, &source[line_start..line_end])
+}
+
+fn error_chain(error: &dyn std::error::Error) -> String {
+  let mut message = error.to_string();
+  let mut source = error.source();
+  while let Some(cause) = source {
+    message.push_str(": ");
+    message.push_str(&cause.to_string());
+    source = cause.source();
+  }
+  message
+}
@@ -0,0 +1,141 @@
+//! Recompiles GLSL shader sources when they change on disk,
+//! so shaders can be edited without rebuilding the crate.
+//! Sources are compiled by `shader_compiler`, the same way build.rs compiles them
+use crate::{
+  renderer_common::handle::Handle,
+  wgpu_renderer::shader_compiler::{glsl_to_spirv, shader_stage},
+};
+use anyhow::anyhow;
+use naga::ShaderStage;
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+use wgpu::ShaderModule;
+
+/// The GLSL sources of this crate's shaders, for watching a checkout of the repository
+pub const SHADER_SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+#[derive(Debug)]
+struct WatchedShader {
+  path: PathBuf,
+  stage: ShaderStage,
+  handle: Handle<ShaderModule>,
+  /// modification time of the last source that was compiled, or found invalid
+  modified: Option<SystemTime>,
+}
+
+///
+/// Tracks the sources of shader modules in `ResourceContext::shaders`.
+/// Polled by `Context::update` after `Context::watch_shaders` is called
+#[derive(Default)]
+pub struct ShaderWatcher {
+  shaders: Vec<WatchedShader>,
+}
+
+impl fmt::Debug for ShaderWatcher {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_list()
+      .entries(self.shaders.iter().map(|shader| &shader.path))
+      .finish()
+  }
+}
+
+impl ShaderWatcher {
+  ///
+  /// Watches the GLSL source at `path`, whose compiled module is stored under `handle`.
+  /// The stage is taken from the extension
+  pub fn watch<P: Into<PathBuf>>(
+    &mut self,
+    path: P,
+    handle: Handle<ShaderModule>,
+  ) -> anyhow::Result<()> {
+    let path = path.into();
+    let stage =
+      shader_stage(&path).ok_or_else(|| anyhow!("unknown shader stage for {:?}", path))?;
+    let modified = modified_time(&path)?;
+    self.shaders.push(WatchedShader {
+      path,
+      stage,
+      handle,
+      modified: Some(modified),
+    });
+    Ok(())
+  }
+
+  ///
+  /// Compiles every watched source that changed since it was last compiled.
+  /// Sources that fail to compile are logged, and skipped until they change again
+  pub fn compile_changed(&mut self) -> Vec<(Handle<ShaderModule>, Vec<u32>)> {
+    let mut compiled = Vec::new();
+    for shader in self.shaders.iter_mut() {
+      let modified = match modified_time(&shader.path) {
+        Ok(modified) => modified,
+        Err(e) => {
+          log::warn!("could not check shader {:?}: {:?}", shader.path, e);
+          continue;
+        }
+      };
+      if shader.modified == Some(modified) {
+        continue;
+      }
+      shader.modified = Some(modified);
+      let result = std::fs::read_to_string(&shader.path)
+        .map_err(anyhow::Error::from)
+        .and_then(|source| compile_glsl(&source, shader.stage, &shader.path));
+      match result {
+        Ok(words) => {
+          log::info!("recompiled shader {:?}", shader.path);
+          compiled.push((shader.handle, words));
+        }
+        Err(e) => log::error!("could not compile shader {:?}: {}", shader.path, e),
+      }
+    }
+    compiled
+  }
+}
+
+/// Compiles GLSL to SPIR-V with debug info, with the compiler build.rs uses
+pub fn compile_glsl(source: &str, stage: ShaderStage, path: &Path) -> anyhow::Result<Vec<u32>> {
+  glsl_to_spirv(path, source, stage).map_err(|message| anyhow!(message))
+}
+
+fn modified_time(path: &Path) -> anyhow::Result<SystemTime> {
+  Ok(std::fs::metadata(path)?.modified()?)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+  #[test]
+  fn test_shader_stage() {
+    assert_eq!(
+      shader_stage(Path::new("src/shaders/main.frag")),
+      Some(ShaderStage::Fragment)
+    );
+    assert_eq!(
+      shader_stage(Path::new("main.vert")),
+      Some(ShaderStage::Vertex)
+    );
+    assert_eq!(shader_stage(Path::new("main.vert.spv")), None);
+    assert_eq!(shader_stage(Path::new("main")), None);
+  }
+
+  #[test]
+  fn test_compile_glsl() {
+    let source =
+      "#version 450\nlayout(location=0) out vec4 color;\nvoid main() { color = vec4(1.0); }";
+    let words = compile_glsl(source, ShaderStage::Fragment, Path::new("test.frag")).unwrap();
+    assert_eq!(words.first(), Some(&SPIRV_MAGIC));
+
+    let invalid = "#version 450\nvoid main() {\n  undeclared = 1.0;\n}";
+    let error = compile_glsl(invalid, ShaderStage::Fragment, Path::new("test.frag"))
+      .unwrap_err()
+      .to_string();
+    assert!(error.starts_with("test.frag:3:"), "{}", error);
+  }
+}
@@ -0,0 +1,579 @@
+//! Shadow maps for directional and spot lights.
+//!
+//! Every shadow casting light is given one or more layers of a depth texture array.
+//! Directional lights use cascaded shadow maps, with one orthographic layer fit around
+//! each slice of the main camera's frustum. Spot lights use a single perspective layer
+//! covering their outer cone. Shadow casters are drawn into each layer by a depth only
+//! pass before the main pass, which filters the maps with PCF in main.frag.
+use nalgebra_glm::*;
+use wgpu::{util::DeviceExt, BindGroup, Buffer, Device, Queue, RenderPipeline, TextureView};
+
+use crate::{
+  camera::{Camera, OPENGL_TO_WGPU_MATRIX},
+  renderer_common::{allocator::ResourceManager, geometry::Vertex},
+  wgpu_renderer::{
+    mesh::Mesh,
+    model::StreamingMesh,
+    model_instance::InstanceRange,
+    textures::TextureResource,
+    uniforms::{
+      LightUniform, ShadowUniform, LIGHT_TYPE_DIRECTIONAL, LIGHT_TYPE_SPOT, MAX_SHADOW_LAYERS,
+    },
+    ModelInstance,
+  },
+};
+
+/// Width and height of each shadow map layer
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+/// Number of cascades per directional light. At most 4, the size of `cascade_splits`
+pub const SHADOW_CASCADE_COUNT: usize = 3;
+/// Blend between logarithmic (1.0) and uniform (0.0) cascade splits
+pub const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+/// Directional light shadows end at this view depth, or the camera's far plane if closer
+pub const MAX_SHADOW_DISTANCE: f32 = 50.0;
+
+/// Distance behind a cascade's bounds which casters are still drawn from
+const CASTER_MARGIN: f32 = 20.0;
+const SPOT_SHADOW_NEAR: f32 = 0.05;
+/// Far plane of spot light shadows, for lights with unlimited range
+const SPOT_SHADOW_DEFAULT_RANGE: f32 = 100.0;
+/// Offset between per-layer pass uniforms, which satisfies the default
+/// min_uniform_buffer_offset_alignment
+const PASS_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+
+/// The `ShadowPass` uniform block in shadow.vert
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowPassUniform {
+  light_view_projection: [[f32; 4]; 4],
+}
+
+///
+/// View depths at the far end of each cascade, splitting near..far with the
+/// practical split scheme. The last split is always `far`
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+  (1..=count)
+    .map(|i| {
+      let p = i as f32 / count as f32;
+      let log = near * (far / near).powf(p);
+      let uniform = near + (far - near) * p;
+      lambda * log + (1.0 - lambda) * uniform
+    })
+    .collect()
+}
+
+///
+/// World space corners of the frustum whose clip space is mapped back by
+/// `inverse_view_projection`, with wgpu's 0..1 clip depth
+pub fn frustum_corners(inverse_view_projection: &Mat4) -> [Vec3; 8] {
+  let mut corners = [Vec3::zeros(); 8];
+  let mut i = 0;
+  for &x in &[-1.0, 1.0] {
+    for &y in &[-1.0, 1.0] {
+      for &z in &[0.0, 1.0] {
+        let corner = inverse_view_projection * vec4(x, y, z, 1.0);
+        corners[i] = corner.xyz() / corner.w;
+        i += 1;
+      }
+    }
+  }
+  corners
+}
+
+/// World space corners of the slice of the camera's frustum between view depths near and far
+pub fn camera_slice_corners(camera: &Camera, near: f32, far: f32) -> [Vec3; 8] {
+  let projection = *OPENGL_TO_WGPU_MATRIX * perspective(camera.aspect, camera.fovy, near, far);
+  let inverse = (projection * camera.view())
+    .try_inverse()
+    .unwrap_or_else(Mat4::identity);
+  frustum_corners(&inverse)
+}
+
+/// An up vector which is not parallel to direction
+fn light_up(direction: &Vec3) -> Vec3 {
+  if direction.y.abs() > 0.99 {
+    vec3(1.0, 0.0, 0.0)
+  } else {
+    vec3(0.0, 1.0, 0.0)
+  }
+}
+
+///
+/// Orthographic view projection for a directional light, covering `corners`.
+/// The projection is fit to the corners' bounding sphere and snapped to whole texels,
+/// so that shadow edges don't shimmer as the camera moves and turns
+pub fn directional_light_view_projection(
+  direction: &Vec3,
+  corners: &[Vec3; 8],
+  map_size: u32,
+) -> Mat4 {
+  let direction = direction
+    .try_normalize(f32::EPSILON)
+    .unwrap_or_else(|| vec3(0.0, 0.0, -1.0));
+  let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+  let radius = corners
+    .iter()
+    .map(|corner| distance(corner, &center))
+    .fold(0.0, f32::max);
+  // keep the projection's size constant while the camera turns
+  let radius = (radius * 16.0).ceil() / 16.0;
+
+  let eye = center - direction * (radius + CASTER_MARGIN);
+  let view = look_at(&eye, &center, &light_up(&direction));
+  let mut projection = *OPENGL_TO_WGPU_MATRIX
+    * ortho(
+      -radius,
+      radius,
+      -radius,
+      radius,
+      0.0,
+      2.0 * radius + CASTER_MARGIN,
+    );
+
+  let half_size = map_size as f32 / 2.0;
+  let origin = (projection * view * vec4(0.0, 0.0, 0.0, 1.0)).xy() * half_size;
+  let offset = (vec2(origin.x.round(), origin.y.round()) - origin) / half_size;
+  projection[(0, 3)] += offset.x;
+  projection[(1, 3)] += offset.y;
+  projection * view
+}
+
+///
+/// Perspective view projection for a spot light, covering its outer cone out to range.
+/// outer_cutoff is the cone's half-angle in radians
+pub fn spot_light_view_projection(
+  position: &Vec3,
+  direction: &Vec3,
+  outer_cutoff: f32,
+  range: f32,
+) -> Mat4 {
+  let direction = direction
+    .try_normalize(f32::EPSILON)
+    .unwrap_or_else(|| vec3(0.0, 0.0, -1.0));
+  let far = if range > 0.0 {
+    range
+  } else {
+    SPOT_SHADOW_DEFAULT_RANGE
+  };
+  let fovy = (2.0 * outer_cutoff).clamp(0.01, std::f32::consts::PI - 0.01);
+  let view = look_at(position, &(position + direction), &light_up(&direction));
+  *OPENGL_TO_WGPU_MATRIX * perspective(1.0, fovy, SPOT_SHADOW_NEAR, far) * view
+}
+
+///
+/// Builds a frame's ShadowUniform from the main camera,
+/// handing out shadow map layers to lights as they are added
+#[derive(Debug, Clone)]
+pub struct ShadowUniformBuilder {
+  uniform: ShadowUniform,
+  cascades: Vec<[Vec3; 8]>,
+}
+
+impl ShadowUniformBuilder {
+  pub fn new(camera: &Camera) -> Self {
+    let near = camera.znear;
+    let far = camera.zfar.min(MAX_SHADOW_DISTANCE).max(near);
+    let splits = cascade_splits(near, far, SHADOW_CASCADE_COUNT, CASCADE_SPLIT_LAMBDA);
+    let mut uniform = ShadowUniform {
+      texel_size: 1.0 / SHADOW_MAP_SIZE as f32,
+      ..Default::default()
+    };
+    let forward = camera.front().normalize();
+    uniform.camera_forward = [forward.x, forward.y, forward.z, 0.0];
+    let mut cascades = Vec::with_capacity(splits.len());
+    let mut cascade_near = near;
+    for (i, &split) in splits.iter().enumerate() {
+      uniform.cascade_splits[i] = split;
+      cascades.push(camera_slice_corners(camera, cascade_near, split));
+      cascade_near = split;
+    }
+    Self { uniform, cascades }
+  }
+
+  ///
+  /// Assigns shadow map layers to a directional or spot light, and writes them
+  /// to `light.shadow_layer`. Returns false if the light is left without a shadow,
+  /// because it is a point light or there are no free layers
+  pub fn add_light(&mut self, light: &mut LightUniform) -> bool {
+    let first = self.uniform.layer_count as usize;
+    let direction = Vec3::from(light.direction);
+    let view_projections: Vec<Mat4> = match light.light_type {
+      LIGHT_TYPE_DIRECTIONAL => self
+        .cascades
+        .iter()
+        .map(|corners| directional_light_view_projection(&direction, corners, SHADOW_MAP_SIZE))
+        .collect(),
+      LIGHT_TYPE_SPOT => vec![spot_light_view_projection(
+        &Vec3::from(light.position),
+        &direction,
+        light.outer_cutoff.clamp(-1.0, 1.0).acos(),
+        light.range,
+      )],
+      _ => return false,
+    };
+    if first + view_projections.len() > MAX_SHADOW_LAYERS {
+      return false;
+    }
+    for (i, view_projection) in view_projections.iter().enumerate() {
+      self.uniform.view_projections[first + i] = (*view_projection).into();
+    }
+    light.shadow_layer = first as i32;
+    light.shadow_layer_count = view_projections.len() as u32;
+    self.uniform.layer_count += view_projections.len() as u32;
+    true
+  }
+
+  pub fn build(self) -> ShadowUniform {
+    self.uniform
+  }
+}
+
+///
+/// GPU resources for shadow mapping: the shadow map array and its per-layer views,
+/// the depth only pipeline which renders into them, and the ShadowUniform buffer
+/// bound alongside the lights
+#[derive(Debug)]
+pub struct ShadowMaps {
+  maps: TextureResource,
+  layer_views: Vec<TextureView>,
+  uniform_buffer: Buffer,
+  pass_buffer: Buffer,
+  pass_bind_group: BindGroup,
+  pipeline: RenderPipeline,
+}
+
+impl ShadowMaps {
+  pub fn new(device: &Device) -> Self {
+    let maps = TextureResource::new_depth_texture_array(
+      device,
+      SHADOW_MAP_SIZE,
+      MAX_SHADOW_LAYERS as u32,
+      "shadow_maps",
+    );
+    let layer_views = (0..MAX_SHADOW_LAYERS as u32)
+      .map(|layer| {
+        maps.texture().create_view(&wgpu::TextureViewDescriptor {
+          label: Some("shadow_map_layer"),
+          dimension: Some(wgpu::TextureViewDimension::D2),
+          base_array_layer: layer,
+          array_layer_count: std::num::NonZeroU32::new(1),
+          ..Default::default()
+        })
+      })
+      .collect();
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Shadow UBO"),
+      contents: bytemuck::cast_slice(&[ShadowUniform::default()]),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let pass_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Shadow pass UBO"),
+      size: PASS_UNIFORM_STRIDE * MAX_SHADOW_LAYERS as wgpu::BufferAddress,
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let pass_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+      label: Some("shadow_pass_layout"),
+      entries: &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: true,
+          min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ShadowPassUniform>() as _),
+        },
+        count: None,
+      }],
+    });
+    let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("shadow_pass_bind_group"),
+      layout: &pass_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+          buffer: &pass_buffer,
+          offset: 0,
+          size: wgpu::BufferSize::new(std::mem::size_of::<ShadowPassUniform>() as _),
+        }),
+      }],
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("shadow_pipeline_layout"),
+      bind_group_layouts: &[&pass_layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(&include_shader!("shadow.vert"));
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Shadow Pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: "main",
+        buffers: &[Vertex::desc(), ModelInstance::desc()],
+      },
+      fragment: None,
+      primitive: wgpu::PrimitiveState::default(),
+      depth_stencil: Some(wgpu::DepthStencilState {
+        format: TextureResource::DEPTH_TEXTURE_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::LessEqual,
+        stencil: Default::default(),
+        // slope scaled bias keeps surfaces at grazing angles from shadowing themselves
+        bias: wgpu::DepthBiasState {
+          constant: 2,
+          slope_scale: 2.0,
+          clamp: 0.0,
+        },
+      }),
+      multisample: wgpu::MultisampleState::default(),
+    });
+    Self {
+      maps,
+      layer_views,
+      uniform_buffer,
+      pass_buffer,
+      pass_bind_group,
+      pipeline,
+    }
+  }
+
+  /// Buffer holding the ShadowUniform
+  pub fn uniform_buffer(&self) -> &Buffer {
+    &self.uniform_buffer
+  }
+
+  /// Array view of every shadow map layer, with a comparison sampler
+  pub fn maps(&self) -> &TextureResource {
+    &self.maps
+  }
+
+  /// Uploads the ShadowUniform read by main.frag, and the matrices for each layer's pass
+  pub fn write(&self, queue: &Queue, uniform: &ShadowUniform) {
+    queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[*uniform]));
+    for (layer, view_projection) in uniform.view_projections[..uniform.layer_count as usize]
+      .iter()
+      .enumerate()
+    {
+      let pass = ShadowPassUniform {
+        light_view_projection: *view_projection,
+      };
+      queue.write_buffer(
+        &self.pass_buffer,
+        layer as wgpu::BufferAddress * PASS_UNIFORM_STRIDE,
+        bytemuck::cast_slice(&[pass]),
+      );
+    }
+  }
+
+  ///
+  /// Draws the shadow casters into every layer in use, with one depth only pass per layer.
+  /// `casters` are ranges of `instance_buffer`
+  pub fn render(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    uniform: &ShadowUniform,
+    instance_buffer: &Buffer,
+    casters: &[InstanceRange],
+    models: &ResourceManager<StreamingMesh>,
+    meshes: &ResourceManager<Mesh>,
+  ) {
+    for (layer, view) in self.layer_views[..uniform.layer_count as usize]
+      .iter()
+      .enumerate()
+    {
+      let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("shadow pass"),
+        color_attachments: &[],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+          view,
+          depth_ops: Some(wgpu::Operations {
+            load: wgpu::LoadOp::Clear(1.0),
+            store: true,
+          }),
+          stencil_ops: None,
+        }),
+      });
+      pass.set_pipeline(&self.pipeline);
+      pass.set_bind_group(
+        0,
+        &self.pass_bind_group,
+        &[(layer as wgpu::BufferAddress * PASS_UNIFORM_STRIDE) as u32],
+      );
+      pass.set_vertex_buffer(1, instance_buffer.slice(..));
+      for draw in casters {
+        let model = match models.try_get_ref(draw.model) {
+          Ok(model) => model,
+          Err(_) => continue,
+        };
+        for mesh_handle in model.primitives() {
+          let buffers = match mesh_handle.read(meshes).and_then(|mesh| mesh.buffers()) {
+            Some(buffers) => buffers,
+            None => continue,
+          };
+          pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+          pass.set_index_buffer(buffers.index_buffer.slice(..), buffers.index_format);
+          pass.draw_indexed(buffers.lod_range(draw.lod), 0, draw.instances.clone());
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn assert_in_clip_volume(view_projection: &Mat4, point: &Vec3, tolerance: f32) {
+    let clip = view_projection * vec4(point.x, point.y, point.z, 1.0);
+    let ndc = clip.xyz() / clip.w;
+    assert!(
+      ndc.x.abs() <= 1.0 + tolerance
+        && ndc.y.abs() <= 1.0 + tolerance
+        && ndc.z >= -tolerance
+        && ndc.z <= 1.0 + tolerance,
+      "{:?} is outside of the clip volume at {:?}",
+      point,
+      ndc
+    );
+  }
+
+  fn test_camera() -> Camera {
+    let mut camera = Camera::new(16.0 / 9.0);
+    camera.position = vec3(1.0, 2.0, 3.0);
+    camera.yaw = 0.3;
+    camera.pitch = -0.2;
+    camera.update_front();
+    camera
+  }
+
+  #[test]
+  fn test_cascade_splits() {
+    let splits = cascade_splits(0.1, 50.0, 3, 0.75);
+    assert_eq!(splits.len(), 3);
+    assert!((splits[2] - 50.0).abs() < 1e-4);
+    assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(splits[0] > 0.1);
+
+    let uniform = cascade_splits(1.0, 4.0, 3, 0.0);
+    for (split, expected) in uniform.iter().zip(&[2.0, 3.0, 4.0]) {
+      assert!((split - expected).abs() < 1e-5, "{:?}", uniform);
+    }
+    let logarithmic = cascade_splits(1.0, 8.0, 3, 1.0);
+    for (split, expected) in logarithmic.iter().zip(&[2.0, 4.0, 8.0]) {
+      assert!((split - expected).abs() < 1e-4, "{:?}", logarithmic);
+    }
+  }
+
+  #[test]
+  fn test_frustum_corners_of_identity() {
+    let corners = frustum_corners(&Mat4::identity());
+    assert_eq!(corners[0], vec3(-1.0, -1.0, 0.0));
+    assert_eq!(corners[7], vec3(1.0, 1.0, 1.0));
+  }
+
+  #[test]
+  fn test_camera_slice_corners_depth() {
+    let camera = test_camera();
+    let front = camera.front().normalize();
+    let corners = camera_slice_corners(&camera, 2.0, 10.0);
+    let mut depths: Vec<f32> = corners
+      .iter()
+      .map(|corner| dot(&(corner - camera.position), &front))
+      .collect();
+    depths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    for near in &depths[..4] {
+      assert!((near - 2.0).abs() < 1e-3, "{:?}", depths);
+    }
+    for far in &depths[4..] {
+      assert!((far - 10.0).abs() < 1e-2, "{:?}", depths);
+    }
+  }
+
+  #[test]
+  fn test_directional_light_covers_cascade() {
+    let camera = test_camera();
+    let corners = camera_slice_corners(&camera, 0.1, 12.0);
+    for direction in &[
+      vec3(0.3, -1.0, 0.2),
+      vec3(0.0, -1.0, 0.0),
+      vec3(1.0, 0.0, 0.0),
+    ] {
+      let view_projection = directional_light_view_projection(direction, &corners, 2048);
+      for corner in &corners {
+        assert_in_clip_volume(&view_projection, corner, 2.0 / 2048.0);
+      }
+    }
+  }
+
+  #[test]
+  fn test_directional_light_snaps_to_texels() {
+    let camera = test_camera();
+    let corners = camera_slice_corners(&camera, 0.1, 12.0);
+    let view_projection = directional_light_view_projection(&vec3(0.3, -1.0, 0.2), &corners, 2048);
+    let origin = (view_projection * vec4(0.0, 0.0, 0.0, 1.0)).xy() * 1024.0;
+    assert!((origin.x - origin.x.round()).abs() < 1e-2, "{:?}", origin);
+    assert!((origin.y - origin.y.round()).abs() < 1e-2, "{:?}", origin);
+  }
+
+  #[test]
+  fn test_spot_light_view_projection() {
+    let position = vec3(0.0, 3.0, 0.0);
+    let direction = vec3(0.0, -1.0, 0.0);
+    let view_projection = spot_light_view_projection(&position, &direction, 0.5, 10.0);
+    let center = view_projection * vec4(0.0, 0.0, 0.0, 1.0);
+    let center = center.xyz() / center.w;
+    assert!(
+      center.x.abs() < 1e-5 && center.y.abs() < 1e-5,
+      "{:?}",
+      center
+    );
+    assert!(center.z > 0.0 && center.z < 1.0);
+    // a point just inside the outer cone
+    let edge = position + vec3(0.0, -2.0, 0.0) + vec3(2.0 * (0.49f32).tan(), 0.0, 0.0);
+    assert_in_clip_volume(&view_projection, &edge, 0.0);
+  }
+
+  #[test]
+  fn test_add_light_assigns_layers() {
+    let mut builder = ShadowUniformBuilder::new(&test_camera());
+    let mut directional = LightUniform {
+      light_type: LIGHT_TYPE_DIRECTIONAL,
+      direction: [0.0, -1.0, 0.0],
+      ..Default::default()
+    };
+    let mut spot = LightUniform {
+      light_type: LIGHT_TYPE_SPOT,
+      outer_cutoff: 0.5f32.cos(),
+      ..Default::default()
+    };
+    let mut point = LightUniform::default();
+
+    assert!(builder.add_light(&mut directional));
+    assert_eq!(directional.shadow_layer, 0);
+    assert_eq!(directional.shadow_layer_count, SHADOW_CASCADE_COUNT as u32);
+    assert!(builder.add_light(&mut spot));
+    assert_eq!(spot.shadow_layer, SHADOW_CASCADE_COUNT as i32);
+    assert_eq!(spot.shadow_layer_count, 1);
+    assert!(!builder.add_light(&mut point));
+    assert_eq!(point.shadow_layer, -1);
+
+    let uniform = builder.build();
+    assert_eq!(uniform.layer_count, SHADOW_CASCADE_COUNT as u32 + 1);
+    assert!((uniform.cascade_splits[SHADOW_CASCADE_COUNT - 1] - MAX_SHADOW_DISTANCE).abs() < 1e-3);
+  }
+
+  #[test]
+  fn test_add_light_when_layers_are_full() {
+    let mut builder = ShadowUniformBuilder::new(&test_camera());
+    let mut spot = LightUniform {
+      light_type: LIGHT_TYPE_SPOT,
+      ..Default::default()
+    };
+    for _ in 0..MAX_SHADOW_LAYERS {
+      assert!(builder.add_light(&mut spot.clone()));
+    }
+    assert!(!builder.add_light(&mut spot));
+    assert_eq!(spot.shadow_layer, -1);
+    assert_eq!(builder.build().layer_count, MAX_SHADOW_LAYERS as u32);
+  }
+}
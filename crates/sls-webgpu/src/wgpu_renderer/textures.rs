@@ -1,11 +1,15 @@
-use std::num::NonZeroU32;
+use std::{
+  collections::HashMap,
+  num::{NonZeroU32, NonZeroU8},
+  sync::Arc,
+};
 
 use image::{DynamicImage, GenericImageView};
 use thiserror::Error;
 
 use wgpu::{
-  BindGroup, BindGroupDescriptor, BindGroupEntry, Device, Queue, Sampler, ShaderStages, Texture,
-  TextureSampleType, TextureView,
+  AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, Device, Queue, Texture,
+  TextureFormat, TextureView,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -14,6 +18,12 @@ pub use native::*;
 use crate::{
   renderer_common::handle::{Handle, HandleIndex},
   wgpu::{BindGroupLayout, BindingResource, FilterMode, TextureViewDimension},
+  wgpu_renderer::{
+    compressed_texture::{decode_compressed, CompressedFormats, DecodedTexture, MipChain},
+    environment::f32_to_f16,
+    mipmaps::mip_level_count,
+    resource_view::ResourceContext,
+  },
   Context,
 };
 
@@ -27,50 +37,301 @@ pub enum TextureError {
   Other(String),
 }
 
+/// Largest anisotropy clamp supported by wgpu
+pub const MAX_ANISOTROPY: u8 = 16;
+
+///
+/// How the color channels of a texture are encoded. Colors, such as albedo and emissive,
+/// are sRGB, while data such as normals or metallic-roughness is linear
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+  Srgb,
+  Linear,
+}
+
+/// Formats with both linear and sRGB variants, as (linear, sRGB) pairs
+const SRGB_FORMAT_PAIRS: &[(TextureFormat, TextureFormat)] = &[
+  (TextureFormat::Rgba8Unorm, TextureFormat::Rgba8UnormSrgb),
+  (TextureFormat::Bgra8Unorm, TextureFormat::Bgra8UnormSrgb),
+  (TextureFormat::Bc1RgbaUnorm, TextureFormat::Bc1RgbaUnormSrgb),
+  (TextureFormat::Bc2RgbaUnorm, TextureFormat::Bc2RgbaUnormSrgb),
+  (TextureFormat::Bc3RgbaUnorm, TextureFormat::Bc3RgbaUnormSrgb),
+  (TextureFormat::Bc7RgbaUnorm, TextureFormat::Bc7RgbaUnormSrgb),
+  (TextureFormat::Etc2RgbUnorm, TextureFormat::Etc2RgbUnormSrgb),
+  (
+    TextureFormat::Etc2RgbA1Unorm,
+    TextureFormat::Etc2RgbA1UnormSrgb,
+  ),
+  (
+    TextureFormat::Astc4x4RgbaUnorm,
+    TextureFormat::Astc4x4RgbaUnormSrgb,
+  ),
+];
+
+impl ColorSpace {
+  ///
+  /// The variant of `format` in this color space.
+  /// Formats without an sRGB variant, like float formats, are always linear
+  pub fn format(self, format: TextureFormat) -> TextureFormat {
+    SRGB_FORMAT_PAIRS
+      .iter()
+      .find(|(linear, srgb)| *linear == format || *srgb == format)
+      .map_or(format, |(linear, srgb)| match self {
+        Self::Srgb => *srgb,
+        Self::Linear => *linear,
+      })
+  }
+}
+
+/// Converts an sRGB encoded channel in 0..=1 to linear
+pub fn srgb_to_linear(value: f32) -> f32 {
+  if value <= 0.04045 {
+    value / 12.92
+  } else {
+    ((value + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+///
+/// Addressing and filtering of a texture. Identical samplers are
+/// shared through the `SamplerCache`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Sampler {
+  pub address_mode_u: AddressMode,
+  pub address_mode_v: AddressMode,
+  pub mag_filter: FilterMode,
+  pub min_filter: FilterMode,
+  pub mipmap_filter: FilterMode,
+  /// if false, only the base mip level is sampled
+  pub use_mipmaps: bool,
+}
+
+impl Default for Sampler {
+  /// Repeating, trilinear filtered sampler. The glTF defaults
+  fn default() -> Self {
+    Self {
+      address_mode_u: AddressMode::Repeat,
+      address_mode_v: AddressMode::Repeat,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      mipmap_filter: FilterMode::Linear,
+      use_mipmaps: true,
+    }
+  }
+}
+
+impl Sampler {
+  pub fn from_gltf(gltf_sampler: &gltf::texture::Sampler) -> Self {
+    Self::from_gltf_modes(
+      gltf_sampler.wrap_s(),
+      gltf_sampler.wrap_t(),
+      gltf_sampler.mag_filter(),
+      gltf_sampler.min_filter(),
+    )
+  }
+
+  /// Unset filters use linear filtering, with mipmaps
+  pub fn from_gltf_modes(
+    wrap_s: gltf::texture::WrappingMode,
+    wrap_t: gltf::texture::WrappingMode,
+    mag_filter: Option<gltf::texture::MagFilter>,
+    min_filter: Option<gltf::texture::MinFilter>,
+  ) -> Self {
+    use gltf::texture::{MagFilter, MinFilter};
+    let mag_filter = match mag_filter {
+      Some(MagFilter::Nearest) => FilterMode::Nearest,
+      Some(MagFilter::Linear) | None => FilterMode::Linear,
+    };
+    let (min_filter, mipmap_filter, use_mipmaps) = match min_filter {
+      Some(MinFilter::Nearest) => (FilterMode::Nearest, FilterMode::Nearest, false),
+      Some(MinFilter::Linear) => (FilterMode::Linear, FilterMode::Nearest, false),
+      Some(MinFilter::NearestMipmapNearest) => (FilterMode::Nearest, FilterMode::Nearest, true),
+      Some(MinFilter::LinearMipmapNearest) => (FilterMode::Linear, FilterMode::Nearest, true),
+      Some(MinFilter::NearestMipmapLinear) => (FilterMode::Nearest, FilterMode::Linear, true),
+      Some(MinFilter::LinearMipmapLinear) | None => (FilterMode::Linear, FilterMode::Linear, true),
+    };
+    Self {
+      address_mode_u: address_mode_from_gltf(wrap_s),
+      address_mode_v: address_mode_from_gltf(wrap_t),
+      mag_filter,
+      min_filter,
+      mipmap_filter,
+      use_mipmaps,
+    }
+  }
+
+  /// Anisotropic filtering is only applied if every filter is linear
+  pub fn descriptor(&self, max_anisotropy: Option<NonZeroU8>) -> wgpu::SamplerDescriptor<'static> {
+    let all_linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+      .iter()
+      .all(|filter| *filter == FilterMode::Linear);
+    wgpu::SamplerDescriptor {
+      label: Some("texture_sampler"),
+      address_mode_u: self.address_mode_u,
+      address_mode_v: self.address_mode_v,
+      address_mode_w: AddressMode::ClampToEdge,
+      mag_filter: self.mag_filter,
+      min_filter: self.min_filter,
+      mipmap_filter: self.mipmap_filter,
+      lod_max_clamp: if self.use_mipmaps { f32::MAX } else { 0.0 },
+      anisotropy_clamp: if all_linear && self.use_mipmaps {
+        max_anisotropy
+      } else {
+        None
+      },
+      ..Default::default()
+    }
+  }
+}
+
+fn address_mode_from_gltf(mode: gltf::texture::WrappingMode) -> AddressMode {
+  match mode {
+    gltf::texture::WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+    gltf::texture::WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+    gltf::texture::WrappingMode::Repeat => AddressMode::Repeat,
+  }
+}
+
+///
+/// Creates samplers, sharing one wgpu sampler between textures
+/// with the same `Sampler` state
+#[derive(Debug, Default)]
+pub struct SamplerCache {
+  max_anisotropy: Option<NonZeroU8>,
+  samplers: HashMap<Sampler, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+  pub fn new(max_anisotropy: Option<NonZeroU8>) -> Self {
+    Self {
+      max_anisotropy,
+      samplers: HashMap::new(),
+    }
+  }
+
+  /// Uses anisotropic filtering if the adapter supports it
+  pub fn for_adapter(adapter: &wgpu::Adapter) -> Self {
+    let anisotropic = adapter
+      .get_downlevel_properties()
+      .flags
+      .contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING);
+    Self::new(if anisotropic {
+      NonZeroU8::new(MAX_ANISOTROPY)
+    } else {
+      None
+    })
+  }
+
+  pub fn max_anisotropy(&self) -> Option<NonZeroU8> {
+    self.max_anisotropy
+  }
+
+  pub fn get_or_create(&mut self, device: &Device, sampler: &Sampler) -> Arc<wgpu::Sampler> {
+    let max_anisotropy = self.max_anisotropy;
+    self
+      .samplers
+      .entry(*sampler)
+      .or_insert_with(|| Arc::new(device.create_sampler(&sampler.descriptor(max_anisotropy))))
+      .clone()
+  }
+
+  /// number of distinct samplers created
+  pub fn len(&self) -> usize {
+    self.samplers.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.samplers.is_empty()
+  }
+}
+
 /// Texture Wrapper object
 ///
 #[derive(Debug)]
 pub struct TextureResource {
   texture: Texture,
   view: TextureView,
-  sampler: Sampler,
+  sampler: Arc<wgpu::Sampler>,
 }
 
 impl TextureResource {
   pub const DEPTH_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+  ///
+  /// Uploads an image with a full mip chain, generated on the device.
+  /// The sampler is shared with other textures through `resources.samplers`
   pub fn from_image(
     img: &DynamicImage,
+    color_space: ColorSpace,
+    sampler: &Sampler,
     queue: &Queue,
     device: &Device,
+    resources: &ResourceContext,
   ) -> Result<Self, TextureError> {
-    let tex = load_texture_from_image(img, queue, device)?;
-    Self::from_texture(tex, queue, device)
+    let (width, height) = img.dimensions();
+    let mip_levels = mip_level_count(width, height);
+    let tex = load_texture_from_image(img, color_space, mip_levels, queue, device)?;
+    resources
+      .mipmaps
+      .write()
+      .map_err(|e| TextureError::Other(e.to_string()))?
+      .generate(
+        device,
+        queue,
+        &tex,
+        image_texture_format(img, color_space),
+        mip_levels,
+      );
+    let sampler = resources
+      .samplers
+      .write()
+      .map_err(|e| TextureError::Other(e.to_string()))?
+      .get_or_create(device, sampler);
+    Ok(Self::from_texture(tex, sampler))
   }
 
-  /// Creates a new texture resource with sampler and view
+  ///
+  /// Uploads a KTX2 or DDS file, in a compressed format if the device supports one.
+  /// Otherwise it is decompressed and uploaded like `from_image`.
+  /// `color_space` overrides the color space stored in the file
+  pub fn from_compressed(
+    bytes: &[u8],
+    color_space: ColorSpace,
+    sampler: &Sampler,
+    queue: &Queue,
+    device: &Device,
+    resources: &ResourceContext,
+  ) -> Result<Self, TextureError> {
+    let supported = CompressedFormats::from_features(device.features());
+    match decode_compressed(bytes, supported)? {
+      DecodedTexture::MipChain(mut chain) => {
+        chain.format = color_space.format(chain.format);
+        let tex = load_texture_from_mip_chain(&chain, queue, device);
+        let sampler = resources
+          .samplers
+          .write()
+          .map_err(|e| TextureError::Other(e.to_string()))?
+          .get_or_create(device, sampler);
+        Ok(Self::from_texture(tex, sampler))
+      }
+      DecodedTexture::Image(img) => {
+        Self::from_image(&img, color_space, sampler, queue, device, resources)
+      }
+    }
+  }
+
+  /// Creates a new texture resource with a view of every mip level
   /// from a wgpu texture object
-  pub fn from_texture(tex: Texture, _queue: &Queue, device: &Device) -> Result<Self, TextureError> {
+  pub fn from_texture(tex: Texture, sampler: Arc<wgpu::Sampler>) -> Self {
     let texture_view = tex.create_view(&wgpu::TextureViewDescriptor {
       label: Some(concat!(std::file!(), ":", std::line!())),
       ..Default::default()
     });
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-      address_mode_u: wgpu::AddressMode::ClampToEdge,
-      address_mode_v: wgpu::AddressMode::ClampToEdge,
-      address_mode_w: wgpu::AddressMode::ClampToEdge,
-      mag_filter: wgpu::FilterMode::Linear,
-      min_filter: wgpu::FilterMode::Linear,
-      mipmap_filter: wgpu::FilterMode::Linear,
-      lod_min_clamp: 0.1,
-      lod_max_clamp: f32::MAX,
-      ..Default::default()
-    });
-    Ok(Self {
+    Self {
       texture: tex,
       view: texture_view,
       sampler,
-    })
+    }
   }
 
   // accessors
@@ -91,7 +352,7 @@ impl TextureResource {
   pub fn sampler(&self) -> &wgpu::Sampler {
     &self.sampler
   }
-  pub fn set_sampler(&mut self, sampler: wgpu::Sampler) {
+  pub fn set_sampler(&mut self, sampler: Arc<wgpu::Sampler>) {
     self.sampler = sampler;
   }
 
@@ -133,17 +394,191 @@ impl TextureResource {
     Self {
       texture,
       view,
-      sampler,
+      sampler: Arc::new(sampler),
+    }
+  }
+
+  /// Creates a depth texture array with an array view and a comparison sampler,
+  /// for shadow maps. Each layer can be rendered into through a 2D view
+  pub fn new_depth_texture_array(device: &Device, size: u32, layers: u32, label: &str) -> Self {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some(label),
+      size: wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: layers,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format: Self::DEPTH_TEXTURE_FORMAT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+      label: Some(label),
+      dimension: Some(TextureViewDimension::D2Array),
+      ..Default::default()
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      mipmap_filter: FilterMode::Nearest,
+      compare: Some(wgpu::CompareFunction::LessEqual),
+      ..Default::default()
+    });
+    Self {
+      texture,
+      view,
+      sampler: Arc::new(sampler),
+    }
+  }
+
+  /// Creates a color texture which can be rendered into,
+  /// and copied back to the CPU
+  pub fn new_render_target_texture(
+    device: &Device,
+    (width, height): (u32, u32),
+    format: wgpu::TextureFormat,
+    label: &str,
+  ) -> Self {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some(label),
+      size: wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+        | wgpu::TextureUsages::TEXTURE_BINDING
+        | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      mipmap_filter: FilterMode::Nearest,
+      ..Default::default()
+    });
+    Self {
+      texture,
+      view,
+      sampler: Arc::new(sampler),
     }
   }
+
+  /// Creates a cubemap with a cube view, which each face
+  /// and mip level can be rendered into through a 2D view
+  pub fn new_cube_texture(
+    device: &Device,
+    size: u32,
+    mip_level_count: u32,
+    format: wgpu::TextureFormat,
+    label: &str,
+  ) -> Self {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+      label: Some(label),
+      size: wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: 6,
+      },
+      mip_level_count,
+      sample_count: 1,
+      dimension: wgpu::TextureDimension::D2,
+      format,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+        | wgpu::TextureUsages::TEXTURE_BINDING
+        | wgpu::TextureUsages::COPY_DST,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+      label: Some(label),
+      dimension: Some(TextureViewDimension::Cube),
+      ..Default::default()
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      mipmap_filter: FilterMode::Linear,
+      ..Default::default()
+    });
+    Self {
+      texture,
+      view,
+      sampler: Arc::new(sampler),
+    }
+  }
+}
+
+///
+/// Format of a texture uploaded from `img`. 8 bit images are uploaded as RGBA8,
+/// in `color_space`, and 16 bit images as linear half floats
+pub fn image_texture_format(img: &DynamicImage, color_space: ColorSpace) -> TextureFormat {
+  match img {
+    DynamicImage::ImageLuma16(_)
+    | DynamicImage::ImageLumaA16(_)
+    | DynamicImage::ImageRgb16(_)
+    | DynamicImage::ImageRgba16(_) => TextureFormat::Rgba16Float,
+    _ => color_space.format(TextureFormat::Rgba8Unorm),
+  }
 }
 
+///
+/// Pixels of `img` in the layout of `image_texture_format`.
+/// sRGB 16 bit images are converted to linear, since float formats have no sRGB variant
+pub fn image_texture_bytes(img: &DynamicImage, color_space: ColorSpace) -> Vec<u8> {
+  match image_texture_format(img, color_space) {
+    TextureFormat::Rgba16Float => {
+      let to_half = |channel: u16, is_color: bool| {
+        let value = channel as f32 / u16::MAX as f32;
+        f32_to_f16(match (is_color, color_space) {
+          (true, ColorSpace::Srgb) => srgb_to_linear(value),
+          _ => value,
+        })
+      };
+      let halfs: Vec<u16> = img
+        .to_rgba16()
+        .pixels()
+        .flat_map(|pixel| {
+          let [r, g, b, a] = pixel.0;
+          [
+            to_half(r, true),
+            to_half(g, true),
+            to_half(b, true),
+            to_half(a, false),
+          ]
+        })
+        .collect();
+      bytemuck::cast_slice(&halfs).to_vec()
+    }
+    _ => img.to_rgba8().into_raw(),
+  }
+}
+
+///
+/// Uploads an image into the base level of a new texture. The texture can be rendered
+/// into, so that its other `mip_level_count - 1` levels can be generated by a `MipmapGenerator`
 pub fn load_texture_from_image(
   img: &image::DynamicImage,
+  color_space: ColorSpace,
+  mip_level_count: u32,
   queue: &Queue,
   device: &Device,
 ) -> Result<Texture, TextureError> {
-  let rgba = img.to_rgba8();
+  let format = image_texture_format(img, color_space);
+  let bytes = image_texture_bytes(img, color_space);
 
   let dimensions = img.dimensions();
   let texture_size = wgpu::Extent3d {
@@ -153,11 +588,13 @@ pub fn load_texture_from_image(
   };
   let texture = device.create_texture(&wgpu::TextureDescriptor {
     size: texture_size,
-    mip_level_count: 1,
+    mip_level_count,
     sample_count: 1,
     dimension: wgpu::TextureDimension::D2,
-    format: wgpu::TextureFormat::Rgba8UnormSrgb,
-    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    format,
+    usage: wgpu::TextureUsages::TEXTURE_BINDING
+      | wgpu::TextureUsages::COPY_DST
+      | wgpu::TextureUsages::RENDER_ATTACHMENT,
     label: Some("by__load_image_from_file"),
   });
   queue.write_texture(
@@ -167,10 +604,10 @@ pub fn load_texture_from_image(
       origin: wgpu::Origin3d::ZERO,
       aspect: Default::default(),
     },
-    &rgba,
+    &bytes,
     wgpu::ImageDataLayout {
       offset: 0,
-      bytes_per_row: NonZeroU32::new(4 * dimensions.0),
+      bytes_per_row: NonZeroU32::new(format.describe().block_size as u32 * dimensions.0),
       rows_per_image: NonZeroU32::new(dimensions.1),
     },
     texture_size,
@@ -179,32 +616,53 @@ pub fn load_texture_from_image(
   Ok(texture)
 }
 
-pub fn create_texture_bind_group_layout(device: &Device) -> BindGroupLayout {
-  let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-    label: Some("create_texture_bind_group"),
-    entries: &[
-      wgpu::BindGroupLayoutEntry {
-        binding: 0,
-        visibility: ShaderStages::FRAGMENT,
-        ty: wgpu::BindingType::Texture {
-          multisampled: false,
-          view_dimension: TextureViewDimension::D2,
-          sample_type: TextureSampleType::Float { filterable: true },
-        },
-        count: None,
+///
+/// Uploads every level of `chain`. Levels are uploaded in whole blocks,
+/// so levels smaller than a block are padded to the block size
+pub fn load_texture_from_mip_chain(chain: &MipChain, queue: &Queue, device: &Device) -> Texture {
+  let texture = device.create_texture(&wgpu::TextureDescriptor {
+    size: wgpu::Extent3d {
+      width: chain.width,
+      height: chain.height,
+      depth_or_array_layers: 1,
+    },
+    mip_level_count: chain.levels.len() as u32,
+    sample_count: 1,
+    dimension: wgpu::TextureDimension::D2,
+    format: chain.format,
+    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    label: Some("by__load_texture_from_mip_chain"),
+  });
+  let info = chain.format.describe();
+  let (block_width, block_height) = (
+    info.block_dimensions.0 as u32,
+    info.block_dimensions.1 as u32,
+  );
+  for (level, data) in chain.levels.iter().enumerate() {
+    let (width, height) = chain.level_size(level as u32);
+    let blocks_x = (width + block_width - 1) / block_width;
+    let blocks_y = (height + block_height - 1) / block_height;
+    queue.write_texture(
+      wgpu::ImageCopyTexture {
+        texture: &texture,
+        mip_level: level as u32,
+        origin: wgpu::Origin3d::ZERO,
+        aspect: Default::default(),
       },
-      wgpu::BindGroupLayoutEntry {
-        binding: 1,
-        visibility: ShaderStages::FRAGMENT,
-        ty: wgpu::BindingType::Sampler {
-          comparison: false,
-          filtering: true,
-        },
-        count: None,
+      data,
+      wgpu::ImageDataLayout {
+        offset: 0,
+        bytes_per_row: NonZeroU32::new(blocks_x * info.block_size as u32),
+        rows_per_image: NonZeroU32::new(blocks_y),
       },
-    ],
-  });
-  layout
+      wgpu::Extent3d {
+        width: blocks_x * block_width,
+        height: blocks_y * block_height,
+        depth_or_array_layers: 1,
+      },
+    );
+  }
+  texture
 }
 
 pub trait BindTexture {
@@ -254,11 +712,129 @@ mod native {
 
   pub fn load_image_from_file<P: AsRef<Path>>(
     path: P,
+    color_space: ColorSpace,
     queue: &Queue,
     device: &Device,
   ) -> Result<Texture, TextureError> {
     let img = image::open(path)?;
-    let texture = load_texture_from_image(&img, queue, device)?;
+    let texture = load_texture_from_image(&img, color_space, 1, queue, device)?;
     Ok(texture)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+  #[test]
+  fn test_sampler_from_gltf_modes() {
+    let sampler = Sampler::from_gltf_modes(
+      WrappingMode::MirroredRepeat,
+      WrappingMode::ClampToEdge,
+      Some(MagFilter::Nearest),
+      Some(MinFilter::LinearMipmapNearest),
+    );
+    assert_eq!(sampler.address_mode_u, AddressMode::MirrorRepeat);
+    assert_eq!(sampler.address_mode_v, AddressMode::ClampToEdge);
+    assert_eq!(sampler.mag_filter, FilterMode::Nearest);
+    assert_eq!(sampler.min_filter, FilterMode::Linear);
+    assert_eq!(sampler.mipmap_filter, FilterMode::Nearest);
+    assert!(sampler.use_mipmaps);
+
+    let unset = Sampler::from_gltf_modes(WrappingMode::Repeat, WrappingMode::Repeat, None, None);
+    assert_eq!(unset, Sampler::default());
+
+    // non mipmapped min filters only sample the base level
+    let base_only = Sampler::from_gltf_modes(
+      WrappingMode::Repeat,
+      WrappingMode::Repeat,
+      None,
+      Some(MinFilter::Linear),
+    );
+    assert!(!base_only.use_mipmaps);
+    assert_eq!(base_only.descriptor(None).lod_max_clamp, 0.0);
+  }
+
+  #[test]
+  fn test_sampler_anisotropy_requires_linear_filtering() {
+    let max_anisotropy = NonZeroU8::new(MAX_ANISOTROPY);
+    let trilinear = Sampler::default();
+    assert_eq!(
+      trilinear.descriptor(max_anisotropy).anisotropy_clamp,
+      max_anisotropy
+    );
+    assert_eq!(trilinear.descriptor(None).anisotropy_clamp, None);
+
+    let nearest = Sampler {
+      mag_filter: FilterMode::Nearest,
+      ..Sampler::default()
+    };
+    assert_eq!(nearest.descriptor(max_anisotropy).anisotropy_clamp, None);
+  }
+
+  #[test]
+  fn test_color_space_format() {
+    assert_eq!(
+      ColorSpace::Srgb.format(TextureFormat::Rgba8Unorm),
+      TextureFormat::Rgba8UnormSrgb
+    );
+    assert_eq!(
+      ColorSpace::Linear.format(TextureFormat::Bc7RgbaUnormSrgb),
+      TextureFormat::Bc7RgbaUnorm
+    );
+    assert_eq!(
+      ColorSpace::Linear.format(TextureFormat::Bc7RgbaUnorm),
+      TextureFormat::Bc7RgbaUnorm
+    );
+    // formats without an sRGB variant are unchanged
+    assert_eq!(
+      ColorSpace::Srgb.format(TextureFormat::Bc5RgUnorm),
+      TextureFormat::Bc5RgUnorm
+    );
+  }
+
+  #[test]
+  fn test_image_texture_format() {
+    let rgb8 = DynamicImage::new_rgb8(2, 2);
+    assert_eq!(
+      image_texture_format(&rgb8, ColorSpace::Srgb),
+      TextureFormat::Rgba8UnormSrgb
+    );
+    assert_eq!(
+      image_texture_format(&rgb8, ColorSpace::Linear),
+      TextureFormat::Rgba8Unorm
+    );
+    assert_eq!(
+      image_texture_bytes(&rgb8, ColorSpace::Srgb).len(),
+      2 * 2 * 4
+    );
+
+    let luma16 = DynamicImage::new_luma16(2, 2);
+    assert_eq!(
+      image_texture_format(&luma16, ColorSpace::Srgb),
+      TextureFormat::Rgba16Float
+    );
+    assert_eq!(
+      image_texture_bytes(&luma16, ColorSpace::Srgb).len(),
+      2 * 2 * 8
+    );
+  }
+
+  #[test]
+  fn test_16_bit_images_are_linearized() {
+    let mut img = image::ImageBuffer::<image::Rgba<u16>, Vec<u16>>::new(1, 1);
+    img.put_pixel(0, 0, image::Rgba([u16::MAX / 2, 0, u16::MAX, u16::MAX / 2]));
+    let img = DynamicImage::ImageRgba16(img);
+    let halfs = |color_space| -> Vec<u16> {
+      bytemuck::cast_slice(&image_texture_bytes(&img, color_space)).to_vec()
+    };
+    let srgb = halfs(ColorSpace::Srgb);
+    let linear = halfs(ColorSpace::Linear);
+    assert_eq!(srgb[0], f32_to_f16(srgb_to_linear(0.5)));
+    assert_eq!(linear[0], f32_to_f16(0.5));
+    assert_eq!(srgb[2], f32_to_f16(1.0));
+    // alpha is always linear
+    assert_eq!(srgb[3], linear[3]);
+  }
+}
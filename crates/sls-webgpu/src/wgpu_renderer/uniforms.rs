@@ -5,6 +5,10 @@ use nalgebra_glm::*;
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Uniforms {
   pub view_projection: [[f32; 4]; 4],
+  /// world space camera position. w is unused
+  pub camera_position: [f32; 4],
+  /// maps clip space back to world space, for the skybox
+  pub inverse_view_projection: [[f32; 4]; 4],
 }
 
 impl Default for Uniforms {
@@ -12,6 +16,8 @@ impl Default for Uniforms {
     let view_projection = Mat4::identity();
     Self {
       view_projection: *view_projection.as_ref(),
+      camera_position: [0.0, 0.0, 0.0, 1.0],
+      inverse_view_projection: *view_projection.as_ref(),
     }
   }
 }
@@ -21,44 +27,265 @@ impl Uniforms {
     let view_projection = camera.view_projection();
     let proj: [[f32; 4]; 4] = view_projection.into();
     self.view_projection = proj;
+    self.inverse_view_projection = view_projection
+      .try_inverse()
+      .unwrap_or_else(Mat4::identity)
+      .into();
+    self.camera_position = [camera.position.x, camera.position.y, camera.position.z, 1.0];
   }
 }
 
+/// Capacity of the light array bound to the pbr pipeline.
+/// Must match MAX_LIGHTS in main.frag
+pub const MAX_LIGHTS: usize = 16;
+
+pub const LIGHT_TYPE_POINT: u32 = 0;
+pub const LIGHT_TYPE_DIRECTIONAL: u32 = 1;
+pub const LIGHT_TYPE_SPOT: u32 = 2;
+
+///
+/// A single light, laid out to match the std140 `Light` struct in main.frag.
+/// Cutoffs are stored as cosines of the cone half-angles
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct PointLightUniform {
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
   pub position: [f32; 3],
-  pub _padding: u32,
+  pub light_type: u32,
+  pub direction: [f32; 3],
+  /// distance at which the light's contribution reaches zero. 0 for unlimited range
+  pub range: f32,
   pub color: [f32; 3],
+  pub intensity: f32,
+  pub inner_cutoff: f32,
+  pub outer_cutoff: f32,
+  /// first layer of the light's shadow maps, or -1 if it has no shadow
+  pub shadow_layer: i32,
+  /// number of shadow map layers, one per cascade for directional lights
+  pub shadow_layer_count: u32,
 }
 
-pub fn make_light_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
-  device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-    label: None,
-    entries: &[wgpu::BindGroupLayoutEntry {
-      binding: 0,
-      visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-      ty: wgpu::BindingType::Buffer {
-        ty: wgpu::BufferBindingType::Uniform,
-        has_dynamic_offset: false,
-        min_binding_size: None,
-      },
-      count: None,
-    }],
-  })
-}
-
-impl Default for PointLightUniform {
+impl Default for LightUniform {
   fn default() -> Self {
     Self {
-      _padding: 0,
       position: [0.0, 0.0, 0.0],
+      light_type: LIGHT_TYPE_POINT,
+      direction: [0.0, 0.0, -1.0],
+      range: 0.0,
       color: [1.0, 1.0, 1.0],
+      intensity: 1.0,
+      inner_cutoff: 1.0,
+      outer_cutoff: 0.0,
+      shadow_layer: -1,
+      shadow_layer_count: 0,
+    }
+  }
+}
+
+/// The `Lights` uniform block in main.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightArrayUniform {
+  pub count: u32,
+  pub _padding: [u32; 3],
+  pub lights: [LightUniform; MAX_LIGHTS],
+}
+
+impl Default for LightArrayUniform {
+  fn default() -> Self {
+    Self {
+      count: 0,
+      _padding: [0; 3],
+      lights: [LightUniform::default(); MAX_LIGHTS],
+    }
+  }
+}
+
+impl LightArrayUniform {
+  ///
+  /// Packs up to MAX_LIGHTS lights. Returns the array,
+  /// and the number of lights which did not fit
+  pub fn from_lights<I: IntoIterator<Item = LightUniform>>(lights: I) -> (Self, usize) {
+    let mut array = Self::default();
+    let mut dropped = 0;
+    for light in lights {
+      match array.lights.get_mut(array.count as usize) {
+        Some(slot) => {
+          *slot = light;
+          array.count += 1;
+        }
+        None => dropped += 1,
+      }
+    }
+    (array, dropped)
+  }
+
+  pub fn lights(&self) -> &[LightUniform] {
+    &self.lights[..self.count as usize]
+  }
+}
+
+///
+/// Shadow data for the `Shadows` uniform block in main.frag.
+/// Lights reference their layers through `LightUniform::shadow_layer`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+  /// world to light clip space, for each layer of the shadow map array
+  pub view_projections: [[[f32; 4]; 4]; MAX_SHADOW_LAYERS],
+  /// view depth at the far end of each directional light cascade
+  pub cascade_splits: [f32; 4],
+  /// main camera's forward vector, used to find a fragment's view depth
+  pub camera_forward: [f32; 4],
+  pub layer_count: u32,
+  /// size of a shadow map texel in uv coordinates
+  pub texel_size: f32,
+  pub _padding: [u32; 2],
+}
+
+/// Capacity of the shadow map array.
+/// Must match MAX_SHADOW_LAYERS in main.frag
+pub const MAX_SHADOW_LAYERS: usize = 8;
+
+impl Default for ShadowUniform {
+  fn default() -> Self {
+    Self {
+      view_projections: [*Mat4::identity().as_ref(); MAX_SHADOW_LAYERS],
+      cascade_splits: [0.0; 4],
+      camera_forward: [0.0, 0.0, -1.0, 0.0],
+      layer_count: 0,
+      texel_size: 0.0,
+      _padding: [0; 2],
     }
   }
 }
 
-pub enum DynLightUniform {
-  Point(PointLightUniform),
-  Unsupported,
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::{
+    game::components::{LightSource, LightType, Transform3D},
+    scene_graph::components::LocalToWorld,
+  };
+  use memoffset::offset_of;
+  use std::mem::size_of;
+
+  #[test]
+  fn test_light_uniform_std140_layout() {
+    assert_eq!(offset_of!(LightUniform, position), 0);
+    assert_eq!(offset_of!(LightUniform, light_type), 12);
+    assert_eq!(offset_of!(LightUniform, direction), 16);
+    assert_eq!(offset_of!(LightUniform, range), 28);
+    assert_eq!(offset_of!(LightUniform, color), 32);
+    assert_eq!(offset_of!(LightUniform, intensity), 44);
+    assert_eq!(offset_of!(LightUniform, inner_cutoff), 48);
+    assert_eq!(offset_of!(LightUniform, outer_cutoff), 52);
+    assert_eq!(offset_of!(LightUniform, shadow_layer), 56);
+    assert_eq!(offset_of!(LightUniform, shadow_layer_count), 60);
+    // std140 rounds struct array strides up to 16 bytes
+    assert_eq!(size_of::<LightUniform>(), 64);
+  }
+
+  #[test]
+  fn test_light_array_std140_layout() {
+    assert_eq!(offset_of!(LightArrayUniform, count), 0);
+    assert_eq!(offset_of!(LightArrayUniform, lights), 16);
+    assert_eq!(
+      size_of::<LightArrayUniform>(),
+      16 + MAX_LIGHTS * size_of::<LightUniform>()
+    );
+  }
+
+  #[test]
+  fn test_shadow_uniform_std140_layout() {
+    assert_eq!(offset_of!(ShadowUniform, view_projections), 0);
+    assert_eq!(
+      offset_of!(ShadowUniform, cascade_splits),
+      64 * MAX_SHADOW_LAYERS
+    );
+    assert_eq!(
+      offset_of!(ShadowUniform, camera_forward),
+      64 * MAX_SHADOW_LAYERS + 16
+    );
+    assert_eq!(
+      offset_of!(ShadowUniform, layer_count),
+      64 * MAX_SHADOW_LAYERS + 32
+    );
+    assert_eq!(
+      offset_of!(ShadowUniform, texel_size),
+      64 * MAX_SHADOW_LAYERS + 36
+    );
+    assert_eq!(size_of::<ShadowUniform>(), 64 * MAX_SHADOW_LAYERS + 48);
+  }
+
+  #[test]
+  fn test_light_array_truncates_to_capacity() {
+    let lights = (0..MAX_LIGHTS + 3).map(|i| LightUniform {
+      intensity: i as f32,
+      ..Default::default()
+    });
+    let (array, dropped) = LightArrayUniform::from_lights(lights);
+    assert_eq!(array.count as usize, MAX_LIGHTS);
+    assert_eq!(dropped, 3);
+    assert_eq!(
+      array.lights()[MAX_LIGHTS - 1].intensity,
+      (MAX_LIGHTS - 1) as f32
+    );
+  }
+
+  #[test]
+  fn test_pack_point_light() {
+    let transform = Transform3D::default().with_position(vec3(2.0, 4.0, 1.0));
+    let light = LightSource {
+      light_type: LightType::Point,
+      color: vec3(1.0, 0.5, 0.0),
+      intensity: 3.0,
+      range: 10.0,
+      ..Default::default()
+    };
+    let uniform = light.as_uniform(&LocalToWorld(transform.matrix()));
+    assert_eq!(uniform.light_type, LIGHT_TYPE_POINT);
+    assert_eq!(uniform.position, [2.0, 4.0, 1.0]);
+    assert_eq!(uniform.color, [1.0, 0.5, 0.0]);
+    assert_eq!(uniform.intensity, 3.0);
+    assert_eq!(uniform.range, 10.0);
+  }
+
+  #[test]
+  fn test_pack_directional_light_direction() {
+    // rotating -Z by 90 degrees about Y points the light down -X
+    let transform = Transform3D::default().with_rotation(quat_angle_axis(
+      std::f32::consts::FRAC_PI_2,
+      &vec3(0.0, 1.0, 0.0),
+    ));
+    let light = LightSource {
+      light_type: LightType::Directional,
+      ..Default::default()
+    };
+    let uniform = light.as_uniform(&LocalToWorld(transform.matrix()));
+    assert_eq!(uniform.light_type, LIGHT_TYPE_DIRECTIONAL);
+    let expected = [-1.0, 0.0, 0.0];
+    for (a, e) in uniform.direction.iter().zip(expected.iter()) {
+      assert!(
+        (a - e).abs() < 1e-5,
+        "{:?} != {:?}",
+        uniform.direction,
+        expected
+      );
+    }
+  }
+
+  #[test]
+  fn test_pack_spot_light_cutoffs() {
+    let light = LightSource {
+      light_type: LightType::Spotlight,
+      cutoff: 0.0,
+      outer_cutoff: std::f32::consts::FRAC_PI_2,
+      ..Default::default()
+    };
+    let uniform = light.as_uniform(&LocalToWorld::identity());
+    assert_eq!(uniform.light_type, LIGHT_TYPE_SPOT);
+    assert_eq!(uniform.direction, [0.0, 0.0, -1.0]);
+    assert!((uniform.inner_cutoff - 1.0).abs() < 1e-6);
+    assert!(uniform.outer_cutoff.abs() < 1e-6);
+  }
 }
@@ -1,2 +1,3 @@
 pub mod renderer_common;
+pub mod rendering;
 pub mod util;
@@ -0,0 +1,207 @@
+//! Golden image comparisons for headless renders.
+//!
+//! Reference images live in `tests/rendering/golden_images`. Set `SLS_UPDATE_GOLDEN=1`
+//! to re-record them. On a mismatch, the actual, expected, and diff images are written to
+//! `$CARGO_TARGET_TMPDIR/golden`.
+//!
+//! Golden tests whose reference isn't committed yet are ignored. Record it with
+//! `SLS_UPDATE_GOLDEN=1 cargo test -- --ignored golden_`, then remove the `#[ignore]`.
+use sls_webgpu::image::{self, Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+
+pub const UPDATE_GOLDEN_ENV: &str = "SLS_UPDATE_GOLDEN";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+  /// largest per-channel difference for two pixels to be considered equal
+  pub channel: u8,
+  /// fraction of pixels allowed to differ by more than `channel`
+  pub max_mismatched_ratio: f32,
+}
+
+impl Default for Tolerance {
+  fn default() -> Self {
+    Self {
+      channel: 2,
+      max_mismatched_ratio: 0.001,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct ImageDiff {
+  pub mismatched_pixels: usize,
+  pub total_pixels: usize,
+  pub max_channel_delta: u8,
+  /// mismatched pixels in red, over a darkened copy of the expected image
+  pub diff_image: RgbaImage,
+}
+
+impl ImageDiff {
+  pub fn mismatched_ratio(&self) -> f32 {
+    if self.total_pixels == 0 {
+      0.0
+    } else {
+      self.mismatched_pixels as f32 / self.total_pixels as f32
+    }
+  }
+
+  pub fn passes(&self, tolerance: &Tolerance) -> bool {
+    self.mismatched_ratio() <= tolerance.max_mismatched_ratio
+  }
+}
+
+///
+/// Compares two images pixel by pixel.
+/// Returns None if the images have different dimensions
+pub fn compare_images(
+  actual: &RgbaImage,
+  expected: &RgbaImage,
+  channel_tolerance: u8,
+) -> Option<ImageDiff> {
+  if actual.dimensions() != expected.dimensions() {
+    return None;
+  }
+  let (width, height) = expected.dimensions();
+  let mut diff_image = RgbaImage::new(width, height);
+  let mut mismatched_pixels = 0;
+  let mut max_channel_delta = 0;
+  for ((a, e), d) in actual
+    .pixels()
+    .zip(expected.pixels())
+    .zip(diff_image.pixels_mut())
+  {
+    let delta = a
+      .0
+      .iter()
+      .zip(e.0.iter())
+      .map(|(a, e)| (*a as i16 - *e as i16).abs() as u8)
+      .max()
+      .unwrap_or(0);
+    max_channel_delta = max_channel_delta.max(delta);
+    *d = if delta > channel_tolerance {
+      mismatched_pixels += 1;
+      Rgba([255, 0, 0, 255])
+    } else {
+      let luma = (e.0[0] as u32 + e.0[1] as u32 + e.0[2] as u32) / 12;
+      Rgba([luma as u8, luma as u8, luma as u8, 255])
+    };
+  }
+  Some(ImageDiff {
+    mismatched_pixels,
+    total_pixels: (width * height) as usize,
+    max_channel_delta,
+    diff_image,
+  })
+}
+
+pub fn golden_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/rendering/golden_images")
+}
+
+pub fn output_dir() -> PathBuf {
+  Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn should_update() -> bool {
+  std::env::var(UPDATE_GOLDEN_ENV)
+    .map(|v| !v.is_empty() && v != "0")
+    .unwrap_or(false)
+}
+
+///
+/// Panics if `actual` does not match the golden image `name`, or if it is missing.
+/// With `SLS_UPDATE_GOLDEN` set, records `actual` as the golden image instead
+pub fn assert_matches_golden(name: &str, actual: &RgbaImage, tolerance: Tolerance) {
+  let golden_path = golden_dir().join(format!("{}.png", name));
+  if should_update() {
+    std::fs::create_dir_all(golden_dir()).expect("could not create golden image directory");
+    actual
+      .save(&golden_path)
+      .expect("could not write golden image");
+    eprintln!("recorded golden image {}", golden_path.display());
+    return;
+  }
+  if !golden_path.exists() {
+    panic!(
+      "missing golden image {}. Run with {}=1 to record it",
+      golden_path.display(),
+      UPDATE_GOLDEN_ENV
+    );
+  }
+
+  let expected = image::open(&golden_path)
+    .expect("could not read golden image")
+    .to_rgba8();
+  let diff = compare_images(actual, &expected, tolerance.channel);
+  let failure = match &diff {
+    None => format!(
+      "size mismatch: actual {:?}, expected {:?}",
+      actual.dimensions(),
+      expected.dimensions()
+    ),
+    Some(diff) if !diff.passes(&tolerance) => format!(
+      "{} of {} pixels differ (max channel delta {})",
+      diff.mismatched_pixels, diff.total_pixels, diff.max_channel_delta
+    ),
+    Some(_) => return,
+  };
+
+  let out = output_dir();
+  std::fs::create_dir_all(&out).expect("could not create golden output directory");
+  actual
+    .save(out.join(format!("{}.actual.png", name)))
+    .expect("could not write actual image");
+  expected
+    .save(out.join(format!("{}.expected.png", name)))
+    .expect("could not write expected image");
+  if let Some(diff) = diff {
+    diff
+      .diff_image
+      .save(out.join(format!("{}.diff.png", name)))
+      .expect("could not write diff image");
+  }
+  panic!(
+    "render '{}' does not match {}: {}. Outputs written to {}",
+    name,
+    golden_path.display(),
+    failure,
+    out.display()
+  );
+}
+
+#[test]
+fn test_compare_identical_images() {
+  let img = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+  let diff = compare_images(&img, &img, 0).unwrap();
+  assert_eq!(diff.mismatched_pixels, 0);
+  assert_eq!(diff.max_channel_delta, 0);
+  assert!(diff.passes(&Tolerance::default()));
+}
+
+#[test]
+fn test_compare_images_tolerance() {
+  let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+  let mut actual = expected.clone();
+  actual.put_pixel(0, 0, Rgba([102, 100, 100, 255]));
+  actual.put_pixel(1, 0, Rgba([90, 100, 100, 255]));
+
+  let diff = compare_images(&actual, &expected, 2).unwrap();
+  assert_eq!(diff.mismatched_pixels, 1);
+  assert_eq!(diff.total_pixels, 16);
+  assert_eq!(diff.max_channel_delta, 10);
+  assert_eq!(*diff.diff_image.get_pixel(1, 0), Rgba([255, 0, 0, 255]));
+  assert_ne!(*diff.diff_image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+  assert!(!diff.passes(&Tolerance::default()));
+  assert!(diff.passes(&Tolerance {
+    channel: 2,
+    max_mismatched_ratio: 0.1,
+  }));
+}
+
+#[test]
+fn test_compare_images_size_mismatch() {
+  let a = RgbaImage::new(4, 4);
+  let b = RgbaImage::new(4, 2);
+  assert!(compare_images(&a, &b, 0).is_none());
+}
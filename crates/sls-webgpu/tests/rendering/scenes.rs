@@ -0,0 +1,314 @@
+use super::golden::{assert_matches_golden, compare_images, Tolerance};
+use sls_webgpu::{
+  camera::Camera,
+  game::{
+    components::Transform3D,
+    resources::{PickRequest, PostProcessSettings, Scene},
+    GameState, GameStateBuilder,
+  },
+  image::RgbaImage,
+  legion::{systems::CommandBuffer, Resources},
+  nalgebra_glm::{vec3, vec4, Vec3},
+  wgpu,
+  wgpu_renderer::{gltf_scene::GltfScene, picking::PickingPass, render_graph::RenderNode},
+  Context,
+};
+use std::{
+  cell::Cell,
+  path::{Path, PathBuf},
+};
+
+const SIZE: (u32, u32) = (256, 256);
+
+fn asset_path<P: AsRef<Path>>(p: P) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join(p)
+}
+
+fn backends() -> wgpu::Backends {
+  wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY)
+}
+
+///
+/// Creates a headless context on a software adapter,
+/// or None if the machine does not have one
+fn software_context() -> Option<Context> {
+  software_context_with_samples(1)
+}
+
+fn software_context_with_samples(sample_count: u32) -> Option<Context> {
+  let instance = wgpu::Instance::new(backends());
+  let has_software_adapter = instance
+    .enumerate_adapters(backends())
+    .any(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu);
+  if !has_software_adapter {
+    eprintln!("no software adapter available, skipping golden image test");
+    return None;
+  }
+  let builder = Context::headless(SIZE, wgpu::TextureFormat::Rgba8UnormSrgb)
+    .with_instance(instance)
+    .with_backends(Some(backends()))
+    .with_software_adapter(true)
+    .with_sample_count(sample_count);
+  Some(pollster::block_on(builder.build()).expect("could not create headless context"))
+}
+
+fn fixed_camera(position: Vec3) -> Camera {
+  let mut camera = Camera::new(SIZE.0 as f32 / SIZE.1 as f32);
+  camera.position = position;
+  camera.aspect_matches_window = false;
+  camera
+}
+
+///
+/// Spawns the gltf document's default scene,
+/// renders a single frame from `camera`, and reads it back
+fn render_gltf(context: &mut Context, path: &Path, camera: Camera) -> RgbaImage {
+  // the golden images are of the scene itself, without tonemapping or antialiasing
+  render_gltf_with(context, path, camera, PostProcessSettings::disabled())
+}
+
+fn render_gltf_with(
+  context: &mut Context,
+  path: &Path,
+  camera: Camera,
+  post_process: PostProcessSettings,
+) -> RgbaImage {
+  let scene = GltfScene::import(path).expect("could not load gltf doc");
+  let models = scene.load_models(context).expect("could not load models");
+  let mut game = GameStateBuilder::default().build();
+  let mut commands = CommandBuffer::new(game.world());
+  scene
+    .spawn(&mut commands, &models)
+    .expect("could not spawn scene");
+  commands.flush(game.world_mut(), &mut Resources::default());
+  spawn_camera(&mut game, camera);
+  game.resources_mut().insert(post_process);
+
+  context.render(&mut game).expect("render failed");
+  let raw = pollster::block_on(context.read_back_rgba()).expect("read back failed");
+  let (width, height) = raw.size();
+  RgbaImage::from_raw(width as u32, height as u32, raw.data().clone())
+    .expect("read back has the wrong size")
+}
+
+fn spawn_camera(game: &mut GameState, camera: Camera) {
+  let transform = Transform3D::default().with_position(camera.position);
+  let entity = game.world_mut().push((transform, camera));
+  game
+    .resources_mut()
+    .get_mut::<Scene>()
+    .unwrap()
+    .set_main_camera(Some(entity));
+}
+
+#[test]
+#[ignore = "the golden image is not recorded yet"]
+fn golden_simple_meshes() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  let image = render_gltf(
+    &mut context,
+    &asset_path("tests/renderer_common/simple_meshes.gltf"),
+    fixed_camera(vec3(1.0, 0.5, 3.0)),
+  );
+  assert_matches_golden("simple_meshes", &image, Tolerance::default());
+}
+
+#[test]
+#[ignore = "the golden image is not recorded yet"]
+fn golden_damaged_helmet() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  let image = render_gltf(
+    &mut context,
+    &asset_path("assets/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"),
+    fixed_camera(vec3(0.0, 0.0, 3.0)),
+  );
+  assert_matches_golden("damaged_helmet", &image, Tolerance::default());
+}
+
+#[test]
+#[ignore = "the golden image is not recorded yet"]
+fn golden_simple_meshes_msaa() {
+  let mut context = match software_context_with_samples(4) {
+    Some(context) => context,
+    None => return,
+  };
+  assert_eq!(context.sample_count(), 4);
+  let image = render_gltf(
+    &mut context,
+    &asset_path("tests/renderer_common/simple_meshes.gltf"),
+    fixed_camera(vec3(1.0, 0.5, 3.0)),
+  );
+  // only the antialiased edges differ from the single sampled render
+  let tolerance = Tolerance {
+    channel: 2,
+    max_mismatched_ratio: 0.05,
+  };
+  assert_matches_golden("simple_meshes", &image, tolerance);
+}
+
+#[test]
+fn sample_count_changes_at_runtime() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  assert!(context.set_sample_count(3).is_err());
+  assert_eq!(context.sample_count(), 1);
+  if !context.supported_sample_counts().contains(&4) {
+    return;
+  }
+  context.set_sample_count(4).expect("could not enable MSAA");
+  assert_eq!(context.sample_count(), 4);
+  render_gltf(
+    &mut context,
+    &asset_path("tests/renderer_common/simple_meshes.gltf"),
+    fixed_camera(vec3(1.0, 0.5, 3.0)),
+  );
+  context.set_sample_count(1).expect("could not disable MSAA");
+  assert_eq!(context.sample_count(), 1);
+}
+
+#[test]
+fn post_processing_changes_the_frame() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  let path = asset_path("tests/renderer_common/simple_meshes.gltf");
+  let camera = fixed_camera(vec3(1.0, 0.5, 3.0));
+  let unprocessed = render_gltf(&mut context, &path, camera.clone());
+  let processed = render_gltf_with(&mut context, &path, camera, PostProcessSettings::default());
+  let diff = compare_images(&processed, &unprocessed, 2).expect("render sizes differ");
+  assert!(diff.mismatched_pixels > 0);
+}
+
+#[test]
+#[ignore = "the golden image is not recorded yet"]
+fn golden_simple_meshes_gpu_culling() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  if !context.gpu_culling_supported() {
+    assert!(context.set_gpu_culling(true).is_err());
+    assert!(!context.gpu_culling_enabled());
+    return;
+  }
+  context
+    .set_gpu_culling(true)
+    .expect("could not enable gpu culling");
+  let image = render_gltf(
+    &mut context,
+    &asset_path("tests/renderer_common/simple_meshes.gltf"),
+    fixed_camera(vec3(1.0, 0.5, 3.0)),
+  );
+  // indirect draws of the culled instances render the same scene
+  assert_matches_golden("simple_meshes", &image, Tolerance::default());
+}
+
+#[test]
+fn picking_finds_the_entity_under_a_pixel() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  context.set_picking(true);
+  let scene = GltfScene::import(asset_path("tests/renderer_common/simple_meshes.gltf"))
+    .expect("could not load gltf doc");
+  let models = scene
+    .load_models(&mut context)
+    .expect("could not load models");
+  let mut game = GameStateBuilder::default().build();
+  let mut commands = CommandBuffer::new(game.world());
+  let roots = scene
+    .spawn(&mut commands, &models)
+    .expect("could not spawn scene");
+  commands.flush(game.world_mut(), &mut Resources::default());
+  let camera = fixed_camera(vec3(1.0, 0.5, 3.0));
+  let view_projection = camera.view_projection();
+  spawn_camera(&mut game, camera);
+
+  let mut pick = |world_position: Vec3| {
+    let clip = view_projection * vec4(world_position.x, world_position.y, world_position.z, 1.0);
+    let pixel = (
+      ((clip.x / clip.w + 1.0) * 0.5 * SIZE.0 as f32) as u32,
+      ((1.0 - clip.y / clip.w) * 0.5 * SIZE.1 as f32) as u32,
+    );
+    game
+      .resources_mut()
+      .insert(PickRequest { pixel: Some(pixel) });
+    context.render(&mut game).expect("render failed");
+    context.wait_for_pick().expect("pick was not rendered")
+  };
+  // inside the second node's triangle, which is translated by 1 along x
+  let hit = pick(vec3(1.2, 0.3, 0.0));
+  assert_eq!(hit.entity, Some(roots[1]));
+  assert!(hit.depth < 1.0);
+  assert!(
+    (hit.world_position - vec3(1.2, 0.3, 0.0)).norm() < 0.05,
+    "{:?}",
+    hit.world_position
+  );
+  let miss = pick(vec3(1.8, 0.8, 0.0));
+  assert_eq!(miss.entity, None);
+  assert_eq!(miss.depth, 1.0);
+  // the picking node is only in the graph for frames which render a pick
+  context.render(&mut game).expect("render failed");
+  assert!(context
+    .render_graph_mut()
+    .node_names()
+    .all(|name| name != PickingPass.name()));
+}
+
+#[test]
+fn ui_node_runs_the_ui_hook() {
+  let mut context = match software_context() {
+    Some(context) => context,
+    None => return,
+  };
+  let mut game = GameStateBuilder::default().build();
+  spawn_camera(&mut game, fixed_camera(vec3(0.0, 0.0, 3.0)));
+  let ran = Cell::new(false);
+  // the hook can borrow from the caller, as it only lives for the call
+  context
+    .render_with_ui(
+      &mut game,
+      Box::new(|_queue, _device, encoder, target| {
+        ran.set(true);
+        // covers the frame, so the read back shows whether the hook drew into it
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+          label: Some("test ui pass"),
+          color_attachments: &[wgpu::RenderPassColorAttachment {
+            ops: wgpu::Operations {
+              load: wgpu::LoadOp::Clear(wgpu::Color {
+                r: 1.0,
+                g: 0.0,
+                b: 1.0,
+                a: 1.0,
+              }),
+              store: true,
+            },
+            ..target.color_attachment()
+          }],
+          depth_stencil_attachment: None,
+        });
+        Ok(())
+      }),
+    )
+    .expect("render failed");
+  let frame = |context: &mut Context| {
+    let raw = pollster::block_on(context.read_back_rgba()).expect("read back failed");
+    raw.data()[..4].to_vec()
+  };
+  assert_eq!(frame(&mut context), vec![255, 0, 255, 255]);
+  assert!(ran.get());
+  // the hook is only run for the frame it was passed to
+  context.render(&mut game).expect("render failed");
+  assert_ne!(frame(&mut context), vec![255, 0, 255, 255]);
+}